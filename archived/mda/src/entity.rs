@@ -83,4 +83,8 @@ pub struct AudioMetaData {
 pub struct VideoMetaData {
     pub duration: f64,
     pub resolution: (u16, u16),
+    /// Frames per second, `None` without sample timing
+    pub frame_rate: Option<f64>,
+    /// Codec of the sample entry, e.g. `H264`
+    pub codec: Option<String>,
 }
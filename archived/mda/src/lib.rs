@@ -14,10 +14,12 @@ pub mod mda_operations{
     pub mod generate;
     pub mod extract;
     pub mod update;
+    pub mod reader;
 }
 pub use mda_operations::generate;
 pub use mda_operations::extract;
 pub use mda_operations::update;
+pub use mda_operations::reader;
 
 pub mod map{
     pub mod read_from_file;
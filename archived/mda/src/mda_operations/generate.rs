@@ -247,12 +247,20 @@ pub fn config_annotation_data_by_content(content: &str) -> Result<RevAnno, Box<d
 /// Extract metadata from training data
 pub fn process_file(file_path: &str) -> Option<Box<dyn std::any::Any>> {
     if file_path.ends_with(".jpg") || file_path.ends_with(".png") {
-        let image_metadata = extract_image_metadata(file_path);
-        Some(Box::new(image_metadata) as Box<dyn std::any::Any>)
+        match extract_image_metadata(file_path) {
+            Ok(image_metadata) => Some(Box::new(image_metadata) as Box<dyn std::any::Any>),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                None
+            }
+        }
     } else if file_path.ends_with(".mp4") || file_path.ends_with(".avi") {
         match extract_video_info(file_path) {
-            Some(info) => Some(Box::new(info) as Box<dyn std::any::Any>),
-            None => None,
+            Ok(info) => Some(Box::new(info) as Box<dyn std::any::Any>),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                None
+            }
         }
     } else if file_path.ends_with(".mp3") || file_path.ends_with(".wav") {
         match extract_audio_metadata(file_path) {
@@ -500,7 +508,31 @@ pub fn write_mda_data(
     };
 
     //Config Anno data
-    let mut tmp_anno_offsets_for_annotations: Vec<AnnoOffset> = Vec::new();
+    let tmp_anno_offsets_for_annotations = write_rev_annos(&mut file, rev_anno_ids)?;
+
+    // Return to MDAIndex and update offset
+    file.seek(SeekFrom::Start(index_placeholder_offset))?;
+
+    serialize_into(
+        &file,
+        &MDAIndex {
+            header_offset,
+            train_data_offset,
+            annotations_offset: tmp_anno_offsets_for_annotations,
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Write the entries then the headers of each anno group at the end of `file`
+/// - return the offsets of the groups, in order
+pub fn write_rev_annos(
+    file: &mut File,
+    rev_anno_ids: &mut [RevAnnoWithID],
+) -> Result<Vec<AnnoOffset>, Box<dyn Error>> {
+    file.seek(SeekFrom::End(0))?;
+    let mut anno_offsets: Vec<AnnoOffset> = Vec::new();
 
     for rev_anno_id in rev_anno_ids.iter_mut() {
         let mut rev_anno = rev_anno_id.clone().rev_anno;
@@ -530,22 +562,10 @@ pub fn write_mda_data(
             header_offset: anno_headers_offset,
             entries_offset: store_anno_entries_offset,
         };
-        tmp_anno_offsets_for_annotations.push(tmp);
+        anno_offsets.push(tmp);
     }
 
-    // Return to MDAIndex and update offset
-    file.seek(SeekFrom::Start(index_placeholder_offset))?;
-
-    serialize_into(
-        &file,
-        &MDAIndex {
-            header_offset,
-            train_data_offset,
-            annotations_offset: tmp_anno_offsets_for_annotations,
-        },
-    )?;
-
-    Ok(())
+    Ok(anno_offsets)
 }
 
 /// Merge the same group(used to map train and anno)
@@ -1,3 +1,4 @@
 pub mod extract;
 pub mod generate;
-pub mod update;
\ No newline at end of file
+pub mod update;
+pub mod reader;
\ No newline at end of file
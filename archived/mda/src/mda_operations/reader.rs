@@ -0,0 +1,226 @@
+//! Read an MDA file on demand, seeking to the regions recorded in its `MDAIndex` instead of
+//! loading the whole file, so multi-gigabyte training data can be streamed.
+use crate::{DataType, MDAHeader, MDAIndex, RevAnno, RevAnnoEntry, RevAnnoHeader};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Take};
+
+/// Reader of an MDA file, only the index is kept in memory
+pub struct MdaReader {
+    reader: BufReader<File>,
+    index: MDAIndex,
+    file_len: u64,
+}
+
+impl MdaReader {
+    /// Open an MDA file and read its index
+    pub fn open(file_path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(file_path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let index: MDAIndex = bincode::deserialize_from(&mut reader)?;
+        Ok(MdaReader {
+            reader,
+            index,
+            file_len,
+        })
+    }
+
+    pub fn index(&self) -> &MDAIndex {
+        &self.index
+    }
+
+    /// Read the MDAHeader
+    pub fn read_header(&mut self) -> Result<MDAHeader, Box<dyn Error>> {
+        self.reader
+            .seek(SeekFrom::Start(self.index.header_offset))?;
+        Ok(bincode::deserialize_from(&mut self.reader)?)
+    }
+
+    /// Stream the training data: its type, and a reader of exactly its bytes (the text of
+    /// `DataType::Text` as UTF-8).
+    /// The reader borrows the file, so it must be dropped before reading anything else.
+    pub fn read_training_data_stream(
+        &mut self,
+    ) -> Result<(DataType, Take<&mut BufReader<File>>), Box<dyn Error>> {
+        self.reader
+            .seek(SeekFrom::Start(self.index.train_data_offset))?;
+        let data_type: DataType = bincode::deserialize_from(&mut self.reader)?;
+        // the content is serialized as a u64 length followed by the bytes
+        let mut len = [0u8; 8];
+        self.reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        let position = self.reader.stream_position()?;
+        if len > self.file_len.saturating_sub(position) {
+            return Err(format!(
+                "Training data of {} bytes exceeds the end of the file at {}",
+                len, position
+            )
+            .into());
+        }
+        Ok((data_type, (&mut self.reader).take(len)))
+    }
+
+    /// Read the `i`th annotation group: all its headers, and the entries they point to
+    pub fn read_annotation(&mut self, i: usize) -> Result<RevAnno, Box<dyn Error>> {
+        let group = self.index.annotations_offset.get(i).ok_or_else(|| {
+            format!(
+                "Annotation {} out of range, the file has {}",
+                i,
+                self.index.annotations_offset.len()
+            )
+        })?;
+        // headers follow the entries of their group and end where the next group starts
+        let headers_end = match self.index.annotations_offset.get(i + 1) {
+            Some(next) => next.entries_offset,
+            None => self.file_len,
+        };
+        let headers_offset = group.header_offset;
+
+        self.reader.seek(SeekFrom::Start(headers_offset))?;
+        let mut headers: Vec<RevAnnoHeader> = Vec::new();
+        while self.reader.stream_position()? < headers_end {
+            headers.push(bincode::deserialize_from(&mut self.reader)?);
+        }
+
+        let mut entries: Vec<RevAnnoEntry> = Vec::new();
+        for header in &headers {
+            self.reader.seek(SeekFrom::Start(header.offset))?;
+            let entry_reader = (&mut self.reader).take(header.length);
+            entries.push(bincode::deserialize_from(entry_reader)?);
+        }
+        Ok(RevAnno::new(headers, entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::{write_mda_data, RevAnnoWithID};
+    use crate::{get_full_data, TrainData, TrainingData};
+    use bincode::serialize_into;
+    use std::fs;
+    use std::io::{BufWriter, Write};
+
+    fn header(data_type: &str) -> MDAHeader {
+        MDAHeader {
+            tags: vec![],
+            train_data: TrainData {
+                data_type: data_type.to_string(),
+                metadata: String::new(),
+            },
+        }
+    }
+
+    fn byte_at(position: u64) -> u8 {
+        (position % 251) as u8
+    }
+
+    #[test]
+    fn test_stream_large_training_data() {
+        const LEN: u64 = 64 * 1024 * 1024;
+        const CHUNK: usize = 1024 * 1024;
+        let dir = std::env::temp_dir().join("mda_reader_stream_test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("large.mda");
+
+        // written chunk by chunk, in the layout of write_mda_data, with no annotation
+        let mut file = BufWriter::new(File::create(&path).unwrap());
+        let mut index = MDAIndex {
+            header_offset: 0,
+            train_data_offset: 0,
+            annotations_offset: vec![],
+        };
+        serialize_into(&mut file, &index).unwrap();
+        index.header_offset = file.stream_position().unwrap();
+        serialize_into(&mut file, &header("video")).unwrap();
+        index.train_data_offset = file.stream_position().unwrap();
+        serialize_into(&mut file, &DataType::Video).unwrap();
+        file.write_all(&LEN.to_le_bytes()).unwrap();
+        let mut written = 0;
+        while written < LEN {
+            let chunk: Vec<u8> = (written..written + CHUNK as u64).map(byte_at).collect();
+            file.write_all(&chunk).unwrap();
+            written += CHUNK as u64;
+        }
+        file.seek(SeekFrom::Start(0)).unwrap();
+        serialize_into(&mut file, &index).unwrap();
+        drop(file);
+
+        let mut reader = MdaReader::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(reader.read_header().unwrap().train_data.data_type, "video");
+        let (data_type, mut stream) = reader.read_training_data_stream().unwrap();
+        assert!(matches!(data_type, DataType::Video));
+        assert_eq!(stream.limit(), LEN);
+
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut position = 0;
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(buf[..n]
+                .iter()
+                .zip(position..)
+                .all(|(&byte, i)| byte == byte_at(i)));
+            position += n as u64;
+        }
+        assert_eq!(position, LEN);
+
+        // a length past the end of the file is rejected instead of read
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(LEN / 2).unwrap();
+        let mut reader = MdaReader::open(path.to_str().unwrap()).unwrap();
+        assert!(reader.read_training_data_stream().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_annotation() {
+        let dir = std::env::temp_dir().join("mda_reader_annotation_test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("annotated.mda");
+
+        let first = RevAnno::set_initial_element("label: cat");
+        let second = RevAnno::add_element(
+            "label: cat, dog",
+            first.entries.clone(),
+            first.headers.clone(),
+        );
+        let mut groups = vec![
+            RevAnnoWithID {
+                id: "labels".to_string(),
+                rev_anno: second,
+            },
+            RevAnnoWithID {
+                id: "boxes".to_string(),
+                rev_anno: RevAnno::set_initial_element("0 0 10 10"),
+            },
+        ];
+        write_mda_data(
+            path.to_str().unwrap(),
+            header("text"),
+            TrainingData::Text("a cat and a dog".to_string()),
+            &mut groups,
+        )
+        .unwrap();
+
+        let mut reader = MdaReader::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(reader.index().annotations_offset.len(), 2);
+        let labels = reader.read_annotation(0).unwrap();
+        assert_eq!(labels.headers.len(), 2);
+        assert_eq!(get_full_data(1, labels.entries), "label: cat, dog");
+        let boxes = reader.read_annotation(1).unwrap();
+        assert_eq!(boxes.headers.len(), 1);
+        assert_eq!(get_full_data(0, boxes.entries), "0 0 10 10");
+        assert!(reader.read_annotation(2).is_err());
+
+        let (data_type, mut stream) = reader.read_training_data_stream().unwrap();
+        assert!(matches!(data_type, DataType::Text));
+        let mut text = String::new();
+        stream.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "a cat and a dog");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
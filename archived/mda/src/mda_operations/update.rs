@@ -1,17 +1,20 @@
 use crate::extract:: get_all_rev_anno_with_id  ;
 use crate::{extract_file_name, MDAIndex};
 use crate::generate::{
-    write_mda_data, AnnoItem, Annotation, RevAnnoWithID, TrainMapAnno,
+    write_mda_data, write_rev_annos, AnnoItem, Annotation, RevAnnoWithID, TrainMapAnno,
 };
 use crate::read_from_file::get_train_path_and_anno_content;
+use crate::reader::MdaReader;
  
 use crate::{  DataType, MDAHeader,   RevAnno, TrainingData};
 use anyhow::Result;
 use indicatif::ProgressBar;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::process;
+use fs2::FileExt;
+
 /// update anno in combined anno file
 pub fn update_anno_in_combined_file(
     mda: &str,
@@ -159,3 +162,133 @@ fn update_rev_anno(
 
     Ok(())
 }
+
+/// Append `annotation.content` as a new revision of the anno group `annotation.id` in an MDA file.
+/// The training data stays in place, only the annotations after it and their offsets in the
+/// MDAIndex are rewritten. The file is locked meanwhile, so appends from concurrent threads or
+/// processes are serialized.
+pub fn append_annotation(mda_path: &str, annotation: &AnnoItem) -> Result<(), Box<dyn Error>> {
+    // released when `file` is closed
+    let mut file = OpenOptions::new().read(true).write(true).open(mda_path)?;
+    file.lock_exclusive()?;
+
+    let mut reader = MdaReader::open(mda_path)?;
+    let anno_offsets = reader.index().annotations_offset.clone();
+    let header_offset = reader.index().header_offset;
+    let train_data_offset = reader.index().train_data_offset;
+    let mut rev_anno_ids: Vec<RevAnnoWithID> = Vec::new();
+    for (i, offset) in anno_offsets.iter().enumerate() {
+        rev_anno_ids.push(RevAnnoWithID {
+            id: offset.id.clone(),
+            rev_anno: reader.read_annotation(i)?,
+        });
+    }
+    drop(reader);
+
+    let item = rev_anno_ids
+        .iter_mut()
+        .find(|item| item.id == annotation.id)
+        .ok_or_else(|| format!("No anno group {} in {}", annotation.id, mda_path))?;
+    item.rev_anno = RevAnno::add_element(
+        &annotation.content,
+        item.rev_anno.entries.clone(),
+        item.rev_anno.headers.clone(),
+    );
+
+    // the annotations follow the training data, in the order of the index
+    let annotations_start = anno_offsets
+        .iter()
+        .map(|offset| offset.entries_offset)
+        .min()
+        .ok_or("No annotation to append to")?;
+    file.set_len(annotations_start)?;
+    let annotations_offset = write_rev_annos(&mut file, &mut rev_anno_ids)?;
+
+    // same ids, so the index keeps its size and doesn't overwrite the MDAHeader
+    let index = MDAIndex {
+        header_offset,
+        train_data_offset,
+        annotations_offset,
+    };
+    if bincode::serialized_size(&index)? != header_offset {
+        return Err("MDAIndex size changed, it would overwrite the MDAHeader".into());
+    }
+    file.seek(SeekFrom::Start(0))?;
+    bincode::serialize_into(&file, &index)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_full_data, TrainData};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn test_append_annotation() {
+        let dir = std::env::temp_dir().join("mda_append_annotation_test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("append.mda").to_str().unwrap().to_string();
+        let mut groups = vec![
+            RevAnnoWithID {
+                id: "labels".to_string(),
+                rev_anno: RevAnno::set_initial_element("label: cat"),
+            },
+            RevAnnoWithID {
+                id: "boxes".to_string(),
+                rev_anno: RevAnno::set_initial_element("0 0 10 10"),
+            },
+        ];
+        let header = MDAHeader {
+            tags: vec!["pets".to_string()],
+            train_data: TrainData {
+                data_type: "text".to_string(),
+                metadata: String::new(),
+            },
+        };
+        let train_data = TrainingData::Text("a cat and a dog".to_string());
+        write_mda_data(&path, header, train_data, &mut groups).unwrap();
+
+        let appends = ["label: cat, dog", "label: cat, bird"];
+        thread::scope(|scope| {
+            for content in appends {
+                let path = &path;
+                scope.spawn(move || {
+                    let annotation = AnnoItem {
+                        id: "labels".to_string(),
+                        content: content.to_string(),
+                    };
+                    append_annotation(path, &annotation).unwrap();
+                });
+            }
+        });
+
+        let mut reader = MdaReader::open(&path).unwrap();
+        let labels = reader.read_annotation(0).unwrap();
+        assert_eq!(labels.headers.len(), 3);
+        assert_eq!(get_full_data(0, labels.entries.clone()), "label: cat");
+        let appended: HashSet<String> = (1..3)
+            .map(|rev| get_full_data(rev, labels.entries.clone()))
+            .collect();
+        assert_eq!(appended, appends.iter().map(|x| x.to_string()).collect());
+        let boxes = reader.read_annotation(1).unwrap();
+        assert_eq!(get_full_data(0, boxes.entries), "0 0 10 10");
+
+        assert_eq!(reader.read_header().unwrap().tags, ["pets"]);
+        let (_, mut stream) = reader.read_training_data_stream().unwrap();
+        let mut text = String::new();
+        stream.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "a cat and a dog");
+
+        let missing = AnnoItem {
+            id: "masks".to_string(),
+            content: String::new(),
+        };
+        assert!(append_annotation(&path, &missing).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,6 +2,7 @@
 //! that help simplify the development process and provide shared functionalities.
 
 extern crate image;
+use crate::extract::read_info_from_mda;
 use crate::{
     AnnoOffset, AudioMetaData, ImageMetaData, MDAHeader , MDAIndex, TextMetaData,
     VideoMetaData,
@@ -9,13 +10,15 @@ use crate::{
 use anyhow::Context;
 use chrono::Local;
 use encoding::{DecoderTrap, Encoding};
-use hound::{Error as boundError, WavReader};
-use image::{ColorType, GenericImageView};
+use hound::WavReader;
+use image::{ColorType, GenericImageView, ImageFormat};
 use mp4parse::read_mp4;
 use prettytable::{Cell, Row, Table};
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::{BufWriter, Write};
 use std::path::Path;
 use std::time::Instant;
@@ -56,12 +59,32 @@ pub fn save_text_to_file(text: &str, file_path: &str) -> Result<(), Box<dyn Erro
     file.write_all(text.as_bytes())?;
     Ok(())
 }
-/// Save image file
-pub fn save_image_to_file(image_data: &[u8], file_path: &str) -> Result<(), Box<dyn Error>> {
-    let file_path = file_path.to_string() + ".png";
-    let mut file = BufWriter::new(File::create(file_path)?);
+/// Image formats saved from MDA files, with their file extension
+const IMAGE_FORMATS: [(ImageFormat, &str); 6] = [
+    (ImageFormat::Png, "png"),
+    (ImageFormat::Jpeg, "jpg"),
+    (ImageFormat::Gif, "gif"),
+    (ImageFormat::Bmp, "bmp"),
+    (ImageFormat::WebP, "webp"),
+    (ImageFormat::Tiff, "tiff"),
+];
+
+/// Save image file, the extension follows the format detected from the bytes.
+/// Returns the path written, fails if the bytes are not an image of [IMAGE_FORMATS].
+pub fn save_image_to_file(image_data: &[u8], file_path: &str) -> Result<String, Box<dyn Error>> {
+    let format = image::guess_format(image_data).ok();
+    let extension = IMAGE_FORMATS
+        .iter()
+        .find(|(f, _)| Some(*f) == format)
+        .map(|(_, extension)| *extension)
+        .ok_or_else(|| match format {
+            Some(format) => format!("Unsupported image format {:?}", format),
+            None => "Data is not a recognized image".to_string(),
+        })?;
+    let file_path = format!("{}.{}", file_path, extension);
+    let mut file = BufWriter::new(File::create(&file_path)?);
     file.write_all(image_data)?;
-    Ok(())
+    Ok(file_path)
 }
 /// Save video file
 pub fn save_video_to_file(video_data: &[u8], file_path: &str) -> Result<(), Box<dyn Error>> {
@@ -78,10 +101,45 @@ pub fn save_audio_to_file(audio_data: &[u8], file_path: &str) -> Result<(), Box<
     Ok(())
 }
 
+/// Why the metadata of a media file could not be extracted
+#[derive(Debug)]
+pub enum MediaError {
+    /// the file has no content
+    Empty(String),
+    Io(String, io::Error),
+    /// truncated or corrupt content, or a format that isn't supported
+    Invalid(String, String),
+    /// an audio file with a sample rate of 0, its duration is unknown
+    ZeroSampleRate(String),
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaError::Empty(path) => write!(f, "{} is empty", path),
+            MediaError::Io(path, err) => write!(f, "Failed to read {}: {}", path, err),
+            MediaError::Invalid(path, reason) => write!(f, "Invalid media {}: {}", path, reason),
+            MediaError::ZeroSampleRate(path) => write!(f, "{} has a sample rate of 0", path),
+        }
+    }
+}
+
+impl Error for MediaError {}
+
+/// Reject a missing or empty file before handing it to a decoder
+fn check_not_empty(file_path: &str) -> Result<(), MediaError> {
+    let metadata = fs::metadata(file_path).map_err(|e| MediaError::Io(file_path.to_string(), e))?;
+    if metadata.len() == 0 {
+        return Err(MediaError::Empty(file_path.to_string()));
+    }
+    Ok(())
+}
+
 /// Extract metadata from training data(image)
-pub fn extract_image_metadata(image_path: &str) -> ImageMetaData {
-    let msg = "Failed to open file ".to_owned() + image_path;
-    let image = image::open(image_path).expect(&msg);
+pub fn extract_image_metadata(image_path: &str) -> Result<ImageMetaData, MediaError> {
+    check_not_empty(image_path)?;
+    let invalid = |reason: String| MediaError::Invalid(image_path.to_string(), reason);
+    let image = image::open(image_path).map_err(|e| invalid(e.to_string()))?;
 
     let (width, height) = image.dimensions();
     let channel_count = match image.color() {
@@ -89,7 +147,7 @@ pub fn extract_image_metadata(image_path: &str) -> ImageMetaData {
         ColorType::La8 => 2,
         ColorType::Rgb8 => 3,
         ColorType::Rgba8 => 4,
-        _ => panic!("Unsupported color type"),
+        color => return Err(invalid(format!("unsupported color type {:?}", color))),
     };
     let color_space = match image {
         image::DynamicImage::ImageRgb8(_) => "RGB".to_string(),
@@ -97,11 +155,11 @@ pub fn extract_image_metadata(image_path: &str) -> ImageMetaData {
         _ => "Unknown".to_string(),
     };
 
-    ImageMetaData {
+    Ok(ImageMetaData {
         size: (width, height),
         channel_count,
         color_space,
-    }
+    })
 }
 
 /// Extract metadata from training data(text)
@@ -138,19 +196,40 @@ pub fn extract_text_metadata(text_path: &str) -> TextMetaData {
 }
 
 /// Extract metadata from training data(video)
-pub fn extract_video_info(file_path: &str) -> Option<VideoMetaData> {
-    let mut file = File::open(file_path).ok()?;
-    let context = read_mp4(&mut file).ok()?;
+pub fn extract_video_info(file_path: &str) -> Result<VideoMetaData, MediaError> {
+    check_not_empty(file_path)?;
+    let invalid = |reason: &str| MediaError::Invalid(file_path.to_string(), reason.to_string());
+    let mut file = File::open(file_path).map_err(|e| MediaError::Io(file_path.to_string(), e))?;
+    let context = read_mp4(&mut file).map_err(|e| invalid(&format!("{:?}", e)))?;
 
     let video_track = context
         .tracks
         .iter()
-        .find(|track| track.track_type == mp4parse::TrackType::Video)?;
-    let duration = video_track.duration?;
+        .find(|track| track.track_type == mp4parse::TrackType::Video)
+        .ok_or_else(|| invalid("no video track"))?;
+    let duration = video_track
+        .duration
+        .ok_or_else(|| invalid("no track duration"))?;
+
+    // the track duration is in units of the track (mdhd) timescale
+    let track_timescale = match video_track.timescale {
+        Some(timescale) if timescale.0 > 0 => timescale.0,
+        _ => return Err(invalid("no timescale")),
+    };
+    let track_duration_seconds = duration.0 as f64 / track_timescale as f64;
 
-    let media_timescale = context.timescale?.0;
-    let total_time = duration.0 / 10 + duration.1 as u64;
-    let track_duration_seconds = total_time as f64 / media_timescale as f64;
+    // every sample of a video track is a frame
+    let frame_rate = video_track
+        .stts
+        .as_ref()
+        .map(|stts| {
+            stts.samples
+                .iter()
+                .map(|x| x.sample_count as u64)
+                .sum::<u64>()
+        })
+        .filter(|&frames| frames > 0 && track_duration_seconds > 0.0)
+        .map(|frames| frames as f64 / track_duration_seconds);
 
     if let Some(mp4parse::SampleEntry::Video(video_sample_entry)) = video_track
         .stsd
@@ -158,21 +237,32 @@ pub fn extract_video_info(file_path: &str) -> Option<VideoMetaData> {
         .and_then(|stsd| stsd.descriptions.get(0))
     {
         let resolution = (video_sample_entry.width, video_sample_entry.height);
-        return Some(VideoMetaData {
+        let codec = match video_sample_entry.codec_type {
+            mp4parse::CodecType::Unknown => None,
+            codec => Some(format!("{:?}", codec)),
+        };
+        return Ok(VideoMetaData {
             duration: track_duration_seconds,
             resolution,
+            frame_rate,
+            codec,
         });
     }
 
-    None
+    Err(invalid("no video sample entry"))
 }
 
 /// Extract metadata from training data(audio)
-pub fn extract_audio_metadata(file_path: &str) -> Result<AudioMetaData, boundError> {
-    let reader = WavReader::open(file_path)?;
-    let duration = reader.duration() as f64 / reader.spec().sample_rate as f64;
-
+pub fn extract_audio_metadata(file_path: &str) -> Result<AudioMetaData, MediaError> {
+    check_not_empty(file_path)?;
+    let reader = WavReader::open(file_path)
+        .map_err(|e| MediaError::Invalid(file_path.to_string(), e.to_string()))?;
     let sample_rate = reader.spec().sample_rate;
+    if sample_rate == 0 {
+        return Err(MediaError::ZeroSampleRate(file_path.to_string()));
+    }
+    let duration = reader.duration() as f64 / sample_rate as f64;
+
     let channels = reader.spec().channels;
     let bit_depth = reader.spec().bits_per_sample;
 
@@ -302,6 +392,19 @@ pub fn print_table_cell(file:&str,mut table: Table, index: MDAIndex, header: MDA
     table
 }
 
+/// Export the index and header of a single MDA file as a JSON object
+pub fn mda_to_json(mda_path: &str) -> Result<serde_json::Value, Box<dyn Error>> {
+    let (index, header) = read_info_from_mda(mda_path)?;
+    let annotation_count = index.annotations_offset.len();
+
+    Ok(serde_json::json!({
+        "file": mda_path,
+        "index": serde_json::to_value(&index)?,
+        "header": serde_json::to_value(&header)?,
+        "annotation_count": annotation_count,
+    }))
+}
+
 use serde::Deserialize;
 use std::process;
 
@@ -385,3 +488,234 @@ pub fn create_anno_offsets(anno_config: &AnnoConfig) -> Vec<AnnoOffset> {
 
     anno_offsets
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrainData;
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn encode_image(format: ImageOutputFormat) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    /// A PCM WAV header announcing `data_len` bytes of samples
+    fn wav_header(sample_rate: u32, data_len: u32) -> Vec<u8> {
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + data_len).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes()); // PCM
+        wav.extend(1u16.to_le_bytes()); // mono
+        wav.extend(sample_rate.to_le_bytes());
+        wav.extend((sample_rate * 2).to_le_bytes());
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(data_len.to_le_bytes());
+        wav
+    }
+
+    #[test]
+    fn test_broken_media_errors() {
+        let dir = std::env::temp_dir().join("mda_broken_media_test");
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            fs::write(&path, data).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+
+        for name in ["empty.wav", "empty.mp4", "empty.png"] {
+            let path = write(name, b"");
+            let errors = [
+                extract_audio_metadata(&path).err(),
+                extract_video_info(&path).err(),
+                extract_image_metadata(&path).err(),
+            ];
+            for err in errors {
+                assert!(
+                    matches!(err, Some(MediaError::Empty(_))),
+                    "{}: {:?}",
+                    name,
+                    err
+                );
+            }
+        }
+
+        let mut wav = wav_header(16000, 4);
+        wav.extend([0u8; 4]);
+        let path = write("valid.wav", &wav);
+        let metadata = extract_audio_metadata(&path).unwrap();
+        assert_eq!(
+            (metadata.sample_rate, metadata.duration),
+            (16000, 2.0 / 16000.0)
+        );
+
+        let path = write("truncated.wav", &wav[..20]);
+        let err = extract_audio_metadata(&path).unwrap_err();
+        assert!(matches!(err, MediaError::Invalid(..)), "{:?}", err);
+
+        let mut wav = wav_header(0, 4);
+        wav.extend([0u8; 4]);
+        let path = write("zero_rate.wav", &wav);
+        let err = extract_audio_metadata(&path).unwrap_err();
+        assert!(matches!(err, MediaError::ZeroSampleRate(_)), "{:?}", err);
+
+        let mut png = encode_image(ImageOutputFormat::Png);
+        png.truncate(png.len() / 2);
+        let path = write("corrupt.png", &png);
+        let err = extract_image_metadata(&path).unwrap_err();
+        assert!(matches!(err, MediaError::Invalid(..)), "{:?}", err);
+
+        let path = write("corrupt.mp4", b"\0\0\0\x18ftypmp42 truncated");
+        let err = extract_video_info(&path).unwrap_err();
+        assert!(matches!(err, MediaError::Invalid(..)), "{:?}", err);
+
+        let err = extract_audio_metadata(dir.join("missing.wav").to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, MediaError::Io(..)), "{:?}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = (8 + payload.len() as u32).to_be_bytes().to_vec();
+        data.extend(kind);
+        data.extend(payload);
+        data
+    }
+
+    /// An mp4 with one avc1 video track of `frames` samples lasting `duration` units of `timescale`
+    fn mp4_video(width: u16, height: u16, frames: u32, timescale: u32, duration: u32) -> Vec<u8> {
+        let full_box = |kind: &[u8; 4], payload: &[u8]| {
+            let mut data = vec![0u8; 4]; // version & flags
+            data.extend(payload);
+            mp4_box(kind, &data)
+        };
+        let mut mvhd = vec![0u8; 8]; // creation & modification time
+        mvhd.extend(1000u32.to_be_bytes());
+        mvhd.extend(((duration as u64 * 1000 / timescale as u64) as u32).to_be_bytes());
+        mvhd.extend([0u8; 80]);
+
+        let mut tkhd = vec![0u8; 8];
+        tkhd.extend(1u32.to_be_bytes()); // track id
+        tkhd.extend([0u8; 4]);
+        tkhd.extend(duration.to_be_bytes());
+        tkhd.extend([0u8; 16 + 36]); // layer, volume.., matrix
+        tkhd.extend(((width as u32) << 16).to_be_bytes());
+        tkhd.extend(((height as u32) << 16).to_be_bytes());
+
+        let mut mdhd = vec![0u8; 8];
+        mdhd.extend(timescale.to_be_bytes());
+        mdhd.extend(duration.to_be_bytes());
+        mdhd.extend([0u8; 4]); // language
+        let mut hdlr = vec![0u8; 4];
+        hdlr.extend(b"vide");
+        hdlr.extend([0u8; 12 + 1]); // reserved, empty name
+
+        let mut avc1 = vec![0u8; 6];
+        avc1.extend(1u16.to_be_bytes()); // data reference index
+        avc1.extend([0u8; 16]);
+        avc1.extend(width.to_be_bytes());
+        avc1.extend(height.to_be_bytes());
+        avc1.extend([0u8; 14 + 32 + 4]); // resolution.., compressor name, depth
+        avc1.extend(mp4_box(b"avcC", &[1, 0x42, 0, 0x1e, 0xff, 0xe0, 0]));
+        let mut stsd = 1u32.to_be_bytes().to_vec();
+        stsd.extend(mp4_box(b"avc1", &avc1));
+        let mut stts = 1u32.to_be_bytes().to_vec();
+        stts.extend(frames.to_be_bytes());
+        stts.extend((duration / frames).to_be_bytes());
+
+        let stbl = [full_box(b"stsd", &stsd), full_box(b"stts", &stts)].concat();
+        let minf = mp4_box(b"stbl", &stbl);
+        let mdia = [
+            full_box(b"mdhd", &mdhd),
+            full_box(b"hdlr", &hdlr),
+            mp4_box(b"minf", &minf),
+        ]
+        .concat();
+        let trak = [full_box(b"tkhd", &tkhd), mp4_box(b"mdia", &mdia)].concat();
+        let moov = [full_box(b"mvhd", &mvhd), mp4_box(b"trak", &trak)].concat();
+        [
+            mp4_box(b"ftyp", b"isom\0\0\0\0isomavc1"),
+            mp4_box(b"moov", &moov),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_video_frame_rate_and_codec() {
+        let dir = std::env::temp_dir().join("mda_video_info_test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("video.mp4");
+        // 60 frames of 512 units at 15360 units per second: 2 seconds at 30 fps
+        fs::write(&path, mp4_video(320, 240, 60, 15360, 60 * 512)).unwrap();
+
+        let metadata = extract_video_info(path.to_str().unwrap()).unwrap();
+        assert_eq!(metadata.resolution, (320, 240));
+        assert!((metadata.duration - 2.0).abs() < 1e-6, "{:?}", metadata);
+        let frame_rate = metadata.frame_rate.unwrap();
+        assert!((frame_rate - 30.0).abs() < 1e-6, "{:?}", metadata);
+        assert_eq!(metadata.codec.as_deref(), Some("H264"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_image_extension() {
+        let dir = std::env::temp_dir().join("mda_save_image_test");
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("image").to_str().unwrap().to_string();
+
+        let png = encode_image(ImageOutputFormat::Png);
+        let path = save_image_to_file(&png, &base).unwrap();
+        assert_eq!(path, format!("{}.png", base));
+        assert_eq!(fs::read(&path).unwrap(), png);
+
+        let jpeg = encode_image(ImageOutputFormat::Jpeg(80));
+        let path = save_image_to_file(&jpeg, &base).unwrap();
+        assert_eq!(path, format!("{}.jpg", base));
+        assert_eq!(fs::read(&path).unwrap(), jpeg);
+
+        assert!(save_image_to_file(b"not an image", &base).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mda_to_json() {
+        let mda_path = std::env::temp_dir().join("mda_to_json_test.mda");
+        let mut index = MDAIndex {
+            header_offset: 0,
+            train_data_offset: 0,
+            annotations_offset: vec![AnnoOffset::new("labels"), AnnoOffset::new("boxes")],
+        };
+        let header = MDAHeader {
+            tags: vec!["cat".to_string(), "dog".to_string()],
+            train_data: TrainData {
+                data_type: "Image".to_string(),
+                metadata: "size: (32, 32)".to_string(),
+            },
+        };
+        index.header_offset = bincode::serialized_size(&index).unwrap();
+        index.train_data_offset = index.header_offset + bincode::serialized_size(&header).unwrap();
+
+        let mut file = File::create(&mda_path).unwrap();
+        bincode::serialize_into(&mut file, &index).unwrap();
+        bincode::serialize_into(&mut file, &header).unwrap();
+        drop(file);
+
+        let json = mda_to_json(mda_path.to_str().unwrap()).unwrap();
+        fs::remove_file(&mda_path).unwrap();
+
+        assert_eq!(json["index"]["header_offset"], index.header_offset);
+        assert_eq!(json["index"]["train_data_offset"], index.train_data_offset);
+        assert_eq!(json["index"]["annotations_offset"][1]["id"], "boxes");
+        assert_eq!(json["header"]["tags"], serde_json::json!(["cat", "dog"]));
+        assert_eq!(json["header"]["train_data"]["data_type"], "Image");
+        assert_eq!(json["header"]["train_data"]["metadata"], "size: (32, 32)");
+        assert_eq!(json["annotation_count"], 2);
+    }
+}
@@ -43,6 +43,9 @@ async fn main() {
     init_log(&config.log);
 
     tracing::info!("{:?}", option);
+    gemini::ztm::set_tunnel_host(&option.ztm_tunnel_host);
+    gemini::util::set_max_response_size(option.ztm_max_response_size);
+    gemini::ztm::set_tunnel_pool_ttl(option.ztm_tunnel_ttl.map(time::Duration::from_secs));
 
     if option.only_agent {
         let (peer_id, _) = vault::init();
@@ -165,6 +165,9 @@ async fn transfer_event_to_subscribed_nodes(
             subscription_id.clone(),
             "api/v1/mega/nostr".to_string(),
             msg,
+            None,
+            None,
+            None,
         )
         .await
         {
@@ -38,6 +38,18 @@ pub struct RelayOptions {
     #[arg(long, default_value_t = 7777)]
     pub ztm_agent_port: u16,
 
+    /// Local address the inbound ends of ZTM tunnels listen on
+    #[arg(long, default_value_t = String::from("127.0.0.1"))]
+    pub ztm_tunnel_host: String,
+
+    /// Largest response body read from a peer, in bytes
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    pub ztm_max_response_size: usize,
+
+    /// Seconds an idle ZTM tunnel is reused for, tunnels are looked up for every request if unset
+    #[arg(long)]
+    pub ztm_tunnel_ttl: Option<u64>,
+
     #[arg(long, default_value_t = 8888)]
     pub ztm_hub_port: u16,
 
@@ -285,7 +297,8 @@ async fn send_message(
             return Err((StatusCode::BAD_REQUEST, String::from("path not provide\n")));
         }
     };
-    let result = match send_get_request_to_peer_by_tunnel(ztm_agent_port, peer_id, path).await {
+    let result = match send_get_request_to_peer_by_tunnel(ztm_agent_port, peer_id, path, None).await
+    {
         Ok(s) => s,
         Err(e) => {
             tracing::error!(e);
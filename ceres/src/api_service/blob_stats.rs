@@ -0,0 +1,161 @@
+//! How much blob content a tree shares: the same content at several paths is stored once.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future::Future,
+};
+
+use mercury::{
+    errors::GitError,
+    hash::SHA1,
+    internal::object::tree::{Tree, TreeItemMode},
+};
+
+/// Count the paths referencing each blob under `root`.
+/// - each distinct subtree is loaded & walked once, however many paths it is present at: the
+///   blobs in it are counted once per path reaching it
+pub async fn count_blob_refs<F, Fut>(
+    root: Tree,
+    load_tree: F,
+) -> Result<HashMap<SHA1, u64>, GitError>
+where
+    F: Fn(SHA1) -> Fut,
+    Fut: Future<Output = Result<Tree, GitError>>,
+{
+    // load the distinct trees, counting the items referencing each one
+    let root_id = root.id;
+    let mut trees: HashMap<SHA1, Tree> = HashMap::from([(root_id, root)]);
+    let mut parents: HashMap<SHA1, usize> = HashMap::new();
+    let mut pending = vec![root_id];
+    while let Some(id) = pending.pop() {
+        let subtrees: Vec<SHA1> = trees[&id]
+            .tree_items
+            .iter()
+            .filter(|item| item.mode == TreeItemMode::Tree)
+            .map(|item| item.id)
+            .collect();
+        for subtree in subtrees {
+            *parents.entry(subtree).or_default() += 1;
+            if let Entry::Vacant(entry) = trees.entry(subtree) {
+                entry.insert(load_tree(subtree).await?);
+                pending.push(subtree);
+            }
+        }
+    }
+
+    // a tree is reached by as many paths as its parents together, so its count is final once
+    // all of them are done
+    let mut paths: HashMap<SHA1, u64> = HashMap::from([(root_id, 1)]);
+    let mut refs = HashMap::new();
+    let mut ready = vec![root_id];
+    while let Some(id) = ready.pop() {
+        let count = paths[&id];
+        for item in &trees[&id].tree_items {
+            match item.mode {
+                TreeItemMode::Tree => {
+                    *paths.entry(item.id).or_default() += count;
+                    let left = parents.get_mut(&item.id).unwrap();
+                    *left -= 1;
+                    if *left == 0 {
+                        ready.push(item.id);
+                    }
+                }
+                // submodules live in another repo
+                TreeItemMode::Commit => {}
+                _ => *refs.entry(item.id).or_default() += count,
+            }
+        }
+    }
+    Ok(refs)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use mercury::{
+        errors::GitError,
+        hash::SHA1,
+        internal::object::{
+            blob::Blob,
+            tree::{Tree, TreeItem, TreeItemMode},
+        },
+    };
+
+    use super::count_blob_refs;
+    use crate::model::tree::BlobDedupStats;
+
+    #[tokio::test]
+    async fn test_shared_blob_counted_once() {
+        let license = Blob::from_content("MIT License");
+        let main = Blob::from_content("fn main() {}");
+        let blob =
+            |blob: &Blob, name: &str| TreeItem::new(TreeItemMode::Blob, blob.id, name.to_string());
+        // `a` & `b` are the same tree, both with the license
+        let project =
+            Tree::from_tree_items(vec![blob(&license, "LICENSE"), blob(&main, "main.rs")]).unwrap();
+        let root = Tree::from_tree_items(vec![
+            blob(&license, "LICENSE"),
+            TreeItem::new(TreeItemMode::Tree, project.id, "a".to_string()),
+            TreeItem::new(TreeItemMode::Tree, project.id, "b".to_string()),
+        ])
+        .unwrap();
+        let trees = HashMap::from([(project.id, project)]);
+        let loads = &AtomicUsize::new(0);
+        let refs = count_blob_refs(root, |id| {
+            loads.fetch_add(1, Ordering::SeqCst);
+            let tree = trees.get(&id).cloned();
+            async move { tree.ok_or(GitError::ObjectNotFound(id.to_string())) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(refs[&license.id], 3);
+        assert_eq!(refs[&main.id], 2);
+
+        let sizes: HashMap<SHA1, u64> = [&license, &main]
+            .iter()
+            .map(|x| (x.id, x.data.len() as u64))
+            .collect();
+        let stats = BlobDedupStats::new(&refs, &sizes);
+        assert_eq!(stats.unique_blobs, 2);
+        assert_eq!(stats.referenced_blobs, 5);
+        assert_eq!(
+            stats.bytes_saved,
+            2 * "MIT License".len() as u64 + "fn main() {}".len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nested_shared_tree_walked_once() {
+        let readme = Blob::from_content("readme");
+        // `lib` is in `a` & `b`, which are in `x` & `y`: 4 paths reach it
+        let lib = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            readme.id,
+            "README".to_string(),
+        )])
+        .unwrap();
+        let subtree =
+            |tree: &Tree, name: &str| TreeItem::new(TreeItemMode::Tree, tree.id, name.to_string());
+        let pair = |tree: &Tree, a: &str, b: &str| {
+            Tree::from_tree_items(vec![subtree(tree, a), subtree(tree, b)]).unwrap()
+        };
+        let inner = pair(&lib, "a", "b");
+        let root = pair(&inner, "x", "y");
+        let trees = HashMap::from([(lib.id, lib), (inner.id, inner)]);
+        let loads = &AtomicUsize::new(0);
+        let refs = count_blob_refs(root, |id| {
+            loads.fetch_add(1, Ordering::SeqCst);
+            let tree = trees.get(&id).cloned();
+            async move { tree.ok_or(GitError::ObjectNotFound(id.to_string())) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+        assert_eq!(refs, HashMap::from([(readme.id, 4)]));
+    }
+}
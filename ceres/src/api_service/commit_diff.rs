@@ -0,0 +1,329 @@
+//! Diff two monorepo trees file by file, and the content of the changed text files.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use mercury::{
+    errors::GitError,
+    hash::SHA1,
+    internal::object::tree::{Tree, TreeItem, TreeItemMode},
+};
+use similar::{ChangeTag, TextDiff};
+
+use crate::model::diff::{DiffHunk, DiffKind};
+
+/// Lines of context around the changes of a hunk, as git
+const CONTEXT_LINES: usize = 3;
+
+/// A changed file, `path` is absolute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: DiffKind,
+    pub old: Option<SHA1>,
+    pub new: Option<SHA1>,
+    /// a gitlink, `old` & `new` are commits of another repository, not blobs
+    pub submodule: bool,
+}
+
+/// Diff tree `old` against tree `new`, keeping the changes at or under `scope`.
+/// - subtrees with the same id, or out of `scope`, are not loaded
+/// - an added or deleted directory is reported as its files, sorted by path
+/// - a submodule is reported as a file, a blob replaced by a submodule as deleted & added
+pub async fn diff_trees<F, Fut>(
+    old: Tree,
+    new: Tree,
+    scope: &Path,
+    load_tree: F,
+) -> Result<Vec<FileChange>, GitError>
+where
+    F: Fn(SHA1) -> Fut,
+    Fut: Future<Output = Result<Tree, GitError>>,
+{
+    let mut changes = Vec::new();
+    let mut pending = vec![(PathBuf::from("/"), old.tree_items, new.tree_items)];
+    while let Some((dir, old, new)) = pending.pop() {
+        let old: BTreeMap<String, TreeItem> =
+            old.into_iter().map(|x| (x.name.clone(), x)).collect();
+        let new: BTreeMap<String, TreeItem> =
+            new.into_iter().map(|x| (x.name.clone(), x)).collect();
+        let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        for name in names {
+            let path = dir.join(name);
+            if !path.starts_with(scope) && !scope.starts_with(&path) {
+                continue;
+            }
+            let (o, n) = (old.get(name), new.get(name));
+            if let (Some(o), Some(n)) = (o, n) {
+                if o.id == n.id && o.mode == n.mode {
+                    continue;
+                }
+                let (o_tree, n_tree) = (o.mode == TreeItemMode::Tree, n.mode == TreeItemMode::Tree);
+                if o_tree && n_tree {
+                    let (o, n) = (load_tree(o.id).await?, load_tree(n.id).await?);
+                    pending.push((path, o.tree_items, n.tree_items));
+                    continue;
+                }
+                let submodule = o.mode == TreeItemMode::Commit;
+                if !o_tree && !n_tree && submodule == (n.mode == TreeItemMode::Commit) {
+                    if path.starts_with(scope) {
+                        changes.push(FileChange {
+                            path,
+                            kind: DiffKind::Modified,
+                            old: Some(o.id),
+                            new: Some(n.id),
+                            submodule,
+                        });
+                    }
+                    continue;
+                }
+            }
+            // a file replaced by a directory is a deleted file and added files
+            for (item, kind) in [(o, DiffKind::Deleted), (n, DiffKind::Added)] {
+                let Some(item) = item else { continue };
+                if item.mode == TreeItemMode::Tree {
+                    let items = load_tree(item.id).await?.tree_items;
+                    pending.push(match kind {
+                        DiffKind::Deleted => (path.clone(), items, vec![]),
+                        _ => (path.clone(), vec![], items),
+                    });
+                } else if path.starts_with(scope) {
+                    let (old, new) = match kind {
+                        DiffKind::Deleted => (Some(item.id), None),
+                        _ => (None, Some(item.id)),
+                    };
+                    changes.push(FileChange {
+                        path: path.clone(),
+                        kind,
+                        old,
+                        new,
+                        submodule: item.mode == TreeItemMode::Commit,
+                    });
+                }
+            }
+        }
+    }
+    // a file deleted goes before the one added at the same path
+    changes.sort_by_key(|x| (x.path.clone(), x.kind != DiffKind::Deleted));
+    Ok(changes)
+}
+
+/// What a diff shows for a submodule at commit `id`, as git
+pub fn submodule_text(id: SHA1) -> Vec<u8> {
+    format!("Subproject commit {}\n", id).into_bytes()
+}
+
+fn as_text(data: &[u8]) -> Option<&str> {
+    std::str::from_utf8(data)
+        .ok()
+        .filter(|text| !text.contains('\0'))
+}
+
+/// Unified diff hunks from `old` to `new`, `None` if either is binary (not UTF-8, or has a NUL)
+pub fn text_hunks(old: &[u8], new: &[u8]) -> Option<Vec<DiffHunk>> {
+    let (old, new) = (as_text(old)?, as_text(new)?);
+    let diff = TextDiff::from_lines(old, new);
+    let hunks = diff
+        .grouped_ops(CONTEXT_LINES)
+        .iter()
+        .map(|group| {
+            let (first, last) = (group.first().unwrap(), group.last().unwrap());
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+            let start = |range: &std::ops::Range<usize>| match range.len() {
+                0 => range.start,
+                _ => range.start + 1,
+            };
+            let mut lines = Vec::new();
+            for change in group.iter().flat_map(|op| diff.iter_changes(op)) {
+                let sign = match change.tag() {
+                    ChangeTag::Delete => '-',
+                    ChangeTag::Insert => '+',
+                    ChangeTag::Equal => ' ',
+                };
+                lines.push(format!("{}{}", sign, change.value().trim_end_matches('\n')));
+                if change.missing_newline() {
+                    lines.push("\\ No newline at end of file".to_string());
+                }
+            }
+            DiffHunk {
+                old_start: start(&old_range),
+                old_lines: old_range.len(),
+                new_start: start(&new_range),
+                new_lines: new_range.len(),
+                lines,
+            }
+        })
+        .collect();
+    Some(hunks)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        path::Path,
+    };
+
+    use mercury::{
+        errors::GitError,
+        hash::SHA1,
+        internal::object::{
+            blob::Blob,
+            tree::{Tree, TreeItem, TreeItemMode},
+        },
+    };
+
+    use super::{diff_trees, text_hunks, FileChange};
+    use crate::model::diff::DiffKind;
+
+    /// Build the tree of `files` (path, content), saving every tree in `trees`
+    fn build(files: &[(&str, &str)], trees: &mut HashMap<SHA1, Tree>) -> Tree {
+        let mut dirs: BTreeMap<&str, Vec<(&str, &str)>> = BTreeMap::new();
+        let mut items = Vec::new();
+        for &(path, content) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => dirs.entry(dir).or_default().push((rest, content)),
+                None => {
+                    let blob = Blob::from_content(content);
+                    items.push(TreeItem::new(TreeItemMode::Blob, blob.id, path.to_string()));
+                }
+            }
+        }
+        for (dir, files) in dirs {
+            let tree = build(&files, trees);
+            items.push(TreeItem::new(TreeItemMode::Tree, tree.id, dir.to_string()));
+        }
+        let tree = Tree::from_tree_items(items).unwrap();
+        trees.insert(tree.id, tree.clone());
+        tree
+    }
+
+    async fn diff(
+        old: &[(&str, &str)],
+        new: &[(&str, &str)],
+        scope: &str,
+    ) -> Vec<(String, DiffKind)> {
+        let mut trees = HashMap::new();
+        let (old, new) = (build(old, &mut trees), build(new, &mut trees));
+        let changes = diff_trees(old, new, Path::new(scope), |id| {
+            let tree = trees.get(&id).cloned();
+            async move { tree.ok_or(GitError::ObjectNotFound(id.to_string())) }
+        })
+        .await
+        .unwrap();
+        changes
+            .into_iter()
+            .map(|FileChange { path, kind, .. }| (path.display().to_string(), kind))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_diff_trees() {
+        let base = [
+            ("README.md", "# mega"),
+            ("project/main.rs", "fn main() {}"),
+            ("project/lib.rs", "pub mod a;"),
+            ("docs/guide.md", "# guide"),
+        ];
+        let head = [
+            ("README.md", "# mega\n\nmonorepo"),
+            ("project/main.rs", "fn main() {}"),
+            ("src/lib.rs", "pub mod a;"),
+            ("docs", "a file now"),
+        ];
+        assert_eq!(
+            diff(&base, &head, "/").await,
+            vec![
+                ("/README.md".to_string(), DiffKind::Modified),
+                ("/docs".to_string(), DiffKind::Added),
+                ("/docs/guide.md".to_string(), DiffKind::Deleted),
+                ("/project/lib.rs".to_string(), DiffKind::Deleted),
+                ("/src/lib.rs".to_string(), DiffKind::Added),
+            ]
+        );
+        assert_eq!(
+            diff(&base, &head, "/project").await,
+            vec![("/project/lib.rs".to_string(), DiffKind::Deleted)]
+        );
+        // unchanged paths yield nothing
+        assert!(diff(&base, &head, "/project/main.rs").await.is_empty());
+        assert!(diff(&base, &head, "/nope").await.is_empty());
+        assert!(diff(&base, &base, "/").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_diff_submodule() {
+        let item =
+            |mode, id: &str| TreeItem::new(mode, SHA1::new(id.as_bytes()), "vendor".to_string());
+        let diff = |old: TreeItem, new: TreeItem| async move {
+            let (old, new) = (
+                Tree::from_tree_items(vec![old]),
+                Tree::from_tree_items(vec![new]),
+            );
+            // submodules live in another repository, nothing is loaded
+            diff_trees(
+                old.unwrap(),
+                new.unwrap(),
+                Path::new("/"),
+                |id| async move { Err::<Tree, _>(GitError::ObjectNotFound(id.to_string())) },
+            )
+            .await
+            .unwrap()
+        };
+        let change = |kind, old: Option<&str>, new: Option<&str>, submodule| FileChange {
+            path: "/vendor".into(),
+            kind,
+            old: old.map(|id| SHA1::new(id.as_bytes())),
+            new: new.map(|id| SHA1::new(id.as_bytes())),
+            submodule,
+        };
+
+        let (c1, c2) = (
+            item(TreeItemMode::Commit, "c1"),
+            item(TreeItemMode::Commit, "c2"),
+        );
+        assert_eq!(
+            diff(c1.clone(), c2).await,
+            vec![change(DiffKind::Modified, Some("c1"), Some("c2"), true)]
+        );
+        assert_eq!(
+            diff(item(TreeItemMode::Blob, "blob"), c1).await,
+            vec![
+                change(DiffKind::Deleted, Some("blob"), None, false),
+                change(DiffKind::Added, None, Some("c1"), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk";
+        let hunks = text_hunks(old.as_bytes(), new.as_bytes()).unwrap();
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(
+            (hunks[0].old_start, hunks[0].old_lines),
+            (hunks[0].new_start, hunks[0].new_lines)
+        );
+        assert_eq!((hunks[0].old_start, hunks[0].old_lines), (1, 5));
+        assert_eq!(hunks[0].lines[..3], [" a", "-b", "+B"]);
+        assert_eq!((hunks[1].old_start, hunks[1].old_lines), (8, 3));
+        assert_eq!((hunks[1].new_start, hunks[1].new_lines), (8, 4));
+        assert_eq!(
+            hunks[1].lines,
+            [" h", " i", " j", "+k", "\\ No newline at end of file"]
+        );
+
+        // added file
+        let hunks = text_hunks(b"", b"x\n").unwrap();
+        assert_eq!((hunks[0].old_start, hunks[0].old_lines), (0, 0));
+        assert_eq!(hunks[0].lines, ["+x"]);
+        assert!(text_hunks(b"a", b"a").unwrap().is_empty());
+        assert_eq!(text_hunks(b"a\0b", b"a"), None);
+        assert_eq!(text_hunks(b"a", &[0xff, 0xfe]), None);
+    }
+}
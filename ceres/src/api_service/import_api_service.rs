@@ -1,6 +1,4 @@
 use std::collections::HashMap;
-use std::collections::HashSet;
-use std::collections::VecDeque;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -10,10 +8,9 @@ use jupiter::context::Context;
 use mercury::errors::GitError;
 use mercury::internal::object::commit::Commit;
 use mercury::internal::object::tree::Tree;
-use mercury::internal::object::tree::TreeItem;
 
-use crate::api_service::ApiHandler;
-use crate::model::create_file::CreateFileInfo;
+use crate::api_service::{ApiHandler, CreateFileError};
+use crate::model::create_file::{CreateFileInfo, WriteCondition};
 use crate::protocol::repo::Repo;
 
 #[derive(Clone)]
@@ -28,12 +25,36 @@ impl ApiHandler for ImportApiService {
         self.context.clone()
     }
 
-    async fn create_monorepo_file(&self, _: CreateFileInfo) -> Result<(), GitError> {
+    async fn create_monorepo_file(
+        &self,
+        _: CreateFileInfo,
+        _: WriteCondition,
+    ) -> Result<(), CreateFileError> {
         return Err(GitError::CustomError(
             "import dir does not support create file".to_string(),
-        ));
+        )
+        .into());
+    }
+
+    async fn create_monorepo_files(
+        &self,
+        _: Vec<(CreateFileInfo, WriteCondition)>,
+    ) -> Result<(), CreateFileError> {
+        Err(GitError::CustomError("import dir does not support create file".to_string()).into())
+    }
+
+    async fn delete_monorepo_file(&self, _: PathBuf) -> Result<(), CreateFileError> {
+        Err(GitError::CustomError("import dir does not support delete file".to_string()).into())
     }
 
+    async fn move_monorepo_file(
+        &self,
+        _: PathBuf,
+        _: PathBuf,
+        _: bool,
+    ) -> Result<(), CreateFileError> {
+        Err(GitError::CustomError("import dir does not support move file".to_string()).into())
+    }
 
     fn strip_relative(&self, path: &Path) -> Result<PathBuf, GitError> {
         if let Ok(relative_path) = path.strip_prefix(self.repo.repo_path.clone()) {
@@ -145,41 +166,4 @@ impl ApiHandler for ImportApiService {
             .unwrap();
         Ok(commits.into_iter().map(|x| x.into()).collect())
     }
-
-    async fn traverse_commit_history(
-        &self,
-        path: &Path,
-        start_commit: Commit,
-        target: &TreeItem,
-    ) -> Commit {
-        let mut target_commit = start_commit.clone();
-        let mut visited = HashSet::new();
-        let mut p_stack = VecDeque::new();
-
-        visited.insert(start_commit.id);
-        p_stack.push_back(start_commit);
-
-        while let Some(commit) = p_stack.pop_front() {
-            let root_tree = self.get_tree_by_hash(&commit.tree_id.to_string()).await;
-            let reachable = self
-                .reachable_in_tree(&root_tree, path, target)
-                .await
-                .unwrap();
-            if reachable {
-                let mut p_ids = vec![];
-                for p_id in commit.parent_commit_ids.clone() {
-                    if !visited.contains(&p_id) {
-                        p_ids.push(p_id.to_string());
-                        visited.insert(p_id);
-                    }
-                }
-                if target_commit.committer.timestamp > commit.committer.timestamp {
-                    target_commit = commit;
-                }
-                let parent_commits = self.get_commits_by_hashes(p_ids).await.unwrap();
-                p_stack.extend(parent_commits);
-            }
-        }
-        target_commit
-    }
 }
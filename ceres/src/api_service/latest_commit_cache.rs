@@ -0,0 +1,116 @@
+//! Latest commit of the paths of the monorepo, by path, until a new commit changes them.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::model::tree::LatestCommitInfo;
+
+/// The latest commit of a path depends on everything under it, so an entry is stale once a
+/// commit changes the path, a path above it, or a path under it; see [Self::invalidate].
+#[derive(Default)]
+pub struct LatestCommitCache {
+    entries: Mutex<HashMap<PathBuf, Arc<LatestCommitInfo>>>,
+}
+
+impl LatestCommitCache {
+    pub fn get(&self, path: &Path) -> Option<Arc<LatestCommitInfo>> {
+        self.entries.lock().unwrap().get(path).cloned()
+    }
+
+    pub fn insert(&self, path: PathBuf, info: Arc<LatestCommitInfo>) {
+        self.entries.lock().unwrap().insert(path, info);
+    }
+
+    /// Drops the entries made stale by a commit changing `path`: the directories above it, whose
+    /// latest commit is now this one, `path` itself and everything under it.
+    pub fn invalidate(&self, path: &Path) {
+        self.invalidate_all(&[path]);
+    }
+
+    /// [Self::invalidate] all the paths a commit changed, walking the entries once.
+    /// Called by the snapshot sync, and by anything else writing commits.
+    pub fn invalidate_all<P: AsRef<Path>>(&self, paths: &[P]) {
+        let stale = |cached: &Path| {
+            paths.iter().any(|path| {
+                let path = path.as_ref();
+                cached.starts_with(path) || path.starts_with(cached)
+            })
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|cached, _| !stale(cached));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::model::tree::UserInfo;
+
+    pub(crate) fn info(oid: &str) -> Arc<LatestCommitInfo> {
+        Arc::new(LatestCommitInfo {
+            oid: oid.to_string(),
+            date: String::new(),
+            short_message: String::new(),
+            author: UserInfo::default(),
+            committer: UserInfo::default(),
+            status: String::new(),
+        })
+    }
+
+    #[test]
+    fn test_invalidate_by_prefix() {
+        let cache = LatestCommitCache::default();
+        for path in [
+            "/",
+            "/project",
+            "/project/src",
+            "/project/src/main.rs",
+            "/docs",
+        ] {
+            cache.insert(path.into(), info(path));
+        }
+        assert_eq!(cache.get(Path::new("/docs")).unwrap().oid, "/docs");
+
+        cache.invalidate(Path::new("/project/src"));
+        assert!(cache.get(Path::new("/")).is_none());
+        assert!(cache.get(Path::new("/project")).is_none());
+        assert!(cache.get(Path::new("/project/src/main.rs")).is_none());
+        // a sibling isn't changed by the commit
+        assert!(cache.get(Path::new("/docs")).is_some());
+        assert_eq!(cache.len(), 1);
+
+        // not a path prefix of `/docs`
+        cache.invalidate(Path::new("/doc"));
+        assert_eq!(cache.len(), 1);
+        cache.invalidate(Path::new("/"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_invalidate_all() {
+        let cache = LatestCommitCache::default();
+        for path in ["/a", "/a/x.rs", "/b", "/b/y.rs", "/c", "/c/z.rs"] {
+            cache.insert(path.into(), info(path));
+        }
+        cache.invalidate_all(&[Path::new("/a/x.rs"), Path::new("/c")]);
+        let cached: Vec<&str> = ["/a", "/a/x.rs", "/b", "/b/y.rs", "/c", "/c/z.rs"]
+            .into_iter()
+            .filter(|path| cache.get(Path::new(path)).is_some())
+            .collect();
+        assert_eq!(cached, ["/b", "/b/y.rs"]);
+        // nothing changed
+        cache.invalidate_all::<&Path>(&[]);
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -4,12 +4,17 @@ use std::{
 };
 
 use axum::async_trait;
+use thiserror::Error;
 
 use callisto::raw_blob;
 use common::errors::MegaError;
-use jupiter::{context::Context, utils::converter::generate_git_keep_with_timestamp};
+use jupiter::{
+    context::Context, storage::mono_storage::MonoStorage,
+    utils::converter::generate_git_keep_with_timestamp,
+};
 use mercury::{
     errors::GitError,
+    hash::SHA1,
     internal::object::{
         commit::Commit,
         tree::{Tree, TreeItem, TreeItemMode},
@@ -18,18 +23,97 @@ use mercury::{
 };
 
 use crate::model::{
-    create_file::CreateFileInfo,
+    create_file::{CreateFileInfo, WriteCondition},
     tree::{LatestCommitInfo, TreeBriefItem, TreeCommitItem, UserInfo},
 };
 
+pub mod blob_stats;
+pub mod commit_diff;
 pub mod import_api_service;
+pub mod latest_commit_cache;
 pub mod mono_api_service;
+pub mod snapshot_sync;
+pub mod tree_edit;
+
+/// Why a path could not be read as a blob
+#[derive(Error, Debug)]
+pub enum BlobError {
+    #[error("Path not found: {0}")]
+    NotFound(String),
+
+    #[error("Path is a directory: {0}")]
+    IsDirectory(String),
+
+    #[error(transparent)]
+    Internal(#[from] GitError),
+}
+
+/// Why two commits could not be diffed
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("Commit not found: {0}")]
+    NotFound(String),
+
+    #[error(transparent)]
+    Internal(#[from] GitError),
+}
+
+#[derive(Error, Debug)]
+pub enum CreateFileError {
+    /// The file changed since the client read it, or already exists
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    /// The path to delete or move doesn't exist
+    #[error("Path not found: {0}")]
+    NotFound(String),
+
+    #[error(transparent)]
+    Internal(#[from] GitError),
+}
+
+/// A failure of the database, as the [GitError] of the object it was reading or writing
+pub(crate) fn storage_error(e: MegaError) -> GitError {
+    GitError::CustomError(e.to_string())
+}
+
+/// Load the tree `id` saved by the monorepo `storage`
+pub(crate) async fn load_tree(storage: &MonoStorage, id: SHA1) -> Result<Tree, GitError> {
+    storage
+        .get_tree_by_hash(&id.to_string())
+        .await
+        .map_err(storage_error)?
+        .map(Tree::from)
+        .ok_or_else(|| GitError::ObjectNotFound(id.to_string()))
+}
 
 #[async_trait]
 pub trait ApiHandler: Send + Sync {
     fn get_context(&self) -> Context;
 
-    async fn create_monorepo_file(&self, file_info: CreateFileInfo) -> Result<(), GitError>;
+    async fn create_monorepo_file(
+        &self,
+        file_info: CreateFileInfo,
+        condition: WriteCondition,
+    ) -> Result<(), CreateFileError>;
+
+    /// Apply all `files` in a single commit, nothing is committed if one of them fails
+    async fn create_monorepo_files(
+        &self,
+        files: Vec<(CreateFileInfo, WriteCondition)>,
+    ) -> Result<(), CreateFileError>;
+
+    /// Remove the file or directory at `path` in a new commit, a directory left empty is
+    /// removed too
+    async fn delete_monorepo_file(&self, path: PathBuf) -> Result<(), CreateFileError>;
+
+    /// Rename `from` to `to` in a new commit, the moved blob or tree keeps its id
+    async fn move_monorepo_file(
+        &self,
+        from: PathBuf,
+        to: PathBuf,
+        overwrite: bool,
+    ) -> Result<(), CreateFileError>;
 
     async fn get_raw_blob_by_hash(&self, hash: &str) -> Result<Option<raw_blob::Model>, MegaError> {
         let context = self.get_context();
@@ -72,27 +156,136 @@ pub trait ApiHandler: Send + Sync {
 
     async fn get_commits_by_hashes(&self, c_hashes: Vec<String>) -> Result<Vec<Commit>, GitError>;
 
+    /// Find the oldest commit reachable from `start_commit` through commits that all contain
+    /// `target` under the directory `path`, that is the commit `target` was last changed in.
     async fn traverse_commit_history(
         &self,
         path: &Path,
-        commit: Commit,
+        start_commit: Commit,
         target: &TreeItem,
-    ) -> Commit;
-
-    async fn get_blob_as_string(&self, file_path: PathBuf) -> Result<Option<String>, GitError> {
-        let filename = file_path.file_name().unwrap().to_str().unwrap();
-        let parent = file_path.parent().unwrap();
-        if let Some(tree) = self.search_tree_by_path(parent).await? {
-            if let Some(item) = tree.tree_items.into_iter().find(|x| x.name == filename) {
-                match self.get_raw_blob_by_hash(&item.id.to_string()).await {
-                    Ok(Some(model)) => {
-                        return Ok(Some(String::from_utf8(model.data.unwrap()).unwrap()))
+    ) -> Commit {
+        let mut target_commit = start_commit.clone();
+        let mut visited = HashSet::new();
+        let mut p_stack = VecDeque::new();
+
+        visited.insert(start_commit.id);
+        p_stack.push_back(start_commit);
+
+        while let Some(commit) = p_stack.pop_front() {
+            let root_tree = self.get_tree_by_hash(&commit.tree_id.to_string()).await;
+            let reachable = self
+                .reachable_in_tree(&root_tree, path, target)
+                .await
+                .unwrap();
+            if reachable {
+                let mut p_ids = vec![];
+                for p_id in commit.parent_commit_ids.clone() {
+                    if !visited.contains(&p_id) {
+                        p_ids.push(p_id.to_string());
+                        visited.insert(p_id);
                     }
-                    _ => return Ok(None),
-                };
+                }
+                if target_commit.committer.timestamp > commit.committer.timestamp {
+                    target_commit = commit;
+                }
+                let parent_commits = self.get_commits_by_hashes(p_ids).await.unwrap();
+                p_stack.extend(parent_commits);
             }
         }
-        return Ok(None);
+        target_commit
+    }
+
+    /// [traverse_commit_history](ApiHandler::traverse_commit_history) for all `targets` of the
+    /// directory `path` in a single walk, each commit & its tree is loaded once instead of once
+    /// per target. The result is in the order of `targets`.
+    async fn traverse_commit_history_batch(
+        &self,
+        path: &Path,
+        start_commit: Commit,
+        targets: &[TreeItem],
+    ) -> Vec<Commit> {
+        let mut target_commits = vec![start_commit.clone(); targets.len()];
+        // targets already checked in each commit, a commit is walked again only for new targets
+        let mut checked: HashMap<SHA1, HashSet<usize>> = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        checked.insert(start_commit.id, (0..targets.len()).collect());
+        queue.push_back((start_commit, (0..targets.len()).collect::<Vec<usize>>()));
+
+        while let Some((commit, pending)) = queue.pop_front() {
+            let root_tree = self.get_tree_by_hash(&commit.tree_id.to_string()).await;
+            let dir = self.tree_at_path(&root_tree, path).await.unwrap();
+            let reachable: Vec<usize> = match dir {
+                Some(dir) => pending
+                    .into_iter()
+                    .filter(|&i| dir.tree_items.iter().any(|x| *x == targets[i]))
+                    .collect(),
+                None => vec![],
+            };
+            if reachable.is_empty() {
+                continue;
+            }
+            for &i in &reachable {
+                if target_commits[i].committer.timestamp > commit.committer.timestamp {
+                    target_commits[i] = commit.clone();
+                }
+            }
+
+            let mut parents = HashMap::new();
+            for p_id in &commit.parent_commit_ids {
+                let seen = checked.entry(*p_id).or_default();
+                let new: Vec<usize> = reachable
+                    .iter()
+                    .copied()
+                    .filter(|i| seen.insert(*i))
+                    .collect();
+                if !new.is_empty() {
+                    parents.insert(p_id.to_string(), new);
+                }
+            }
+            if parents.is_empty() {
+                continue;
+            }
+            let parent_commits = self
+                .get_commits_by_hashes(parents.keys().cloned().collect())
+                .await
+                .unwrap();
+            for parent in parent_commits {
+                if let Some(pending) = parents.remove(&parent.id.to_string()) {
+                    queue.push_back((parent, pending));
+                }
+            }
+        }
+        target_commits
+    }
+
+    async fn get_blob_as_string(&self, file_path: PathBuf) -> Result<String, BlobError> {
+        let not_found = || BlobError::NotFound(file_path.display().to_string());
+        let filename = match file_path.file_name() {
+            Some(name) => name.to_str().unwrap(),
+            // only the root has no file name
+            None => return Err(BlobError::IsDirectory(file_path.display().to_string())),
+        };
+        let parent = file_path.parent().unwrap();
+        let tree = self
+            .search_tree_by_path(parent)
+            .await?
+            .ok_or_else(not_found)?;
+        let item = tree
+            .tree_items
+            .into_iter()
+            .find(|x| x.name == filename)
+            .ok_or_else(not_found)?;
+        if item.mode == TreeItemMode::Tree {
+            return Err(BlobError::IsDirectory(file_path.display().to_string()));
+        }
+        let model = self
+            .get_raw_blob_by_hash(&item.id.to_string())
+            .await
+            .map_err(|e| BlobError::Internal(GitError::CustomError(e.to_string())))?
+            .ok_or_else(|| BlobError::Internal(GitError::ObjectNotFound(item.id.to_string())))?;
+        String::from_utf8(model.data.unwrap_or_default())
+            .map_err(|e| BlobError::Internal(GitError::ConversionError(e.to_string())))
     }
 
     async fn get_latest_commit(&self, path: PathBuf) -> Result<LatestCommitInfo, GitError> {
@@ -161,26 +354,38 @@ pub trait ApiHandler: Send + Sync {
                     .map(|x| (x.id.to_string(), x))
                     .collect();
 
-                let root_commit: Option<Commit> = None;
+                // items whose commit is not stored are resolved from history, all in one walk
+                let missing: Vec<TreeItem> = tree
+                    .tree_items
+                    .iter()
+                    .filter(|item| {
+                        item_to_commit
+                            .get(&item.id.to_string())
+                            .is_some_and(|commit_id| !commit_map.contains_key(commit_id))
+                    })
+                    .cloned()
+                    .collect();
+                let mut traversed = HashMap::new();
+                if !missing.is_empty() {
+                    tracing::warn!("failed fetch commits of {} items", missing.len());
+                    let root_commit = self.get_root_commit().await;
+                    let commits = self
+                        .traverse_commit_history_batch(&path, root_commit, &missing)
+                        .await;
+                    traversed = missing.into_iter().map(|x| x.id).zip(commits).collect();
+                }
+
                 for item in tree.tree_items {
                     let mut info: TreeCommitItem = item.clone().into();
                     if let Some(commit_id) = item_to_commit.get(&item.id.to_string()) {
-                        let commit = if let Some(commit) = commit_map.get(commit_id) {
-                            commit
-                        } else {
-                            tracing::warn!("failed fecth commit: {}", commit_id);
-                            let root_commit = if let Some(ref root_commit) = root_commit {
-                                root_commit.clone()
-                            } else {
-                                self.get_root_commit().await
-                            };
-                            &self
-                                .traverse_commit_history(&path, root_commit, &item)
-                                .await
-                        };
-                        info.oid = commit.id.to_string();
-                        info.message = commit.format_message();
-                        info.date = commit.committer.timestamp.to_string();
+                        let commit = commit_map
+                            .get(commit_id)
+                            .or_else(|| traversed.get(&item.id));
+                        if let Some(commit) = commit {
+                            info.oid = commit.id.to_string();
+                            info.message = commit.format_message();
+                            info.date = commit.committer.timestamp.to_string();
+                        }
                     }
                     items.push(info);
                 }
@@ -396,9 +601,17 @@ pub trait ApiHandler: Send + Sync {
         path: &Path,
         target: &TreeItem,
     ) -> Result<bool, GitError> {
-        let relative_path = self.strip_relative(path).unwrap();
+        // check item exist under search tree
+        match self.tree_at_path(root_tree, path).await? {
+            Some(search_tree) => Ok(search_tree.tree_items.iter().any(|x| x == target)),
+            None => Ok(false),
+        }
+    }
+
+    /// Find the tree of directory `path` under `root_tree`, `None` if the path doesn't exist
+    async fn tree_at_path(&self, root_tree: &Tree, path: &Path) -> Result<Option<Tree>, GitError> {
+        let relative_path = self.strip_relative(path)?;
         let mut search_tree = root_tree.clone();
-        // first find search tree by path
         for component in relative_path.components() {
             // root tree already found
             if component != Component::RootDir {
@@ -410,14 +623,212 @@ pub trait ApiHandler: Send + Sync {
                 if let Some(search_res) = search_res {
                     search_tree = self.get_tree_by_hash(&search_res.id.to_string()).await;
                 } else {
-                    return Ok(false);
+                    return Ok(None);
                 }
             }
         }
-        // check item exist under search tree
-        if search_tree.tree_items.iter().any(|x| x == target) {
-            return Ok(true);
+        Ok(Some(search_tree))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use axum::async_trait;
+    use callisto::raw_blob;
+    use common::errors::MegaError;
+    use jupiter::context::Context;
+    use mercury::{
+        errors::GitError,
+        internal::object::{
+            blob::Blob,
+            commit::Commit,
+            tree::{Tree, TreeItem, TreeItemMode},
+        },
+    };
+
+    use super::{ApiHandler, BlobError, CreateFileError};
+    use crate::model::create_file::{CreateFileInfo, WriteCondition};
+
+    /// In-memory handler holding `/README.md` and `/src/main.rs`
+    struct MockHandler {
+        root: Tree,
+        trees: HashMap<String, Tree>,
+        blobs: HashMap<String, raw_blob::Model>,
+        commits: HashMap<String, Commit>,
+    }
+
+    impl MockHandler {
+        fn new() -> Self {
+            let readme = Blob::from_content("# mega");
+            let main = Blob::from_content("fn main() {}");
+            let src = Tree::from_tree_items(vec![TreeItem {
+                mode: TreeItemMode::Blob,
+                id: main.id,
+                name: "main.rs".to_string(),
+            }])
+            .unwrap();
+            let root = Tree::from_tree_items(vec![
+                TreeItem {
+                    mode: TreeItemMode::Blob,
+                    id: readme.id,
+                    name: "README.md".to_string(),
+                },
+                TreeItem {
+                    mode: TreeItemMode::Tree,
+                    id: src.id,
+                    name: "src".to_string(),
+                },
+            ])
+            .unwrap();
+            let blobs = [readme, main]
+                .into_iter()
+                .map(|b| (b.id.to_string(), b.into()))
+                .collect();
+            MockHandler {
+                root,
+                trees: HashMap::from([(src.id.to_string(), src)]),
+                blobs,
+                commits: HashMap::new(),
+            }
+        }
+
+        /// Add a commit of `items` as root tree, committed at `timestamp`
+        fn commit(
+            &mut self,
+            items: Vec<TreeItem>,
+            parents: Vec<&Commit>,
+            timestamp: usize,
+        ) -> Commit {
+            let root = Tree::from_tree_items(items).unwrap();
+            let parents = parents.into_iter().map(|c| c.id).collect();
+            let mut commit = Commit::from_tree_id(root.id, parents, "\nmock");
+            commit.committer.timestamp = timestamp;
+            self.root = root.clone();
+            self.trees.insert(root.id.to_string(), root);
+            self.commits.insert(commit.id.to_string(), commit.clone());
+            commit
+        }
+    }
+
+    fn blob_item(name: &str, content: &str) -> TreeItem {
+        TreeItem {
+            mode: TreeItemMode::Blob,
+            id: Blob::from_content(content).id,
+            name: name.to_string(),
+        }
+    }
+
+    #[async_trait]
+    impl ApiHandler for MockHandler {
+        fn get_context(&self) -> Context {
+            unreachable!()
         }
-        Ok(false)
+
+        async fn create_monorepo_file(
+            &self,
+            _: CreateFileInfo,
+            _: WriteCondition,
+        ) -> Result<(), CreateFileError> {
+            unreachable!()
+        }
+
+        async fn get_raw_blob_by_hash(
+            &self,
+            hash: &str,
+        ) -> Result<Option<raw_blob::Model>, MegaError> {
+            Ok(self.blobs.get(hash).cloned())
+        }
+
+        fn strip_relative(&self, path: &Path) -> Result<PathBuf, GitError> {
+            Ok(path.to_path_buf())
+        }
+
+        async fn get_root_commit(&self) -> Commit {
+            unreachable!()
+        }
+
+        async fn get_root_tree(&self) -> Tree {
+            self.root.clone()
+        }
+
+        async fn get_tree_by_hash(&self, hash: &str) -> Tree {
+            self.trees[hash].clone()
+        }
+
+        async fn get_tree_relate_commit(&self, _: &str) -> Commit {
+            unreachable!()
+        }
+
+        async fn add_trees_to_map(&self, _: &mut HashMap<String, String>, _: Vec<String>) {}
+
+        async fn add_blobs_to_map(&self, _: &mut HashMap<String, String>, _: Vec<String>) {}
+
+        async fn get_commits_by_hashes(
+            &self,
+            hashes: Vec<String>,
+        ) -> Result<Vec<Commit>, GitError> {
+            Ok(hashes
+                .iter()
+                .filter_map(|hash| self.commits.get(hash).cloned())
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_commit_history_matches_per_entry() {
+        let mut handler = MockHandler::new();
+        let (readme_v1, readme_v2) = (blob_item("README.md", "v1"), blob_item("README.md", "v2"));
+        let (main_v1, main_v2) = (blob_item("main.rs", "v1"), blob_item("main.rs", "v2"));
+        let license = blob_item("LICENSE", "MIT");
+
+        let c1 = handler.commit(vec![readme_v1, main_v1.clone()], vec![], 1);
+        let c2 = handler.commit(vec![readme_v2.clone(), main_v1.clone()], vec![&c1], 2);
+        // a side branch changing main.rs, merged by c4
+        let c3 = handler.commit(vec![readme_v2.clone(), main_v2.clone()], vec![&c1], 3);
+        let c4 = handler.commit(
+            vec![readme_v2.clone(), main_v2.clone(), license.clone()],
+            vec![&c2, &c3],
+            4,
+        );
+
+        let entries = vec![readme_v2, main_v2, license, main_v1];
+        let path = Path::new("/");
+        let batch = handler
+            .traverse_commit_history_batch(path, c4.clone(), &entries)
+            .await;
+        assert_eq!(batch.len(), entries.len());
+        for (entry, commit) in entries.iter().zip(&batch) {
+            let single = handler
+                .traverse_commit_history(path, c4.clone(), entry)
+                .await;
+            assert_eq!(commit.id, single.id, "{}", entry.name);
+        }
+        let ids: Vec<_> = batch.iter().map(|c| c.id).collect();
+        // main.rs v1 is not in c4, so nothing is walked for it
+        assert_eq!(ids, vec![c2.id, c3.id, c4.id, c4.id]);
+    }
+
+    #[tokio::test]
+    async fn test_get_blob_as_string() {
+        let handler = MockHandler::new();
+
+        let content = handler
+            .get_blob_as_string("/src/main.rs".into())
+            .await
+            .unwrap();
+        assert_eq!(content, "fn main() {}");
+
+        let res = handler.get_blob_as_string("/src/lib.rs".into()).await;
+        assert!(matches!(res, Err(BlobError::NotFound(_))));
+        let res = handler.get_blob_as_string("/docs/index.md".into()).await;
+        assert!(matches!(res, Err(BlobError::NotFound(_))));
+
+        let res = handler.get_blob_as_string("/src".into()).await;
+        assert!(matches!(res, Err(BlobError::IsDirectory(_))));
+        let res = handler.get_blob_as_string("/".into()).await;
+        assert!(matches!(res, Err(BlobError::IsDirectory(_))));
     }
 }
@@ -1,9 +1,11 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 use std::{env, fs};
 
 use axum::async_trait;
+use sea_orm::TransactionTrait;
 use tokio::process::Command;
 
 use callisto::db_enums::ConvType;
@@ -11,20 +13,27 @@ use callisto::{mega_blob, mega_tree, raw_blob};
 use common::errors::MegaError;
 use jupiter::context::Context;
 use jupiter::storage::batch_save_model;
-use jupiter::utils::converter::generate_git_keep_with_timestamp;
 use mercury::errors::GitError;
 use mercury::hash::SHA1;
-use mercury::internal::object::blob::Blob;
 use mercury::internal::object::commit::Commit;
-use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
-
-use crate::api_service::ApiHandler;
-use crate::model::create_file::CreateFileInfo;
+use mercury::internal::object::tree::Tree;
+
+use crate::api_service::blob_stats::count_blob_refs;
+use crate::api_service::commit_diff::{diff_trees, submodule_text, text_hunks};
+use crate::api_service::latest_commit_cache::LatestCommitCache;
+use crate::api_service::snapshot_sync::sync_commit_and_invalidate;
+use crate::api_service::tree_edit::{stage_changes, TreeChange};
+use crate::api_service::{load_tree, storage_error, ApiHandler, CreateFileError, DiffError};
+use crate::model::create_file::{CreateFileInfo, WriteCondition};
+use crate::model::diff::{DiffKind, FileDiff};
+use crate::model::tree::BlobDedupStats;
 use crate::protocol::mr::MergeRequest;
 
 #[derive(Clone)]
 pub struct MonoApiService {
     pub context: Context,
+    /// invalidated by the commits written through this service, shared by the API handlers
+    pub latest_commits: Arc<LatestCommitCache>,
 }
 
 #[async_trait]
@@ -38,84 +47,49 @@ impl ApiHandler for MonoApiService {
     /// # Arguments
     ///
     /// * `file_info` - Information about the file or directory to create.
+    /// * `condition` - Expected state of the file, an existing file is only replaced
+    ///   when `If-Match` carries its current oid. Ignored for directories.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` on success, or a `GitError` on failure.
-    async fn create_monorepo_file(&self, file_info: CreateFileInfo) -> Result<(), GitError> {
-        let storage = self.context.services.mono_storage.clone();
-        let path = PathBuf::from(file_info.path);
-        let mut save_trees = vec![];
-
-        // Search for the tree to update and get its tree items
-        let (update_trees, search_tree) = self.search_tree_for_update(&path).await?;
-        let mut t_items = search_tree.tree_items;
-
-        // Create a new tree item based on whether it's a directory or file
-        let new_item = if file_info.is_directory {
-            if t_items
-                .iter()
-                .any(|x| x.mode == TreeItemMode::Tree && x.name == file_info.name)
-            {
-                return Err(GitError::CustomError("Duplicate name".to_string()));
-            }
-            let blob = generate_git_keep_with_timestamp();
-            let tree_item = TreeItem {
-                mode: TreeItemMode::Blob,
-                id: blob.id,
-                name: String::from(".gitkeep"),
-            };
-            let child_tree = Tree::from_tree_items(vec![tree_item]).unwrap();
-            save_trees.push(child_tree.clone());
-            TreeItem {
-                mode: TreeItemMode::Tree,
-                id: child_tree.id,
-                name: file_info.name.clone(),
-            }
-        } else {
-            let content = file_info.content.unwrap();
-            let blob = Blob::from_content(&content);
-            let mega_blob: mega_blob::ActiveModel = Into::<mega_blob::Model>::into(&blob).into();
-            let raw_blob: raw_blob::ActiveModel =
-                Into::<raw_blob::Model>::into(blob.clone()).into();
-
-            let conn = storage.get_connection();
-            batch_save_model(conn, vec![mega_blob]).await.unwrap();
-            batch_save_model(conn, vec![raw_blob]).await.unwrap();
-            TreeItem {
-                mode: TreeItemMode::Blob,
-                id: blob.id,
-                name: file_info.name.clone(),
-            }
-        };
-        // Add the new item to the tree items and create a new tree
-        t_items.push(new_item);
-        let p_tree = Tree::from_tree_items(t_items).unwrap();
-
-        // Create a commit for the new tree
-        let refs = storage.get_ref("/").await.unwrap().unwrap();
-        let commit = Commit::from_tree_id(
-            p_tree.id,
-            vec![SHA1::from_str(&refs.ref_commit_hash).unwrap()],
-            &format!("\ncreate file {} commit", file_info.name),
-        );
-
-        // Update the parent tree with the new commit
-        let commit_id = self.update_parent_tree(path, update_trees, commit).await?;
-        save_trees.push(p_tree);
+    /// Returns `Ok(())` on success, `CreateFileError::Conflict` if the condition doesn't hold,
+    /// or a `GitError` on other failures.
+    async fn create_monorepo_file(
+        &self,
+        file_info: CreateFileInfo,
+        condition: WriteCondition,
+    ) -> Result<(), CreateFileError> {
+        self.create_monorepo_files(vec![(file_info, condition)])
+            .await
+    }
 
-        let save_trees: Vec<mega_tree::ActiveModel> = save_trees
+    async fn create_monorepo_files(
+        &self,
+        files: Vec<(CreateFileInfo, WriteCondition)>,
+    ) -> Result<(), CreateFileError> {
+        let changes = files
             .into_iter()
-            .map(|save_t| {
-                let mut tree_model: mega_tree::Model = save_t.into();
-                tree_model.commit_id.clone_from(&commit_id);
-                tree_model.into()
-            })
+            .map(|(file, condition)| TreeChange::Write(file, condition))
             .collect();
-        batch_save_model(storage.get_connection(), save_trees)
-            .await
-            .unwrap();
-        Ok(())
+        self.commit_changes(changes).await
+    }
+
+    async fn delete_monorepo_file(&self, path: PathBuf) -> Result<(), CreateFileError> {
+        self.commit_changes(vec![TreeChange::Delete(path)]).await
+    }
+
+    async fn move_monorepo_file(
+        &self,
+        from: PathBuf,
+        to: PathBuf,
+        overwrite: bool,
+    ) -> Result<(), CreateFileError> {
+        let change = TreeChange::Move {
+            from,
+            to,
+            overwrite,
+        };
+        self.commit_changes(vec![change]).await
     }
 
     fn strip_relative(&self, path: &Path) -> Result<PathBuf, GitError> {
@@ -189,13 +163,222 @@ impl ApiHandler for MonoApiService {
         let commits = storage.get_commits_by_hashes(&c_hashes).await.unwrap();
         Ok(commits.into_iter().map(|x| x.into()).collect())
     }
-
-    async fn traverse_commit_history(&self, _: &Path, _: Commit, _: &TreeItem) -> Commit {
-        unreachable!()
-    }
 }
 
 impl MonoApiService {
+    /// All changes are staged and checked before the first object is saved, and the root ref
+    /// is moved last, so a failed batch leaves the monorepo as it was.
+    async fn commit_changes(&self, changes: Vec<TreeChange>) -> Result<(), CreateFileError> {
+        let storage = self.context.services.mono_storage.clone();
+        let mut root_ref = storage
+            .get_ref("/")
+            .await
+            .map_err(storage_error)?
+            .ok_or_else(|| GitError::CustomError("monorepo has no root ref".to_string()))?;
+        // stage on the tree of the ref just read, the ref is only moved if it still points
+        // at this commit, so conditions checked here can't be invalidated by a concurrent write
+        let expected_commit = root_ref.ref_commit_hash.clone();
+        let root: Tree = storage
+            .get_tree_by_hash(&root_ref.ref_tree_hash)
+            .await
+            .map_err(storage_error)?
+            .ok_or_else(|| GitError::ObjectNotFound(root_ref.ref_tree_hash.clone()))?
+            .into();
+        let staged = stage_changes(root, changes, |id| load_tree(&storage, id)).await?;
+
+        let parent = SHA1::from_str(&expected_commit).map_err(GitError::CustomError)?;
+        let commit = Commit::from_tree_id(staged.root.id, vec![parent], &staged.message);
+        let new_commit = commit.id;
+        let commit_id = commit.id.to_string();
+
+        let conn = storage.get_connection();
+        let mut mega_blobs: Vec<mega_blob::ActiveModel> = Vec::new();
+        let mut raw_blobs: Vec<raw_blob::ActiveModel> = Vec::new();
+        for blob in staged.blobs {
+            let mut mega_blob: mega_blob::Model = (&blob).into();
+            mega_blob.commit_id.clone_from(&commit_id);
+            mega_blobs.push(mega_blob.into());
+            raw_blobs.push(Into::<raw_blob::Model>::into(blob).into());
+        }
+        batch_save_model(conn, mega_blobs)
+            .await
+            .map_err(storage_error)?;
+        batch_save_model(conn, raw_blobs)
+            .await
+            .map_err(storage_error)?;
+        let save_trees: Vec<mega_tree::ActiveModel> = staged
+            .trees
+            .into_iter()
+            .map(|tree| {
+                let mut tree_model: mega_tree::Model = tree.into();
+                tree_model.commit_id.clone_from(&commit_id);
+                tree_model.into()
+            })
+            .collect();
+        batch_save_model(conn, save_trees)
+            .await
+            .map_err(storage_error)?;
+        storage
+            .save_mega_commits(vec![commit])
+            .await
+            .map_err(storage_error)?;
+
+        // the refs of rewritten sub directories are outdated, as in `update_parent_tree`,
+        // they are dropped together with the move of the root ref
+        let stale_paths: Vec<String> = staged
+            .changed_dirs
+            .iter()
+            .filter(|dir| *dir != Path::new("/"))
+            .map(|dir| dir.to_str().unwrap().to_owned())
+            .collect();
+        root_ref.ref_commit_hash = commit_id;
+        root_ref.ref_tree_hash = staged.root.id.to_string();
+        if !storage
+            .update_ref_if(root_ref, &expected_commit, &stale_paths)
+            .await
+            .map_err(storage_error)?
+        {
+            return Err(CreateFileError::Conflict(
+                "the monorepo was changed by another write, retry".to_string(),
+            ));
+        }
+        self.sync_root_commit(parent, new_commit).await;
+        Ok(())
+    }
+
+    /// Move the snapshot of the monorepo from the root commit `old` to `new`, dropping the
+    /// latest commits of the paths changed. The commit is made already, a failure is only logged.
+    /// - all the rows are moved in a transaction, or none
+    async fn sync_root_commit(&self, old: SHA1, new: SHA1) {
+        let storage = &self.context.services.mono_storage;
+        let cache = &self.latest_commits;
+        let synced = async {
+            let txn = storage.get_connection().begin().await?;
+            sync_commit_and_invalidate(&txn, storage, cache, old, new).await?;
+            txn.commit().await?;
+            Ok::<_, MegaError>(())
+        }
+        .await;
+        if let Err(e) = synced {
+            // rolled back, the entries dropped may not be all those outdated
+            cache.invalidate(Path::new("/"));
+            tracing::error!("failed to sync the snapshot from {} to {}: {}", old, new, e);
+        }
+    }
+
+    /// Files changed from commit `base` to commit `head`, at or under `path`, with the hunks
+    /// of the text ones
+    /// - a submodule shows the commits it moved between, as git
+    /// - a blob larger than `lfs.split_size` is reported binary without being loaded
+    pub async fn commit_diff(
+        &self,
+        base: &str,
+        head: &str,
+        path: &Path,
+    ) -> Result<Vec<FileDiff>, DiffError> {
+        let storage = self.context.services.mono_storage.clone();
+        let mut roots = Vec::new();
+        for hash in [base, head] {
+            let commit = storage
+                .get_commit_by_hash(hash)
+                .await
+                .map_err(storage_error)?
+                .ok_or_else(|| DiffError::NotFound(hash.to_string()))?;
+            let tree_id = SHA1::from_str(&commit.tree).map_err(|e| {
+                GitError::InvalidHashValue(format!("tree of commit {}: {}", hash, e))
+            })?;
+            roots.push(load_tree(&storage, tree_id).await?);
+        }
+        let (new, old) = (roots.pop().unwrap(), roots.pop().unwrap());
+
+        // a path relative to the root is accepted as well
+        let path = Path::new("/").join(path);
+        let changes = diff_trees(old, new, &path, |id| load_tree(&storage, id)).await?;
+
+        // content over the LFS split size is too large to diff, it is not loaded
+        let max_size = self.context.config.lfs.split_size as u64;
+        let ids: Vec<String> = changes
+            .iter()
+            .filter(|change| !change.submodule)
+            .flat_map(|change| [change.old, change.new])
+            .flatten()
+            .map(|id| id.to_string())
+            .collect();
+        let mut sizes = HashMap::new();
+        for chunk in ids.chunks(1000) {
+            let blobs = storage
+                .get_mega_blobs_by_hashes(chunk.to_vec())
+                .await
+                .map_err(storage_error)?;
+            for blob in blobs {
+                sizes.insert(blob.blob_id, blob.size as u64);
+            }
+        }
+
+        let mut diffs = Vec::new();
+        for change in changes {
+            let too_large = [change.old, change.new].into_iter().flatten().any(|id| {
+                sizes
+                    .get(&id.to_string())
+                    .is_some_and(|size| *size > max_size)
+            });
+            let hunks = if too_large && !change.submodule {
+                None
+            } else {
+                let mut data = Vec::new();
+                for id in [change.old, change.new] {
+                    data.push(match id {
+                        Some(id) if change.submodule => submodule_text(id),
+                        Some(id) => self
+                            .get_raw_blob_by_hash(&id.to_string())
+                            .await
+                            .map_err(storage_error)?
+                            .ok_or_else(|| GitError::ObjectNotFound(id.to_string()))?
+                            .data
+                            .unwrap_or_default(),
+                        None => Vec::new(),
+                    });
+                }
+                text_hunks(&data[0], &data[1])
+            };
+            diffs.push(FileDiff {
+                path: change.path.display().to_string(),
+                kind: change.kind,
+                old_oid: change.old.map(|id| id.to_string()),
+                new_oid: change.new.map(|id| id.to_string()),
+                binary: hunks.is_none(),
+                hunks: hunks.unwrap_or_default(),
+            });
+        }
+        Ok(diffs)
+    }
+
+    /// How much blob content the tree of the ref `path` shares, `None` if there is no such ref
+    pub async fn blob_dedup_stats(&self, path: &str) -> Result<Option<BlobDedupStats>, GitError> {
+        let storage = self.context.services.mono_storage.clone();
+        let Some(refs) = storage.get_ref(path).await.map_err(storage_error)? else {
+            return Ok(None);
+        };
+        let root = load_tree(&storage, SHA1::from_str(&refs.ref_tree_hash).unwrap()).await?;
+        let blob_refs = count_blob_refs(root, |id| load_tree(&storage, id)).await?;
+
+        let ids: Vec<String> = blob_refs.keys().map(|id| id.to_string()).collect();
+        let mut sizes = HashMap::new();
+        // keep the `IN` lists within what the databases accept
+        for chunk in ids.chunks(1000) {
+            let blobs = storage
+                .get_mega_blobs_by_hashes(chunk.to_vec())
+                .await
+                .map_err(storage_error)?;
+            for blob in blobs {
+                let id = SHA1::from_str(&blob.blob_id).unwrap();
+                sizes.insert(id, blob.size as u64);
+            }
+        }
+        Ok(Some(BlobDedupStats::new(&blob_refs, &sizes)))
+    }
+
+    /// Files added or modified by the MR `link`, under its path
     pub async fn mr_tree_files(&self, link: &str) -> Result<Vec<PathBuf>, MegaError> {
         let storage = self.context.services.mono_storage.clone();
         let model = self.context.mr_stg().get_mr(link).await.unwrap();
@@ -214,39 +397,22 @@ impl MonoApiService {
                 .unwrap()
                 .tree;
 
-            let mut tree_comparation = TreeComparation {
-                left_tree: VecDeque::new(),
-                result_file: vec![],
-            };
-            tree_comparation.left_tree.push_back((
-                SHA1::from_str(&to_tree_id).unwrap(),
-                Some(SHA1::from_str(&from_tree_id).unwrap()),
-                PathBuf::from(model.path),
-            ));
-            while let Some((new_tree, base_tree, mut current_path)) =
-                tree_comparation.left_tree.pop_front()
-            {
-                let new_tree: Tree = storage
-                    .get_tree_by_hash(&new_tree.to_string())
-                    .await
-                    .unwrap()
-                    .unwrap()
-                    .into();
-
-                let base_tree = if let Some(base_tree) = base_tree {
-                    let base_tree: Tree = storage
-                        .get_tree_by_hash(&base_tree.to_string())
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .into();
-                    Some(base_tree)
-                } else {
-                    None
-                };
-                tree_comparation.compare(new_tree, base_tree, &mut current_path);
-            }
-            return Ok(tree_comparation.result_file);
+            let to_err = |e: GitError| MegaError::with_message(&e.to_string());
+            let from = load_tree(&storage, SHA1::from_str(&from_tree_id).unwrap())
+                .await
+                .map_err(to_err)?;
+            let to = load_tree(&storage, SHA1::from_str(&to_tree_id).unwrap())
+                .await
+                .map_err(to_err)?;
+            let changes = diff_trees(from, to, Path::new("/"), |id| load_tree(&storage, id))
+                .await
+                .map_err(to_err)?;
+            let mr_path = PathBuf::from(model.path);
+            return Ok(changes
+                .into_iter()
+                .filter(|change| change.kind != DiffKind::Deleted)
+                .map(|change| mr_path.join(change.path.strip_prefix("/").unwrap()))
+                .collect());
         }
         Err(MegaError::with_message("Can not find related MR by id"))
     }
@@ -265,14 +431,21 @@ impl MonoApiService {
 
             if mr.path != "/" {
                 let path = PathBuf::from(mr.path.clone());
+                let old_root = storage.get_ref("/").await.unwrap().unwrap().ref_commit_hash;
                 // beacuse only parent tree is needed so we skip current directory
                 let (tree_vec, _) = self
                     .search_tree_for_update(path.parent().unwrap())
                     .await
                     .unwrap();
-                self.update_parent_tree(path, tree_vec, commit)
+                let new_root = self
+                    .update_parent_tree(path, tree_vec, commit)
                     .await
                     .unwrap();
+                self.sync_root_commit(
+                    SHA1::from_str(&old_root).unwrap(),
+                    SHA1::from_str(&new_root).unwrap(),
+                )
+                .await;
                 // remove refs start with path
                 storage.remove_refs(&mr.path).await.unwrap();
                 // TODO: self.clean_dangling_commits().await;
@@ -330,7 +503,7 @@ impl MonoApiService {
                         commit.committer.clone(),
                         target_hash,
                         vec![SHA1::from_str(&p_ref.ref_commit_hash).unwrap()],
-                        &commit.message,
+                        commit.message(),
                     );
                     p_commit_id = p_commit.id.to_string();
                     // update p_ref
@@ -434,91 +607,9 @@ impl MonoApiService {
     }
 }
 
-pub struct TreeComparation {
-    pub left_tree: VecDeque<(SHA1, Option<SHA1>, PathBuf)>,
-    pub result_file: Vec<PathBuf>,
-}
-
-impl TreeComparation {
-    // fn compare(&mut self, new: Tree, base: Option<Tree>) {
-    //     let new_set: HashSet<_> = new.tree_items.into_iter().collect();
-    //     let base_set = if let Some(tree) = base {
-    //         tree.tree_items.into_iter().collect()
-    //     } else {
-    //         HashSet::new()
-    //     };
-    //     let diff: Vec<_> = new_set.symmetric_difference(&base_set).cloned().collect();
-    //     for item in diff {
-    //         if item.mode == TreeItemMode::Tree {
-    //             let t_id = item.id.to_string();
-    //             if !self.left_tree.contains(&t_id) {
-    //                 self.left_tree.push_back(t_id);
-    //             }
-    //         } else {
-    //             self.result_file.push(item.name)
-    //         }
-    //     }
-    // }
-
-    pub fn compare(&mut self, new: Tree, base: Option<Tree>, current_path: &mut PathBuf) {
-        let mut map_name_to_item = HashMap::new();
-        let mut map_id_to_item = HashMap::new();
-
-        if let Some(tree) = base {
-            for item in tree.tree_items {
-                map_name_to_item.insert(item.name.clone(), item.clone());
-                map_id_to_item.insert(item.id, item.clone());
-            }
-        }
-
-        for item in new.tree_items {
-            match (
-                map_name_to_item.get(&item.name),
-                map_id_to_item.get(&item.id),
-            ) {
-                (Some(base_item), _) if base_item.id != item.id => {
-                    self.process_diff(item, Some(base_item.id), current_path);
-                }
-                (_, Some(base_item)) if base_item.name != item.name => {
-                    self.process_diff(item, None, current_path);
-                }
-                (None, None) => {
-                    self.process_diff(item, None, current_path);
-                }
-                _ => {}
-            }
-        }
-    }
-
-    fn process_diff(&mut self, item: TreeItem, base_id: Option<SHA1>, current_path: &mut PathBuf) {
-        if item.mode == TreeItemMode::Tree {
-            if !self
-                .left_tree
-                .contains(&(item.id, base_id, current_path.to_path_buf()))
-            {
-                current_path.push(item.name);
-                self.left_tree
-                    .push_back((item.id, base_id, current_path.to_path_buf()));
-                current_path.pop();
-            }
-        } else {
-            current_path.push(item.name);
-            self.result_file.push(current_path.to_path_buf());
-            current_path.pop();
-        }
-    }
-}
-
 #[cfg(test)]
 mod test {
-    use std::{collections::VecDeque, path::PathBuf};
-
-    use mercury::{
-        hash::SHA1,
-        internal::object::tree::{Tree, TreeItem, TreeItemMode},
-    };
-
-    use crate::api_service::mono_api_service::TreeComparation;
+    use std::path::PathBuf;
 
     #[test]
     pub fn test() {
@@ -530,66 +621,4 @@ mod test {
             println!("name: {}, path: {:?}", name, full_path);
         }
     }
-
-    #[test]
-    fn test_compare_tree() {
-        let base = Tree::from_tree_items(vec![
-            TreeItem {
-                mode: TreeItemMode::Tree,
-                id: SHA1::new(b"src"),
-                name: "src".to_string(),
-            },
-            TreeItem {
-                mode: TreeItemMode::Tree,
-                id: SHA1::new(b"mega"),
-                name: "mega".to_string(),
-            },
-            TreeItem {
-                mode: TreeItemMode::Blob,
-                id: SHA1::new(b"delete.txt"),
-                name: "delete.txt".to_string(),
-            },
-            TreeItem {
-                mode: TreeItemMode::Blob,
-                id: SHA1::new(b"README.md"),
-                name: "README.md".to_string(),
-            },
-        ])
-        .unwrap();
-
-        let new = Tree::from_tree_items(vec![
-            TreeItem {
-                mode: TreeItemMode::Tree,
-                id: SHA1::new(b"src"),
-                name: "src_new".to_string(),
-            },
-            TreeItem {
-                mode: TreeItemMode::Tree,
-                id: SHA1::new(b"mega222"),
-                name: "mega".to_string(),
-            },
-            TreeItem {
-                mode: TreeItemMode::Blob,
-                id: SHA1::new(b"Cargo.toml"),
-                name: "Cargo.toml".to_string(),
-            },
-            TreeItem {
-                mode: TreeItemMode::Blob,
-                id: SHA1::new(b"README.md"),
-                name: "README.md".to_string(),
-            },
-        ])
-        .unwrap();
-
-        let mut tree_comparation = TreeComparation {
-            left_tree: VecDeque::new(),
-            result_file: vec![],
-        };
-        // let mut current_path = Path::new("/");
-        tree_comparation.compare(new, Some(base), &mut PathBuf::from("/"));
-        println!(
-            "files: {:?} left Tree: {:?}",
-            tree_comparation.result_file, tree_comparation.left_tree
-        );
-    }
 }
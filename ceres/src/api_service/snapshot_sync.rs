@@ -0,0 +1,343 @@
+//! Move the `mega_snapshot` rows of a commit to its successor, rewriting only the paths the
+//! commit changed instead of syncing the whole tree again.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, ConnectionTrait, EntityTrait, PaginatorTrait, QueryFilter,
+};
+
+use callisto::mega_snapshot::{self, SubTrees};
+use common::errors::MegaError;
+use jupiter::{
+    storage::mono_storage::MonoStorage,
+    utils::{
+        snapshot_sync::{
+            blob_sizes, child_path, snapshot_row, sync_commit_to_snapshots, upsert_snapshots,
+        },
+        snapshot_tree::ROOT_PATH,
+    },
+};
+use mercury::{
+    errors::GitError,
+    hash::SHA1,
+    internal::object::tree::{Tree, TreeItemMode},
+};
+
+use crate::{
+    api_service::{commit_diff::diff_trees, latest_commit_cache::LatestCommitCache, load_tree},
+    model::diff::DiffKind,
+};
+
+fn internal(e: impl ToString) -> MegaError {
+    MegaError::with_message(&e.to_string())
+}
+
+async fn root_tree(storage: &MonoStorage, commit: SHA1) -> Result<Tree, MegaError> {
+    let model = storage
+        .get_commit_by_hash(&commit.to_string())
+        .await?
+        .ok_or_else(|| MegaError::with_message(&format!("commit {} not found", commit)))?;
+    let id = SHA1::from_str(&model.tree).map_err(internal)?;
+    load_tree(storage, id).await.map_err(internal)
+}
+
+/// Turn the rows of `old_commit` into those of `new_commit`, returning how many rows are written
+/// or deleted: the rows of the files `new_commit` changed, and of the directories above them with
+/// their `sub_trees` & sizes rolled up again, the others are only moved to `new_commit`.
+/// - `old_commit` keeps no rows, the table holds the snapshot of the tip
+/// - without rows for `old_commit`, the whole tree of `new_commit` is synced instead
+/// - nothing is done if `new_commit` has rows already
+/// - pass a transaction as `db` to apply all the changes or none
+pub async fn sync_commit_incremental(
+    db: &impl ConnectionTrait,
+    storage: &MonoStorage,
+    old_commit: SHA1,
+    new_commit: SHA1,
+) -> Result<usize, MegaError> {
+    let (count, _) = sync_changes(db, storage, old_commit, new_commit).await?;
+    Ok(count)
+}
+
+/// [sync_commit_incremental], then drop the entries of `cache` made stale by the paths
+/// `new_commit` changed, or all of them if the whole tree was synced or the sync failed
+pub async fn sync_commit_and_invalidate(
+    db: &impl ConnectionTrait,
+    storage: &MonoStorage,
+    cache: &LatestCommitCache,
+    old_commit: SHA1,
+    new_commit: SHA1,
+) -> Result<usize, MegaError> {
+    let synced = sync_changes(db, storage, old_commit, new_commit).await;
+    match &synced {
+        Ok((_, Some(paths))) => cache.invalidate_all(paths),
+        _ => cache.invalidate(Path::new(ROOT_PATH)),
+    }
+    synced.map(|(count, _)| count)
+}
+
+/// The count of [sync_commit_incremental] & the changed paths, `None` for the whole tree
+async fn sync_changes(
+    db: &impl ConnectionTrait,
+    storage: &MonoStorage,
+    old_commit: SHA1,
+    new_commit: SHA1,
+) -> Result<(usize, Option<Vec<PathBuf>>), MegaError> {
+    let commit_rows = |commit: SHA1| {
+        mega_snapshot::Entity::find()
+            .filter(mega_snapshot::Column::CommitId.eq(commit.to_string()))
+            .count(db)
+    };
+    if commit_rows(new_commit).await? > 0 {
+        return Ok((0, Some(Vec::new())));
+    }
+    if commit_rows(old_commit).await? == 0 {
+        let count = sync_commit_to_snapshots(db, storage, new_commit).await?;
+        return Ok((count, None));
+    }
+
+    let old_root = root_tree(storage, old_commit).await?;
+    let new_root = root_tree(storage, new_commit).await?;
+    let changes = diff_trees(old_root, new_root.clone(), Path::new(ROOT_PATH), |id| {
+        load_tree(storage, id)
+    })
+    .await
+    .map_err(internal)?;
+
+    // the directories above the changed files, parents first, as in `new_commit` if still there
+    let ancestors: BTreeSet<(usize, String)> = changes
+        .iter()
+        .flat_map(|change| change.path.ancestors().skip(1))
+        .map(|dir| (dir.components().count(), dir.to_string_lossy().into_owned()))
+        .collect();
+    let mut dirs: HashMap<String, Option<Tree>> = HashMap::new();
+    for (_, dir) in &ancestors {
+        let tree = match Path::new(dir).parent() {
+            None => Some(new_root.clone()),
+            Some(parent) => {
+                let name = Path::new(dir).file_name().unwrap().to_string_lossy();
+                let parent = dirs[&*parent.to_string_lossy()].as_ref();
+                match parent.and_then(|tree| tree.tree_items.iter().find(|x| x.name == name)) {
+                    Some(item) if item.mode == TreeItemMode::Tree => {
+                        Some(load_tree(storage, item.id).await.map_err(internal)?)
+                    }
+                    _ => None,
+                }
+            }
+        };
+        dirs.insert(dir.clone(), tree);
+    }
+
+    let (old_id, new_id) = (old_commit.to_string(), new_commit.to_string());
+    mega_snapshot::Entity::update_many()
+        .col_expr(mega_snapshot::Column::CommitId, Expr::value(new_id.clone()))
+        .filter(mega_snapshot::Column::CommitId.eq(old_id))
+        .exec(db)
+        .await?;
+
+    let removed: Vec<String> = changes
+        .iter()
+        .filter(|change| change.kind == DiffKind::Deleted)
+        .map(|change| change.path.to_string_lossy().into_owned())
+        .chain(
+            dirs.iter()
+                .filter(|(_, tree)| tree.is_none())
+                .map(|(dir, _)| dir.clone()),
+        )
+        .collect();
+    let mut count = removed.len();
+    for chunk in removed.chunks(1000) {
+        mega_snapshot::Entity::delete_many()
+            .filter(mega_snapshot::Column::CommitId.eq(&new_id))
+            .filter(mega_snapshot::Column::Path.is_in(chunk.to_vec()))
+            .exec(db)
+            .await?;
+    }
+
+    let files: Vec<(String, SHA1)> = changes
+        .iter()
+        .filter_map(|change| Some((change.path.to_string_lossy().into_owned(), change.new?)))
+        .collect();
+    let hashes = files.iter().map(|(_, id)| id.to_string()).collect();
+    let file_sizes = blob_sizes(db, hashes).await?;
+    let mut rows: Vec<mega_snapshot::Model> = files
+        .iter()
+        .map(|(path, id)| {
+            let size = file_sizes.get(&id.to_string()).copied().unwrap_or(0);
+            snapshot_row(&new_id, path, false, *id, None, size)
+        })
+        .collect();
+
+    // sizes of the entries of the directories: those just written, then the unchanged ones
+    let mut sizes: HashMap<String, i64> = rows.iter().map(|x| (x.path.clone(), x.size)).collect();
+    let entries: Vec<String> = dirs
+        .iter()
+        .filter_map(|(dir, tree)| Some((dir, tree.as_ref()?)))
+        .flat_map(|(dir, tree)| {
+            tree.tree_items
+                .iter()
+                .map(|item| child_path(dir, &item.name))
+        })
+        .filter(|entry| !sizes.contains_key(entry))
+        .collect();
+    for chunk in entries.chunks(1000) {
+        let unchanged = mega_snapshot::Entity::find()
+            .filter(mega_snapshot::Column::CommitId.eq(&new_id))
+            .filter(mega_snapshot::Column::Path.is_in(chunk.to_vec()))
+            .all(db)
+            .await?;
+        sizes.extend(unchanged.into_iter().map(|x| (x.path, x.size)));
+    }
+    for (_, dir) in ancestors.iter().rev() {
+        let Some(tree) = &dirs[dir] else { continue };
+        let entries: Vec<String> = tree
+            .tree_items
+            .iter()
+            .map(|item| child_path(dir, &item.name))
+            .collect();
+        let size = entries.iter().filter_map(|entry| sizes.get(entry)).sum();
+        sizes.insert(dir.clone(), size);
+        let sub_trees = Some(SubTrees(entries));
+        rows.push(snapshot_row(&new_id, dir, true, tree.id, sub_trees, size));
+    }
+    count += rows.len();
+    upsert_snapshots(db, rows).await?;
+    let changed = changes.into_iter().map(|change| change.path).collect();
+    Ok((count, Some(changed)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use sea_orm::{DatabaseConnection, IntoActiveModel, QueryOrder};
+
+    use callisto::{mega_tree, raw_blob};
+    use common::config::DbConfig;
+    use jupiter::storage::{batch_save_model, init::database_connection};
+    use jupiter::utils::snapshot_tree::build_tree_from_snapshots;
+    use mercury::internal::object::{blob::Blob, commit::Commit, tree::TreeItem};
+
+    use super::*;
+    use crate::api_service::latest_commit_cache::test::info;
+
+    /// Save the tree of `files` (path, content) and its blobs, returning the root
+    async fn save_tree(conn: &DatabaseConnection, files: &[(&str, &str)]) -> Tree {
+        let mut dirs: BTreeMap<&str, Vec<(&str, &str)>> = BTreeMap::new();
+        let mut items = Vec::new();
+        let mut blobs = Vec::new();
+        for &(path, content) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => dirs.entry(dir).or_default().push((rest, content)),
+                None => {
+                    let blob = Blob::from_content(content);
+                    items.push(TreeItem::new(TreeItemMode::Blob, blob.id, path.to_string()));
+                    blobs.push(raw_blob::Model::from(blob).into_active_model());
+                }
+            }
+        }
+        for (dir, files) in dirs {
+            let tree = Box::pin(save_tree(conn, &files)).await;
+            items.push(TreeItem::new(TreeItemMode::Tree, tree.id, dir.to_string()));
+        }
+        let tree = Tree::from_tree_items(items).unwrap();
+        let model = mega_tree::Model::from(tree.clone()).into_active_model();
+        batch_save_model(conn, vec![model]).await.unwrap();
+        batch_save_model(conn, blobs).await.unwrap();
+        tree
+    }
+
+    async fn snapshot(conn: &DatabaseConnection, commit: SHA1) -> Vec<mega_snapshot::Model> {
+        mega_snapshot::Entity::find()
+            .filter(mega_snapshot::Column::CommitId.eq(commit.to_string()))
+            .order_by_asc(mega_snapshot::Column::Path)
+            .all(conn)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sync_commit_incremental() {
+        let db_path =
+            std::env::temp_dir().join(format!("ceres_snapshot_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let conn = database_connection(&DbConfig {
+            db_path: db_path.to_string_lossy().into_owned(),
+            max_connection: 1,
+            min_connection: 1,
+            ..Default::default()
+        })
+        .await;
+        let storage = MonoStorage::new(conn.clone().into()).await;
+
+        let mut files = vec![
+            ("README.md", "# mega\n"),
+            ("docs/guide.md", "# guide\n"),
+            ("src/lib.rs", "pub mod api;\n"),
+            ("src/api/mod.rs", "pub fn get() {}\n"),
+        ];
+        let root = save_tree(&conn, &files).await;
+        let base = Commit::from_tree_id(root.id, vec![], "\nbase");
+        files[3].1 = "pub fn get() -> u8 { 0 }\n";
+        let root = save_tree(&conn, &files).await;
+        let head = Commit::from_tree_id(root.id, vec![base.id], "\nhead");
+        storage
+            .save_mega_commits(vec![base.clone(), head.clone()])
+            .await
+            .unwrap();
+
+        sync_commit_to_snapshots(&conn, &storage, base.id)
+            .await
+            .unwrap();
+        let before = snapshot(&conn, base.id).await;
+        // the file & its 3 directories
+        let synced = sync_commit_incremental(&conn, &storage, base.id, head.id).await;
+        assert_eq!(synced.unwrap(), 4);
+        assert!(snapshot(&conn, base.id).await.is_empty());
+
+        let after = snapshot(&conn, head.id).await;
+        let touched: Vec<&str> = before
+            .iter()
+            .zip(&after)
+            .filter(|(old, new)| {
+                assert_eq!((old.id, &old.path), (new.id, &new.path));
+                (&old.tree_id, old.size) != (&new.tree_id, new.size)
+            })
+            .map(|(_, new)| new.path.as_str())
+            .collect();
+        assert_eq!(touched, ["/", "/src", "/src/api", "/src/api/mod.rs"]);
+
+        let root = build_tree_from_snapshots(after).unwrap();
+        assert_eq!(root.row.tree_id, Some(head.tree_id.to_string()));
+        assert_eq!(root.row.size, 7 + 8 + 13 + 25);
+        assert_eq!(root.get("src").unwrap().row.size, 13 + 25);
+        assert_eq!(root.get("src/api/mod.rs").unwrap().row.size, 25);
+        assert_eq!(root.get("docs").unwrap().row.size, 8);
+
+        // synced already
+        let synced = sync_commit_incremental(&conn, &storage, base.id, head.id).await;
+        assert_eq!(synced.unwrap(), 0);
+
+        // a commit under /docs clears the latest commits of /docs and above only
+        let cache = LatestCommitCache::default();
+        for path in ["/", "/docs", "/docs/guide.md", "/src", "/src/lib.rs"] {
+            cache.insert(path.into(), info(&head.id.to_string()));
+        }
+        files[1].1 = "# guide\n\nmore\n";
+        let root = save_tree(&conn, &files).await;
+        let next = Commit::from_tree_id(root.id, vec![head.id], "\nnext");
+        storage.save_mega_commits(vec![next.clone()]).await.unwrap();
+        let synced = sync_commit_and_invalidate(&conn, &storage, &cache, head.id, next.id).await;
+        assert_eq!(synced.unwrap(), 3);
+        let cached: Vec<&str> = ["/", "/docs", "/docs/guide.md", "/src", "/src/lib.rs"]
+            .into_iter()
+            .filter(|path| cache.get(Path::new(path)).is_some())
+            .collect();
+        assert_eq!(cached, ["/src", "/src/lib.rs"]);
+        let _ = std::fs::remove_file(&db_path);
+    }
+}
@@ -0,0 +1,599 @@
+//! Stage several file changes on top of the monorepo root tree, so they land in one commit.
+//!
+//! Every change is checked before anything is saved: a single conflict or missing path fails
+//! the whole batch.
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::{Component, Path, PathBuf},
+};
+
+use jupiter::utils::converter::generate_git_keep_with_timestamp;
+use mercury::{
+    errors::GitError,
+    hash::SHA1,
+    internal::object::{
+        blob::Blob,
+        tree::{Tree, TreeItem, TreeItemMode},
+    },
+};
+
+use crate::api_service::CreateFileError;
+use crate::model::create_file::{CreateFileInfo, WriteCondition};
+
+/// Objects of a staged commit, nothing is saved yet
+#[derive(Debug)]
+pub struct StagedCommit {
+    pub root: Tree,
+    /// all new trees, `root` included
+    pub trees: Vec<Tree>,
+    pub blobs: Vec<Blob>,
+    /// directories rewritten by the commit, from the deepest
+    pub changed_dirs: Vec<PathBuf>,
+    pub message: String,
+}
+
+/// One change of a staged commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeChange {
+    /// create or overwrite a file, or create a directory
+    Write(CreateFileInfo, WriteCondition),
+    /// remove a file or a whole directory
+    Delete(PathBuf),
+    /// rename a file or a directory, keeping its object id
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+        /// replace what is at `to` instead of failing
+        overwrite: bool,
+    },
+}
+
+/// Absolute form of `path`, `/` for an empty one
+fn dir_key(path: &Path) -> PathBuf {
+    let mut key = PathBuf::from("/");
+    key.extend(
+        path.components()
+            .filter(|c| matches!(c, Component::Normal(_))),
+    );
+    key
+}
+
+/// Parent directory and name of `path`, `None` for the root
+fn split_name(path: &Path) -> Option<(PathBuf, String)> {
+    let path = dir_key(path);
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    Some((path.parent().unwrap().to_path_buf(), name))
+}
+
+struct TreeEdit {
+    /// items of every directory loaded or changed, by absolute path
+    dirs: HashMap<PathBuf, Vec<TreeItem>>,
+    changed: HashSet<PathBuf>,
+    blobs: Vec<Blob>,
+    /// `create file a.txt` & co, one per write
+    actions: Vec<String>,
+}
+
+impl TreeEdit {
+    /// The next directory on the way to `dir` that must be loaded, with its tree id
+    /// - `Err` is the first directory that doesn't exist
+    fn next_to_load(&self, dir: &Path) -> Result<Option<(PathBuf, SHA1)>, PathBuf> {
+        let mut parent = PathBuf::from("/");
+        for name in dir.components().skip(1) {
+            let child = parent.join(name);
+            if !self.dirs.contains_key(&child) {
+                let item = self.dirs[&parent]
+                    .iter()
+                    .find(|x| x.mode == TreeItemMode::Tree && *x.name == *name.as_os_str())
+                    .ok_or_else(|| child.clone())?;
+                return Ok(Some((child, item.id)));
+            }
+            parent = child;
+        }
+        Ok(None)
+    }
+
+    /// Load the trees on the way to `dir`, returns the first directory that doesn't exist
+    async fn load<F, Fut>(&mut self, dir: &Path, load_tree: &F) -> Result<Option<PathBuf>, GitError>
+    where
+        F: Fn(SHA1) -> Fut,
+        Fut: Future<Output = Result<Tree, GitError>>,
+    {
+        loop {
+            match self.next_to_load(dir) {
+                Ok(Some((path, id))) => {
+                    let tree = load_tree(id).await?;
+                    self.dirs.insert(path, tree.tree_items);
+                }
+                Ok(None) => return Ok(None),
+                Err(missing) => return Ok(Some(missing)),
+            }
+        }
+    }
+
+    /// Forget what was loaded or staged at and below `path`
+    fn purge(&mut self, path: &Path) {
+        self.dirs.retain(|key, _| !key.starts_with(path));
+        self.changed.retain(|key| !key.starts_with(path));
+    }
+
+    fn mark_changed(&mut self, dir: &Path) {
+        for ancestor in dir.ancestors() {
+            self.changed.insert(ancestor.to_path_buf());
+        }
+    }
+
+    fn write(
+        &mut self,
+        dir: &Path,
+        file: CreateFileInfo,
+        condition: WriteCondition,
+    ) -> Result<(), CreateFileError> {
+        let items = self.dirs.get_mut(dir).unwrap();
+        let current = items.iter().position(|x| x.name == file.name);
+        if file.is_directory {
+            if current.is_some() {
+                return Err(GitError::CustomError(format!("Duplicate name {}", file.name)).into());
+            }
+            let git_keep = generate_git_keep_with_timestamp();
+            // the id is set when the trees are built
+            items.push(TreeItem::new(
+                TreeItemMode::Tree,
+                SHA1::default(),
+                file.name.clone(),
+            ));
+            let child = dir.join(&file.name);
+            self.dirs.insert(
+                child.clone(),
+                vec![TreeItem::new(
+                    TreeItemMode::Blob,
+                    git_keep.id,
+                    String::from(".gitkeep"),
+                )],
+            );
+            self.blobs.push(git_keep);
+            self.mark_changed(&child);
+            self.actions.push(format!("create directory {}", file.name));
+            return Ok(());
+        }
+
+        condition
+            .check(current.map(|i| &items[i]))
+            .map_err(CreateFileError::Conflict)?;
+        let blob = Blob::from_content(&file.content.unwrap_or_default());
+        let item = TreeItem::new(TreeItemMode::Blob, blob.id, file.name.clone());
+        let action = match current {
+            Some(i) => {
+                items[i] = item;
+                "update"
+            }
+            None => {
+                items.push(item);
+                "create"
+            }
+        };
+        self.blobs.push(blob);
+        self.mark_changed(dir);
+        self.actions.push(format!("{} file {}", action, file.name));
+        Ok(())
+    }
+
+    fn delete(&mut self, dir: &Path, name: &str) -> Result<(), CreateFileError> {
+        let path = dir.join(name);
+        let items = self.dirs.get_mut(dir).unwrap();
+        let index = items
+            .iter()
+            .position(|x| x.name == name)
+            .ok_or_else(|| CreateFileError::NotFound(path.display().to_string()))?;
+        let item = items.remove(index);
+        let kind = if item.mode == TreeItemMode::Tree {
+            self.purge(&path);
+            "directory"
+        } else {
+            "file"
+        };
+        self.mark_changed(dir);
+        self.actions.push(format!("delete {} {}", kind, name));
+        Ok(())
+    }
+
+    /// Move the entry `from` to `to`, both split in directory and name and already loaded
+    fn rename(
+        &mut self,
+        (from_dir, from_name): (&Path, &str),
+        (to_dir, to_name): (&Path, &str),
+        overwrite: bool,
+    ) -> Result<(), CreateFileError> {
+        let from = from_dir.join(from_name);
+        let to = to_dir.join(to_name);
+        let items = self.dirs.get_mut(from_dir).unwrap();
+        let index = items
+            .iter()
+            .position(|x| x.name == from_name)
+            .ok_or_else(|| CreateFileError::NotFound(from.display().to_string()))?;
+        let mut item = items.remove(index);
+
+        let items = self.dirs.get_mut(to_dir).unwrap();
+        if let Some(index) = items.iter().position(|x| x.name == to_name) {
+            if !overwrite {
+                return Err(CreateFileError::Conflict(format!(
+                    "{} already exists",
+                    to.display()
+                )));
+            }
+            items.remove(index);
+            self.purge(&to);
+        }
+        item.name = to_name.to_string();
+        let kind = if item.mode == TreeItemMode::Tree {
+            "directory"
+        } else {
+            "file"
+        };
+        self.dirs.get_mut(to_dir).unwrap().push(item);
+
+        // what is staged below a moved directory moves with it
+        let rekey = |key: &PathBuf| match key.strip_prefix(&from) {
+            Ok(rest) => to.join(rest),
+            Err(_) => key.clone(),
+        };
+        self.dirs = self.dirs.drain().map(|(k, v)| (rekey(&k), v)).collect();
+        self.changed = self.changed.iter().map(rekey).collect();
+
+        self.mark_changed(from_dir);
+        self.mark_changed(to_dir);
+        self.actions.push(format!(
+            "move {} {} to {}",
+            kind,
+            from.display(),
+            to.display()
+        ));
+        Ok(())
+    }
+
+    /// Build the changed trees bottom-up, a directory left empty is removed from its parent
+    fn finish(mut self) -> Result<StagedCommit, GitError> {
+        let mut changed_dirs: Vec<PathBuf> = self.changed.into_iter().collect();
+        changed_dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        let mut trees = Vec::new();
+        for dir in &changed_dirs {
+            let items = self.dirs.remove(dir).unwrap();
+            let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) else {
+                // the root, git has no empty tree
+                trees.push(Tree::from_tree_items(items)?);
+                continue;
+            };
+            let parent_items = self.dirs.get_mut(parent).unwrap();
+            let index = parent_items
+                .iter()
+                .position(|x| x.mode == TreeItemMode::Tree && *x.name == *name)
+                .unwrap();
+            if items.is_empty() {
+                parent_items.remove(index);
+                continue;
+            }
+            let tree = Tree::from_tree_items(items)?;
+            parent_items[index].id = tree.id;
+            trees.push(tree);
+        }
+        let message = match self.actions.as_slice() {
+            [action] => format!("\n{} commit", action),
+            actions => format!(
+                "\nupdate {} files commit\n\n{}",
+                actions.len(),
+                actions.join("\n")
+            ),
+        };
+        Ok(StagedCommit {
+            // the root is the last one, it has the fewest components
+            root: trees.last().unwrap().clone(),
+            trees,
+            blobs: self.blobs,
+            changed_dirs,
+            message,
+        })
+    }
+}
+
+/// Apply `changes` in order on top of `root`, loading the trees on their way with `load_tree`.
+/// - a change may rely on a previous one of the same batch, e.g. write into a new directory
+/// - fails without staging anything if any change fails
+pub async fn stage_changes<F, Fut>(
+    root: Tree,
+    changes: Vec<TreeChange>,
+    load_tree: F,
+) -> Result<StagedCommit, CreateFileError>
+where
+    F: Fn(SHA1) -> Fut,
+    Fut: Future<Output = Result<Tree, GitError>>,
+{
+    if changes.is_empty() {
+        return Err(GitError::CustomError("no file to change".to_string()).into());
+    }
+    let mut edit = TreeEdit {
+        dirs: HashMap::from([(PathBuf::from("/"), root.tree_items)]),
+        changed: HashSet::new(),
+        blobs: Vec::new(),
+        actions: Vec::new(),
+    };
+    let not_exist = |missing: PathBuf| -> CreateFileError {
+        GitError::CustomError(format!(
+            "Path {} not exist, please create path first!",
+            missing.display()
+        ))
+        .into()
+    };
+    let not_found = |missing: PathBuf| CreateFileError::NotFound(missing.display().to_string());
+    for change in changes {
+        match change {
+            TreeChange::Write(file, condition) => {
+                let dir = dir_key(Path::new(&file.path));
+                if let Some(missing) = edit.load(&dir, &load_tree).await? {
+                    return Err(not_exist(missing));
+                }
+                edit.write(&dir, file, condition)?;
+            }
+            TreeChange::Delete(path) => {
+                let Some((dir, name)) = split_name(&path) else {
+                    return Err(CreateFileError::Conflict(
+                        "the root directory can't be deleted".to_string(),
+                    ));
+                };
+                if let Some(missing) = edit.load(&dir, &load_tree).await? {
+                    return Err(not_found(missing));
+                }
+                edit.delete(&dir, &name)?;
+            }
+            TreeChange::Move {
+                from,
+                to,
+                overwrite,
+            } => {
+                let (Some((from_dir, from_name)), Some((to_dir, to_name))) =
+                    (split_name(&from), split_name(&to))
+                else {
+                    return Err(CreateFileError::Conflict(
+                        "the root directory can't be moved".to_string(),
+                    ));
+                };
+                let (from, to) = (dir_key(&from), dir_key(&to));
+                if to.starts_with(&from) {
+                    return Err(CreateFileError::Conflict(format!(
+                        "can't move {} into itself",
+                        from.display()
+                    )));
+                }
+                if from.starts_with(&to) {
+                    return Err(CreateFileError::Conflict(format!(
+                        "can't replace {} by its own content",
+                        to.display()
+                    )));
+                }
+                if let Some(missing) = edit.load(&from_dir, &load_tree).await? {
+                    return Err(not_found(missing));
+                }
+                if let Some(missing) = edit.load(&to_dir, &load_tree).await? {
+                    return Err(not_exist(missing));
+                }
+                edit.rename((&from_dir, &from_name), (&to_dir, &to_name), overwrite)?;
+            }
+        }
+    }
+    Ok(edit.finish()?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use mercury::{
+        errors::GitError,
+        hash::SHA1,
+        internal::object::{
+            blob::Blob,
+            tree::{Tree, TreeItem, TreeItemMode},
+        },
+    };
+
+    use super::{stage_changes, StagedCommit, TreeChange};
+    use crate::{
+        api_service::CreateFileError,
+        model::create_file::{CreateFileInfo, WriteCondition},
+    };
+
+    fn file(path: &str, name: &str, content: &str) -> TreeChange {
+        let info = CreateFileInfo {
+            is_directory: false,
+            name: name.to_string(),
+            path: path.to_string(),
+            content: Some(content.to_string()),
+        };
+        TreeChange::Write(info, WriteCondition::IfNoneMatch)
+    }
+
+    fn dir(path: &str, name: &str) -> TreeChange {
+        let info = CreateFileInfo {
+            is_directory: true,
+            name: name.to_string(),
+            path: path.to_string(),
+            content: None,
+        };
+        TreeChange::Write(info, WriteCondition::IfNoneMatch)
+    }
+
+    /// `/README.md`, `/project/main.rs`
+    fn store() -> (Tree, HashMap<SHA1, Tree>) {
+        let main = Blob::from_content("fn main() {}");
+        let project = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            main.id,
+            "main.rs".to_string(),
+        )])
+        .unwrap();
+        let readme = Blob::from_content("# mega");
+        let root = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, readme.id, "README.md".to_string()),
+            TreeItem::new(TreeItemMode::Tree, project.id, "project".to_string()),
+        ])
+        .unwrap();
+        (root, HashMap::from([(project.id, project)]))
+    }
+
+    fn delete(path: &str) -> TreeChange {
+        TreeChange::Delete(path.into())
+    }
+
+    fn rename(from: &str, to: &str, overwrite: bool) -> TreeChange {
+        TreeChange::Move {
+            from: from.into(),
+            to: to.into(),
+            overwrite,
+        }
+    }
+
+    async fn stage(changes: Vec<TreeChange>) -> Result<StagedCommit, CreateFileError> {
+        let (root, trees) = store();
+        stage_changes(root, changes, |id| {
+            let tree = trees.get(&id).cloned();
+            async move { tree.ok_or(GitError::ObjectNotFound(id.to_string())) }
+        })
+        .await
+    }
+
+    fn find<'a>(commit: &'a StagedCommit, tree: &Tree, name: &str) -> &'a Tree {
+        let item = tree.tree_items.iter().find(|x| x.name == name).unwrap();
+        commit.trees.iter().find(|x| x.id == item.id).unwrap()
+    }
+
+    fn names(tree: &Tree) -> Vec<&str> {
+        tree.tree_items.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_stage_files_in_one_commit() {
+        let commit = stage(vec![
+            file("", "LICENSE", "MIT"),
+            file("/project", "lib.rs", "pub mod a;"),
+            dir("/project", "doc"),
+            file("/project/doc", "guide.md", "# guide"),
+        ])
+        .await
+        .unwrap();
+
+        // one new root, all writes under it
+        assert_eq!(commit.trees.len(), 3);
+        assert_eq!(names(&commit.root), vec!["README.md", "project", "LICENSE"]);
+        let project = find(&commit, &commit.root, "project");
+        assert_eq!(names(project), vec!["main.rs", "lib.rs", "doc"]);
+        let doc = find(&commit, project, "doc");
+        assert_eq!(names(doc), vec![".gitkeep", "guide.md"]);
+        let guide = Blob::from_content("# guide");
+        assert!(commit.blobs.iter().any(|x| x.id == guide.id));
+        assert_eq!(commit.changed_dirs.last().unwrap().to_str(), Some("/"));
+        assert!(commit.message.starts_with("\nupdate 4 files commit"));
+    }
+
+    #[tokio::test]
+    async fn test_stage_files_all_or_nothing() {
+        // the second write conflicts with the existing main.rs
+        let res = stage(vec![
+            file("/project", "lib.rs", "pub mod a;"),
+            file("/project", "main.rs", "fn main() { todo!() }"),
+            file("", "LICENSE", "MIT"),
+        ])
+        .await;
+        assert!(matches!(res, Err(CreateFileError::Conflict(_))));
+
+        let res = stage(vec![
+            file("", "LICENSE", "MIT"),
+            file("/nope", "a.txt", "a"),
+        ])
+        .await;
+        assert!(matches!(res, Err(CreateFileError::Internal(_))));
+
+        // overwriting needs the current oid
+        let TreeChange::Write(info, _) = file("/project", "main.rs", "fn main() { todo!() }")
+        else {
+            unreachable!()
+        };
+        let oid = Blob::from_content("fn main() {}").id.to_string();
+        let update = TreeChange::Write(info, WriteCondition::IfMatch(oid));
+        let commit = stage(vec![update]).await.unwrap();
+        assert_eq!(commit.message, "\nupdate file main.rs commit");
+    }
+
+    #[tokio::test]
+    async fn test_stage_delete() {
+        let commit = stage(vec![delete("/README.md")]).await.unwrap();
+        assert_eq!(names(&commit.root), vec!["project"]);
+        assert_eq!(commit.trees.len(), 1);
+        assert_eq!(commit.message, "\ndelete file README.md commit");
+
+        // the last file of a directory takes the directory with it
+        let commit = stage(vec![delete("/project/main.rs")]).await.unwrap();
+        assert_eq!(names(&commit.root), vec!["README.md"]);
+        assert_eq!(commit.trees.len(), 1);
+        let commit = stage(vec![delete("project")]).await.unwrap();
+        assert_eq!(names(&commit.root), vec!["README.md"]);
+        assert_eq!(commit.message, "\ndelete directory project commit");
+
+        for path in ["/LICENSE", "/project/lib.rs", "/nope/a.txt"] {
+            let res = stage(vec![delete(path)]).await;
+            assert!(matches!(res, Err(CreateFileError::NotFound(_))), "{}", path);
+        }
+        let res = stage(vec![delete("/")]).await;
+        assert!(matches!(res, Err(CreateFileError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn test_stage_move() {
+        let readme = Blob::from_content("# mega").id;
+        let commit = stage(vec![rename("/README.md", "/project/README.md", false)])
+            .await
+            .unwrap();
+        assert_eq!(names(&commit.root), vec!["project"]);
+        let project = find(&commit, &commit.root, "project");
+        assert_eq!(names(project), vec!["main.rs", "README.md"]);
+        // same blob, nothing new to save
+        assert_eq!(project.tree_items[1].id, readme);
+        assert!(commit.blobs.is_empty());
+        assert_eq!(
+            commit.message,
+            "\nmove file /README.md to /project/README.md commit"
+        );
+
+        // a directory moves with what the batch staged in it
+        let commit = stage(vec![
+            file("/project", "lib.rs", "pub mod a;"),
+            rename("/project", "/src", false),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(names(&commit.root), vec!["README.md", "src"]);
+        let src = find(&commit, &commit.root, "src");
+        assert_eq!(names(src), vec!["main.rs", "lib.rs"]);
+
+        // onto an existing path
+        let res = stage(vec![rename("/project/main.rs", "/README.md", false)]).await;
+        assert!(matches!(res, Err(CreateFileError::Conflict(_))));
+        let commit = stage(vec![rename("/project/main.rs", "/README.md", true)])
+            .await
+            .unwrap();
+        assert_eq!(names(&commit.root), vec!["README.md"]);
+        let main = Blob::from_content("fn main() {}").id;
+        assert_eq!(commit.root.tree_items[0].id, main);
+
+        for from in ["/LICENSE", "/nope/a.txt"] {
+            let res = stage(vec![rename(from, "/b.txt", false)]).await;
+            assert!(matches!(res, Err(CreateFileError::NotFound(_))), "{}", from);
+        }
+        let res = stage(vec![rename("/README.md", "/nope/README.md", false)]).await;
+        assert!(matches!(res, Err(CreateFileError::Internal(_))));
+        let res = stage(vec![rename("/project", "/project/sub", false)]).await;
+        assert!(matches!(res, Err(CreateFileError::Conflict(_))));
+        let res = stage(vec![rename("/project/main.rs", "/project", true)]).await;
+        assert!(matches!(res, Err(CreateFileError::Conflict(_))));
+    }
+}
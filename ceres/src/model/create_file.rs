@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use mercury::internal::object::tree::{TreeItem, TreeItemMode};
+
 #[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CreateFileInfo {
     /// can be a file or directory
@@ -10,3 +12,155 @@ pub struct CreateFileInfo {
     // pub import_dir: bool,
     pub content: Option<String>,
 }
+
+/// Body of `POST /delete-file`
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeleteFileInfo {
+    /// the file or directory to delete
+    pub path: String,
+}
+
+/// Body of `POST /move-file`
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MoveFileInfo {
+    pub from: String,
+    pub to: String,
+    /// replace what is at `to`, the move fails if it exists otherwise
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// One write of `POST /create-files`, `if_match` plays the role of the `If-Match` header
+/// of `create-file`.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileWrite {
+    #[serde(flatten)]
+    pub file: CreateFileInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub if_match: Option<String>,
+}
+
+impl FileWrite {
+    pub fn condition(&self) -> WriteCondition {
+        match &self.if_match {
+            Some(oid) => WriteCondition::IfMatch(oid.clone()),
+            None => WriteCondition::IfNoneMatch,
+        }
+    }
+}
+
+/// Expected state of the file before a write, taken from the `If-Match` / `If-None-Match` headers.
+///
+/// The oid is the git blob hash of the current content, which the client can compute from
+/// what it loaded.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub enum WriteCondition {
+    /// `If-None-Match: *`, the file must not exist yet. Also applies when no header is sent,
+    /// so a plain create never overwrites an existing file.
+    #[default]
+    IfNoneMatch,
+    /// `If-None-Match: <oid>, ...`, the file must not exist with one of these oids, it is
+    /// created or overwritten otherwise
+    IfNoneMatchAny(Vec<String>),
+    /// `If-Match: <oid>`, the file must exist with this blob oid, `*` matches any oid
+    IfMatch(String),
+}
+
+impl WriteCondition {
+    /// Check the condition against the item currently stored under the file name,
+    /// returns the conflict reason if it doesn't hold.
+    pub fn check(&self, current: Option<&TreeItem>) -> Result<(), String> {
+        match (self, current) {
+            (_, Some(item)) if item.mode == TreeItemMode::Tree => {
+                Err(format!("{} is a directory", item.name))
+            }
+            (WriteCondition::IfNoneMatch, None) => Ok(()),
+            (WriteCondition::IfNoneMatch, Some(item)) => Err(format!(
+                "{} already exists with oid {}, use If-Match to overwrite it",
+                item.name, item.id
+            )),
+            (WriteCondition::IfNoneMatchAny(_), None) => Ok(()),
+            (WriteCondition::IfNoneMatchAny(oids), Some(item)) => {
+                if oids.contains(&item.id.to_string()) {
+                    Err(format!("{} already has oid {}", item.name, item.id))
+                } else {
+                    Ok(())
+                }
+            }
+            (WriteCondition::IfMatch(_), None) => Err("file does not exist".to_string()),
+            (WriteCondition::IfMatch(oid), Some(item)) => {
+                if oid == "*" || *oid == item.id.to_string() {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "{} has been changed, current oid is {}",
+                        item.name, item.id
+                    ))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mercury::{
+        hash::SHA1,
+        internal::object::tree::{TreeItem, TreeItemMode},
+    };
+
+    use super::WriteCondition;
+
+    fn item(mode: TreeItemMode, content: &[u8]) -> TreeItem {
+        TreeItem {
+            mode,
+            id: SHA1::new(content),
+            name: "README.md".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_write_condition() {
+        let current = item(TreeItemMode::Blob, b"v1");
+        let oid = current.id.to_string();
+
+        // conditional write on the latest version
+        assert!(WriteCondition::IfMatch(oid).check(Some(&current)).is_ok());
+        assert!(WriteCondition::IfMatch("*".to_string())
+            .check(Some(&current))
+            .is_ok());
+        // stale oid
+        let stale = SHA1::new(b"v0").to_string();
+        assert!(WriteCondition::IfMatch(stale)
+            .check(Some(&current))
+            .is_err());
+        assert!(WriteCondition::IfMatch("*".to_string())
+            .check(None)
+            .is_err());
+
+        // create
+        assert!(WriteCondition::IfNoneMatch.check(None).is_ok());
+        // create over existing without If-Match
+        assert!(WriteCondition::default().check(Some(&current)).is_err());
+        // overwrite unless it is already at one of the oids
+        let v1 = current.id.to_string();
+        assert!(WriteCondition::IfNoneMatchAny(vec![v1.clone()])
+            .check(None)
+            .is_ok());
+        assert!(
+            WriteCondition::IfNoneMatchAny(vec![SHA1::new(b"v0").to_string()])
+                .check(Some(&current))
+                .is_ok()
+        );
+        assert!(
+            WriteCondition::IfNoneMatchAny(vec![SHA1::new(b"v0").to_string(), v1])
+                .check(Some(&current))
+                .is_err()
+        );
+        // directories are never overwritten
+        let dir = item(TreeItemMode::Tree, b"dir");
+        assert!(WriteCondition::IfMatch("*".to_string())
+            .check(Some(&dir))
+            .is_err());
+    }
+}
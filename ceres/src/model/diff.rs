@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    #[default]
+    Added,
+    Deleted,
+    Modified,
+}
+
+/// One hunk of a unified diff, `start` is 1-based, or the line before the hunk when it has
+/// no line on that side, as git
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    /// lines prefixed by ` `, `-` or `+`, without their line break
+    pub lines: Vec<String>,
+}
+
+/// A file changed between two commits
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub path: String,
+    pub kind: DiffKind,
+    /// blob oid before the change, `None` for an added file, the commit of a submodule
+    pub old_oid: Option<String>,
+    /// blob oid after the change, `None` for a deleted file, the commit of a submodule
+    pub new_oid: Option<String>,
+    /// binary content, or content too large to diff, has no hunks
+    pub binary: bool,
+    pub hunks: Vec<DiffHunk>,
+}
@@ -1,3 +1,4 @@
 pub mod create_file;
+pub mod diff;
 pub mod query;
 pub mod tree;
@@ -9,12 +9,33 @@ pub struct CodePreviewQuery {
     pub path: String,
 }
 
+/// Query of the tree endpoints, which are paginated
+/// - without `per_page`, a page holds `monorepo.default_per_page` items
+#[derive(Debug, Deserialize)]
+pub struct TreeQuery {
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// 1-based
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct BlobContentQuery {
     #[serde(default = "default_path")]
     pub path: String,
 }
 
+/// Query of the diff endpoint, `base` & `head` are commit hashes
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    pub base: String,
+    pub head: String,
+    /// only the changes at or under this path
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
 fn default_path() -> String {
     "/".to_string()
 }
@@ -1,6 +1,11 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
-use mercury::internal::object::tree::{TreeItem, TreeItemMode};
+use mercury::{
+    hash::SHA1,
+    internal::object::tree::{TreeItem, TreeItemMode},
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct LatestCommitInfo {
@@ -73,6 +78,29 @@ impl From<TreeItem> for TreeBriefItem {
     }
 }
 
+/// Blobs referenced by the files of a tree, a blob is stored once however many files have it
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BlobDedupStats {
+    /// distinct blob oids
+    pub unique_blobs: u64,
+    /// files, each one references a blob
+    pub referenced_blobs: u64,
+    /// bytes the files would take if each one had its own copy, minus the stored bytes
+    pub bytes_saved: u64,
+}
+
+impl BlobDedupStats {
+    /// Stats of the blob references `refs`, `sizes` are in bytes, a blob without size counts as empty
+    pub fn new(refs: &HashMap<SHA1, u64>, sizes: &HashMap<SHA1, u64>) -> Self {
+        let size = |id: &SHA1| sizes.get(id).copied().unwrap_or_default();
+        BlobDedupStats {
+            unique_blobs: refs.len() as u64,
+            referenced_blobs: refs.values().sum(),
+            bytes_saved: refs.iter().map(|(id, n)| (n - 1) * size(id)).sum(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MRFileTree {
     pub title: String,
@@ -5,6 +5,7 @@ use std::{
     sync::{
         atomic::{AtomicUsize, Ordering},
         mpsc::Receiver,
+        Arc,
     },
 };
 
@@ -343,8 +344,10 @@ impl ImportRepo {
     // attach import repo to monorepo parent tree
     async fn attach_to_monorepo_parent(&self) -> Result<(), GitError> {
         let path = PathBuf::from(self.repo.repo_path.clone());
+        // only searches & creates trees, the commit below isn't written through it
         let mono_api_service = MonoApiService {
             context: self.context.clone(),
+            latest_commits: Arc::default(),
         };
         let storage = self.context.services.mono_storage.clone();
         let save_trees = mono_api_service.search_and_create_tree(&path).await?;
@@ -99,7 +99,7 @@ impl PackHandler for MonoRepo {
                 commit.committer,
                 tree.id,
                 vec![],
-                &commit.message,
+                commit.message(),
             );
             storage
                 .save_ref(
@@ -153,6 +153,8 @@ pub struct LogConfig {
     pub log_path: PathBuf,
     pub level: String,
     pub print_std: bool,
+    #[serde(default)]
+    pub access_log_format: AccessLogFormat,
 }
 
 impl Default for LogConfig {
@@ -161,10 +163,24 @@ impl Default for LogConfig {
             log_path: PathBuf::from("/tmp/.mega/logs"),
             level: String::from("info"),
             print_std: true,
+            access_log_format: AccessLogFormat::default(),
         }
     }
 }
 
+/// Format of the HTTP access log lines
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// tracing fields: request id, method, path, status, latency & bytes
+    #[default]
+    Structured,
+    /// NCSA Common Log Format
+    Common,
+    /// Common Log Format with referer and user agent
+    Combined,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DbConfig {
     pub db_type: String,
@@ -212,6 +228,27 @@ pub struct MonoConfig {
     pub import_dir: PathBuf,
     pub admin: String,
     pub root_dirs: Vec<String>,
+    /// Maximum request body size of write APIs, Unit is MB
+    #[serde(default = "default_max_request_body_size")]
+    pub max_request_body_size: usize,
+    /// Page size of paginated APIs when the request has none
+    #[serde(default = "default_per_page")]
+    pub default_per_page: u64,
+    /// Largest page size a request may ask for, bigger ones are clamped
+    #[serde(default = "max_per_page")]
+    pub max_per_page: u64,
+}
+
+fn default_max_request_body_size() -> usize {
+    10
+}
+
+fn default_per_page() -> u64 {
+    20
+}
+
+fn max_per_page() -> u64 {
+    100
 }
 
 impl Default for MonoConfig {
@@ -225,6 +262,9 @@ impl Default for MonoConfig {
                 "doc".to_string(),
                 "release".to_string(),
             ],
+            max_request_body_size: default_max_request_body_size(),
+            default_per_page: default_per_page(),
+            max_per_page: max_per_page(),
         }
     }
 }
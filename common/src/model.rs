@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use clap::Args;
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +14,18 @@ pub struct ZtmOptions {
     #[arg(long, default_value_t = 7777)]
     pub ztm_agent_port: u16,
 
+    /// Local address the inbound ends of ZTM tunnels listen on
+    #[arg(long, default_value_t = String::from("127.0.0.1"))]
+    pub ztm_tunnel_host: String,
+
+    /// Largest response body read from a peer, in bytes
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    pub ztm_max_response_size: usize,
+
+    /// Seconds an idle ZTM tunnel is reused for, tunnels are looked up for every request if unset
+    #[arg(long)]
+    pub ztm_tunnel_ttl: Option<u64>,
+
     #[arg(long)]
     pub bootstrap_node: Option<String>,
 
@@ -25,11 +39,27 @@ pub struct InfoRefsParams {
     pub refspec: Option<String>,
 }
 
+/// Machine-readable kind of a failed [CommonResult], so clients don't have to match on `err_message`
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// Generic failure, the code of [CommonResult::failed]
+    Unknown,
+    NotFound,
+    Ambiguous,
+    Validation,
+    Conflict,
+    Internal,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CommonResult<T> {
     pub req_result: bool,
     pub data: Option<T>,
     pub err_message: String,
+    /// Set when `req_result` is false
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
 }
 
 impl<T> CommonResult<T> {
@@ -38,17 +68,67 @@ impl<T> CommonResult<T> {
             req_result: true,
             data,
             err_message: "".to_owned(),
+            code: None,
         }
     }
     pub fn failed(err_message: &str) -> Self {
+        Self::failed_with_code(ErrorCode::Unknown, err_message)
+    }
+    pub fn failed_with_code(code: ErrorCode, err_message: &str) -> Self {
         CommonResult {
             req_result: false,
             data: None,
             err_message: err_message.to_string(),
+            code: Some(code),
+        }
+    }
+
+    /// `Ok` is a success with its value, `Err` a failure with `code` and the error message
+    pub fn from_result<E: Display>(res: Result<T, E>, code: ErrorCode) -> Self {
+        match res {
+            Ok(data) => Self::success(Some(data)),
+            Err(err) => Self::failed_with_code(code, &err.to_string()),
+        }
+    }
+
+    /// `Ok` is a success without data, for calls made for their effect like a deletion
+    pub fn from_effect<U, E: Display>(res: Result<U, E>) -> Self {
+        CommonResult::from(res).and_then(|_| Self::success(None))
+    }
+
+    /// Map the data of a success, a failure is kept as is
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> CommonResult<U> {
+        CommonResult {
+            req_result: self.req_result,
+            data: self.data.map(f),
+            err_message: self.err_message,
+            code: self.code,
+        }
+    }
+
+    /// Chain a fallible step on the data of a success, a failure or a success without data
+    /// is kept as is
+    pub fn and_then<U>(self, f: impl FnOnce(T) -> CommonResult<U>) -> CommonResult<U> {
+        match self.data {
+            Some(data) if self.req_result => f(data),
+            _ => CommonResult {
+                req_result: self.req_result,
+                data: None,
+                err_message: self.err_message,
+                code: self.code,
+            },
         }
     }
 }
 
+/// Errors of a fallible call are unexpected by default, use [CommonResult::from_result]
+/// to tell otherwise
+impl<T, E: Display> From<Result<T, E>> for CommonResult<T> {
+    fn from(res: Result<T, E>) -> Self {
+        Self::from_result(res, ErrorCode::Internal)
+    }
+}
+
 #[derive(Deserialize)]
 pub struct Pagination {
     pub page: u64,
@@ -76,3 +156,89 @@ pub struct CommonPage<T> {
     pub total: u64,
     pub items: Vec<T>,
 }
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_common_result_code() {
+        let res: CommonResult<String> =
+            CommonResult::failed_with_code(ErrorCode::NotFound, "no such mr");
+        assert_eq!(
+            serde_json::to_value(&res).unwrap(),
+            json!({
+                "req_result": false,
+                "data": null,
+                "err_message": "no such mr",
+                "code": "NOT_FOUND",
+            })
+        );
+
+        let res: CommonResult<String> = CommonResult::failed("oops");
+        assert_eq!(res.code, Some(ErrorCode::Unknown));
+        assert_eq!(serde_json::to_value(&res).unwrap()["code"], "UNKNOWN");
+
+        // no code on success, and responses without one still parse
+        let res = CommonResult::success(Some(1));
+        assert!(serde_json::to_value(&res).unwrap().get("code").is_none());
+        let res: CommonResult<i32> =
+            serde_json::from_str(r#"{"req_result":true,"data":1,"err_message":""}"#).unwrap();
+        assert_eq!(res, CommonResult::success(Some(1)));
+    }
+
+    #[test]
+    fn test_common_result_from_result() {
+        let res: CommonResult<i32> = Ok::<_, String>(1).into();
+        assert_eq!(res, CommonResult::success(Some(1)));
+        let res: CommonResult<i32> = Err("broken pipe".to_owned()).into();
+        assert_eq!(
+            res,
+            CommonResult::failed_with_code(ErrorCode::Internal, "broken pipe")
+        );
+        let res: CommonResult<String> = CommonResult::from_effect(Ok::<_, String>(1));
+        assert_eq!(res, CommonResult::success(None));
+        let res: CommonResult<String> = CommonResult::from_effect(Err::<(), _>("locked"));
+        assert_eq!(
+            res,
+            CommonResult::failed_with_code(ErrorCode::Internal, "locked")
+        );
+        let res = CommonResult::from_result(Err::<i32, _>("no such path"), ErrorCode::NotFound);
+        assert_eq!(res.code, Some(ErrorCode::NotFound));
+        assert_eq!(res.err_message, "no such path");
+    }
+
+    #[test]
+    fn test_common_result_combinators() {
+        let res = CommonResult::success(Some(2)).map(|n| n * 10);
+        assert_eq!(res, CommonResult::success(Some(20)));
+        let res = CommonResult::<i32>::failed("oops").map(|n| n * 10);
+        assert_eq!(res, CommonResult::failed("oops"));
+
+        let half = |n: i32| {
+            if n % 2 == 0 {
+                CommonResult::success(Some(n / 2))
+            } else {
+                CommonResult::failed_with_code(ErrorCode::Validation, "odd")
+            }
+        };
+        assert_eq!(
+            CommonResult::success(Some(4)).and_then(half),
+            CommonResult::success(Some(2))
+        );
+        assert_eq!(
+            CommonResult::success(Some(3)).and_then(half),
+            CommonResult::failed_with_code(ErrorCode::Validation, "odd")
+        );
+        assert_eq!(
+            CommonResult::failed_with_code(ErrorCode::NotFound, "gone").and_then(half),
+            CommonResult::failed_with_code(ErrorCode::NotFound, "gone")
+        );
+        assert_eq!(
+            CommonResult::success(None).and_then(half),
+            CommonResult::success(None)
+        );
+    }
+}
@@ -45,19 +45,15 @@ async fn repo_provide(
         .save_alias_mapping(model.clone())
         .await
         .map_err(|_| (StatusCode::BAD_REQUEST, String::from("Invalid Params")))?;
-    let res = match gemini::http::handler::repo_provide(
+    let res = gemini::http::handler::repo_provide(
         bootstrap_node,
         state.inner.context.clone(),
         path,
         alias,
         get_peerid(),
     )
-    .await
-    {
-        Ok(s) => CommonResult::success(Some(s)),
-        Err(err) => CommonResult::failed(err.as_str()),
-    };
-    Ok(Json(res))
+    .await;
+    Ok(Json(res.into()))
 }
 
 async fn repo_fork(
@@ -79,10 +75,7 @@ async fn repo_fork(
         identifier.clone().to_string(),
     )
     .await;
-    let res = match res {
-        Ok(data) => CommonResult::success(Some(data)),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from(res);
 
     //nostr subscribe to Events
     if let Some(bootstrap_node) = state.ztm.bootstrap_node.clone() {
@@ -1,10 +1,11 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::{thread, time};
 
 use axum::routing::get;
-use axum::{http, Router};
+use axum::{http, middleware, Router};
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Args;
 
@@ -17,8 +18,11 @@ use tower_http::trace::TraceLayer;
 use common::model::{CommonOptions, ZtmOptions};
 use gemini::ztm::agent::{run_ztm_client, LocalZTMAgent};
 use jupiter::context::Context;
+use mono::api::handler_cache::HandlerCache;
+use mono::api::idempotency::IdempotencyCache;
 use mono::api::lfs::lfs_router;
-use mono::api::MonoApiServiceState;
+use mono::api::metrics::{self, Metrics};
+use mono::api::{MonoApiServiceState, PageLimits};
 use mono::server::https_server::{get_method_router, post_method_router, AppState};
 
 use crate::api::{github_router, nostr_router, ztm_router, MegaApiServiceState};
@@ -118,22 +122,13 @@ pub async fn app(
     common: CommonOptions,
     ztm: ZtmOptions,
 ) -> Router {
+    let api_handlers = Arc::new(HandlerCache::default());
     let state = AppState {
         host,
         port,
         context: context.clone(),
         common: common.clone(),
-    };
-
-    let mega_api_state = MegaApiServiceState {
-        inner: MonoApiServiceState {
-            context: context.clone(),
-            common: common.clone(),
-            oauth_client: None,
-            store: None,
-        },
-        ztm,
-        port,
+        api_handlers: api_handlers.clone(),
     };
 
     let mono_api_state = MonoApiServiceState {
@@ -141,6 +136,17 @@ pub async fn app(
         common: common.clone(),
         oauth_client: None,
         store: None,
+        api_handlers,
+        metrics: Arc::new(Metrics::default()),
+        page_limits: PageLimits::from(&context.config.monorepo),
+        create_file_outcomes: Arc::new(IdempotencyCache::default()),
+        latest_commits: Arc::default(),
+    };
+    // the caches & metrics are shared with the mono API
+    let mega_api_state = MegaApiServiceState {
+        inner: mono_api_state.clone(),
+        ztm,
+        port,
     };
 
     pub fn mega_routers() -> Router<MegaApiServiceState> {
@@ -160,7 +166,18 @@ pub async fn app(
         )
         .nest(
             "/api/v1/mono",
-            mono::api::api_router::routers().with_state(mono_api_state.clone()),
+            mono::api::api_router::routers(
+                context.config.monorepo.max_request_body_size * 1024 * 1024,
+            )
+            .route_layer(middleware::from_fn_with_state(
+                mono_api_state.metrics.clone(),
+                metrics::track,
+            ))
+            .with_state(mono_api_state.clone()),
+        )
+        .route(
+            "/metrics",
+            get(metrics::render).with_state(mono_api_state.metrics.clone()),
         )
         .nest(
             "/api/v1/mega",
@@ -188,6 +205,9 @@ pub fn check_run_with_ztm(context: Context, ztm: ZtmOptions, http_port: u16) {
                 bootstrap_node.clone()
             );
             let (peer_id, _) = vault::init();
+            gemini::ztm::set_tunnel_host(&ztm.ztm_tunnel_host);
+            gemini::util::set_max_response_size(ztm.ztm_max_response_size);
+            gemini::ztm::set_tunnel_pool_ttl(ztm.ztm_tunnel_ttl.map(time::Duration::from_secs));
             let ztm_agent: LocalZTMAgent = LocalZTMAgent {
                 agent_port: ztm.ztm_agent_port,
             };
@@ -9,7 +9,7 @@ use crate::{
     http::handler::{repo_folk_alias, repo_provide},
     lfs::share_lfs,
     util::{get_git_model_by_path, handle_response},
-    ztm::{agent::LocalZTMAgent, get_or_create_remote_mega_tunnel},
+    ztm::{agent::LocalZTMAgent, get_or_create_remote_mega_tunnel, tunnel_host, tunnel_url},
     LFSInfo, RepoInfo,
 };
 use callisto::ztm_path_mapping;
@@ -382,10 +382,12 @@ async fn download_and_upload_lfs(
 
 async fn download_lfs_by_chunk(local_port: u16, lfs: LFSInfo) {
     tracing::info!("Prepare to download LFS {} by chunks", lfs.file_hash);
-    // fetch chunks info http://localhost:{localport}/objects/{object_id}/chunks
-    let url = format!(
-        "http://localhost:{}/objects/{}/chunks",
-        local_port, lfs.file_hash
+    // fetch chunks info http://{tunnel_host}:{localport}/objects/{object_id}/chunks
+    let host = tunnel_host();
+    let url = tunnel_url(
+        &host,
+        local_port,
+        &format!("objects/{}/chunks", lfs.file_hash),
     );
     let chunk_info = match get(url.clone()).await {
         Ok(response) => {
@@ -430,8 +432,8 @@ async fn download_lfs_by_chunk(local_port: u16, lfs: LFSInfo) {
         chunks.len()
     );
     for (index, chunk) in chunks.iter().enumerate() {
-        // http://localhost:{localport}/objects/{object_id}/chunks
-        let url = format!("http://localhost:{}/objects/{}", local_port, chunk.sub_oid);
+        // http://{tunnel_host}:{localport}/objects/{object_id}
+        let url = tunnel_url(&host, local_port, &format!("objects/{}", chunk.sub_oid));
         let data = match get(url.clone()).await {
             Ok(response) => {
                 if !response.status().is_success() {
@@ -5,6 +5,7 @@ use crate::{
     util::{get_git_model_by_path, repo_alias_to_identifier},
     ztm::{
         agent::share_repo, get_or_create_remote_mega_tunnel, send_get_request_to_peer_by_tunnel,
+        tunnel_host, tunnel_url,
     },
     RepoInfo,
 };
@@ -70,7 +71,7 @@ pub async fn repo_folk_alias(ztm_agent_port: u16, identifier: String) -> Result<
         }
     };
 
-    let msg = format!("http://localhost:{local_port}{path}.git");
+    let msg = tunnel_url(&tunnel_host(), local_port, &format!("{path}.git"));
     Ok(msg)
 }
 
@@ -98,7 +99,8 @@ pub async fn get_git_path_by_alias(
     ztm_agent_port: u16,
 ) -> Result<String, String> {
     let path = format!("api/v1/mega/ztm/alias_to_path?alias={}", alias);
-    let result = match send_get_request_to_peer_by_tunnel(ztm_agent_port, peer_id, path).await {
+    let result = match send_get_request_to_peer_by_tunnel(ztm_agent_port, peer_id, path, None).await
+    {
         Ok(r) => r,
         Err(e) => return Err(e.to_string()),
     };
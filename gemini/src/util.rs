@@ -1,10 +1,56 @@
 use callisto::git_repo;
 use jupiter::context::Context;
+use reqwest::StatusCode;
 use std::{
+    fmt,
     net::TcpListener,
+    sync::atomic::{AtomicUsize, Ordering},
     time::{SystemTime, UNIX_EPOCH},
 };
 
+const P2P_SCHEME: &str = "p2p://";
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Components of a `p2p://` repo identifier.
+///
+/// - alias identifier: `p2p://{peer_id}/{alias}`, `port` is `None` and `path` is the alias
+/// - path identifier: `p2p://{peer_id}/{port}{repo_path}.git`, `path` is the repo path without `.git`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct P2pIdentifier {
+    pub peer_id: String,
+    pub port: Option<u16>,
+    pub path: String,
+}
+
+impl fmt::Display for P2pIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{P2P_SCHEME}{}/{port}{}.git", self.peer_id, self.path),
+            None => write!(f, "{P2P_SCHEME}{}/{}", self.peer_id, self.path),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    InvalidScheme,
+    InvalidPeerId(String),
+    MissingPath,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidScheme => write!(f, "identifier must start with {P2P_SCHEME}"),
+            ParseError::InvalidPeerId(id) => write!(f, "invalid peer id: {id:?}"),
+            ParseError::MissingPath => write!(f, "identifier has no repo path or alias"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 pub fn get_short_peer_id(peer_id: String) -> String {
     if peer_id.len() <= 7 {
         return peer_id;
@@ -30,29 +76,131 @@ pub fn get_utc_timestamp() -> i64 {
         .as_millis() as i64
 }
 
-pub async fn handle_response(
-    request_result: Result<reqwest::Response, reqwest::Error>,
-) -> Result<String, String> {
-    match request_result {
-        Ok(res) => {
-            if res.status().is_success() {
-                Ok(res.text().await.unwrap())
-            } else {
-                Err(res.text().await.unwrap())
+/// Largest response body read by [handle_response], unless set by [set_max_response_size]
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+static MAX_RESPONSE_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_RESPONSE_SIZE);
+
+pub fn max_response_size() -> usize {
+    MAX_RESPONSE_SIZE.load(Ordering::Relaxed)
+}
+
+pub fn set_max_response_size(bytes: usize) {
+    MAX_RESPONSE_SIZE.store(bytes, Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+pub enum ResponseError {
+    /// sending the request or reading the body failed
+    Request(reqwest::Error),
+    /// the server answered with an error status, and this body
+    Status(StatusCode, String),
+    ResponseTooLarge {
+        limit: usize,
+    },
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResponseError::Request(e) => write!(f, "{e}"),
+            ResponseError::Status(_, body) => write!(f, "{body}"),
+            ResponseError::ResponseTooLarge { limit } => {
+                write!(f, "response body exceeds the limit of {limit} bytes")
             }
         }
-        Err(e) => Err(e.to_string()),
     }
 }
 
+impl std::error::Error for ResponseError {}
+
+/// Read the body of a response as text, streaming it so no more than `limit` bytes are buffered.
+/// - `ResponseTooLarge` as soon as the body, or its announced length, exceeds `limit`
+/// - `Status` with the body if the status isn't a success
+pub async fn read_response(
+    request_result: Result<reqwest::Response, reqwest::Error>,
+    limit: usize,
+) -> Result<String, ResponseError> {
+    let mut res = request_result.map_err(ResponseError::Request)?;
+    let too_large = ResponseError::ResponseTooLarge { limit };
+    if res.content_length().is_some_and(|len| len > limit as u64) {
+        return Err(too_large);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await.map_err(ResponseError::Request)? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let text = String::from_utf8_lossy(&body).into_owned();
+    match res.status() {
+        status if status.is_success() => Ok(text),
+        status => Err(ResponseError::Status(status, text)),
+    }
+}
+
+/// The body of a successful response, or the error as text, see [read_response]
+pub async fn handle_response(
+    request_result: Result<reqwest::Response, reqwest::Error>,
+) -> Result<String, String> {
+    read_response(request_result, max_response_size())
+        .await
+        .map_err(|e| e.to_string())
+}
+
 pub fn repo_alias_to_identifier(alias: String) -> String {
     let (peer_id, _) = vault::init();
-    format!("p2p://{}/{alias}", peer_id.clone())
+    P2pIdentifier {
+        peer_id,
+        port: None,
+        path: alias,
+    }
+    .to_string()
 }
 
 pub fn repo_path_to_identifier(http_port: u16, repo_path: String) -> String {
     let (peer_id, _) = vault::init();
-    format!("p2p://{}/{http_port}{repo_path}.git", peer_id.clone())
+    P2pIdentifier {
+        peer_id,
+        port: Some(http_port),
+        path: repo_path,
+    }
+    .to_string()
+}
+
+/// Parse an identifier built by [`repo_alias_to_identifier`] or [`repo_path_to_identifier`].
+pub fn parse_p2p_identifier(identifier: &str) -> Result<P2pIdentifier, ParseError> {
+    let rest = identifier
+        .strip_prefix(P2P_SCHEME)
+        .ok_or(ParseError::InvalidScheme)?;
+    let (peer_id, path) = rest.split_once('/').ok_or(ParseError::MissingPath)?;
+    if peer_id.is_empty() || !peer_id.chars().all(|c| BASE58_ALPHABET.contains(c)) {
+        return Err(ParseError::InvalidPeerId(peer_id.to_string()));
+    }
+    if path.is_empty() {
+        return Err(ParseError::MissingPath);
+    }
+
+    // path identifier: `{port}/{repo_path}.git`
+    if let Some((port, repo_path)) = path.split_once('/') {
+        if let (Ok(port), Some(repo_path)) = (port.parse::<u16>(), repo_path.strip_suffix(".git")) {
+            if repo_path.is_empty() {
+                return Err(ParseError::MissingPath);
+            }
+            return Ok(P2pIdentifier {
+                peer_id: peer_id.to_string(),
+                port: Some(port),
+                path: format!("/{repo_path}"),
+            });
+        }
+    }
+
+    Ok(P2pIdentifier {
+        peer_id: peer_id.to_string(),
+        port: None,
+        path: path.to_string(),
+    })
 }
 
 pub fn get_ztm_app_tunnel_bound_name(remote_peer_id: String) -> String {
@@ -72,3 +220,127 @@ pub async fn get_git_model_by_path(context: Context, path: String) -> Option<git
 
     git_model.unwrap_or_default()
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const PEER_ID: &str = "yfeunFhgJGD83pcB4nXjif9eePeLEmQXP17XjQjFXN4c";
+
+    #[test]
+    fn test_parse_alias_identifier() {
+        let identifier = P2pIdentifier {
+            peer_id: PEER_ID.to_string(),
+            port: None,
+            path: "serde_python".to_string(),
+        };
+        let s = identifier.to_string();
+        assert_eq!(s, format!("p2p://{PEER_ID}/serde_python"));
+        assert_eq!(parse_p2p_identifier(&s).unwrap(), identifier);
+    }
+
+    #[test]
+    fn test_parse_path_identifier() {
+        let s = format!("p2p://{PEER_ID}/8000/third-part/test.git");
+        let identifier = parse_p2p_identifier(&s).unwrap();
+        assert_eq!(identifier.peer_id, PEER_ID);
+        assert_eq!(identifier.port, Some(8000));
+        assert_eq!(identifier.path, "/third-part/test");
+        assert_eq!(identifier.to_string(), s);
+    }
+
+    #[test]
+    fn test_parse_malformed_identifier() {
+        assert_eq!(
+            parse_p2p_identifier("http://peer/alias"),
+            Err(ParseError::InvalidScheme)
+        );
+        assert_eq!(
+            parse_p2p_identifier(&format!("p2p://{PEER_ID}")),
+            Err(ParseError::MissingPath)
+        );
+        assert_eq!(
+            parse_p2p_identifier(&format!("p2p://{PEER_ID}/")),
+            Err(ParseError::MissingPath)
+        );
+        assert_eq!(
+            parse_p2p_identifier("p2p:///alias"),
+            Err(ParseError::InvalidPeerId("".to_string()))
+        );
+        assert_eq!(
+            parse_p2p_identifier("p2p://not-base58!/alias"),
+            Err(ParseError::InvalidPeerId("not-base58!".to_string()))
+        );
+    }
+
+    /// Serve one response of `status` and `body`, announcing its length or chunked
+    async fn serve_once(status: &str, body: Vec<u8>, chunked: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let status = status.to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            let mut response = format!("HTTP/1.1 {status}\r\nconnection: close\r\n").into_bytes();
+            if chunked {
+                response.extend(b"transfer-encoding: chunked\r\n\r\n");
+                for chunk in body.chunks(1024) {
+                    response.extend(format!("{:x}\r\n", chunk.len()).bytes());
+                    response.extend(chunk);
+                    response.extend(b"\r\n");
+                }
+                response.extend(b"0\r\n\r\n");
+            } else {
+                response.extend(format!("content-length: {}\r\n\r\n", body.len()).bytes());
+                response.extend(body);
+            }
+            // the client may hang up once the limit is exceeded
+            let _ = stream.write_all(&response).await;
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_read_response_limit() {
+        let limit = 4096;
+        let body = vec![b'x'; limit];
+        for chunked in [false, true] {
+            let url = serve_once("200 OK", body.clone(), chunked).await;
+            let text = read_response(reqwest::get(url).await, limit).await.unwrap();
+            assert_eq!(text.len(), limit);
+
+            let url = serve_once("200 OK", vec![b'x'; limit + 1], chunked).await;
+            let err = read_response(reqwest::get(url).await, limit)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, ResponseError::ResponseTooLarge { limit: 4096 }),
+                "{:?}",
+                err
+            );
+        }
+
+        let url = serve_once("404 Not Found", b"repo not found".to_vec(), false).await;
+        let err = read_response(reqwest::get(url).await, limit)
+            .await
+            .unwrap_err();
+        match err {
+            ResponseError::Status(status, body) => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(body, "repo not found");
+            }
+            err => panic!("{:?}", err),
+        }
+        let url = serve_once("404 Not Found", b"repo not found".to_vec(), false).await;
+        assert_eq!(
+            handle_response(reqwest::get(url).await).await,
+            Err("repo not found".to_string())
+        );
+    }
+}
@@ -105,6 +105,7 @@ pub trait ZTMAgent {
         provider: String,
         app_name: String,
         bound_name: String,
+        host: String,
         port: u16,
     ) -> Result<String, String>;
 
@@ -332,6 +333,7 @@ impl ZTMAgent for LocalZTMAgent {
         provider: String,
         app_name: String,
         bound_name: String,
+        host: String,
         port: u16,
     ) -> Result<String, String> {
         //POST /api/meshes/{mesh.name}/apps/${provider}/${name}/api/endpoints/{ep}/inbound/{proto}/{name}
@@ -342,7 +344,7 @@ impl ZTMAgent for LocalZTMAgent {
         );
         tracing::info!("create_ztm_app_tunnel_inbound url: {}", url);
         let client = Client::new();
-        let req = format!(r#"{{"listens": [{{"ip":"127.0.0.1","port":{port}}}]}}"#);
+        let req = format!(r#"{{"listens": [{{"ip":"{host}","port":{port}}}]}}"#);
         // let req = r#"{"listens":[{"ip":"127.0.0.1","port":8081}]}"#;
         let request_result = client
             .post(url)
@@ -1,10 +1,22 @@
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
 use agent::{LocalZTMAgent, ZTMAgent};
-use reqwest::{header::CONTENT_TYPE, Client};
+use pool::TunnelPool;
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Client, RequestBuilder,
+};
 
 use crate::util::{get_available_port, get_ztm_app_tunnel_bound_name, handle_response};
 
 pub mod agent;
 pub mod hub;
+pub mod pool;
 
 const MESH_NAME: &str = "relay_mesh";
 
@@ -12,16 +24,99 @@ const ZTM_APP_PROVIDER: &str = "mega";
 
 const ZTM_APP_NAME: &str = "tunnel_punch";
 
+/// Media type of POST bodies and of the expected responses, unless given
+const JSON_MEDIA_TYPE: &str = "application/json";
+
+/// Where the inbound ends of tunnels listen unless set by [set_tunnel_host]
+pub const DEFAULT_TUNNEL_HOST: &str = "127.0.0.1";
+
+static TUNNEL_HOST: RwLock<Option<String>> = RwLock::new(None);
+
+/// Local address the inbound ends of tunnels listen on, requests through a tunnel are sent to it
+pub fn tunnel_host() -> String {
+    TUNNEL_HOST
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT_TUNNEL_HOST.to_string())
+}
+
+pub fn set_tunnel_host(host: &str) {
+    *TUNNEL_HOST.write().unwrap() = Some(host.to_string());
+}
+
+/// Port mega listens on at the remote peers, tunnels to them end there
+const MEGA_REMOTE_PORT: u16 = 8000;
+
+static TUNNEL_POOL: RwLock<Option<Arc<TunnelPool>>> = RwLock::new(None);
+
+/// Reuse tunnels across requests until idle for `ttl`, or look them up on the agent for every
+/// request if `None`, the default
+pub fn set_tunnel_pool_ttl(ttl: Option<Duration>) {
+    *TUNNEL_POOL.write().unwrap() = ttl.map(|ttl| Arc::new(TunnelPool::new(ttl)));
+}
+
+fn tunnel_pool() -> Option<Arc<TunnelPool>> {
+    TUNNEL_POOL.read().unwrap().clone()
+}
+
+/// URL of `path` on the peer at the end of the tunnel listening on `host`:`local_port`
+pub fn tunnel_url(host: &str, local_port: u16, path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    match host.contains(':') {
+        // IPv6
+        true => format!("http://[{host}]:{local_port}/{path}"),
+        false => format!("http://{host}:{local_port}/{path}"),
+    }
+}
+
+/// Credentials of a peer protecting its HTTP endpoints, sent in the `Authorization` header
+#[derive(Clone, PartialEq, Eq)]
+pub enum PeerAuth {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+/// The secret is never printed, so it can't end up in logs
+impl fmt::Debug for PeerAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAuth::Basic { username, .. } => write!(f, "Basic({}, <redacted>)", username),
+            PeerAuth::Bearer(_) => write!(f, "Bearer(<redacted>)"),
+        }
+    }
+}
+
+impl PeerAuth {
+    /// Attach the `Authorization` header, marked sensitive
+    fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            PeerAuth::Basic { username, password } => {
+                request.basic_auth(username, password.as_ref())
+            }
+            PeerAuth::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+fn with_auth(request: RequestBuilder, auth: Option<&PeerAuth>) -> RequestBuilder {
+    match auth {
+        Some(auth) => auth.apply(request),
+        None => request,
+    }
+}
+
 async fn create_tunnel(
-    ztm_agent_port: u16,
+    agent: &(impl ZTMAgent + Sync),
     remote_peer_id: String,
+    local_host: String,
     local_port: u16,
     remote_port: u16,
     bound_name: String,
 ) -> Result<(), String> {
-    let agent: LocalZTMAgent = LocalZTMAgent {
-        agent_port: ztm_agent_port,
-    };
     let local_ep = match agent.get_ztm_local_endpoint().await {
         Ok(ep) => ep,
         Err(e) => return Err(e),
@@ -40,6 +135,7 @@ async fn create_tunnel(
             ZTM_APP_PROVIDER.to_string(),
             ZTM_APP_NAME.to_string(),
             bound_name.clone(),
+            local_host,
             local_port,
         )
         .await
@@ -77,11 +173,42 @@ async fn create_tunnel(
 pub async fn get_or_create_remote_mega_tunnel(
     ztm_agent_port: u16,
     remote_peer_id: String,
+) -> Result<u16, String> {
+    let agent = LocalZTMAgent {
+        agent_port: ztm_agent_port,
+    };
+    let pool = tunnel_pool();
+    get_or_open_tunnel(&agent, pool.as_deref(), remote_peer_id, MEGA_REMOTE_PORT).await
+}
+
+/// Local port of the tunnel to `remote_port` of the peer, reused from `pool` if it has one
+async fn get_or_open_tunnel(
+    agent: &(impl ZTMAgent + Sync),
+    pool: Option<&TunnelPool>,
+    remote_peer_id: String,
+    remote_port: u16,
+) -> Result<u16, String> {
+    match pool {
+        Some(pool) => {
+            pool.get_or_open(&remote_peer_id, remote_port, || {
+                open_tunnel(agent, remote_peer_id.clone(), remote_port)
+            })
+            .await
+        }
+        None => open_tunnel(agent, remote_peer_id, remote_port).await,
+    }
+}
+
+/// Find the tunnel to the peer on the agent, or create it
+async fn open_tunnel(
+    agent: &(impl ZTMAgent + Sync),
+    remote_peer_id: String,
+    remote_port: u16,
 ) -> Result<u16, String> {
     let bound_name = get_ztm_app_tunnel_bound_name(remote_peer_id.clone());
 
     //Check if the tunnel exists
-    let local_port = search_tunnel_inbound_port(ztm_agent_port, bound_name.clone()).await;
+    let local_port = search_tunnel_inbound_port(agent, bound_name.clone()).await;
 
     tracing::debug!(
         "get_or_create_remote_mega_tunnel, local_port exist:{:?}",
@@ -98,10 +225,10 @@ pub async fn get_or_create_remote_mega_tunnel(
                     return Err(e);
                 }
             };
-            let remote_port = 8000;
             match create_tunnel(
-                ztm_agent_port,
+                agent,
                 remote_peer_id.clone(),
+                tunnel_host(),
                 local_port,
                 remote_port,
                 bound_name.clone(),
@@ -135,10 +262,10 @@ pub async fn get_or_create_remote_mega_tunnel(
     Ok(local_port)
 }
 
-async fn search_tunnel_inbound_port(ztm_agent_port: u16, bound_name: String) -> Option<u16> {
-    let agent: LocalZTMAgent = LocalZTMAgent {
-        agent_port: ztm_agent_port,
-    };
+async fn search_tunnel_inbound_port(
+    agent: &(impl ZTMAgent + Sync),
+    bound_name: String,
+) -> Option<u16> {
     let local_ep = match agent.get_ztm_local_endpoint().await {
         Ok(ep) => ep,
         Err(_) => return None,
@@ -159,19 +286,95 @@ pub async fn send_get_request_to_peer_by_tunnel(
     ztm_agent_port: u16,
     remote_peer_id: String,
     path: String,
+    auth: Option<&PeerAuth>,
 ) -> Result<String, String> {
-    let local_port = get_or_create_remote_mega_tunnel(ztm_agent_port, remote_peer_id).await;
+    let agent = LocalZTMAgent {
+        agent_port: ztm_agent_port,
+    };
+    let pool = tunnel_pool();
+    send_by_tunnel(&agent, pool.as_deref(), remote_peer_id, &path, |url| {
+        send_get_request(url, auth)
+    })
+    .await
+}
 
-    let local_port = match local_port {
-        Ok(local_port) => local_port,
-        Err(e) => {
-            return Err(e);
+/// Why a request through a tunnel failed
+#[derive(Debug, PartialEq, Eq)]
+enum SendError {
+    /// nothing listens at the local end of the tunnel, it may have gone
+    Connect(String),
+    /// the peer was reached, but answered an error or nothing usable
+    Response(String),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Connect(e) | SendError::Response(e) => f.write_str(e),
         }
-    };
+    }
+}
+
+impl From<SendError> for String {
+    fn from(e: SendError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Send a request to `path` of the peer with `send`, given the URL of the path through the
+/// tunnel. A tunnel which can't be connected to is dropped from `pool`, and the request is
+/// retried once through a tunnel opened again.
+async fn send_by_tunnel<F, Fut>(
+    agent: &(impl ZTMAgent + Sync),
+    pool: Option<&TunnelPool>,
+    remote_peer_id: String,
+    path: &str,
+    send: F,
+) -> Result<String, String>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<String, SendError>>,
+{
+    let local_port =
+        get_or_open_tunnel(agent, pool, remote_peer_id.clone(), MEGA_REMOTE_PORT).await?;
+    match send(tunnel_url(&tunnel_host(), local_port, path)).await {
+        Err(SendError::Connect(e)) => {
+            tracing::warn!(
+                "tunnel to {} on port {} is unreachable, opening it again: {}",
+                remote_peer_id,
+                local_port,
+                e
+            );
+            if let Some(pool) = pool {
+                pool.remove(&remote_peer_id, MEGA_REMOTE_PORT);
+            }
+            let local_port =
+                get_or_open_tunnel(agent, pool, remote_peer_id, MEGA_REMOTE_PORT).await?;
+            Ok(send(tunnel_url(&tunnel_host(), local_port, path)).await?)
+        }
+        res => Ok(res?),
+    }
+}
 
-    let url = format!("http://127.0.0.1:{local_port}/{path}");
-    let request_result = reqwest::get(url.clone()).await;
-    match handle_response(request_result).await {
+/// Check the request could connect before reading the response
+async fn read_sent(
+    url: &str,
+    request_result: Result<reqwest::Response, reqwest::Error>,
+) -> Result<String, SendError> {
+    match request_result {
+        Err(e) if e.is_connect() => Err(SendError::Connect(format!(
+            "connect to url {} failed: {}",
+            url, e
+        ))),
+        request_result => handle_response(request_result)
+            .await
+            .map_err(SendError::Response),
+    }
+}
+
+async fn send_get_request(url: String, auth: Option<&PeerAuth>) -> Result<String, SendError> {
+    let request_result = with_auth(Client::new().get(url.clone()), auth).send().await;
+    match read_sent(&url, request_result).await {
         Ok(s) => {
             tracing::info!("get response from url {}:\n{}", url, s.clone());
             Ok(s)
@@ -183,32 +386,83 @@ pub async fn send_get_request_to_peer_by_tunnel(
     }
 }
 
+/// Send a GET request to the first peer that answers successfully.
+///
+/// Peers are tried in order, so callers should put the preferred mirror first.
+/// If every peer fails, the returned error lists the failure of each peer.
+pub async fn send_get_to_any_peer(
+    ztm_agent_port: u16,
+    peer_ids: Vec<String>,
+    path: String,
+    auth: Option<&PeerAuth>,
+) -> Result<String, String> {
+    try_peers_in_order(peer_ids, |peer_id| {
+        send_get_request_to_peer_by_tunnel(ztm_agent_port, peer_id, path.clone(), auth)
+    })
+    .await
+}
+
+async fn try_peers_in_order<F, Fut>(peer_ids: Vec<String>, request: F) -> Result<String, String>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<String, String>>,
+{
+    if peer_ids.is_empty() {
+        return Err("no candidate peers to send the request to".to_string());
+    }
+    let mut errors = Vec::new();
+    for peer_id in peer_ids {
+        match request(peer_id.clone()).await {
+            Ok(s) => return Ok(s),
+            Err(e) => {
+                tracing::warn!(
+                    "request to peer {} failed, trying next peer: {}",
+                    peer_id,
+                    e
+                );
+                errors.push(format!("{peer_id}: {e}"));
+            }
+        }
+    }
+    Err(format!("all peers failed: [{}]", errors.join("; ")))
+}
+
+/// POST `body` to `path` of the peer
+/// - `content_type` of the body and the `accept`ed response types are JSON when `None`
 pub async fn send_post_request_to_peer_by_tunnel(
     ztm_agent_port: u16,
     remote_peer_id: String,
     path: String,
     body: String,
+    content_type: Option<&str>,
+    accept: Option<&str>,
+    auth: Option<&PeerAuth>,
 ) -> Result<String, String> {
-    let local_port = get_or_create_remote_mega_tunnel(ztm_agent_port, remote_peer_id).await;
-
-    let local_port = match local_port {
-        Ok(local_port) => local_port,
-        Err(e) => {
-            return Err(e);
-        }
+    let agent = LocalZTMAgent {
+        agent_port: ztm_agent_port,
     };
+    let pool = tunnel_pool();
+    send_by_tunnel(&agent, pool.as_deref(), remote_peer_id, &path, |url| {
+        send_post_request(url, body.clone(), content_type, accept, auth)
+    })
+    .await
+}
 
-    let url = format!("http://127.0.0.1:{local_port}/{path}");
-
+async fn send_post_request(
+    url: String,
+    body: String,
+    content_type: Option<&str>,
+    accept: Option<&str>,
+    auth: Option<&PeerAuth>,
+) -> Result<String, SendError> {
     let client = Client::new();
-
-    let request_result = client
+    let request = client
         .post(url.clone())
-        .header(CONTENT_TYPE, "application/json")
-        .body(body)
-        .send()
-        .await;
-    match handle_response(request_result).await {
+        .header(CONTENT_TYPE, content_type.unwrap_or(JSON_MEDIA_TYPE))
+        .header(ACCEPT, accept.unwrap_or(JSON_MEDIA_TYPE))
+        .body(body);
+    let request_result = with_auth(request, auth).send().await;
+    match read_sent(&url, request_result).await {
         Ok(s) => {
             tracing::info!("post response from url {}:\n{}", url, s.clone());
             Ok(s)
@@ -219,3 +473,356 @@ pub async fn send_post_request_to_peer_by_tunnel(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use axum::async_trait;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{
+        agent::{ZTMEndPoint, ZTMMesh},
+        hub::ZTMUserPermit,
+        *,
+    };
+
+    async fn mock_peer_request(peer_id: String) -> Result<String, String> {
+        match peer_id.as_str() {
+            "peer_ok" => Ok("repo info".to_string()),
+            _ => Err("500 Internal Server Error".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_try_peers_in_order_falls_back() {
+        let peers = vec!["peer_err".to_string(), "peer_ok".to_string()];
+        let res = try_peers_in_order(peers, mock_peer_request).await;
+        assert_eq!(res, Ok("repo info".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_try_peers_in_order_aggregates_errors() {
+        let peers = vec!["peer_a".to_string(), "peer_b".to_string()];
+        let err = try_peers_in_order(peers, mock_peer_request)
+            .await
+            .unwrap_err();
+        assert!(err.contains("peer_a: 500 Internal Server Error"));
+        assert!(err.contains("peer_b: 500 Internal Server Error"));
+
+        assert!(try_peers_in_order(vec![], mock_peer_request).await.is_err());
+    }
+
+    /// Log output, shared with the subscriber writing it
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Serve one request with `ok`, returning the URL and the request head as received
+    async fn serve_once() -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/api/v1/mega/ztm/repo_provide",
+            listener.local_addr().unwrap()
+        );
+        let handle = tokio::spawn(async move { respond_ok(&listener).await });
+        (url, handle)
+    }
+
+    /// Answer the next request on `listener` with `ok`, returning its head
+    async fn respond_ok(listener: &TcpListener) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !head.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed within the request head");
+            head.extend_from_slice(&buf[..n]);
+        }
+        let response = "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok";
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(head).unwrap()
+    }
+
+    /// Value of the header `name` in the request head
+    fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+        head.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    fn authorization(head: &str) -> Option<&str> {
+        header(head, "authorization")
+    }
+
+    #[tokio::test]
+    async fn test_auth_header_attached_not_logged() {
+        let logs = CapturedLogs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let basic = PeerAuth::Basic {
+            username: "user".to_string(),
+            password: Some("s3cret".to_string()),
+        };
+        let bearer = PeerAuth::Bearer("tok-123".to_string());
+        let cases = [
+            (Some(&basic), Some("Basic dXNlcjpzM2NyZXQ=")),
+            (Some(&bearer), Some("Bearer tok-123")),
+            (None, None),
+        ];
+        for (auth, expected) in cases {
+            let (url, server) = serve_once().await;
+            assert_eq!(send_get_request(url, auth).await, Ok("ok".to_string()));
+            assert_eq!(authorization(&server.await.unwrap()), expected);
+
+            let (url, server) = serve_once().await;
+            let body = "{}".to_string();
+            assert_eq!(
+                send_post_request(url, body, None, None, auth).await,
+                Ok("ok".to_string())
+            );
+            assert_eq!(authorization(&server.await.unwrap()), expected);
+            tracing::debug!("sent with {:?}", auth);
+        }
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("get response from url"), "{}", logs);
+        assert!(logs.contains("post response from url"), "{}", logs);
+        for secret in ["s3cret", "dXNlcjpzM2NyZXQ=", "tok-123"] {
+            assert!(!logs.contains(secret), "{} leaked in:\n{}", secret, logs);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_media_types() {
+        // JSON unless given
+        let (url, server) = serve_once().await;
+        let body = r#"{"alias":"mega"}"#.to_string();
+        send_post_request(url, body, None, None, None)
+            .await
+            .unwrap();
+        let head = server.await.unwrap();
+        assert_eq!(header(&head, "content-type"), Some("application/json"));
+        assert_eq!(header(&head, "accept"), Some("application/json"));
+
+        let (url, server) = serve_once().await;
+        let body = "alias=mega&path=%2Fproject".to_string();
+        let content_type = Some("application/x-www-form-urlencoded");
+        send_post_request(url, body, content_type, Some("text/plain"), None)
+            .await
+            .unwrap();
+        let head = server.await.unwrap();
+        assert_eq!(header(&head, "content-type"), content_type);
+        assert_eq!(header(&head, "accept"), Some("text/plain"));
+    }
+
+    /// Agent recording the listen address of the inbounds it creates
+    #[derive(Default)]
+    struct MockAgent {
+        inbounds: Mutex<Vec<(String, u16)>>,
+    }
+
+    fn endpoint(id: &str) -> ZTMEndPoint {
+        ZTMEndPoint {
+            id: id.to_string(),
+            username: id.to_string(),
+            name: id.to_string(),
+            online: true,
+            is_local: id == "local",
+        }
+    }
+
+    #[async_trait]
+    impl ZTMAgent for MockAgent {
+        async fn connect_ztm_hub(&self, _: ZTMUserPermit) -> Result<ZTMMesh, String> {
+            Err("not supported by the mock agent".to_string())
+        }
+        async fn get_ztm_endpoints(&self) -> Result<Vec<ZTMEndPoint>, String> {
+            Ok(vec![endpoint("local"), endpoint("remote")])
+        }
+        async fn get_ztm_local_endpoint(&self) -> Result<ZTMEndPoint, String> {
+            Ok(endpoint("local"))
+        }
+        async fn get_ztm_remote_endpoint(&self, _: String) -> Result<ZTMEndPoint, String> {
+            Ok(endpoint("remote"))
+        }
+        async fn create_ztm_service(&self, _: String, _: String, _: u16) -> Result<String, String> {
+            Err("not supported by the mock agent".to_string())
+        }
+        async fn create_ztm_port(&self, _: String, _: String, _: u16) -> Result<String, String> {
+            Err("not supported by the mock agent".to_string())
+        }
+        async fn start_ztm_app(&self, _: String, _: String, _: String) -> Result<String, String> {
+            Err("not supported by the mock agent".to_string())
+        }
+        async fn create_ztm_app_tunnel_inbound(
+            &self,
+            ep_id: String,
+            _: String,
+            _: String,
+            _: String,
+            host: String,
+            port: u16,
+        ) -> Result<String, String> {
+            assert_eq!(ep_id, "local");
+            self.inbounds.lock().unwrap().push((host, port));
+            Ok(String::new())
+        }
+        async fn get_ztm_app_tunnel_inbound_port(
+            &self,
+            _: String,
+            _: String,
+            _: String,
+            _: String,
+        ) -> Option<u16> {
+            None
+        }
+        async fn create_ztm_app_tunnel_outbound(
+            &self,
+            ep_id: String,
+            _: String,
+            _: String,
+            _: String,
+            _: u16,
+        ) -> Result<String, String> {
+            assert_eq!(ep_id, "remote");
+            Ok(String::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_host() {
+        assert_eq!(tunnel_host(), DEFAULT_TUNNEL_HOST);
+
+        let host = "10.0.0.5";
+        let agent = MockAgent::default();
+        create_tunnel(
+            &agent,
+            "peer".to_string(),
+            host.to_string(),
+            9000,
+            8000,
+            "bound".to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*agent.inbounds.lock().unwrap(), [(host.to_string(), 9000)]);
+        assert_eq!(
+            tunnel_url(host, 9000, "api/v1/mega/ztm/repo_provide"),
+            "http://10.0.0.5:9000/api/v1/mega/ztm/repo_provide"
+        );
+        assert_eq!(
+            tunnel_url("::1", 9000, "/project/mega.git"),
+            "http://[::1]:9000/project/mega.git"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pooled_tunnel_reused() {
+        let agent = MockAgent::default();
+        let pool = TunnelPool::new(Duration::from_secs(60));
+        let path = "api/v1/mega/ztm/repo_provide";
+
+        // the peer behind the tunnel is served on its local port
+        let mut listener: Option<TcpListener> = None;
+        for _ in 0..2 {
+            let port = get_or_open_tunnel(&agent, Some(&pool), "peer".to_string(), 8000)
+                .await
+                .unwrap();
+            if listener.is_none() {
+                listener = Some(TcpListener::bind(("127.0.0.1", port)).await.unwrap());
+            }
+            let url = tunnel_url(DEFAULT_TUNNEL_HOST, port, path);
+            let (res, _) = tokio::join!(
+                send_get_request(url, None),
+                respond_ok(listener.as_ref().unwrap())
+            );
+            assert_eq!(res, Ok("ok".to_string()));
+        }
+        assert_eq!(agent.inbounds.lock().unwrap().len(), 1);
+
+        // without a pool every request opens its tunnel again
+        let agent = MockAgent::default();
+        for _ in 0..2 {
+            get_or_open_tunnel(&agent, None, "peer".to_string(), 8000)
+                .await
+                .unwrap();
+        }
+        assert_eq!(agent.inbounds.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_tunnel_reopened() {
+        let agent = MockAgent::default();
+        let pool = TunnelPool::new(Duration::from_secs(60));
+        let stale = get_or_open_tunnel(&agent, Some(&pool), "peer".to_string(), 8000)
+            .await
+            .unwrap();
+        // nothing listens on the port of the pooled tunnel anymore
+        let url = tunnel_url(DEFAULT_TUNNEL_HOST, stale, "api/v1/mega/ztm/repo_provide");
+        assert!(matches!(
+            send_get_request(url, None).await,
+            Err(SendError::Connect(_))
+        ));
+
+        let urls = Mutex::new(Vec::new());
+        let res = send_by_tunnel(&agent, Some(&pool), "peer".to_string(), "path", |url| {
+            let mut urls = urls.lock().unwrap();
+            urls.push(url.clone());
+            let res = match urls.len() {
+                1 => Err(SendError::Connect("refused".to_string())),
+                _ => Ok(url),
+            };
+            async move { res }
+        })
+        .await;
+        let urls = urls.into_inner().unwrap();
+        assert_eq!(urls.len(), 2);
+        assert_eq!(urls[0], tunnel_url(DEFAULT_TUNNEL_HOST, stale, "path"));
+        assert_eq!(res.as_ref(), Ok(&urls[1]));
+        // the tunnel opened again is pooled instead
+        assert_eq!(agent.inbounds.lock().unwrap().len(), 2);
+        let port = get_or_open_tunnel(&agent, Some(&pool), "peer".to_string(), 8000)
+            .await
+            .unwrap();
+        assert_eq!(tunnel_url(DEFAULT_TUNNEL_HOST, port, "path"), urls[1]);
+
+        // an error answered by the peer isn't retried
+        let calls = AtomicUsize::new(0);
+        let res = send_by_tunnel(&agent, Some(&pool), "peer".to_string(), "path", |_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(SendError::Response("404 Not Found".to_string())) }
+        })
+        .await;
+        assert_eq!(res, Err("404 Not Found".to_string()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
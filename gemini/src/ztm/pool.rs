@@ -0,0 +1,142 @@
+//! Reuse of ZTM tunnels across requests to the same peer, so the agent isn't asked to look up
+//! or create a tunnel for every request.
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Local ports of the open tunnels, keyed by `(peer_id, remote_port)`.
+/// - a tunnel unused for longer than the TTL is reaped, the next request opens one again
+/// - reaping only forgets the tunnel, the inbound stays on the agent and is found again on a miss
+pub struct TunnelPool {
+    ttl: Duration,
+    tunnels: Mutex<HashMap<(String, u16), PooledTunnel>>,
+}
+
+struct PooledTunnel {
+    local_port: u16,
+    last_used: Instant,
+}
+
+impl TunnelPool {
+    pub fn new(ttl: Duration) -> Self {
+        TunnelPool {
+            ttl,
+            tunnels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Local port of the tunnel to `remote_port` of the peer, opened by `open` only on a miss.
+    /// Concurrent misses of the same tunnel may each open it, the last one is kept.
+    pub async fn get_or_open<F, Fut>(
+        &self,
+        peer_id: &str,
+        remote_port: u16,
+        open: F,
+    ) -> Result<u16, String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<u16, String>>,
+    {
+        self.reap();
+        let key = (peer_id.to_string(), remote_port);
+        if let Some(tunnel) = self.tunnels.lock().unwrap().get_mut(&key) {
+            tunnel.last_used = Instant::now();
+            tracing::debug!("reuse tunnel to {}:{}", peer_id, remote_port);
+            return Ok(tunnel.local_port);
+        }
+
+        // not locked while opening, it takes several requests to the agent
+        let local_port = open().await?;
+        self.tunnels.lock().unwrap().insert(
+            key,
+            PooledTunnel {
+                local_port,
+                last_used: Instant::now(),
+            },
+        );
+        Ok(local_port)
+    }
+
+    /// Forget the tunnel, e.g. after a request through it couldn't connect
+    pub fn remove(&self, peer_id: &str, remote_port: u16) {
+        self.tunnels
+            .lock()
+            .unwrap()
+            .remove(&(peer_id.to_string(), remote_port));
+    }
+
+    /// Forget the tunnels idle for longer than the TTL, returning how many were reaped
+    pub fn reap(&self) -> usize {
+        let mut tunnels = self.tunnels.lock().unwrap();
+        let before = tunnels.len();
+        tunnels.retain(|_, tunnel| tunnel.last_used.elapsed() <= self.ttl);
+        before - tunnels.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tunnels.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_open_on_miss_only() {
+        let pool = TunnelPool::new(Duration::from_secs(60));
+        let opened = AtomicU16::new(0);
+        let open = || async { Ok(9000 + opened.fetch_add(1, Ordering::SeqCst)) };
+
+        assert_eq!(pool.get_or_open("peer", 8000, open).await, Ok(9000));
+        assert_eq!(pool.get_or_open("peer", 8000, open).await, Ok(9000));
+        // another port or peer is another tunnel
+        assert_eq!(pool.get_or_open("peer", 8001, open).await, Ok(9001));
+        assert_eq!(pool.get_or_open("other", 8000, open).await, Ok(9002));
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+        assert_eq!(pool.len(), 3);
+
+        // a failed open isn't pooled
+        let failed = pool
+            .get_or_open("down", 8000, || async { Err("offline".to_string()) })
+            .await;
+        assert_eq!(failed, Err("offline".to_string()));
+        assert_eq!(pool.len(), 3);
+
+        pool.remove("peer", 8000);
+        assert_eq!(pool.get_or_open("peer", 8000, open).await, Ok(9003));
+    }
+
+    #[tokio::test]
+    async fn test_reap_idle() {
+        let pool = TunnelPool::new(Duration::from_millis(50));
+        pool.get_or_open("peer", 8000, || async { Ok(9000) })
+            .await
+            .unwrap();
+        assert_eq!(pool.reap(), 0);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pool.reap(), 1);
+        assert!(pool.is_empty());
+
+        // reaped on access too
+        pool.get_or_open("peer", 8000, || async { Ok(9000) })
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let port = pool.get_or_open("peer", 8000, || async { Ok(9001) }).await;
+        assert_eq!(port, Ok(9001));
+    }
+}
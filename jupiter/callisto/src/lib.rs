@@ -21,6 +21,7 @@ pub mod mega_issue;
 pub mod mega_mr;
 pub mod mega_conversation;
 pub mod mega_refs;
+pub mod mega_snapshot;
 pub mod mega_tag;
 pub mod mega_tree;
 pub mod mq_storage;
@@ -18,6 +18,7 @@ pub use crate::mega_issue::Entity as MegaIssue;
 pub use crate::mega_mr::Entity as MegaMr;
 pub use crate::mega_conversation::Entity as MegaMrConv;
 pub use crate::mega_refs::Entity as MegaRefs;
+pub use crate::mega_snapshot::Entity as MegaSnapshot;
 pub use crate::mega_tag::Entity as MegaTag;
 pub use crate::mega_tree::Entity as MegaTree;
 pub use crate::raw_blob::Entity as RawBlob;
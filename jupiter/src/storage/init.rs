@@ -1,6 +1,6 @@
 use std::{path::Path, time::Duration};
 
-use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbErr, Statement, TransactionError, TransactionTrait};
+use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseBackend, DatabaseConnection, DbErr, Statement, TransactionError, TransactionTrait};
 use tracing::log;
 
 use common::config::DbConfig;
@@ -41,11 +41,13 @@ pub async fn database_connection(db_config: &DbConfig) -> DatabaseConnection {
         log::info!("Setting up sqlite database");
         setup_sql(&conn).await.expect("Failed to setup sqlite database");
     }
+    // the init script only sets up a new database, the tables added since are created here
+    migrate(&conn).await.expect("Failed to migrate database");
     conn
 }
 
 /// create table from .sql file
-async fn setup_sql(conn: &DatabaseConnection) -> Result<(), TransactionError<DbErr>> {
+pub(crate) async fn setup_sql(conn: &DatabaseConnection) -> Result<(), TransactionError<DbErr>> {
     conn.transaction::<_, _, DbErr>(|txn| {
         Box::pin(async move {
             let backend = txn.get_database_backend();
@@ -59,6 +61,33 @@ async fn setup_sql(conn: &DatabaseConnection) -> Result<(), TransactionError<DbE
     .await
 }
 
+/// Tables added after the init scripts, in the order they were added: each `.sql` is
+/// idempotent, so they all run on every start
+const SQLITE_MIGRATIONS: &[&str] = &[include_str!(
+    "../../../sql/sqlite/sqlite_20261016_mega_snapshot.sql"
+)];
+const POSTGRES_MIGRATIONS: &[&str] = &[include_str!(
+    "../../../sql/postgres/pg_20261016_mega_snapshot.sql"
+)];
+
+/// Bring a database set up by an older init script up to date
+pub(crate) async fn migrate(conn: &DatabaseConnection) -> Result<(), TransactionError<DbErr>> {
+    conn.transaction::<_, _, DbErr>(|txn| {
+        Box::pin(async move {
+            let backend = txn.get_database_backend();
+            let migrations = match backend {
+                DatabaseBackend::Postgres => POSTGRES_MIGRATIONS,
+                _ => SQLITE_MIGRATIONS,
+            };
+            for migration in migrations {
+                txn.execute(Statement::from_string(backend, *migration)).await?;
+            }
+            Ok(())
+        })
+    })
+    .await
+}
+
 fn is_file_empty(path: &str) -> bool {
     let metadata = std::fs::metadata(path).unwrap();
     metadata.len() == 0
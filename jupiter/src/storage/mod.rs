@@ -70,3 +70,23 @@ where
     futures::future::join_all(results).await;
     Ok(())
 }
+
+/// [batch_save_model_with_conflict] one chunk after the other, stopping at the first error
+/// instead of ignoring it, so it's safe to run on a transaction
+pub async fn batch_upsert_model<E, A>(
+    connection: &impl ConnectionTrait,
+    save_models: Vec<A>,
+    onconflict: OnConflict,
+) -> Result<(), MegaError>
+where
+    E: EntityTrait,
+    A: ActiveModelTrait<Entity = E> + From<<E as EntityTrait>::Model> + Send,
+{
+    for chunk in save_models.chunks(1000) {
+        E::insert_many(chunk.iter().cloned())
+            .on_conflict(onconflict.clone())
+            .exec(connection)
+            .await?;
+    }
+    Ok(())
+}
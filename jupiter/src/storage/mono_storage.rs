@@ -1,8 +1,9 @@
 use std::sync::{Arc, Mutex};
 
 use futures::{stream, StreamExt};
+use sea_orm::sea_query::Expr;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder, QuerySelect
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder, QuerySelect, TransactionTrait
 };
 
 use callisto::{mega_blob, mega_commit, mega_refs, mega_tag, mega_tree, raw_blob};
@@ -134,6 +135,48 @@ impl MonoStorage {
         Ok(())
     }
 
+    /// Move `refs` to its new commit & tree only if it still points at `expected_commit`,
+    /// and drop the refs of `stale_paths` in the same transaction.
+    /// Returns false, changing nothing, if another writer moved it first.
+    pub async fn update_ref_if(
+        &self,
+        refs: mega_refs::Model,
+        expected_commit: &str,
+        stale_paths: &[String],
+    ) -> Result<bool, MegaError> {
+        let txn = self.get_connection().begin().await?;
+        if !stale_paths.is_empty() {
+            mega_refs::Entity::delete_many()
+                .filter(mega_refs::Column::Path.is_in(stale_paths.iter().cloned()))
+                .filter(mega_refs::Column::RefName.eq(MEGA_BRANCH_NAME.to_owned()))
+                .exec(&txn)
+                .await?;
+        }
+        let updated = mega_refs::Entity::update_many()
+            .col_expr(
+                mega_refs::Column::RefCommitHash,
+                Expr::value(refs.ref_commit_hash),
+            )
+            .col_expr(
+                mega_refs::Column::RefTreeHash,
+                Expr::value(refs.ref_tree_hash),
+            )
+            .col_expr(
+                mega_refs::Column::UpdatedAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
+            .filter(mega_refs::Column::Id.eq(refs.id))
+            .filter(mega_refs::Column::RefCommitHash.eq(expected_commit))
+            .exec(&txn)
+            .await?;
+        if updated.rows_affected != 1 {
+            // dropping the transaction rolls the deletes back
+            return Ok(false);
+        }
+        txn.commit().await?;
+        Ok(true)
+    }
+
     pub async fn save_entry(
         &self,
         commit_id: &str,
@@ -298,4 +341,35 @@ impl MonoStorage {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+    use crate::utils::test_db::test_db;
+
+    #[tokio::test]
+    async fn test_update_ref_if() {
+        let storage = MonoStorage::new(Arc::new(test_db().await)).await;
+        storage.save_ref("/", None, "c1", "t1").await.unwrap();
+        let current = storage.get_ref("/").await.unwrap().unwrap();
+
+        // two writers read the ref at c1, the second one loses
+        let moved_to = |commit: &str, tree: &str| mega_refs::Model {
+            ref_commit_hash: commit.to_owned(),
+            ref_tree_hash: tree.to_owned(),
+            ..current.clone()
+        };
+        let (first, second) = (moved_to("c2", "t2"), moved_to("c3", "t3"));
+        storage.save_ref("/a", None, "c1", "t1a").await.unwrap();
+        storage.save_ref("/b", None, "c1", "t1b").await.unwrap();
+        let stale_a = ["/a".to_owned()];
+        let stale_b = ["/b".to_owned()];
+        assert!(storage.update_ref_if(first, "c1", &stale_a).await.unwrap());
+        assert!(!storage.update_ref_if(second, "c1", &stale_b).await.unwrap());
+
+        let moved = storage.get_ref("/").await.unwrap().unwrap();
+        assert_eq!(moved.ref_commit_hash, "c2");
+        assert_eq!(moved.ref_tree_hash, "t2");
+        // the loser's deletes are rolled back with its update
+        assert!(storage.get_ref("/a").await.unwrap().is_none());
+        assert!(storage.get_ref("/b").await.unwrap().is_some());
+    }
+}
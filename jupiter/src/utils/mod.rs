@@ -1,2 +1,6 @@
 pub mod converter;
 pub mod id_generator;
+pub mod snapshot_sync;
+pub mod snapshot_tree;
+#[cfg(test)]
+pub mod test_db;
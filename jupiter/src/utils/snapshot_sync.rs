@@ -0,0 +1,330 @@
+//! Flatten the tree of a commit into `mega_snapshot` rows, one per directory & file, the
+//! reverse of [build_tree_from_snapshots](super::snapshot_tree::build_tree_from_snapshots).
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, QueryFilter};
+
+use callisto::mega_snapshot::{self, SubTrees};
+use callisto::raw_blob;
+use common::errors::MegaError;
+use common::utils::generate_id;
+use mercury::hash::SHA1;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+
+use crate::storage::batch_upsert_model;
+use crate::storage::mono_storage::MonoStorage;
+use crate::utils::snapshot_tree::ROOT_PATH;
+
+/// Write the rows of all the paths of `commit`, replacing those already saved for it, and return
+/// how many there are. A directory lists its entries in `sub_trees` and its size is the sum of
+/// theirs, a file has its blob in `tree_id` and the size of its content.
+/// - the commit, its trees & blobs must be saved by `storage`, the rows are written to `db`
+pub async fn sync_commit_to_snapshots(
+    db: &impl ConnectionTrait,
+    storage: &MonoStorage,
+    commit: SHA1,
+) -> Result<usize, MegaError> {
+    let commit_model = storage
+        .get_commit_by_hash(&commit.to_string())
+        .await?
+        .ok_or_else(|| MegaError::with_message(&format!("commit {} not found", commit)))?;
+    let root = SHA1::from_str(&commit_model.tree).map_err(|e| MegaError::with_message(&e))?;
+
+    let trees = load_trees(storage, root).await?;
+    let blob_ids: HashSet<String> = trees
+        .values()
+        .flat_map(|tree| &tree.tree_items)
+        .filter(|item| item.mode != TreeItemMode::Tree)
+        .map(|item| item.id.to_string())
+        .collect();
+    let sizes = blob_sizes(db, blob_ids.into_iter().collect()).await?;
+
+    let mut rows = Vec::new();
+    snapshot_rows(
+        &commit.to_string(),
+        ROOT_PATH,
+        &trees[&root],
+        &trees,
+        &sizes,
+        &mut rows,
+    );
+    let count = rows.len();
+    upsert_snapshots(db, rows).await?;
+    Ok(count)
+}
+
+/// Insert `rows`, replacing the directory or file saved at the same path of the same commit.
+/// A replaced row keeps its `id` & `created_at`.
+/// - nothing is written if a row has an invalid shape, `insert_many` skips the check of
+///   `before_save`
+pub async fn upsert_snapshots(
+    db: &impl ConnectionTrait,
+    rows: Vec<mega_snapshot::Model>,
+) -> Result<(), MegaError> {
+    for row in &rows {
+        if let Err(e) = row.validate_shape() {
+            return Err(MegaError::with_message(&format!(
+                "invalid mega_snapshot row {}: {}",
+                row.path, e
+            )));
+        }
+    }
+    let upsert =
+        OnConflict::columns([mega_snapshot::Column::CommitId, mega_snapshot::Column::Path])
+            .update_columns([
+                mega_snapshot::Column::ImportDir,
+                mega_snapshot::Column::TreeId,
+                mega_snapshot::Column::SubTrees,
+                mega_snapshot::Column::Size,
+            ])
+            .to_owned();
+    let rows = rows
+        .into_iter()
+        .map(IntoActiveModel::into_active_model)
+        .collect();
+    batch_upsert_model(db, rows, upsert).await
+}
+
+/// All the trees under `root` included, loaded one level at a time
+async fn load_trees(storage: &MonoStorage, root: SHA1) -> Result<HashMap<SHA1, Tree>, MegaError> {
+    let mut trees = HashMap::new();
+    let mut level = vec![root];
+    while !level.is_empty() {
+        let hashes = level.iter().map(SHA1::to_string).collect();
+        for model in storage.get_trees_by_hashes(hashes).await? {
+            let tree = Tree::from(model);
+            trees.insert(tree.id, tree);
+        }
+        if let Some(missing) = level.iter().find(|id| !trees.contains_key(*id)) {
+            return Err(MegaError::with_message(&format!(
+                "tree {} not found",
+                missing
+            )));
+        }
+        let mut next: Vec<SHA1> = level
+            .iter()
+            .flat_map(|id| &trees[id].tree_items)
+            .filter(|item| item.mode == TreeItemMode::Tree && !trees.contains_key(&item.id))
+            .map(|item| item.id)
+            .collect();
+        next.sort();
+        next.dedup();
+        level = next;
+    }
+    Ok(trees)
+}
+
+/// Size of the content of the blobs saved in the database, a blob stored elsewhere is missing
+pub async fn blob_sizes(
+    db: &impl ConnectionTrait,
+    hashes: Vec<String>,
+) -> Result<HashMap<String, i64>, MegaError> {
+    let mut sizes = HashMap::new();
+    for chunk in hashes.chunks(1000) {
+        let blobs = raw_blob::Entity::find()
+            .filter(raw_blob::Column::Sha1.is_in(chunk.to_vec()))
+            .all(db)
+            .await?;
+        for blob in blobs {
+            let size = match (&blob.data, &blob.content) {
+                (Some(data), _) => data.len(),
+                (None, Some(content)) => content.len(),
+                (None, None) => continue,
+            };
+            sizes.insert(blob.sha1, size as i64);
+        }
+    }
+    Ok(sizes)
+}
+
+/// Push the row of the directory `path` after those of its entries, returning its size
+fn snapshot_rows(
+    commit_id: &str,
+    path: &str,
+    tree: &Tree,
+    trees: &HashMap<SHA1, Tree>,
+    sizes: &HashMap<String, i64>,
+    rows: &mut Vec<mega_snapshot::Model>,
+) -> i64 {
+    let mut entries = Vec::new();
+    let mut dir_size = 0;
+    for item in &tree.tree_items {
+        let entry = child_path(path, &item.name);
+        let size = if item.mode == TreeItemMode::Tree {
+            snapshot_rows(commit_id, &entry, &trees[&item.id], trees, sizes, rows)
+        } else {
+            // a file, or a submodule pointing at its commit
+            let size = sizes.get(&item.id.to_string()).copied().unwrap_or(0);
+            rows.push(snapshot_row(commit_id, &entry, false, item.id, None, size));
+            size
+        };
+        dir_size += size;
+        entries.push(entry);
+    }
+    let sub_trees = Some(SubTrees(entries));
+    rows.push(snapshot_row(
+        commit_id, path, true, tree.id, sub_trees, dir_size,
+    ));
+    dir_size
+}
+
+/// Path of the entry `name` of the directory `dir`
+pub fn child_path(dir: &str, name: &str) -> String {
+    match dir {
+        ROOT_PATH => format!("/{}", name),
+        _ => format!("{}/{}", dir, name),
+    }
+}
+
+/// A new row, `id` is the tree of a directory, the blob of a file or the commit of a submodule
+pub fn snapshot_row(
+    commit_id: &str,
+    path: &str,
+    import_dir: bool,
+    id: SHA1,
+    sub_trees: Option<SubTrees>,
+    size: i64,
+) -> mega_snapshot::Model {
+    mega_snapshot::Model {
+        id: generate_id(),
+        commit_id: commit_id.to_owned(),
+        path: path.to_owned(),
+        import_dir: Some(import_dir),
+        tree_id: Some(id.to_string()),
+        sub_trees,
+        size,
+        created_at: chrono::Utc::now().naive_utc(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use sea_orm::QueryOrder;
+
+    use callisto::mega_tree;
+    use mercury::internal::object::blob::Blob;
+    use mercury::internal::object::commit::Commit;
+    use mercury::internal::object::tree::TreeItem;
+
+    use super::*;
+    use crate::storage::batch_save_model;
+    use crate::utils::snapshot_tree::build_tree_from_snapshots;
+    use crate::utils::test_db::test_db;
+
+    fn item(mode: TreeItemMode, id: SHA1, name: &str) -> TreeItem {
+        TreeItem {
+            mode,
+            id,
+            name: name.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_commit_to_snapshots() {
+        let conn = test_db().await;
+        let storage = MonoStorage::new(Arc::new(conn.clone())).await;
+
+        // /README.md, /src/lib.rs, /src/api/mod.rs
+        let readme = Blob::from_content("# snapshot\n");
+        let lib = Blob::from_content("pub mod api;\n");
+        let module = Blob::from_content("pub fn get() {}\n");
+        let api =
+            Tree::from_tree_items(vec![item(TreeItemMode::Blob, module.id, "mod.rs")]).unwrap();
+        let src = Tree::from_tree_items(vec![
+            item(TreeItemMode::Tree, api.id, "api"),
+            item(TreeItemMode::Blob, lib.id, "lib.rs"),
+        ])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![
+            item(TreeItemMode::Blob, readme.id, "README.md"),
+            item(TreeItemMode::Tree, src.id, "src"),
+        ])
+        .unwrap();
+        let commit = Commit::from_tree_id(root.id, vec![], "\nsnapshot");
+
+        storage
+            .save_mega_commits(vec![commit.clone()])
+            .await
+            .unwrap();
+        let trees: Vec<mega_tree::ActiveModel> = [&api, &src, &root]
+            .into_iter()
+            .map(|tree| mega_tree::Model::from(tree.clone()).into_active_model())
+            .collect();
+        batch_save_model(&conn, trees).await.unwrap();
+        let blobs: Vec<raw_blob::ActiveModel> = [&readme, &lib, &module]
+            .into_iter()
+            .map(|blob| raw_blob::Model::from(blob.clone()).into_active_model())
+            .collect();
+        batch_save_model(&conn, blobs).await.unwrap();
+
+        let synced = sync_commit_to_snapshots(&conn, &storage, commit.id).await;
+        assert_eq!(synced.unwrap(), 6);
+        // synced again, the rows are replaced
+        let synced = sync_commit_to_snapshots(&conn, &storage, commit.id).await;
+        assert_eq!(synced.unwrap(), 6);
+        let rows = mega_snapshot::Entity::find()
+            .filter(mega_snapshot::Column::CommitId.eq(commit.id.to_string()))
+            .order_by_asc(mega_snapshot::Column::Path)
+            .all(&conn)
+            .await
+            .unwrap();
+        let paths: Vec<&str> = rows.iter().map(|row| row.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/",
+                "/README.md",
+                "/src",
+                "/src/api",
+                "/src/api/mod.rs",
+                "/src/lib.rs"
+            ]
+        );
+
+        let root_node = build_tree_from_snapshots(rows).unwrap();
+        assert_eq!(root_node.row.tree_id, Some(root.id.to_string()));
+        assert_eq!(root_node.row.size, 11 + 13 + 16);
+        let src_node = root_node.get("src").unwrap();
+        assert_eq!(src_node.row.tree_id, Some(src.id.to_string()));
+        assert_eq!(
+            src_node.row.sub_trees,
+            Some(SubTrees(vec!["/src/api".into(), "/src/lib.rs".into()]))
+        );
+        assert_eq!(src_node.row.size, 13 + 16);
+        let module_node = root_node.get("src/api/mod.rs").unwrap();
+        assert_eq!(module_node.row.import_dir, Some(false));
+        assert_eq!(module_node.row.tree_id, Some(module.id.to_string()));
+        assert_eq!(module_node.row.size, 16);
+
+        let unknown = SHA1::from_str("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert!(sync_commit_to_snapshots(&conn, &storage, unknown)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upsert_invalid_snapshot() {
+        let conn = test_db().await;
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let dir = snapshot_row(commit, ROOT_PATH, true, SHA1::default(), None, 0);
+        let file = snapshot_row(commit, "/a.txt", false, SHA1::default(), None, 1);
+        let mut no_blob = snapshot_row(commit, "/b.txt", false, SHA1::default(), None, 1);
+        no_blob.tree_id = None;
+
+        assert!(upsert_snapshots(&conn, vec![file.clone(), dir])
+            .await
+            .is_err());
+        assert!(upsert_snapshots(&conn, vec![file, no_blob]).await.is_err());
+        // the valid rows of the batch aren't written either
+        let count = mega_snapshot::Entity::find()
+            .all(&conn)
+            .await
+            .unwrap()
+            .len();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,224 @@
+//! Assemble the flat `mega_snapshot` rows of a commit back into the tree of its paths.
+use std::collections::HashMap;
+use std::fmt;
+
+use callisto::mega_snapshot::{self, ShapeError};
+
+/// Path of the root directory of a commit
+pub const ROOT_PATH: &str = "/";
+
+/// A path of a commit, with its entries if it's a directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotNode {
+    /// Last component of the path, empty for the root
+    pub name: String,
+    pub row: mega_snapshot::Model,
+    /// Sorted by name
+    pub children: Vec<SnapshotNode>,
+}
+
+impl SnapshotNode {
+    pub fn is_dir(&self) -> bool {
+        self.row.import_dir == Some(true)
+    }
+
+    /// The node of `path`, relative to this one
+    pub fn get(&self, path: &str) -> Option<&SnapshotNode> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(self, |node, name| {
+                node.children.iter().find(|child| child.name == name)
+            })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotTreeError {
+    /// No directory row for [ROOT_PATH]
+    NoRoot,
+    DuplicatePath(String),
+    Shape(String, ShapeError),
+    /// `entry` in the `sub_trees` of `dir` isn't a row directly under it
+    UnresolvedEntry {
+        dir: String,
+        entry: String,
+    },
+    /// Rows not listed by the directory above them, sorted
+    Orphans(Vec<String>),
+}
+
+impl fmt::Display for SnapshotTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotTreeError::NoRoot => write!(f, "no root directory {}", ROOT_PATH),
+            SnapshotTreeError::DuplicatePath(path) => write!(f, "duplicate path {}", path),
+            SnapshotTreeError::Shape(path, e) => write!(f, "invalid row {}: {}", path, e),
+            SnapshotTreeError::UnresolvedEntry { dir, entry } => {
+                write!(f, "entry {} of {} has no row", entry, dir)
+            }
+            SnapshotTreeError::Orphans(paths) => {
+                write!(f, "orphan paths: {}", paths.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotTreeError {}
+
+/// Directory containing `path`, `None` for the root
+fn parent_path(path: &str) -> Option<&str> {
+    match path.rsplit_once('/') {
+        _ if path == ROOT_PATH => None,
+        Some(("", _)) => Some(ROOT_PATH),
+        Some((parent, _)) => Some(parent),
+        None => None,
+    }
+}
+
+/// Nest the rows of a commit by their path, starting from the [ROOT_PATH] directory.
+/// Every row must be listed in the `sub_trees` of the directory above it, and every path listed
+/// in `sub_trees` must have a row.
+pub fn build_tree_from_snapshots(
+    rows: Vec<mega_snapshot::Model>,
+) -> Result<SnapshotNode, SnapshotTreeError> {
+    let mut by_path: HashMap<String, mega_snapshot::Model> = HashMap::new();
+    for row in rows {
+        if let Err(e) = row.validate_shape() {
+            return Err(SnapshotTreeError::Shape(row.path, e));
+        }
+        if by_path.contains_key(&row.path) {
+            return Err(SnapshotTreeError::DuplicatePath(row.path));
+        }
+        by_path.insert(row.path.clone(), row);
+    }
+
+    for dir in by_path.values() {
+        for entry in dir.sub_trees.iter().flat_map(|sub_trees| &sub_trees.0) {
+            if !by_path.contains_key(entry) || parent_path(entry) != Some(dir.path.as_str()) {
+                return Err(SnapshotTreeError::UnresolvedEntry {
+                    dir: dir.path.clone(),
+                    entry: entry.clone(),
+                });
+            }
+        }
+    }
+    match by_path.get(ROOT_PATH) {
+        Some(root) if root.import_dir == Some(true) => (),
+        _ => return Err(SnapshotTreeError::NoRoot),
+    }
+
+    // as all entries resolve, a row listed by its parent is reachable from the root
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    let mut orphans = Vec::new();
+    for path in by_path.keys().filter(|path| *path != ROOT_PATH) {
+        let listed = parent_path(path)
+            .and_then(|parent| by_path.get(parent))
+            .and_then(|parent| parent.sub_trees.as_ref())
+            .is_some_and(|sub_trees| sub_trees.0.contains(path));
+        if listed {
+            let parent = parent_path(path).unwrap().to_owned();
+            children.entry(parent).or_default().push(path.clone());
+        } else {
+            orphans.push(path.clone());
+        }
+    }
+    if !orphans.is_empty() {
+        orphans.sort();
+        return Err(SnapshotTreeError::Orphans(orphans));
+    }
+    Ok(build_node(ROOT_PATH, &mut by_path, &mut children))
+}
+
+fn build_node(
+    path: &str,
+    by_path: &mut HashMap<String, mega_snapshot::Model>,
+    children: &mut HashMap<String, Vec<String>>,
+) -> SnapshotNode {
+    let row = by_path.remove(path).unwrap();
+    let mut entries = children.remove(path).unwrap_or_default();
+    entries.sort();
+    SnapshotNode {
+        name: path.rsplit('/').next().unwrap_or_default().to_owned(),
+        children: entries
+            .iter()
+            .map(|entry| build_node(entry, by_path, children))
+            .collect(),
+        row,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_db::SnapshotRows;
+
+    fn names(node: &SnapshotNode) -> Vec<&str> {
+        node.children
+            .iter()
+            .map(|child| child.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_build_nested_tree() {
+        let rows = SnapshotRows::new("c0ffee")
+            .file("/src/lib.rs", "b1", 10)
+            .dir("/", &["/src", "/README.md"])
+            .dir("/src", &["/src/lib.rs", "/src/api"])
+            .file("/README.md", "b2", 3)
+            .dir("/src/api", &["/src/api/mod.rs"])
+            .file("/src/api/mod.rs", "b3", 5)
+            .build();
+        let root = build_tree_from_snapshots(rows).unwrap();
+
+        assert_eq!(root.name, "");
+        assert_eq!(names(&root), ["README.md", "src"]);
+        let src = root.get("src").unwrap();
+        assert!(src.is_dir());
+        assert_eq!(names(src), ["api", "lib.rs"]);
+        let module = root.get("/src/api/mod.rs").unwrap();
+        assert!(!module.is_dir());
+        assert_eq!(module.row.tree_id.as_deref(), Some("b3"));
+        assert!(module.children.is_empty());
+        assert_eq!(src.get("api/mod.rs"), Some(module));
+        assert!(root.get("src/main.rs").is_none());
+    }
+
+    #[test]
+    fn test_invalid_trees() {
+        // a listed entry without a row
+        let rows = SnapshotRows::new("c0ffee").dir("/", &["/src"]).build();
+        assert_eq!(
+            build_tree_from_snapshots(rows),
+            Err(SnapshotTreeError::UnresolvedEntry {
+                dir: "/".to_string(),
+                entry: "/src".to_string()
+            })
+        );
+
+        // rows unlisted by their directory, or under a missing one
+        let rows = SnapshotRows::new("c0ffee")
+            .dir("/", &["/src"])
+            .dir("/src", &[])
+            .file("/src/lib.rs", "b1", 10)
+            .file("/docs/guide.md", "b2", 3)
+            .build();
+        let err = build_tree_from_snapshots(rows).unwrap_err();
+        assert_eq!(
+            err,
+            SnapshotTreeError::Orphans(vec![
+                "/docs/guide.md".to_string(),
+                "/src/lib.rs".to_string()
+            ])
+        );
+        assert_eq!(err.to_string(), "orphan paths: /docs/guide.md, /src/lib.rs");
+
+        let rows = SnapshotRows::new("c0ffee")
+            .file("/README.md", "b2", 3)
+            .build();
+        assert_eq!(
+            build_tree_from_snapshots(rows),
+            Err(SnapshotTreeError::NoRoot)
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Reproducible database for the tests of the entities and their queries: an in-memory sqlite
+//! set up by the same `.sql` as a new sqlite database, seeded with rows of fixed ids and times.
+use chrono::{NaiveDate, NaiveDateTime};
+use sea_orm::{
+    ActiveModelTrait, ConnectOptions, Database, DatabaseConnection, DbErr, EntityTrait,
+    IntoActiveModel,
+};
+
+use callisto::mega_snapshot::{self, SubTrees};
+
+use crate::storage::init::{migrate, setup_sql};
+
+/// An empty database with all the tables.
+/// Each test gets its own, the memory is freed when the connection is dropped.
+pub async fn test_db() -> DatabaseConnection {
+    let conn = memory_db().await;
+    setup_sql(&conn)
+        .await
+        .expect("Failed to setup in-memory sqlite database");
+    migrate(&conn)
+        .await
+        .expect("Failed to migrate in-memory sqlite database");
+    conn
+}
+
+/// A database without any table
+async fn memory_db() -> DatabaseConnection {
+    // an in-memory database lives in its connection, so the pool must keep exactly one
+    let mut opt = ConnectOptions::new("sqlite::memory:");
+    opt.max_connections(1)
+        .min_connections(1)
+        .sqlx_logging(false);
+    Database::connect(opt)
+        .await
+        .expect("Failed to open in-memory sqlite database")
+}
+
+/// Insert `rows`, failing on the first error instead of skipping conflicting rows
+pub async fn seed<A, M>(conn: &DatabaseConnection, rows: Vec<M>) -> Result<(), DbErr>
+where
+    A: ActiveModelTrait + Send,
+    M: IntoActiveModel<A>,
+{
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let rows = rows.into_iter().map(IntoActiveModel::into_active_model);
+    <A::Entity as EntityTrait>::insert_many(rows)
+        .exec(conn)
+        .await?;
+    Ok(())
+}
+
+/// `created_at` of the seeded rows
+pub fn fixed_time() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2024, 12, 4)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+}
+
+/// `mega_snapshot` rows of a commit, numbered from 1 in the order they are added
+pub struct SnapshotRows {
+    commit_id: String,
+    rows: Vec<mega_snapshot::Model>,
+}
+
+impl SnapshotRows {
+    pub fn new(commit_id: &str) -> Self {
+        SnapshotRows {
+            commit_id: commit_id.to_owned(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// A directory, with the paths of its entries
+    pub fn dir(self, path: &str, entries: &[&str]) -> Self {
+        let sub_trees = SubTrees(entries.iter().map(|entry| entry.to_string()).collect());
+        self.row(path, Some(true), None, Some(sub_trees), 0)
+    }
+
+    pub fn file(self, path: &str, blob_id: &str, size: i64) -> Self {
+        self.row(path, Some(false), Some(blob_id.to_owned()), None, size)
+    }
+
+    pub fn row(
+        mut self,
+        path: &str,
+        import_dir: Option<bool>,
+        tree_id: Option<String>,
+        sub_trees: Option<SubTrees>,
+        size: i64,
+    ) -> Self {
+        self.rows.push(mega_snapshot::Model {
+            id: self.rows.len() as i64 + 1,
+            commit_id: self.commit_id.clone(),
+            path: path.to_owned(),
+            import_dir,
+            tree_id,
+            sub_trees,
+            size,
+            created_at: fixed_time(),
+        });
+        self
+    }
+
+    pub fn build(self) -> Vec<mega_snapshot::Model> {
+        self.rows
+    }
+}
+
+mod test {
+    use sea_orm::{
+        ActiveValue::{Set, Unchanged},
+        ColumnTrait, QueryFilter, QueryOrder,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let conn = test_db().await;
+        let rows = SnapshotRows::new("c0ffee")
+            .dir("/", &["/src"])
+            .dir("/src", &["/src/lib.rs"])
+            .file("/src/lib.rs", "5e1f", 42)
+            .build();
+        seed(&conn, rows.clone()).await.unwrap();
+
+        let read = mega_snapshot::Entity::find()
+            .filter(mega_snapshot::Column::CommitId.eq("c0ffee"))
+            .order_by_asc(mega_snapshot::Column::Id)
+            .all(&conn)
+            .await
+            .unwrap();
+        assert_eq!(read, rows);
+        assert_eq!(
+            read[1].sub_trees,
+            Some(SubTrees(vec!["/src/lib.rs".into()]))
+        );
+        assert_eq!(read[2].created_at, fixed_time());
+
+        // a path is only once in a commit
+        let mut duplicate = SnapshotRows::new("c0ffee").file("/", "5e1f", 0).build();
+        duplicate[0].id = 10;
+        assert!(seed(&conn, duplicate).await.is_err());
+
+        // every test starts from an empty database
+        let other = test_db().await;
+        let rows = mega_snapshot::Entity::find().all(&other).await.unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_database_of_init_script() {
+        let conn = memory_db().await;
+        setup_sql(&conn).await.unwrap();
+        assert!(mega_snapshot::Entity::find().all(&conn).await.is_err());
+
+        // on every start
+        for _ in 0..2 {
+            migrate(&conn).await.unwrap();
+        }
+        let rows = SnapshotRows::new("c0ffee").dir("/", &[]).build();
+        seed(&conn, rows).await.unwrap();
+        migrate(&conn).await.unwrap();
+        let rows = mega_snapshot::Entity::find().all(&conn).await.unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shape_checked_on_save() {
+        let conn = test_db().await;
+        let mut rows = SnapshotRows::new("c0ffee")
+            .file("/README.md", "5e1f", 7)
+            .row("/src", Some(true), Some("7ree".to_string()), None, 0)
+            .build();
+        let invalid = rows.pop().unwrap().into_active_model();
+        let err = invalid.insert(&conn).await.unwrap_err();
+        assert!(
+            err.to_string().contains("directory without sub_trees"),
+            "{}",
+            err
+        );
+
+        let valid = rows.pop().unwrap();
+        let inserted = valid.clone().into_active_model().insert(&conn).await;
+        assert_eq!(inserted.unwrap(), valid);
+
+        // the columns an insert doesn't set are NULL
+        let unset = mega_snapshot::ActiveModel {
+            id: Set(2),
+            commit_id: Set("c0ffee".to_string()),
+            path: Set("/src".to_string()),
+            tree_id: Set(Some("7ree".to_string())),
+            size: Set(0),
+            created_at: Set(fixed_time()),
+            ..Default::default()
+        };
+        let err = unset.insert(&conn).await.unwrap_err();
+        assert!(err.to_string().contains("import_dir is not set"), "{}", err);
+
+        // an update is checked against the import_dir it sets
+        let update = |import_dir: Option<Option<bool>>, sub_trees: Option<Option<SubTrees>>| {
+            let mut update = mega_snapshot::ActiveModel {
+                id: Unchanged(valid.id),
+                size: Set(8),
+                ..Default::default()
+            };
+            if let Some(import_dir) = import_dir {
+                update.import_dir = Set(import_dir);
+            }
+            if let Some(sub_trees) = sub_trees {
+                update.sub_trees = Set(sub_trees);
+            }
+            update.update(&conn)
+        };
+        let entries = Some(SubTrees(vec!["/README.md/index.md".to_string()]));
+        assert_eq!(update(None, None).await.unwrap().size, 8);
+        assert!(update(Some(Some(false)), None).await.is_ok());
+        assert!(update(Some(None), None).await.is_err());
+        assert!(update(Some(Some(false)), Some(entries.clone()))
+            .await
+            .is_err());
+        assert!(update(Some(Some(true)), Some(None)).await.is_err());
+        assert!(update(Some(Some(true)), Some(entries)).await.is_ok());
+    }
+}
@@ -5,6 +5,7 @@
 use clap::{Parser, Subcommand};
 use mercury::errors::GitError;
 use crate::command;
+use crate::internal::config::Config;
 use crate::utils;
 
 // The Cli struct represents the root of the command line interface.
@@ -25,7 +26,7 @@ enum Commands {
 
     // Init and Clone are the only commands that can be executed without a repository
     #[command(about = "Initialize a new repository")]
-    Init,
+    Init(command::init::InitArgs),
     #[command(about = "Clone a repository into a new directory")]
     Clone(command::clone::CloneArgs),
 
@@ -37,7 +38,7 @@ enum Commands {
     #[command(about = "Restore working tree files")]
     Restore(command::restore::RestoreArgs),
     #[command(about = "Show the working tree status")]
-    Status,
+    Status(command::status::StatusArgs),
     #[command(subcommand, about = "Large File Storage")]
     Lfs(command::lfs::LfsCmds),
     #[command(about = "Show commit logs")]
@@ -50,6 +51,14 @@ enum Commands {
     Switch(command::switch::SwitchArgs),
     #[command(about = "Merge changes")]
     Merge(command::merge::MergeArgs),
+    #[command(subcommand, about = "Stash the changes in a dirty working directory away")]
+    Stash(command::stash::StashCmds),
+    #[command(about = "Apply the changes introduced by an existing commit")]
+    CherryPick(command::cherry_pick::CherryPickArgs),
+    #[command(about = "Revert an existing commit with a new commit")]
+    Revert(command::revert::RevertArgs),
+    #[command(about = "Reapply commits on top of another base")]
+    Rebase(command::rebase::RebaseArgs),
     #[command(about = "Update remote refs along with associated objects")]
     Push(command::push::PushArgs),
     #[command(about = "Download objects and refs from another repository")]
@@ -61,6 +70,8 @@ enum Commands {
 
     #[command(subcommand, about = "Manage set of tracked repositories")]
     Remote(command::remote::RemoteCmds),
+    #[command(about = "Get and set repository options")]
+    Config(command::config::ConfigArgs),
 
     // other hidden commands
     #[command(
@@ -70,6 +81,29 @@ enum Commands {
     IndexPack(command::index_pack::IndexPackArgs),
 }
 
+impl Commands {
+    /// Whether the command works on the working tree or the index, so can't run in a bare repository
+    fn needs_work_tree(&self) -> bool {
+        matches!(
+            self,
+            Commands::Add(_)
+                | Commands::Rm(_)
+                | Commands::Restore(_)
+                | Commands::Status(_)
+                | Commands::Lfs(_)
+                | Commands::Commit(_)
+                | Commands::Switch(_)
+                | Commands::Merge(_)
+                | Commands::Stash(_)
+                | Commands::CherryPick(_)
+                | Commands::Revert(_)
+                | Commands::Rebase(_)
+                | Commands::Pull(_)
+                | Commands::Diff(_)
+        )
+    }
+}
+
 /// The main function is the entry point of the Libra application.
 /// It parses the command-line arguments and executes the corresponding function.
 /// - Caution: This is a `synchronous` function, it's declared as `async` to be able to use `[tokio::main]`
@@ -86,30 +120,56 @@ pub async fn parse_async(args: Option<&[&str]>) -> Result<(), GitError> {
         None => Cli::parse(),
     };
     // TODO: try check repo before parsing
-    if let Commands::Init = args.command {
+    if let Commands::Init(_) = args.command {
     } else if let Commands::Clone(_) = args.command {
     } else if !utils::util::check_repo_exist() {
         return Err(GitError::RepoNotFound);
+    } else {
+        if let Some(codec) = Config::get("core", None, "compression").await {
+            let codec = codec.parse().map_err(GitError::InvalidArgument)?;
+            utils::compression::set_configured(codec);
+        }
+        if let Some(threshold) = Config::get("core", None, "chunkThreshold").await {
+            let threshold =
+                utils::chunking::parse_threshold(&threshold).map_err(GitError::InvalidArgument)?;
+            utils::chunking::set_configured_threshold(threshold);
+        }
+        if Config::get("core", None, "fsyncObjectFiles").await.as_deref() == Some("true") {
+            utils::fsync::set_configured(true);
+        }
+        if Config::get("core", None, "bare").await.as_deref() == Some("true") {
+            utils::util::set_bare(&utils::util::storage_path(), true);
+            if args.command.needs_work_tree() {
+                return Err(GitError::CustomError(
+                    "fatal: this operation must be run in a work tree".to_string(),
+                ));
+            }
+        }
     }
     // parse the command and execute the corresponding function with it's args
     match args.command {
-        Commands::Init => command::init::execute().await,
+        Commands::Init(args) => command::init::execute(args).await,
         Commands::Clone(args) => command::clone::execute(args).await,
         Commands::Add(args) => command::add::execute(args).await,
         Commands::Rm(args) => command::remove::execute(args).unwrap(),
         Commands::Restore(args) => command::restore::execute(args).await,
-        Commands::Status => command::status::execute().await,
+        Commands::Status(args) => command::status::execute(args).await,
         Commands::Lfs(cmd) => command::lfs::execute(cmd).await,
         Commands::Log(args) => command::log::execute(args).await,
         Commands::Branch(args) => command::branch::execute(args).await,
         Commands::Commit(args) => command::commit::execute(args).await,
         Commands::Switch(args) => command::switch::execute(args).await,
         Commands::Merge(args) => command::merge::execute(args).await,
+        Commands::Stash(cmd) => command::stash::execute(cmd).await,
+        Commands::CherryPick(args) => command::cherry_pick::execute(args).await,
+        Commands::Revert(args) => command::revert::execute(args).await,
+        Commands::Rebase(args) => command::rebase::execute(args).await,
         Commands::Push(args) => command::push::execute(args).await,
         Commands::IndexPack(args) => command::index_pack::execute(args),
         Commands::Fetch(args) => command::fetch::execute(args).await,
         Commands::Diff(args) => command::diff::execute(args).await,
         Commands::Remote(cmd) => command::remote::execute(cmd).await,
+        Commands::Config(args) => command::config::execute(args).await,
         Commands::Pull(args) => command::pull::execute(args).await,
     }
     Ok(())
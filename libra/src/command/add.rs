@@ -1,9 +1,15 @@
 use crate::command::status;
 use crate::utils::object_ext::BlobExt;
 use clap::Parser;
+use mercury::hash::SHA1;
 use mercury::internal::index::{Index, IndexEntry};
 use mercury::internal::object::blob::Blob;
+use std::collections::HashMap;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
 use crate::utils::{lfs, path, util};
 
@@ -30,7 +36,6 @@ pub struct AddArgs {
 }
 
 pub async fn execute(args: AddArgs) {
-    // TODO .gitignore
     if !util::check_repo_exist() {
         return;
     }
@@ -51,6 +56,30 @@ pub async fn execute(args: AddArgs) {
     let mut changes = status::changes_to_be_staged(); // to workdir
                                                       // filter paths to fit `pathspec` that user inputs
     changes.new = util::filter_to_fit_paths(&changes.new, &paths);
+    // files matching `.libraignore` are only added once tracked, as git without `--force`
+    let ignore = status::ignore_patterns();
+    let (ignored, new): (Vec<PathBuf>, Vec<PathBuf>) = changes
+        .new
+        .into_iter()
+        .partition(|file| status::is_ignored(&ignore, file));
+    changes.new = new;
+    let named: Vec<&PathBuf> = ignored
+        .iter()
+        .filter(|file| {
+            paths
+                .iter()
+                .any(|path| util::try_to_workdir_path(path).is_ok_and(|path| path == **file))
+        })
+        .collect();
+    if !named.is_empty() {
+        println!(
+            "The following paths are ignored by one of your {} files:",
+            util::IGNORE
+        );
+        for file in named {
+            println!("{}", file.display());
+        }
+    }
     // if `--all` & <pathspec> is given, it will update `index` as well, so no need to filter `deleted` & `modified`
     if args.pathspec.is_empty() || !args.all {
         changes.modified = util::filter_to_fit_paths(&changes.modified, &paths);
@@ -66,80 +95,170 @@ pub async fn execute(args: AddArgs) {
 
     let index_file = path::index();
     let mut index = Index::load(&index_file).unwrap();
+    let workdir = util::working_dir();
+    // hash & store the blobs up front, on all cores
+    let to_hash: Vec<PathBuf> = files
+        .iter()
+        .filter(|f| needs_blob(f, &index, &workdir))
+        .cloned()
+        .collect();
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let blobs: HashMap<PathBuf, SHA1> = hash_files(&to_hash, workers)
+        .unwrap()
+        .into_iter()
+        .map(|(file, hash, _)| (file, hash))
+        .collect();
     for file in &files {
-        add_a_file(file, &mut index, args.verbose).await;
+        add_a_file(file, &mut index, &blobs, args.verbose).await;
     }
     index.save(&index_file).unwrap();
 }
 
+/// What `add` does with a file, see [staging_of]
+#[derive(Debug, PartialEq, Eq)]
+enum Staging {
+    /// out of the working directory
+    Outside,
+    /// in `.libra`
+    InStorage,
+    /// tracked but removed from the working directory
+    Removed,
+    /// neither tracked nor in the working directory
+    NoMatch,
+    /// not tracked yet
+    New,
+    /// tracked & its metadata changed, the content may have too
+    Modified,
+    /// tracked & unchanged
+    Unchanged,
+}
+
 /// `file` path must relative to the working directory
-async fn add_a_file(file: &Path, index: &mut Index, verbose: bool) {
-    let workdir = util::working_dir();
-    if !util::is_sub_path(file, &workdir) {
-        // file is not in the working directory
-        // TODO check this earlier, once fatal occurs, nothing should be done
-        println!(
-            "fatal: '{}' is outside workdir at '{}'",
-            file.display(),
-            workdir.display()
-        );
-        return;
-    }
-    if util::is_sub_path(file, util::storage_path()) {
-        // file is in `.libra`
-        // Git won't print this
-        println!(
-            "warning: '{}' is inside '{}' repo, which will be ignored by `add`",
-            file.display(),
-            util::ROOT_DIR
-        );
-        return;
+fn staging_of(file: &Path, index: &Index, workdir: &Path) -> Staging {
+    let file_str = file.to_str().unwrap();
+    if !util::is_sub_path(file, workdir) {
+        Staging::Outside
+    } else if util::is_sub_path(file, util::storage_path()) {
+        Staging::InStorage
+    } else if !util::workdir_to_absolute(file).exists() {
+        if index.tracked(file_str, 0) {
+            Staging::Removed
+        } else {
+            Staging::NoMatch
+        }
+    } else if !index.tracked(file_str, 0) {
+        Staging::New
+    } else if index.is_modified(file_str, 0, workdir) {
+        Staging::Modified
+    } else {
+        Staging::Unchanged
     }
+}
+
+/// Whether [add_a_file] will need the blob of `file`
+fn needs_blob(file: &Path, index: &Index, workdir: &Path) -> bool {
+    matches!(
+        staging_of(file, index, workdir),
+        Staging::New | Staging::Modified
+    )
+}
+
+/// Hash & store the blobs of `files` (relative to the workdir) on `workers` threads, as `(path, hash, mode)`.
+/// - the result is in the order of `files` whatever the number of workers
+pub(crate) fn hash_files(
+    files: &[PathBuf],
+    workers: usize,
+) -> io::Result<Vec<(PathBuf, SHA1, u32)>> {
+    let workdir = util::working_dir();
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<io::Result<(SHA1, u32)>>>> =
+        Mutex::new((0..files.len()).map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, files.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(i) else { break };
+                let blob = gen_blob_from_file(util::workdir_to_absolute(file));
+                blob.save();
+                let entry =
+                    IndexEntry::new_from_file(file, blob.id, &workdir).map(|e| (blob.id, e.mode));
+                results.lock().unwrap()[i] = Some(entry);
+            });
+        }
+    });
+    files
+        .iter()
+        .zip(results.into_inner().unwrap())
+        .map(|(file, res)| {
+            let (hash, mode) = res.unwrap()?;
+            Ok((file.clone(), hash, mode))
+        })
+        .collect()
+}
 
-    let file_abs = util::workdir_to_absolute(file);
+/// `file` path must relative to the working directory
+/// - `blobs`: hashes of the files [needs_blob] selected, already stored; a missing one is
+///   hashed & stored here
+async fn add_a_file(file: &Path, index: &mut Index, blobs: &HashMap<PathBuf, SHA1>, verbose: bool) {
+    let workdir = util::working_dir();
     let file_str = file.to_str().unwrap();
-    if !file_abs.exists() {
-        if index.tracked(file_str, 0) {
-            // file is removed
+    let blob_of = |file: &Path| {
+        blobs.get(file).copied().unwrap_or_else(|| {
+            let blob = gen_blob_from_file(util::workdir_to_absolute(file));
+            blob.save();
+            blob.id
+        })
+    };
+    match staging_of(file, index, &workdir) {
+        Staging::Outside => {
+            // TODO check this earlier, once fatal occurs, nothing should be done
+            println!(
+                "fatal: '{}' is outside workdir at '{}'",
+                file.display(),
+                workdir.display()
+            );
+        }
+        Staging::InStorage => {
+            // Git won't print this
+            println!(
+                "warning: '{}' is inside '{}' repo, which will be ignored by `add`",
+                file.display(),
+                util::root_dir_name()
+            );
+        }
+        Staging::Removed => {
             index.remove(file_str, 0);
             if verbose {
                 println!("removed: {}", file_str);
             }
-        } else {
+        }
+        Staging::NoMatch => {
             // FIXME: unreachable code! This situation is not included in `status::changes_to_be_staged()`
             // FIXME: should check files in original input paths
             // TODO do this check earlier, once fatal occurs, nothing should be done
-            // file is not tracked && not exists, which means wrong pathspec
             println!(
                 "fatal: pathspec '{}' did not match any files",
                 file.display()
             );
         }
-    } else {
-        // file exists
-        if !index.tracked(file_str, 0) {
-            // file is not tracked
-            let blob = gen_blob_from_file(&file_abs);
-            blob.save();
-            index.add(IndexEntry::new_from_file(file, blob.id, &workdir).unwrap());
+        Staging::New => {
+            let hash = blob_of(file);
+            index.add(IndexEntry::new_from_file(file, hash, &workdir).unwrap());
             if verbose {
                 println!("add(new): {}", file.display());
             }
-        } else {
-            // file is tracked, maybe modified
-            if index.is_modified(file_str, 0, &workdir) {
-                // file is modified(meta), but content may not change
-                let blob = gen_blob_from_file(&file_abs);
-                if !index.verify_hash(file_str, 0, &blob.id) {
-                    // content is changed
-                    blob.save();
-                    index.update(IndexEntry::new_from_file(file, blob.id, &workdir).unwrap());
-                    if verbose {
-                        println!("add(modified): {}", file.display());
-                    }
+        }
+        Staging::Modified => {
+            // the content may not have changed
+            let hash = blob_of(file);
+            if !index.verify_hash(file_str, 0, &hash) {
+                index.update(IndexEntry::new_from_file(file, hash, &workdir).unwrap());
+                if verbose {
+                    println!("add(modified): {}", file.display());
                 }
             }
         }
+        Staging::Unchanged => {}
     }
 }
 
@@ -156,6 +275,112 @@ fn gen_blob_from_file(path: impl AsRef<Path>) -> Blob {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::utils::test;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_hash_files_in_parallel() {
+        test::setup_with_new_libra().await;
+        let files: Vec<PathBuf> = (0..64)
+            .map(|i| {
+                let file = PathBuf::from(format!("dir_{}/file_{}.txt", i % 4, i));
+                test::ensure_file(&file, Some(&format!("content {}\n", i % 16)));
+                file
+            })
+            .collect();
+        let serial = hash_files(&files, 1).unwrap();
+        for workers in [2, 8, 100] {
+            assert_eq!(
+                hash_files(&files, workers).unwrap(),
+                serial,
+                "{} workers",
+                workers
+            );
+        }
+
+        let storage = util::objects_storage();
+        for (file, hash, mode) in &serial {
+            assert_eq!(*hash, Blob::from_file(file).id);
+            assert!(storage.exist(hash));
+            assert_eq!(
+                *mode,
+                IndexEntry::new_from_file(file, *hash, &util::working_dir())
+                    .unwrap()
+                    .mode
+            );
+        }
+        // no temp file left behind
+        let objects = util::storage_path().join("objects");
+        for dir in fs::read_dir(objects).unwrap() {
+            for entry in fs::read_dir(dir.unwrap().path()).unwrap() {
+                let name = entry.unwrap().file_name().into_string().unwrap();
+                assert!(!name.starts_with("tmp_obj_"), "{}", name);
+            }
+        }
+        assert!(hash_files(&[], 4).unwrap().is_empty());
+    }
+
+    async fn add(pathspec: &[&str], all: bool) {
+        execute(AddArgs {
+            pathspec: pathspec.iter().map(|path| path.to_string()).collect(),
+            all,
+            update: false,
+            verbose: false,
+        })
+        .await;
+    }
+
+    /// Hashes the index has for `files`
+    fn index_hashes(files: &[&str]) -> Vec<Option<SHA1>> {
+        let index = Index::load(path::index()).unwrap();
+        files.iter().map(|file| index.get_hash(file, 0)).collect()
+    }
+
+    fn blob(content: &str) -> Option<SHA1> {
+        Some(Blob::from_content(content).id)
+    }
+
+    #[tokio::test]
+    async fn test_add_updates_index() {
+        test::setup_with_new_libra().await;
+        let files = ["a.txt", "b/c.txt", "d.txt"];
+
+        test::ensure_file("a.txt", Some("a"));
+        test::ensure_file("b/c.txt", Some("c"));
+        add(&[], true).await;
+        assert_eq!(index_hashes(&files), [blob("a"), blob("c"), None]);
+        let storage = util::objects_storage();
+        assert!(storage.exist(&blob("a").unwrap()) && storage.exist(&blob("c").unwrap()));
+
+        test::ensure_file("a.txt", Some("a, modified"));
+        fs::remove_file("b/c.txt").unwrap();
+        test::ensure_file("d.txt", Some("d"));
+        add(&[], true).await;
+        assert_eq!(index_hashes(&files), [blob("a, modified"), None, blob("d")]);
+    }
+
+    #[tokio::test]
+    async fn test_add_skips_ignored() {
+        test::setup_with_new_libra().await;
+        let files = [
+            "ignore_test/keep.txt",
+            "ignore_test/debug.log",
+            "ignore_test/build/out.bin",
+        ];
+        test::ensure_file(util::IGNORE, Some("*.log\nignore_test/build/\n"));
+        for file in files {
+            test::ensure_file(file, Some(file));
+        }
+
+        // named explicitly, still ignored
+        add(&["ignore_test/debug.log"], false).await;
+        assert_eq!(index_hashes(&files), [None, None, None]);
+        add(&["."], false).await;
+        assert_eq!(index_hashes(&files), [blob(files[0]), None, None]);
+        add(&[], true).await;
+        assert_eq!(index_hashes(&files), [blob(files[0]), None, None]);
+        assert!(index_hashes(&[util::IGNORE])[0].is_some());
+    }
 
     #[test]
     #[should_panic]
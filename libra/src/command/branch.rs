@@ -61,20 +61,15 @@ pub async fn execute(args: BranchArgs) {
 }
 
 pub async fn set_upstream(branch: &str, upstream: &str) {
-    let branch_config = Config::branch_config(branch).await;
-    if branch_config.is_none() {
-        let (remote, remote_branch) = match upstream.split_once('/') {
-            Some((remote, branch)) => (remote, branch),
-            None => {
-                eprintln!("fatal: invalid upstream '{}'", upstream);
-                return;
-            }
-        };
-        Config::insert("branch", Some(branch), "remote", remote).await;
-        // set upstream branch (tracking branch)
-        Config::insert("branch", Some(branch), "merge",
-                       &format!("refs/heads/{}", remote_branch)).await;
-    }
+    let (remote, remote_branch) = match upstream.split_once('/') {
+        Some((remote, branch)) => (remote, branch),
+        None => {
+            eprintln!("fatal: invalid upstream '{}'", upstream);
+            return;
+        }
+    };
+    // set upstream branch (tracking branch)
+    Config::set_tracking(branch, remote, &format!("refs/heads/{}", remote_branch)).await;
     println!("Branch '{}' set up to track remote branch '{}'", branch, upstream);
 }
 
@@ -235,6 +230,7 @@ mod tests {
             message: "first".to_string(),
             allow_empty: true,
             conventional: false,
+            verify: false,
         };
         commit::execute(commit_args).await;
         let first_commit_id = Branch::find_branch("master", None).await.unwrap().commit;
@@ -243,6 +239,7 @@ mod tests {
             message: "second".to_string(),
             allow_empty: true,
             conventional: false,
+            verify: false,
         };
         commit::execute(commit_args).await;
         let second_commit_id = Branch::find_branch("master", None).await.unwrap().commit;
@@ -321,6 +318,7 @@ mod tests {
             message: "first".to_string(),
             allow_empty: true,
             conventional: false,
+            verify: false,
         };
         commit::execute(args).await;
         let hash = Head::current_commit().await.unwrap();
@@ -353,6 +351,7 @@ mod tests {
             message: "first".to_string(),
             allow_empty: true,
             conventional: false,
+            verify: false,
         };
         commit::execute(args).await;
 
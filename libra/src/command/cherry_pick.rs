@@ -0,0 +1,477 @@
+//! Apply the change introduced by an existing commit on top of HEAD, like `git cherry-pick`.
+
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::os::unix::{ffi::OsStrExt, fs::PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use clap::Parser;
+use common::utils::parse_commit_msg;
+use mercury::hash::SHA1;
+use mercury::internal::index::Index;
+use mercury::internal::object::blob::Blob;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::signature::Signature;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+
+use crate::command::commit::{create_tree, log_head_update, new_commit, update_head};
+use crate::command::restore::restore_to_file;
+use crate::command::{get_target_commit, save_object, status};
+use crate::internal::config::{Config, UserConfig};
+use crate::internal::head::Head;
+use crate::internal::tree_diff::diff_trees;
+use crate::utils::object_ext::{BlobExt, CommitExt, TreeExt};
+use crate::utils::{path, util};
+
+#[derive(Parser, Debug)]
+pub struct CherryPickArgs {
+    /// The commit to apply, e.g. a hash, a branch, `HEAD~2`
+    pub commit: String,
+}
+
+/// Result of applying a commit to HEAD, by `cherry-pick` or `revert`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyResult {
+    /// the new commit, now HEAD
+    Committed(SHA1),
+    /// files changed both by the commit and on HEAD, to workdir; nothing is committed
+    Conflicts(Vec<PathBuf>),
+}
+
+pub async fn execute(args: CherryPickArgs) {
+    let commit = match get_target_commit(&args.commit).await {
+        Ok(commit) => commit,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    let subject = Commit::load(&commit).format_message();
+    match cherry_pick(commit).await {
+        Ok(ApplyResult::Committed(picked)) => {
+            println!("[{}] {}", &picked.to_string()[..7], subject);
+        }
+        Ok(ApplyResult::Conflicts(conflicts)) => {
+            print_conflicts("apply", &commit, &subject, &conflicts)
+        }
+        Err(e) => eprintln!("error: {}", e),
+    }
+}
+
+/// Report the files left unmerged by applying `commit`, `action` e.g. `apply`, `revert`
+pub(crate) fn print_conflicts(action: &str, commit: &SHA1, subject: &str, conflicts: &[PathBuf]) {
+    for path in conflicts {
+        let path = util::workdir_to_current(path);
+        println!("CONFLICT (content): Merge conflict in {}", path.display());
+    }
+    eprintln!(
+        "error: could not {} {}... {}",
+        action,
+        &commit.to_string()[..7],
+        subject
+    );
+    eprintln!("hint: after resolving the conflicts, mark them with 'libra add <paths>'");
+    eprintln!("hint: and commit the result with 'libra commit'");
+}
+
+/// Apply the diff of `commit` against its first parent to HEAD, the working tree & the index,
+/// then commit it with the author & message of `commit`.
+/// A file changed by `commit` which differs in HEAD from the parent's version is a conflict:
+/// it is kept as in HEAD and recorded as unmerged in the index, the other files are applied.
+/// - fails without changing anything if the commit would overwrite local changes
+pub async fn cherry_pick(commit_id: SHA1) -> Result<ApplyResult, String> {
+    let head = Head::current_commit()
+        .await
+        .ok_or("cannot cherry-pick on a branch without commits")?;
+    let user = Config::user_config().await?;
+    pick(commit_id, head, &user, "cherry-pick")
+        .await?
+        .ok_or_else(|| format!("the changes of {} are already in HEAD", commit_id))
+}
+
+/// [cherry_pick] `commit_id` on `head`, which must be HEAD, committed by `user` & logged as made
+/// by the command `kind`
+/// - `None` if the changes of the commit are already in `head`, nothing is committed
+pub(crate) async fn pick(
+    commit_id: SHA1,
+    head: SHA1,
+    user: &UserConfig,
+    kind: &str,
+) -> Result<Option<ApplyResult>, String> {
+    let commit = Commit::load(&commit_id);
+    let parent = *commit
+        .parent_commit_ids
+        .first()
+        .ok_or_else(|| format!("commit {} has no parent to diff against", commit_id))?;
+
+    let applied = apply_diff(Commit::load(&parent).tree_id, commit.tree_id, head, true).await?;
+    if !applied.conflicts.is_empty() {
+        return Ok(Some(ApplyResult::Conflicts(applied.conflicts)));
+    }
+    if !applied.changed {
+        return Ok(None);
+    }
+    // a signature of the original commit doesn't sign the new one
+    let (message, _) = parse_commit_msg(commit.message_without_encoding());
+    let picked = commit_applied(head, user, Some(&commit.author), message, kind).await?;
+    Ok(Some(ApplyResult::Committed(picked)))
+}
+
+/// What [apply_diff] did, the index is saved unless nothing was applied
+pub(crate) struct Applied {
+    /// files changed by the diff which differ in HEAD from its old side, to workdir
+    pub conflicts: Vec<PathBuf>,
+    /// whether a file was changed, `false` if the diff is already in HEAD
+    pub changed: bool,
+}
+
+/// Apply the diff of tree `old` to tree `new` to the commit `head`, the working tree & the index.
+/// A changed file which isn't in `head` as in `old`, content & mode, is a conflict: it is kept
+/// as in `head` and recorded as unmerged in the index. So is a file added where `head` has a
+/// file in the way, above it or below it.
+/// - a file changed into a directory or a symlink, or the reverse, is deleted then added
+/// - fails without changing anything if the diff would overwrite local changes
+/// - `partial`: whether the changes without conflict are applied when others conflict, or
+///   nothing is
+pub(crate) async fn apply_diff(
+    old: SHA1,
+    new: SHA1,
+    head: SHA1,
+    partial: bool,
+) -> Result<Applied, String> {
+    let storage = util::objects_storage();
+    let changes = diff_trees(&storage, old, new).map_err(|e| e.to_string())?;
+    // the files of `head`, updated as the changes are decided
+    let mut files = HashMap::new();
+    tree_files(&Commit::load(&head).tree_id, Path::new(""), &mut files);
+
+    let mut index = Index::load(path::index()).map_err(|e| e.to_string())?;
+    if index.has_conflicts() {
+        return Err("not possible because you have unmerged files".to_string());
+    }
+    let current = status::status().await;
+    let local = current.changed_paths();
+    let overwritten: Vec<String> = changes
+        .iter()
+        .filter(|change| local.contains(&change.path))
+        .map(|change| util::path_to_string(&change.path))
+        .collect();
+    if !overwritten.is_empty() {
+        return Err(format!(
+            "your local changes to the following files would be overwritten:\n\t{}",
+            overwritten.join("\n\t")
+        ));
+    }
+
+    // decided before anything is written, `ours` of each conflict
+    let mut conflicts = Vec::new();
+    let mut to_apply = Vec::new();
+    for change in &changes {
+        let old_entry = change.old.zip(change.old_mode);
+        let new_entry = change.new.zip(change.new_mode);
+        let ours = files.get(&change.path).copied();
+        if ours == new_entry {
+            continue; // already in HEAD
+        }
+        // a file of HEAD above or below the one to add
+        let in_the_way = new_entry.is_some()
+            && files.keys().any(|file| {
+                file != &change.path
+                    && (file.starts_with(&change.path) || change.path.starts_with(file))
+            });
+        if ours != old_entry || in_the_way {
+            conflicts.push((change, ours.map(|(id, _)| id)));
+            continue;
+        }
+        match new_entry {
+            Some(entry) => files.insert(change.path.clone(), entry),
+            None => files.remove(&change.path),
+        };
+        to_apply.push(change);
+    }
+    let applied = Applied {
+        conflicts: conflicts
+            .iter()
+            .map(|(change, _)| change.path.clone())
+            .collect(),
+        changed: !to_apply.is_empty(),
+    };
+    if !partial && !conflicts.is_empty() {
+        return Ok(applied);
+    }
+
+    for change in to_apply {
+        let name = util::path_to_string(&change.path);
+        let file = util::workdir_to_absolute(&change.path);
+        if let Some(mode) = change.old_mode {
+            remove_entry(&file, mode).map_err(|e| e.to_string())?;
+        }
+        match change.new.zip(change.new_mode) {
+            Some((hash, mode)) => {
+                write_entry(&change.path, hash, mode)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let stat = match mode {
+                    TreeItemMode::Commit => None,
+                    _ => Some(fs::symlink_metadata(&file).map_err(|e| e.to_string())?),
+                };
+                index.stage(&name, hash, index_mode(mode), stat.as_ref());
+            }
+            None => {
+                util::clear_empty_dir(&file);
+                index.unstage(&name);
+            }
+        }
+    }
+    for (change, ours) in conflicts {
+        let name = util::path_to_string(&change.path);
+        index.add_conflict(&name, change.old, ours, change.new);
+    }
+    index.save(path::index()).map_err(|e| e.to_string())?;
+    Ok(applied)
+}
+
+/// Collect the files of the tree `id` under `dir` (to workdir), with their modes
+fn tree_files(id: &SHA1, dir: &Path, files: &mut HashMap<PathBuf, (SHA1, TreeItemMode)>) {
+    for item in Tree::load(id).tree_items {
+        let path = dir.join(&item.name);
+        match item.mode {
+            TreeItemMode::Tree => tree_files(&item.id, &path, files),
+            mode => {
+                files.insert(path, (item.id, mode));
+            }
+        }
+    }
+}
+
+/// The mode of an index entry, e.g. `0o100755`
+fn index_mode(mode: TreeItemMode) -> u32 {
+    u32::from_str_radix(std::str::from_utf8(mode.to_bytes()).unwrap(), 8).unwrap()
+}
+
+/// Remove the file, symlink or (empty) submodule `file` of a tree entry of `mode`
+fn remove_entry(file: &Path, mode: TreeItemMode) -> io::Result<()> {
+    match mode {
+        TreeItemMode::Commit => fs::remove_dir(file),
+        _ => fs::remove_file(file),
+    }
+}
+
+/// Write the blob `hash` at `path` (to workdir) as a tree entry of `mode`: a file, executable or
+/// not, or a symlink to its content. A submodule is an empty directory, it isn't checked out.
+async fn write_entry(path: &PathBuf, hash: SHA1, mode: TreeItemMode) -> io::Result<()> {
+    let file = util::workdir_to_absolute(path);
+    match mode {
+        TreeItemMode::Commit => fs::create_dir_all(&file),
+        #[cfg(unix)]
+        TreeItemMode::Link => {
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let target = Blob::load(&hash).data;
+            std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(&target), &file)
+        }
+        _ => {
+            restore_to_file(&hash, path).await?;
+            #[cfg(unix)]
+            {
+                let mut permissions = fs::metadata(&file)?.permissions();
+                let bits = permissions.mode();
+                permissions.set_mode(match mode {
+                    TreeItemMode::BlobExecutable => bits | 0o111,
+                    _ => bits & !0o111,
+                });
+                fs::set_permissions(&file, permissions)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Commit the index on `head` by `user` & move HEAD to it, logged as made by the command `kind`
+/// - `author`: `user` if `None`
+pub(crate) async fn commit_applied(
+    head: SHA1,
+    user: &UserConfig,
+    author: Option<&Signature>,
+    message: &str,
+    kind: &str,
+) -> Result<SHA1, String> {
+    let storage = util::objects_storage();
+    let index = Index::load(path::index()).map_err(|e| e.to_string())?;
+    let tree = create_tree(&index, &storage, "".into()).await;
+    let mut commit = new_commit(tree.id, vec![head], message, user);
+    if let Some(author) = author {
+        commit.author = author.clone();
+        commit.id = SHA1::from_type_and_data(
+            ObjectType::Commit,
+            &commit.to_data().map_err(|e| e.to_string())?,
+        );
+    }
+    save_object(&commit, &commit.id).map_err(|e| e.to_string())?;
+
+    update_head(&commit.id.to_string()).await;
+    log_head_update(Some(head), &commit, kind).await;
+    Ok(commit.id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::add::{self, AddArgs};
+    use crate::command::switch::{self, SwitchArgs};
+    use crate::utils::test;
+
+    async fn stage_removal(path: &str) {
+        add::execute(AddArgs {
+            pathspec: vec![path.to_string()],
+            all: false,
+            update: false,
+            verbose: false,
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_cherry_pick() {
+        test::setup_with_new_libra().await;
+        let base = test::commit_files(&[("pick_shared.txt", "v1")], "base").await;
+        let feature = test::commit_files(
+            &[("pick_shared.txt", "v2"), ("pick_new.txt", "new")],
+            "feature\n\nwith a body",
+        )
+        .await;
+        switch::execute(SwitchArgs::parse_from([
+            "switch",
+            "--create",
+            "pick_target",
+            &base.to_string(),
+        ]))
+        .await;
+        assert_eq!(fs::read_to_string("pick_shared.txt").unwrap(), "v1");
+        let other = test::commit_files(&[("pick_other.txt", "other")], "other").await;
+
+        let picked = match cherry_pick(feature).await.unwrap() {
+            ApplyResult::Committed(picked) => picked,
+            conflicts => panic!("{:?}", conflicts),
+        };
+        assert_ne!(picked, feature);
+        assert_eq!(Head::current_commit().await, Some(picked));
+        let picked = Commit::load(&picked);
+        let feature = Commit::load(&feature);
+        assert_eq!(picked.parent_commit_ids, [other]);
+        assert_eq!(picked.message(), feature.message());
+        assert_eq!(picked.author, feature.author);
+        // committed by the user of the config
+        assert_eq!(picked.committer.name, "mega");
+        assert_eq!(picked.committer.email, "admin@mega.org");
+        assert_eq!(fs::read_to_string("pick_shared.txt").unwrap(), "v2");
+        assert_eq!(fs::read_to_string("pick_new.txt").unwrap(), "new");
+        assert_eq!(fs::read_to_string("pick_other.txt").unwrap(), "other");
+        assert!(status::status().await.staged.is_empty());
+
+        // applied already
+        assert!(cherry_pick(feature.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cherry_pick_conflict() {
+        test::setup_with_new_libra().await;
+        let base = test::commit_files(&[("pick_conflict.txt", "v1")], "base").await;
+        let feature = test::commit_files(&[("pick_conflict.txt", "feature")], "feature").await;
+        switch::execute(SwitchArgs::parse_from([
+            "switch",
+            "--create",
+            "pick_target",
+            &base.to_string(),
+        ]))
+        .await;
+        let head = test::commit_files(&[("pick_conflict.txt", "target")], "target").await;
+
+        assert_eq!(
+            cherry_pick(feature).await,
+            Ok(ApplyResult::Conflicts(vec![PathBuf::from(
+                "pick_conflict.txt"
+            )]))
+        );
+        assert_eq!(Head::current_commit().await, Some(head));
+        assert_eq!(fs::read_to_string("pick_conflict.txt").unwrap(), "target");
+        let index = Index::load(path::index()).unwrap();
+        assert_eq!(index.conflicted_paths(), ["pick_conflict.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_cherry_pick_file_to_dir() {
+        test::setup_with_new_libra().await;
+        let base = test::commit_files(&[("pick_kind", "a file")], "base").await;
+        fs::remove_file("pick_kind").unwrap();
+        stage_removal("pick_kind").await;
+        let to_dir = test::commit_files(&[("pick_kind/inner.txt", "in a dir")], "to dir").await;
+        fs::remove_dir_all("pick_kind").unwrap();
+        stage_removal("pick_kind/inner.txt").await;
+        let to_file = test::commit_files(&[("pick_kind", "a file again")], "to file").await;
+        switch::execute(SwitchArgs::parse_from([
+            "switch",
+            "--create",
+            "pick_kind_target",
+            &base.to_string(),
+        ]))
+        .await;
+
+        let index_entry = |name: &str| {
+            let index = Index::load(path::index()).unwrap();
+            index.get(name, 0).map(|entry| entry.hash)
+        };
+        assert!(matches!(
+            cherry_pick(to_dir).await,
+            Ok(ApplyResult::Committed(_))
+        ));
+        assert_eq!(
+            fs::read_to_string("pick_kind/inner.txt").unwrap(),
+            "in a dir"
+        );
+        assert_eq!(index_entry("pick_kind"), None);
+        assert!(index_entry("pick_kind/inner.txt").is_some());
+
+        assert!(matches!(
+            cherry_pick(to_file).await,
+            Ok(ApplyResult::Committed(_))
+        ));
+        assert_eq!(fs::read_to_string("pick_kind").unwrap(), "a file again");
+        assert_eq!(index_entry("pick_kind/inner.txt"), None);
+        let head = Commit::load(&Head::current_commit().await.unwrap());
+        assert_eq!(head.tree_id, Commit::load(&to_file).tree_id);
+        assert!(status::status().await.staged.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cherry_pick_mode_change() {
+        test::setup_with_new_libra().await;
+        let base = test::commit_files(&[("pick_mode.sh", "echo")], "base").await;
+        // the same content, executable
+        let storage = util::objects_storage();
+        let blob = test::save_blob(&storage, b"echo");
+        let tree = test::save_tree(
+            &storage,
+            vec![("pick_mode.sh", TreeItemMode::BlobExecutable, blob)],
+        );
+        let executable = Commit::from_tree_id(tree, vec![base], "executable");
+        save_object(&executable, &executable.id).unwrap();
+        let mode = || fs::metadata("pick_mode.sh").unwrap().permissions().mode();
+        assert_eq!(mode() & 0o111, 0);
+
+        assert!(matches!(
+            cherry_pick(executable.id).await,
+            Ok(ApplyResult::Committed(_))
+        ));
+        assert_eq!(mode() & 0o111, 0o111);
+        let index = Index::load(path::index()).unwrap();
+        assert_eq!(index.get("pick_mode.sh", 0).unwrap().mode, 0o100755);
+        let head = Commit::load(&Head::current_commit().await.unwrap());
+        assert_eq!(head.tree_id, tree);
+    }
+}
@@ -71,7 +71,7 @@ pub async fn execute(args: CloneArgs) {
 
     // CAUTION: change [current_dir] to the repo directory
     env::set_current_dir(&local_path).unwrap();
-    command::init::execute().await;
+    command::init::init().await.unwrap();
 
     /* fetch remote */
     let remote_config = RemoteConfig {
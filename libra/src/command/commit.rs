@@ -2,16 +2,21 @@ use std::str::FromStr;
 use std::{collections::HashSet, path::PathBuf};
 
 use crate::internal::branch::Branch;
+use crate::internal::config::{Config, UserConfig, DEFAULT_BRANCH};
 use crate::internal::head::Head;
+use crate::internal::reflog;
 use crate::utils::client_storage::ClientStorage;
 use crate::utils::path;
 use crate::utils::util;
 use clap::Parser;
 use common::utils::{check_conventional_commits_message, format_commit_msg};
+use mercury::errors::GitError;
 use mercury::hash::SHA1;
 use mercury::internal::index::Index;
 use mercury::internal::object::commit::Commit;
+use mercury::internal::object::signature::{Signature, SignatureType};
 use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use mercury::internal::object::types::ObjectType;
 use mercury::internal::object::ObjectTrait;
 
 use super::save_object;
@@ -28,12 +33,25 @@ pub struct CommitArgs {
     /// check if commit message follows conventional commits
     #[arg(long, requires("message"))]
     pub conventional: bool,
+
+    /// read the commit object back after writing it and check it is intact (always on in debug builds)
+    #[arg(long)]
+    pub verify: bool,
 }
 
 pub async fn execute(args: CommitArgs) {
     /* check args */
     let index = Index::load(path::index()).unwrap();
     let storage = ClientStorage::init(path::objects());
+    let conflicted = index.conflicted_paths();
+    if !conflicted.is_empty() {
+        println!("error: committing is not possible because you have unmerged files:");
+        for path in conflicted {
+            println!("\t{}", path);
+        }
+        println!("fatal: fix them up in the work tree, and then use 'libra add <file>' as appropriate");
+        return;
+    }
     let tracked_entries = index.tracked_entries(0);
     if tracked_entries.is_empty() && !args.allow_empty {
         println!("fatal: no changes added to commit, use --allow-empty to override");
@@ -43,31 +61,110 @@ pub async fn execute(args: CommitArgs) {
         println!("fatal: commit message does not follow conventional commits");
         return;
     }
+    let user = match Config::user_config().await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
 
     /* Create tree */
     let tree = create_tree(&index, &storage, "".into()).await;
 
     /* Create & save commit objects */
-    let parents_commit_ids = get_parents_ids().await;
-    // There must be a `blank line`(\n) before `message`, or remote unpack failed
-    let commit = Commit::from_tree_id(
-        tree.id,
-        parents_commit_ids,
-        &format_commit_msg(&args.message, None),
-    );
+    let parents_commit_ids = get_parents_ids(&[]).await;
+    let commit = create_commit(&storage, tree.id, parents_commit_ids, &args.message, &user);
+    if args.verify || cfg!(debug_assertions) {
+        if let Err(e) = verify_commit(&storage, &commit) {
+            panic!("fatal: commit {} is corrupted in storage: {}", commit.id, e);
+        }
+    }
 
-    // TODO  default signature created in `from_tree_id`, wait `git config` to set correct user info
+    /* update HEAD */
+    let old_commit = Head::current_commit().await;
+    update_head(&commit.id.to_string()).await;
+    log_commit(old_commit, &commit).await;
+}
 
+/// Record the commit in the reflogs of HEAD & of the current branch
+async fn log_commit(old_commit: Option<SHA1>, commit: &Commit) {
+    let kind = match old_commit {
+        None => "commit (initial)",
+        Some(_) => "commit",
+    };
+    log_head_update(old_commit, commit, kind).await;
+}
+
+/// Record `commit`, made by the command `kind` (e.g. `cherry-pick`), in the reflogs of HEAD
+/// & of the current branch
+pub(crate) async fn log_head_update(old_commit: Option<SHA1>, commit: &Commit, kind: &str) {
+    let message = format!("{}: {}", kind, commit.format_message());
+    reflog::log_head(old_commit, commit.id, &message, true).await;
+}
+
+/// Create & save a commit object with ordered parents, authored & committed now by `user`
+/// - `parents`: the order is kept in the commit object, the first parent must be the commit of the branch
+///   that was checked out (HEAD), because first-parent traversal depends on it
+fn create_commit(
+    storage: &ClientStorage,
+    tree_id: SHA1,
+    parents: Vec<SHA1>,
+    message: &str,
+    user: &UserConfig,
+) -> Commit {
+    let commit = new_commit(tree_id, parents, message, user);
     storage
         .put(&commit.id, &commit.to_data().unwrap(), commit.get_type())
         .unwrap();
+    commit
+}
 
-    /* update HEAD */
-    update_head(&commit.id.to_string()).await;
+/// A commit object authored & committed now by `user`, not saved; for every command making commits
+pub(crate) fn new_commit(
+    tree_id: SHA1,
+    parents: Vec<SHA1>,
+    message: &str,
+    user: &UserConfig,
+) -> Commit {
+    let author = Signature::new(SignatureType::Author, user.name.clone(), user.email.clone());
+    // the same time for both, like git
+    let committer = Signature {
+        signature_type: SignatureType::Committer,
+        ..author.clone()
+    };
+    // There must be a `blank line`(\n) before `message`, or remote unpack failed
+    Commit::new(
+        author,
+        committer,
+        tree_id,
+        parents,
+        &format_commit_msg(message, None),
+    )
+}
+
+/// Read the commit back from storage, it must hash to its id and parse to the same tree & parents
+fn verify_commit(storage: &ClientStorage, commit: &Commit) -> Result<(), GitError> {
+    let data = storage.get(&commit.id)?;
+    let id = SHA1::from_type_and_data(ObjectType::Commit, &data);
+    if id != commit.id {
+        return Err(GitError::InvalidObjectInfo(format!(
+            "commit {} reads back with hash {}",
+            commit.id, id
+        )));
+    }
+    let stored = Commit::from_bytes(&data, id)?;
+    if stored.tree_id != commit.tree_id || stored.parent_commit_ids != commit.parent_commit_ids {
+        return Err(GitError::InvalidObjectInfo(format!(
+            "commit {} reads back with different tree or parents",
+            commit.id
+        )));
+    }
+    Ok(())
 }
 
 /// recursively create tree from index's tracked entries
-async fn create_tree(index: &Index, storage: &ClientStorage, current_root: PathBuf) -> Tree {
+pub(crate) async fn create_tree(index: &Index, storage: &ClientStorage, current_root: PathBuf) -> Tree {
     // blob created when add file to index
     let get_blob_entry = |path: &PathBuf| {
         let name = util::path_to_string(path);
@@ -141,20 +238,33 @@ async fn create_tree(index: &Index, storage: &ClientStorage, current_root: PathB
 }
 
 /// get current head commit id as parent, if in branch, get branch's commit id, if detached head, get head's commit id
-async fn get_parents_ids() -> Vec<SHA1> {
+/// - `merge_heads`: the commits being merged, appended in order after the head commit (first parent)
+/// - duplicate parents are removed, keeping the first occurrence
+async fn get_parents_ids(merge_heads: &[SHA1]) -> Vec<SHA1> {
     // let current_commit_id = reference::Model::current_commit_hash(db).await.unwrap();
     let current_commit_id = Head::current_commit().await;
-    match current_commit_id {
-        Some(id) => vec![id],
-        None => vec![], // first commit
+    let mut parents: Vec<SHA1> = current_commit_id.into_iter().collect(); // empty if first commit
+    for id in merge_heads {
+        if !parents.contains(id) {
+            parents.push(*id);
+        }
     }
+    parents
 }
 
 /// update HEAD to new commit, if in branch, update branch's commit id, if detached head, update head's commit id
-async fn update_head(commit_id: &str) {
+/// - the first commit on the initial HEAD creates the branch named by `init.defaultBranch`
+pub(crate) async fn update_head(commit_id: &str) {
     // let head = reference::Model::current_head(db).await.unwrap();
     match Head::current().await {
-        Head::Branch(name) => {
+        Head::Branch(mut name) => {
+            if name == DEFAULT_BRANCH && Branch::list_branches(None).await.is_empty() {
+                let default_branch = Config::default_branch().await;
+                if default_branch != name {
+                    Head::update(Head::Branch(default_branch.clone()), None).await;
+                    name = default_branch;
+                }
+            }
             // in branch
             Branch::update_branch(&name, commit_id, None).await;
         }
@@ -168,6 +278,7 @@ async fn update_head(commit_id: &str) {
 
 #[cfg(test)]
 mod test {
+    use mercury::internal::object::blob::Blob;
     use mercury::internal::object::ObjectTrait;
 
     use crate::{
@@ -176,6 +287,14 @@ mod test {
     };
 
     use super::*;
+
+    fn user() -> UserConfig {
+        UserConfig {
+            name: "mega".to_string(),
+            email: "admin@mega.org".to_string(),
+        }
+    }
+
     #[test]
     fn test_parse_args() {
         let args = CommitArgs::try_parse_from(["commit", "-m", "init"]);
@@ -216,6 +335,79 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn test_merge_commit_parents_order() {
+        test::setup_with_new_libra().await;
+        let storage = ClientStorage::init(path::objects());
+        let tree = Tree::from_bytes(&[], SHA1::default()).unwrap();
+
+        let first = create_commit(&storage, tree.id, vec![], "first", &user());
+        update_head(&first.id.to_string()).await;
+        let second = create_commit(&storage, tree.id, vec![], "second", &user());
+
+        let parents = get_parents_ids(&[second.id, first.id]).await;
+        assert_eq!(parents, vec![first.id, second.id]);
+
+        let merge = create_commit(&storage, tree.id, parents, "merge", &user());
+        let loaded: Commit = load_object(&merge.id).unwrap();
+        assert_eq!(loaded.parent_commit_ids, vec![first.id, second.id]);
+        let reparsed = Commit::from_bytes(&merge.to_data().unwrap(), merge.id).unwrap();
+        assert_eq!(reparsed.parent_commit_ids, vec![first.id, second.id]);
+
+        // the order of parents is part of the commit
+        let parents = vec![second.id, first.id];
+        let reversed = create_commit(&storage, tree.id, parents, "merge", &user());
+        assert_ne!(reversed.to_data().unwrap(), merge.to_data().unwrap());
+    }
+
+    #[test]
+    fn test_verify_commit_detects_corruption() {
+        let (_dir, storage) = test::temp_storage();
+        let tree = Tree::from_bytes(&[], SHA1::default()).unwrap();
+
+        let commit = create_commit(&storage, tree.id, vec![], "intact", &user());
+        assert!(verify_commit(&storage, &commit).is_ok());
+
+        // storage mutating the object on write, e.g. a flipped byte
+        let mut data = commit.to_data().unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0x20;
+        storage.put(&commit.id, &data, ObjectType::Commit).unwrap();
+        let err = verify_commit(&storage, &commit).unwrap_err();
+        assert!(err.to_string().contains(&commit.id.to_string()), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_commit_tree_in_bare_repo() {
+        test::setup_clean_testing_env();
+        crate::command::init::init_repo(true).await.unwrap();
+        let is_bare = util::is_bare();
+        let working_dir = util::try_working_dir();
+        let panicked = std::panic::catch_unwind(util::working_dir).is_err();
+
+        // objects and refs still work without a working tree
+        let blob = Blob::from_content("bare");
+        save_object(&blob, &blob.id).unwrap();
+        let item = TreeItem::new(TreeItemMode::Blob, blob.id, "README.md".to_string());
+        let tree = Tree::from_tree_items(vec![item]).unwrap();
+        save_object(&tree, &tree.id).unwrap();
+        let storage = ClientStorage::init(path::objects());
+        let commit = create_commit(&storage, tree.id, vec![], "bare", &user());
+        update_head(&commit.id.to_string()).await;
+        let head = Head::current_commit().await;
+        let bare_config = Config::get("core", None, "bare").await;
+        // the other tests share the test repository
+        util::set_bare(&util::storage_path(), false);
+
+        assert!(is_bare);
+        assert!(working_dir.unwrap_err().to_string().contains("work tree"));
+        assert!(panicked);
+        assert_eq!(head, Some(commit.id));
+        assert_eq!(bare_config.as_deref(), Some("true"));
+        let loaded: Tree = load_object(&commit.tree_id).unwrap();
+        assert_eq!(loaded.tree_items[0].id, blob.id);
+    }
+
     #[tokio::test]
     #[should_panic]
     async fn test_execute_commit_with_empty_index_fail() {
@@ -224,10 +416,96 @@ mod test {
             message: "init".to_string(),
             allow_empty: false,
             conventional: false,
+            verify: false,
         };
         execute(args).await;
     }
 
+    #[tokio::test]
+    async fn test_commit_identity_from_config() {
+        test::setup_with_new_libra().await;
+        Config::set("user", None, "name", "Ada Lovelace").await;
+        Config::set("user", None, "email", "ada@example.com").await;
+        let mut index = Index::new();
+        index.stage("identity.txt", SHA1::new(b"identity"), 0o100644, None);
+        index.save(path::index()).unwrap();
+
+        let args = || CommitArgs {
+            message: "identity".to_string(),
+            allow_empty: true,
+            conventional: false,
+            verify: false,
+        };
+        execute(args()).await;
+        let head = Head::current_commit().await.unwrap();
+        let commit: Commit = load_object(&head).unwrap();
+        assert_eq!(commit.author.name, "Ada Lovelace");
+        assert_eq!(commit.author.email, "ada@example.com");
+        assert_eq!(commit.author.signature_type, SignatureType::Author);
+        assert_eq!(commit.committer.signature_type, SignatureType::Committer);
+        assert_eq!(commit.committer.name, commit.author.name);
+        assert_eq!(commit.committer.timestamp, commit.author.timestamp);
+        let timezone = chrono::Local::now().format("%z").to_string();
+        assert_eq!(commit.author.timezone, timezone);
+
+        // no commit without an identity
+        Config::set("user", None, "email", "").await;
+        let err = Config::user_config().await.err().unwrap();
+        assert!(err.contains("Please tell me who you are"), "{}", err);
+        assert!(err.ends_with("unable to auto-detect email address"), "{}", err);
+        execute(args()).await;
+        assert_eq!(Head::current_commit().await, Some(head));
+    }
+
+    #[tokio::test]
+    async fn test_commit_blocked_by_conflicts() {
+        test::setup_with_new_libra().await;
+        let (ours, theirs) = (SHA1::new(b"ours"), SHA1::new(b"theirs"));
+        let mut index = Index::new();
+        index.stage("ok.txt", ours, 0o100644, None);
+        index.add_conflict("a.txt", None, Some(ours), Some(theirs));
+        index.save(path::index()).unwrap();
+
+        let args = || CommitArgs {
+            message: "merge".to_string(),
+            allow_empty: false,
+            conventional: false,
+            verify: false,
+        };
+        execute(args()).await;
+        assert!(Head::current_commit().await.is_none(), "commit with conflicts");
+
+        // resolved to stage 0
+        index.stage("a.txt", theirs, 0o100644, None);
+        index.save(path::index()).unwrap();
+        execute(args()).await;
+        let commit: Commit = load_object(&Head::current_commit().await.unwrap()).unwrap();
+        let tree: Tree = load_object(&commit.tree_id).unwrap();
+        assert_eq!(tree.tree_items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_commit_creates_configured_default_branch() {
+        test::setup_with_new_libra().await;
+        Config::insert("init", None, "defaultBranch", "trunk").await;
+
+        execute(CommitArgs {
+            message: "init".to_string(),
+            allow_empty: true,
+            conventional: false,
+            verify: false,
+        })
+        .await;
+
+        match Head::current().await {
+            Head::Branch(name) => assert_eq!(name, "trunk"),
+            _ => panic!("head not in branch"),
+        }
+        let branch = Branch::find_branch("trunk", None).await.unwrap();
+        assert_eq!(Some(branch.commit), Head::current_commit().await);
+        assert!(!Branch::exists(DEFAULT_BRANCH).await);
+    }
+
     #[tokio::test]
     async fn test_execute_commit() {
         test::setup_with_new_libra().await;
@@ -237,6 +515,7 @@ mod test {
                 message: "init".to_string(),
                 allow_empty: true,
                 conventional: false,
+                verify: false,
             };
             execute(args).await;
 
@@ -249,7 +528,7 @@ mod test {
             let branch = Branch::find_branch(&branch_name, None).await.unwrap();
             let commit: Commit = load_object(&branch.commit).unwrap();
 
-            assert_eq!(commit.message, "init");
+            assert_eq!(commit.message(), "init");
             let branch = Branch::find_branch(&branch_name, None).await.unwrap();
             assert_eq!(branch.commit, commit.id);
         }
@@ -274,20 +553,30 @@ mod test {
                 message: "add some files".to_string(),
                 allow_empty: false,
                 conventional: false,
+                verify: false,
             };
             execute(args).await;
 
             let commit_id = Head::current_commit().await.unwrap();
             let commit: Commit = load_object(&commit_id).unwrap();
-            assert_eq!(commit.message, "add some files", "{}", commit.message);
+            assert_eq!(commit.message(), "add some files", "{}", commit.message());
 
             let pre_commit_id = commit.parent_commit_ids[0];
             let pre_commit: Commit = load_object(&pre_commit_id).unwrap();
-            assert_eq!(pre_commit.message, "init");
+            assert_eq!(pre_commit.message(), "init");
 
             let tree_id = commit.tree_id;
             let tree: Tree = load_object(&tree_id).unwrap();
             assert_eq!(tree.tree_items.len(), 2); // 2 subtree according to the test data
+
+            // both commits are in the reflog of HEAD
+            let reflog = reflog::read_reflog(reflog::HEAD).unwrap();
+            assert_eq!(reflog.len(), 2);
+            assert_eq!(reflog[0].message, "commit (initial): init");
+            assert_eq!(reflog[1].old, pre_commit_id);
+            assert_eq!(reflog[1].new, commit_id);
+            assert_eq!(reflog[1].message, "commit: add some files");
+            assert_eq!(reflog[1].identity, "mega <admin@mega.org>");
         }
     }
 }
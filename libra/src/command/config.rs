@@ -0,0 +1,68 @@
+use clap::Parser;
+
+use crate::internal::config::Config;
+
+#[derive(Parser, Debug)]
+pub struct ConfigArgs {
+    /// The option, `<section>.<key>` or `<section>.<name>.<key>`, e.g. `user.name`
+    pub key: String,
+
+    /// The value to set, the current value is printed if omitted
+    pub value: Option<String>,
+}
+
+pub async fn execute(args: ConfigArgs) {
+    let (configuration, name, key) = match parse_key(&args.key) {
+        Ok(parts) => parts,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    match args.value {
+        Some(value) => Config::set(configuration, name, key, &value).await,
+        None => {
+            if let Some(value) = Config::get(configuration, name, key).await {
+                println!("{}", value);
+            }
+        }
+    }
+}
+
+/// Split `remote.origin.url` into the section, the optional name & the key, the name may
+/// contain dots like git's subsections
+fn parse_key(key: &str) -> Result<(&str, Option<&str>, &str), String> {
+    let (section, rest) = key
+        .split_once('.')
+        .ok_or_else(|| format!("error: key does not contain a section: {}", key))?;
+    let (name, option) = match rest.rsplit_once('.') {
+        Some((name, option)) => (Some(name), option),
+        None => (None, rest),
+    };
+    if section.is_empty() || option.is_empty() || name == Some("") {
+        return Err(format!("error: invalid key: {}", key));
+    }
+    Ok((section, name, option))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("user.name"), Ok(("user", None, "name")));
+        assert_eq!(
+            parse_key("remote.origin.url"),
+            Ok(("remote", Some("origin"), "url"))
+        );
+        assert_eq!(
+            parse_key("branch.release.1.0.merge"),
+            Ok(("branch", Some("release.1.0"), "merge"))
+        );
+        assert!(parse_key("name").is_err());
+        assert!(parse_key("user.").is_err());
+        assert!(parse_key(".name").is_err());
+        assert!(parse_key("remote..url").is_err());
+    }
+}
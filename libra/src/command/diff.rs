@@ -120,7 +120,14 @@ pub async fn execute(args: DiffArgs) {
     };
 
     // use pathspec to filter files
-    let paths: Vec<PathBuf> = args.pathspec.iter().map(util::to_workdir_path).collect();
+    let paths = args.pathspec.iter().map(util::try_to_workdir_path);
+    let paths: Vec<PathBuf> = match paths.collect::<Result<_, _>>() {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("fatal: {}, outside repository", e);
+            return;
+        }
+    };
 
     let mut buf: Vec<u8> = Vec::new();
     // filter files, cross old and new files, and pathspec
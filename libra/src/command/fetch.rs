@@ -1,15 +1,12 @@
-use std::io;
 use std::vec;
-use std::{collections::HashSet, fs, io::Write};
+use std::{collections::HashSet, io::Write};
 use std::time::Instant;
 use ceres::protocol::ServiceType::UploadPack;
 use clap::Parser;
 use indicatif::ProgressBar;
 use mercury::internal::object::commit::Commit;
 use mercury::hash::SHA1;
-use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio_util::io::StreamReader;
-use url::Url;
 
 use crate::command::load_object;
 use crate::{
@@ -18,7 +15,7 @@ use crate::{
         branch::Branch,
         config::{Config, RemoteConfig},
         head::Head,
-        protocol::{https_client::HttpsClient, ProtocolClient},
+        protocol::{https_client::HttpsClient, sideband::SidebandReader, transport, ProtocolClient},
     },
     utils::{self, path_ext::PathExt},
 };
@@ -82,10 +79,11 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
             });
 
     // fetch remote
-    let url = match Url::parse(&remote_config.url) {
+    // `p2p://` remotes are fetched over HTTP through a tunnel to the peer
+    let url = match transport::transport_url(&remote_config.url).await {
         Ok(url) => url,
         Err(e) => {
-            eprintln!("fatal: invalid URL '{}': {}", remote_config.url, e);
+            eprintln!("{}", e);
             return;
         }
     };
@@ -135,47 +133,32 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
 
     let mut reader = StreamReader::new(&mut result_stream);
     let mut pack_data = Vec::new();
-    let mut reach_pack = false;
     let bar = ProgressBar::new_spinner();
     let time = Instant::now();
+    // Side-Band Capability, progress of the server is printed as it comes
+    let mut sideband = SidebandReader::new(&mut reader, |progress: &str| {
+        print!("{}", progress); // progress contains '\r' & '\n' at end
+        std::io::stdout().flush().unwrap();
+    });
     loop {
-        let (len, data) = read_pkt_line(&mut reader).await.unwrap();
-        if len == 0 {
-            break;
-        }
-        if data.len() >= 5 && &data[1..5] == b"PACK" {
-            reach_pack = true;
-            tracing::debug!("Receiving PACK data...");
-        }
-        if reach_pack { // 2.PACK data
-            let bytes_per_sec = pack_data.len() as f64 / time.elapsed().as_secs_f64();
-            let total = util::auto_unit_bytes(pack_data.len() as u64);
-            let bps = util::auto_unit_bytes(bytes_per_sec as u64);
-            bar.set_message(format!("Receiving objects: {total:.2} | {bps:.2}/s"));
-            bar.tick();
-            // Side-Band Capability, should be enabled if Server Support
-            let code = data[0];
-            let data = &data[1..];
-            match code {
-                1 => { // Data
-                    pack_data.extend(data); // TODO: decode meanwhile & calc progress
-                }
-                2 => { // Progress
-                    print!("{}", String::from_utf8_lossy(data));
-                    std::io::stdout().flush().unwrap();
-                }
-                3 => { // Error
-                    eprintln!("{}", String::from_utf8_lossy(data));
-                }
-                _ => {
-                    eprintln!("unknown side-band-64k code: {}", code);
-                }
+        match sideband.next_pack_data().await {
+            Ok(Some(data)) => {
+                pack_data.extend(data); // TODO: decode meanwhile & calc progress
+                let bytes_per_sec = pack_data.len() as f64 / time.elapsed().as_secs_f64();
+                let total = util::auto_unit_bytes(pack_data.len() as u64);
+                let bps = util::auto_unit_bytes(bytes_per_sec as u64);
+                bar.set_message(format!("Receiving objects: {total:.2} | {bps:.2}/s"));
+                bar.tick();
+            }
+            Ok(None) => break,
+            Err(e) => {
+                // an error of the remote (band 3) aborts the fetch
+                bar.abandon();
+                eprintln!("fatal: {}", e);
+                return;
             }
-        } else if &data != b"NAK\n" { // 1.front info (server progress), ignore NAK (first line)
-            print!("{}", String::from_utf8_lossy(&data)); // data contains '\r' & '\n' at end
-            std::io::stdout().flush().unwrap();
         }
-    };
+    }
     bar.finish();
 
     /* save pack file */
@@ -191,8 +174,8 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
             let pack_file = utils::path::objects()
                 .join("pack")
                 .join(format!("pack-{}.pack", checksum));
-            let mut file = fs::File::create(pack_file.clone()).unwrap();
-            file.write_all(&pack_data).expect("write failed");
+            utils::fsync::write_file(&pack_file, &pack_data, utils::fsync::configured_sync())
+                .expect("write failed");
 
             Some(pack_file.to_string_or_panic())
         } else {
@@ -292,22 +275,3 @@ async fn current_have() -> Vec<String> {
 
     have
 }
-
-/// Read 4 bytes hex number
-async fn read_hex_4(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<u32> {
-    let mut buf = [0u8; 4];
-    reader.read_exact(&mut buf).await?;
-    let hex_str = std::str::from_utf8(&buf).unwrap();
-    u32::from_str_radix(hex_str, 16).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-}
-/// async version of `read_pkt_line`
-/// - return (raw length, data)
-async fn read_pkt_line(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<(usize, Vec<u8>)> {
-    let len = read_hex_4(reader).await?;
-    if len == 0 {
-        return Ok((0, Vec::new()));
-    }
-    let mut data = vec![0u8; (len - 4) as usize];
-    reader.read_exact(&mut data).await?;
-    Ok((len as usize, data))
-}
\ No newline at end of file
@@ -1,15 +1,19 @@
 use std::collections::BTreeMap;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use clap::Parser;
 use sha1::{Digest, Sha1};
 
+use mercury::internal::pack::decoder::PackDecoder;
+use mercury::internal::pack::index::{self, IdxEntry};
 use mercury::internal::pack::Pack;
 use mercury::errors::GitError;
 
+use crate::utils::fsync;
+
 #[derive(Parser, Debug)]
 pub struct IndexPackArgs {
     /// Pack file path
@@ -46,7 +50,7 @@ pub fn execute(args: IndexPackArgs) {
     if let Some(version) = args.index_version {
         match version {
             1 => build_index_v1(&pack_file, &index_file).unwrap(),
-            2 => println!("support later"),
+            2 => build_index_v2(&pack_file, &index_file).unwrap(),
             _ => eprintln!("fatal: unsupported index version"),
         }
     } else {
@@ -70,7 +74,7 @@ pub fn build_index_v1(pack_file: &str, index_file: &str) -> Result<(), GitError>
     })?;
 
     let mut index_hash = Sha1::new();
-    let mut index_file = std::fs::File::create(index_file)?;
+    let mut index_data = Vec::new();
     // fan-out table
     // The header consists of 256 4-byte network byte order integers.
     // N-th entry of this table records the number of objects in the corresponding pack,
@@ -97,7 +101,7 @@ pub fn build_index_v1(pack_file: &str, index_file: &str) -> Result<(), GitError>
         i += 1;
     }
     index_hash.update(&fan_out);
-    index_file.write_all(&fan_out)?;
+    index_data.write_all(&fan_out)?;
 
     // 4-byte network byte order integer, recording where the
     // object is stored in the pack-file as the offset from the beginning.
@@ -108,16 +112,37 @@ pub fn build_index_v1(pack_file: &str, index_file: &str) -> Result<(), GitError>
         buf.write_all(&hash.0)?;
 
         index_hash.update(&buf);
-        index_file.write_all(&buf)?;
+        index_data.write_all(&buf)?;
     }
 
     index_hash.update(pack.signature.0);
     // A copy of the pack checksum at the end of the corresponding pack-file.
-    index_file.write_all(&pack.signature.0)?;
+    index_data.write_all(&pack.signature.0)?;
     let index_hash:[u8; 20] = index_hash.finalize().into();
     // Index checksum of all of the above.
-    index_file.write_all(&index_hash)?;
+    index_data.write_all(&index_hash)?;
+    fsync::write_file(Path::new(index_file), &index_data, fsync::configured_sync())?;
+
+    tracing::debug!("Index file is written to {:?}", index_file);
+    Ok(())
+}
 
+/// Build index file for pack file, version 2, with the CRC32 of the objects & 64-bit offsets
+/// [pack-format](https://git-scm.com/docs/pack-format)
+pub fn build_index_v2(pack_file: &str, index_file: &str) -> Result<(), GitError> {
+    let pack_path = PathBuf::from(pack_file);
+    let tmp_path = pack_path.parent().unwrap().to_path_buf();
+    let pack_reader = std::io::BufReader::new(std::fs::File::open(pack_file)?);
+    let mut decoder = PackDecoder::new(pack_reader, Some(1024 * 1024 * 1024), Some(tmp_path))?;
+    let mut entries = Vec::with_capacity(decoder.number());
+    while let Some(obj) = decoder.next() {
+        let obj = obj?;
+        entries.push(IdxEntry::new(&obj, decoder.crc32(obj.offset).unwrap()));
+    }
+    let signature = decoder.signature().unwrap();
+
+    let index_data = index::build_index(entries, signature)?;
+    fsync::write_file(Path::new(index_file), &index_data, fsync::configured_sync())?;
     tracing::debug!("Index file is written to {:?}", index_file);
     Ok(())
-}
\ No newline at end of file
+}
@@ -5,17 +5,26 @@
 // Import necessary standard libraries
 use std::{env, fs, io};
 
+use clap::Parser;
 // Import necessary libraries from sea_orm
 use sea_orm::{ActiveModelTrait, DbConn, DbErr, Set, TransactionTrait};
 
 // Import necessary modules from the internal crate
+use crate::internal::config::DEFAULT_BRANCH;
 use crate::internal::db;
 use crate::internal::model::{config, reference};
-use crate::utils::util::{DATABASE, ROOT_DIR};
+use crate::utils::util::{self, DATABASE};
+
+#[derive(Parser, Debug, Default)]
+pub struct InitArgs {
+    /// Create a bare repository, without a working tree
+    #[arg(long)]
+    pub bare: bool,
+}
 
 /// Execute the init function
-pub async fn execute() {
-    init().await.unwrap();
+pub async fn execute(args: InitArgs) {
+    init_repo(args.bare).await.unwrap();
 }
 
 /// Initialize a new Libra repository
@@ -23,10 +32,16 @@ pub async fn execute() {
 /// It also sets up the database and the initial configuration.
 #[allow(dead_code)]
 pub async fn init() -> io::Result<()> {
+    init_repo(false).await
+}
+
+/// Initialize a new Libra repository, bare if `bare` (`core.bare`): objects and refs only, any
+/// operation on the working tree fails
+pub async fn init_repo(bare: bool) -> io::Result<()> {
     // Get the current directory
     let cur_dir = env::current_dir()?;
     // Join the current directory with the root directory
-    let root_dir = cur_dir.join(ROOT_DIR);
+    let root_dir = cur_dir.join(util::root_dir_name());
     // Check if the root directory already exists
     if root_dir.exists() {
         println!("Already initialized - [{}]", root_dir.display());
@@ -55,11 +70,12 @@ pub async fn init() -> io::Result<()> {
     let conn = db::create_database(database.to_str().unwrap()).await?;
 
     // Create config table
-    init_config(&conn).await.unwrap();
+    init_config(&conn, bare).await.unwrap();
+    util::set_bare(&root_dir, bare);
 
     // Create HEAD
     reference::ActiveModel {
-        name: Set(Some("master".to_owned())),
+        name: Set(Some(DEFAULT_BRANCH.to_owned())),
         kind: Set(reference::ConfigKind::Head),
         ..Default::default() // all others are `NotSet`
     }
@@ -70,7 +86,8 @@ pub async fn init() -> io::Result<()> {
     // Set .libra as hidden
     set_dir_hidden(root_dir.to_str().unwrap())?;
     println!(
-        "Initializing empty Libra repository in {}",
+        "Initializing empty {}Libra repository in {}",
+        if bare { "bare " } else { "" },
         root_dir.display()
     );
 
@@ -79,16 +96,17 @@ pub async fn init() -> io::Result<()> {
 
 /// Initialize the configuration for the Libra repository
 /// This function creates the necessary configuration entries in the database.
-async fn init_config(conn: &DbConn) -> Result<(), DbErr> {
+async fn init_config(conn: &DbConn, bare: bool) -> Result<(), DbErr> {
     // Begin a new transaction
     let txn = conn.begin().await?;
+    let bare = if bare { "true" } else { "false" };
 
     // Define the configuration entries for non-Windows systems
     #[cfg(not(target_os = "windows"))]
         let entries = [
         ("repositoryformatversion", "0"),
         ("filemode", "true"),
-        ("bare", "false"),
+        ("bare", bare),
         ("logallrefupdates", "true"),
     ];
 
@@ -97,7 +115,7 @@ async fn init_config(conn: &DbConn) -> Result<(), DbErr> {
         let entries = [
         ("repositoryformatversion", "0"),
         ("filemode", "false"), // no filemode on windows
-        ("bare", "false"),
+        ("bare", bare),
         ("logallrefupdates", "true"),
         ("symlinks", "false"),  // no symlinks on windows
         ("ignorecase", "true"), // ignorecase on windows
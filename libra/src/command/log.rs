@@ -1,9 +1,12 @@
-use std::cmp::min;
 use std::collections::HashSet;
 
 use crate::command::load_object;
 use crate::internal::branch::Branch;
+use crate::internal::commit_signature::{verify_commit_signature, GpgKeyring, SignatureStatus};
+use crate::internal::commit_walk::CommitWalk;
 use crate::internal::head::Head;
+use crate::internal::rev_parse::rev_parse;
+use crate::utils::util;
 use clap::Parser;
 use colored::Colorize;
 #[cfg(unix)]
@@ -17,11 +20,26 @@ use mercury::hash::SHA1;
 use mercury::internal::object::commit::Commit;
 
 use common::utils::parse_commit_msg;
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Default)]
 pub struct LogArgs {
-    /// Limit the number of output
-    #[clap(short, long)]
-    pub number: Option<usize>,
+    /// The commit to start from, `HEAD` by default
+    pub revision: Option<String>,
+
+    /// Limit the number of commits to output
+    #[clap(short = 'n', long = "max-count", alias = "number")]
+    pub max_count: Option<usize>,
+
+    /// Show each commit on a single line: the abbreviated hash and the subject
+    #[clap(long)]
+    pub oneline: bool,
+
+    /// Follow only the first parent of merge commits
+    #[clap(long)]
+    pub first_parent: bool,
+
+    /// Check the signature of signed commits with gpg
+    #[clap(long)]
+    pub show_signature: bool,
 }
 
 ///  Get all reachable commits from the given commit hash
@@ -51,80 +69,119 @@ pub async fn get_reachable_commits(commit_hash: String) -> Vec<Commit> {
     reachable_commits
 }
 
+/// The commits shown by `args`, newest first
+pub async fn log_commits(args: &LogArgs) -> Result<Vec<Commit>, String> {
+    let revision = args.revision.as_deref().unwrap_or("HEAD");
+    if revision == "HEAD" {
+        // check if the current branch has any commits
+        if let Head::Branch(branch_name) = Head::current().await {
+            if Branch::find_branch(&branch_name, None).await.is_none() {
+                return Err(format!(
+                    "your current branch '{}' does not have any commits yet",
+                    branch_name
+                ));
+            }
+        }
+    }
+    let start = rev_parse(revision).await.map_err(|e| e.to_string())?;
+
+    let walk = CommitWalk::new(util::objects_storage(), start)
+        .map_err(|e| e.to_string())?
+        .first_parent(args.first_parent);
+    walk.take(args.max_count.unwrap_or(usize::MAX))
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())
+}
+
+/// `<abbreviated hash> <subject>`
+pub fn format_oneline(commit: &Commit) -> String {
+    format!(
+        "{} {}",
+        commit.id.to_string()[..7].yellow(),
+        commit.format_message()
+    )
+}
+
+/// Hash, author, date & message of the commit, `head` decorates it as the commit of HEAD.
+/// `signature` is shown for a signed commit.
+fn format_commit(
+    commit: &Commit,
+    head: Option<&Head>,
+    signature: Option<SignatureStatus>,
+) -> String {
+    let mut message = format!("{} {}", "commit".yellow(), &commit.id.to_string().yellow());
+
+    // TODO other branch's head should shown branch name
+    if let Some(head) = head {
+        message = format!("{} {}{}", message, "(".yellow(), "HEAD".blue());
+        if let Head::Branch(name) = head {
+            message = format!("{}{}{}", message, " -> ".blue(), name.green());
+        }
+        message = format!("{}{}", message, ")".yellow());
+    }
+    match signature {
+        Some(SignatureStatus::None) | None => {}
+        Some(status @ SignatureStatus::Good) => {
+            message.push_str(&format!("\n{}", status.to_string().green()))
+        }
+        Some(status) => message.push_str(&format!("\n{}", status.to_string().red())),
+    }
+    // `Signature` displays the date on its second line
+    message.push_str(&format!("\nAuthor: {}", commit.author));
+    if let Some(encoding) = commit.encoding() {
+        message.push_str(&format!("Encoding: {}\n", encoding));
+    }
+    let (msg, _) = parse_commit_msg(commit.message_without_encoding());
+    message.push_str(&format!("\n{}\n", msg));
+    message
+}
+
 pub async fn execute(args: LogArgs) {
+    let commits = match log_commits(&args).await {
+        Ok(commits) => commits,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    // only decorate HEAD when it's the starting point
+    let head = match args.revision {
+        None => Some(Head::current().await),
+        Some(_) => None,
+    };
+
+    // without less, the commits are printed directly
     #[cfg(unix)]
-    let mut process = Command::new("less") // create a pipe to less
+    let mut pager = Command::new("less") // create a pipe to less
         .arg("-R") // raw control characters
-        .arg("-F") 
+        .arg("-F")
         .stdin(Stdio::piped())
         .stdout(Stdio::inherit())
         .spawn()
-        .expect("failed to execute process");
-
-    let head = Head::current().await;
-    // check if the current branch has any commits
-    if let Head::Branch(branch_name) = head.to_owned() {
-        let branch = Branch::find_branch(&branch_name, None).await;
-        if branch.is_none() {
-            panic!(
-                "fatal: your current branch '{}' does not have any commits yet ",
-                branch_name
-            );
-        }
-    }
+        .ok();
 
-    let commit_hash = Head::current_commit().await.unwrap().to_string();
-
-    let mut reachable_commits = get_reachable_commits(commit_hash.clone()).await;
-    // default sort with signature time
-    reachable_commits.sort_by(|a, b| b.committer.timestamp.cmp(&a.committer.timestamp));
-
-    let max_output_number = min(args.number.unwrap_or(usize::MAX), reachable_commits.len());
-    let mut output_number = 0;
-    for commit in reachable_commits {
-        if output_number >= max_output_number {
-            break;
-        }
-        output_number += 1;
-        let mut message = {
-            let mut message = format!(
-                "{} {}",
-                "commit".yellow(),
-                &commit.id.to_string().yellow()
-            );
-
-            // TODO other branch's head should shown branch name
-            if output_number == 1 {
-                message = format!("{} {}{}", message, "(".yellow(), "HEAD".blue());
-                if let Head::Branch(name) = head.to_owned() {
-                    // message += &"-> ".blue();
-                    // message += &head.name.as_ref().unwrap().green();
-                    message = format!("{}{}{}", message, " -> ".blue(), name.green());
-                }
-                message = format!("{}{}", message, ")".yellow());
-            }
-            message
+    for (i, commit) in commits.iter().enumerate() {
+        let message = if args.oneline {
+            format_oneline(commit)
+        } else {
+            let signature = args
+                .show_signature
+                .then(|| verify_commit_signature(commit, &GpgKeyring));
+            format_commit(commit, head.as_ref().filter(|_| i == 0), signature)
         };
-        message.push_str(&format!("\nAuthor: {}", commit.author));
-        let (msg, _) = parse_commit_msg(&commit.message);
-        message.push_str(&format!("\n{}\n", msg));
 
         #[cfg(unix)]
-        {
-            if let Some(ref mut stdin) = process.stdin {
-                writeln!(stdin, "{}", message).unwrap();
-            } else {
-                eprintln!("Failed to capture stdin");
+        if let Some(stdin) = pager.as_mut().and_then(|pager| pager.stdin.as_mut()) {
+            if writeln!(stdin, "{}", message).is_err() {
+                break; // the pager was quit before the end
             }
+            continue;
         }
-        #[cfg(not(unix))]
-        {
-            println!("{}", message);
-        }
+        println!("{}", message);
     }
     #[cfg(unix)]
-    {
-        let _ = process.wait().expect("failed to wait on child");
+    if let Some(mut pager) = pager {
+        let _ = pager.wait();
     }
 }
 
@@ -149,10 +206,71 @@ mod tests {
         test::setup_with_new_libra().await;
         let _ = create_test_commit_tree().await;
 
-        let args = LogArgs { number: Some(6) };
+        let args = LogArgs {
+            max_count: Some(6),
+            ..Default::default()
+        };
         execute(args).await;
     }
 
+    #[tokio::test]
+    async fn test_log_max_count() {
+        test::setup_with_new_libra().await;
+        let _ = create_test_commit_tree().await;
+
+        let all = log_commits(&LogArgs::default()).await.unwrap();
+        assert_eq!(all.len(), 6);
+        // newest first
+        assert!(all
+            .windows(2)
+            .all(|w| w[0].committer.timestamp >= w[1].committer.timestamp));
+        for max_count in [0, 1, 3, 6, 10] {
+            let args = LogArgs {
+                max_count: Some(max_count),
+                ..Default::default()
+            };
+            let commits = log_commits(&args).await.unwrap();
+            assert_eq!(commits.len(), max_count.min(6));
+            assert_eq!(commits[..], all[..commits.len()]);
+        }
+
+        // 6 -> 3 -> 2 -> 1
+        let args = LogArgs {
+            first_parent: true,
+            ..Default::default()
+        };
+        let subjects: Vec<String> = log_commits(&args)
+            .await
+            .unwrap()
+            .iter()
+            .map(Commit::format_message)
+            .collect();
+        assert_eq!(subjects, ["Commit_6", "Commit_3", "Commit_2", "Commit_1"]);
+
+        let args = LogArgs {
+            revision: Some("HEAD~2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(log_commits(&args).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_log_oneline() {
+        test::setup_with_new_libra().await;
+        let head = create_test_commit_tree().await;
+
+        let args = LogArgs {
+            max_count: Some(1),
+            oneline: true,
+            ..Default::default()
+        };
+        let commits = log_commits(&args).await.unwrap();
+        let line = format_oneline(&commits[0]);
+        assert!(line.contains(&head[..7]), "{}", line);
+        assert!(!line.contains(&head[..8]), "{}", line);
+        assert!(line.ends_with(" Commit_6"), "{}", line);
+    }
+
     /// create a test commit tree structure as graph and create branch (master) head to commit 6
     /// return a commit hash of commit 6
     ///            3   6
@@ -1,7 +1,9 @@
 pub mod add;
 pub mod branch;
+pub mod cherry_pick;
 pub mod clone;
 pub mod commit;
+pub mod config;
 pub mod diff;
 pub mod fetch;
 pub mod index_pack;
@@ -11,26 +13,31 @@ pub mod log;
 pub mod merge;
 pub mod pull;
 pub mod push;
+pub mod rebase;
 pub mod remote;
 pub mod remove;
 pub mod restore;
+pub mod revert;
+pub mod stash;
 pub mod status;
 pub mod switch;
 
-use crate::internal::branch::Branch;
-use crate::internal::head::Head;
 use crate::internal::protocol::https_client::BasicAuth;
+use crate::internal::rev_parse::rev_parse;
 use crate::utils;
-use crate::utils::object_ext::BlobExt;
 use crate::utils::util;
 use mercury::internal::object::blob::Blob;
+use mercury::internal::object::types::ObjectType;
 use mercury::{errors::GitError, hash::SHA1, internal::object::ObjectTrait};
 use rpassword::read_password;
+use sha1::{Digest, Sha1};
+use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::Path;
 
-const HEAD: &str = "HEAD";
+/// Chunk size for streaming file content into the hasher
+const HASH_CHUNK_SIZE: usize = 64 * 1024;
 
 // impl load for all objects
 fn load_object<T>(hash: &SHA1) -> Result<T, GitError>
@@ -88,41 +95,44 @@ pub fn ask_basic_auth() -> BasicAuth {
 
 /// Calculate the hash of a file blob
 /// - for `lfs` file: calculate hash of the pointer data
+/// - for other files: stream the content through the hasher, so that huge files are not loaded into memory
 pub fn calc_file_blob_hash(path: impl AsRef<Path>) -> io::Result<SHA1> {
-    let blob = if utils::lfs::is_lfs_tracked(&path) {
+    if utils::lfs::is_lfs_tracked(&path) {
         let (pointer, _) = utils::lfs::generate_pointer_file(&path);
-        Blob::from_content(&pointer)
-    } else {
-        Blob::from_file(&path)
-    };
-    Ok(blob.id)
-}
-
-/// Get the commit hash from branch name or commit hash, support remote branch
-pub async fn get_target_commit(branch_or_commit: &str) -> Result<SHA1, Box<dyn std::error::Error>> {
-    if branch_or_commit == HEAD {
-        return Ok(Head::current_commit().await.unwrap());
+        return Ok(Blob::from_content(&pointer).id);
     }
 
-    let possible_branches = Branch::search_branch(branch_or_commit).await;
-    if possible_branches.len() > 1 {
-        return Err("Ambiguous branch name".into());
-        // TODO: git have a priority list of branches to use, continue with ambiguity, we didn't implement it yet
+    let mut file = fs::File::open(&path)?;
+    let len = file.metadata()?.len();
+    let mut hasher = Sha1::new();
+    // git blob header: `blob <len>\0`, the length must be known before hashing the content
+    hasher.update(format!("{} {}\0", ObjectType::Blob, len));
+
+    let mut buf = vec![0; HASH_CHUNK_SIZE];
+    let mut total = 0;
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+        total += n as u64;
     }
-
-    if possible_branches.is_empty() {
-        let storage = util::objects_storage();
-        let possible_commits = storage.search(branch_or_commit);
-        if possible_commits.len() > 1 {
-            return Err(format!("Ambiguous commit hash '{}'", branch_or_commit).into());
-        }
-        if possible_commits.is_empty() {
-            return Err(format!("No such branch or commit: '{}'", branch_or_commit).into());
-        }
-        Ok(possible_commits[0])
-    } else {
-        Ok(possible_branches[0].commit)
+    if total != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("file {:?} changed while hashing", path.as_ref()),
+        ));
     }
+    Ok(SHA1::from_bytes(hasher.finalize().as_slice()))
+}
+
+/// Get the commit hash from branch name or commit hash, support remote branch
+/// - any revision understood by [rev_parse] is accepted, e.g. `HEAD~2`, a tag
+pub async fn get_target_commit(branch_or_commit: &str) -> Result<SHA1, Box<dyn std::error::Error>> {
+    Ok(rev_parse(branch_or_commit).await?)
 }
 
 #[cfg(test)]
@@ -131,6 +141,7 @@ mod test {
     use mercury::internal::object::commit::Commit;
 
     use super::*;
+    use crate::utils::object_ext::BlobExt;
     use crate::utils::test;
     #[tokio::test]
     async fn test_save_load_object() {
@@ -140,6 +151,24 @@ mod test {
         let _ = load_object::<Commit>(&object.id).unwrap();
     }
 
+    #[tokio::test]
+    async fn test_calc_file_blob_hash_streaming() {
+        test::setup_with_new_libra().await;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("large_file");
+        // larger than several chunks, and not aligned to the chunk size
+        let content: Vec<u8> = (0..HASH_CHUNK_SIZE * 40 + 123).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &content).unwrap();
+
+        let streamed = calc_file_blob_hash(&path).unwrap();
+        assert_eq!(streamed, Blob::from_file(&path).id);
+        assert_eq!(streamed, Blob::from_content_bytes(content).id);
+
+        let empty = temp_dir.path().join("empty_file");
+        fs::write(&empty, b"").unwrap();
+        assert_eq!(calc_file_blob_hash(&empty).unwrap(), Blob::from_content_bytes(vec![]).id);
+    }
+
     #[test]
     fn test_format_and_parse_commit_msg() {
         {
@@ -0,0 +1,315 @@
+//! Replay the commits of the current branch on another base, like a non-interactive `git rebase`.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::Parser;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+
+use crate::command::cherry_pick::{pick, print_conflicts, ApplyResult};
+use crate::command::get_target_commit;
+use crate::command::restore::{self, RestoreArgs};
+use crate::command::status;
+use crate::internal::branch::Branch;
+use crate::internal::commit_walk::CommitWalk;
+use crate::internal::config::Config;
+use crate::internal::head::Head;
+use crate::internal::reflog::{self, ReflogEntry};
+use crate::utils::object_ext::CommitExt;
+use crate::utils::{path, util};
+
+#[derive(Parser, Debug)]
+pub struct RebaseArgs {
+    /// The commits of HEAD which are not in `upstream` are replayed
+    pub upstream: String,
+    /// The new base, `upstream` by default
+    #[clap(long)]
+    pub onto: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebaseResult {
+    /// all the commits are replayed, the branch (or HEAD if detached) points at this new tip
+    Done(SHA1),
+    /// replaying `commit` conflicts, nothing is committed for it: HEAD is detached on the commits
+    /// replayed before it, and the branch is unchanged. The rebase is saved in `rebase-merge`
+    Conflicts {
+        commit: SHA1,
+        conflicts: Vec<PathBuf>,
+    },
+}
+
+pub async fn execute(args: RebaseArgs) {
+    let from = match get_target_commit(&args.upstream).await {
+        Ok(commit) => commit,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    let onto = match args.onto {
+        Some(onto) => match get_target_commit(&onto).await {
+            Ok(commit) => commit,
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        },
+        None => from,
+    };
+    match rebase(onto, from).await {
+        Ok(RebaseResult::Done(tip)) => {
+            println!("Successfully rebased onto {}, now at {}", onto, tip)
+        }
+        Ok(RebaseResult::Conflicts { commit, conflicts }) => {
+            let subject = Commit::load(&commit).format_message();
+            print_conflicts("apply", &commit, &subject, &conflicts);
+            eprintln!(
+                "hint: HEAD is detached on the commits replayed so far, the branch is unchanged"
+            );
+        }
+        Err(e) => eprintln!("fatal: {}", e),
+    }
+}
+
+/// The commits to replay: every commit reachable from HEAD but not from `from`, parents before
+/// their children. As git without `--rebase-merges`, the merge commits are dropped and the
+/// commits of all their sides are replayed in a line.
+fn commits_to_replay(head: SHA1, from: SHA1) -> Result<Vec<SHA1>, String> {
+    let storage = util::objects_storage();
+    let upstream = CommitWalk::new(storage, from)
+        .map_err(|e| e.to_string())?
+        .map(|commit| commit.map(|commit| commit.id))
+        .collect::<Result<HashSet<SHA1>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    // depth first, a commit is listed once all its parents are
+    let mut commits = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![(head, false)];
+    while let Some((id, parents_pushed)) = stack.pop() {
+        if parents_pushed {
+            if Commit::load(&id).parent_commit_ids.len() <= 1 {
+                commits.push(id);
+            }
+            continue;
+        }
+        if upstream.contains(&id) || !visited.insert(id) {
+            continue;
+        }
+        stack.push((id, true));
+        // the first parent on top, so its side is replayed first
+        let parents = Commit::load(&id).parent_commit_ids;
+        stack.extend(parents.into_iter().rev().map(|parent| (parent, false)));
+    }
+    Ok(commits)
+}
+
+/// Keep where a rebase stopped, in the files git uses: the branch rebased (`head-name`, or
+/// `detached HEAD`), the commit it pointed at (`orig-head`) and the new base (`onto`)
+fn save_state(branch: Option<&str>, head: SHA1, onto: SHA1) -> io::Result<()> {
+    let dir = path::rebase_merge();
+    fs::create_dir_all(&dir)?;
+    let head_name = branch.map_or("detached HEAD".to_string(), reflog::branch_ref);
+    fs::write(dir.join("head-name"), head_name + "\n")?;
+    fs::write(dir.join("orig-head"), format!("{}\n", head))?;
+    fs::write(dir.join("onto"), format!("{}\n", onto))
+}
+
+/// Replay the commits of `(from..HEAD]` on `onto` in order, by cherry-picking each one, then
+/// move the branch to the new tip. Commits whose changes are already in `onto` are dropped.
+/// Stops at the first conflict, see [RebaseResult::Conflicts].
+/// - the working tree & the index must be clean
+pub async fn rebase(onto: SHA1, from: SHA1) -> Result<RebaseResult, String> {
+    let head = Head::current_commit()
+        .await
+        .ok_or("cannot rebase a branch without commits")?;
+    let user = Config::user_config().await?;
+    let current = status::status().await;
+    if !current.staged.is_empty() || !current.unstaged.is_empty() {
+        return Err("cannot rebase: you have local changes, commit or stash them".to_string());
+    }
+    let commits = commits_to_replay(head, from)?;
+    let branch = match Head::current().await {
+        Head::Branch(name) => Some(name),
+        Head::Detached(_) => None,
+    };
+    // there's no `--continue`, a stopped rebase is replaced
+    let state = path::rebase_merge();
+    if state.exists() {
+        fs::remove_dir_all(&state).map_err(|e| e.to_string())?;
+    }
+
+    // replay on a detached HEAD, so the branch only moves once all commits are replayed
+    restore::execute(RestoreArgs {
+        worktree: true,
+        staged: true,
+        source: Some(onto.to_string()),
+        pathspec: vec![util::working_dir_string()],
+    })
+    .await;
+    Head::update(Head::Detached(onto), None).await;
+    let message = format!("rebase (start): checkout {}", onto);
+    reflog::log_head(Some(head), onto, &message, false).await;
+    let mut tip = onto;
+    for commit in commits {
+        match pick(commit, tip, &user, "rebase (pick)").await? {
+            Some(ApplyResult::Committed(picked)) => tip = picked,
+            Some(ApplyResult::Conflicts(conflicts)) => {
+                save_state(branch.as_deref(), head, onto).map_err(|e| e.to_string())?;
+                return Ok(RebaseResult::Conflicts { commit, conflicts });
+            }
+            None => {} // already in the new base
+        }
+    }
+
+    if let Some(branch) = branch {
+        let reference = reflog::branch_ref(&branch);
+        Branch::update_branch(&branch, &tip.to_string(), None).await;
+        Head::update(Head::Branch(branch), None).await;
+        // as git: the branch moved from its old head, HEAD only went back to it
+        let entry = ReflogEntry::new(
+            Some(head),
+            tip,
+            &reflog::identity().await,
+            &format!("rebase (finish): {} onto {}", reference, onto),
+        );
+        reflog::log_update(&reference, &entry);
+        let message = format!("rebase (finish): returning to {}", reference);
+        reflog::log_head(Some(tip), tip, &message, false).await;
+    }
+    Ok(RebaseResult::Done(tip))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::command::save_object;
+    use crate::command::switch::{self, SwitchArgs};
+    use crate::utils::test;
+
+    #[tokio::test]
+    async fn test_rebase() {
+        test::setup_with_new_libra().await;
+        // base <- upstream_1 <- upstream_2 on master, base <- topic_1 <- topic_2 on topic
+        let base = test::commit_files(&[("rebase_base.txt", "base")], "base").await;
+        test::commit_files(&[("rebase_upstream.txt", "1")], "upstream 1").await;
+        let upstream = test::commit_files(&[("rebase_upstream.txt", "2")], "upstream 2").await;
+        switch::execute(SwitchArgs::parse_from([
+            "switch",
+            "--create",
+            "rebase_topic",
+            &base.to_string(),
+        ]))
+        .await;
+        test::commit_files(&[("rebase_topic.txt", "1")], "topic 1").await;
+        let old_tip = test::commit_files(&[("rebase_topic.txt", "2")], "topic 2").await;
+
+        let tip = match rebase(upstream, upstream).await.unwrap() {
+            RebaseResult::Done(tip) => tip,
+            conflicts => panic!("{:?}", conflicts),
+        };
+        assert!(matches!(Head::current().await, Head::Branch(name) if name == "rebase_topic"));
+        assert_eq!(
+            Branch::find_branch("rebase_topic", None)
+                .await
+                .unwrap()
+                .commit,
+            tip
+        );
+        // replayed in order on upstream
+        let topic_2 = Commit::load(&tip);
+        assert_eq!(topic_2.format_message(), "topic 2");
+        let topic_1 = Commit::load(&topic_2.parent_commit_ids[0]);
+        assert_eq!(topic_1.format_message(), "topic 1");
+        assert_eq!(topic_1.parent_commit_ids, [upstream]);
+        assert_eq!(fs::read_to_string("rebase_upstream.txt").unwrap(), "2");
+        assert_eq!(fs::read_to_string("rebase_topic.txt").unwrap(), "2");
+        assert!(status::status().await.staged.is_empty());
+
+        let head_log: Vec<String> = reflog::read_reflog(reflog::HEAD)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(
+            head_log[head_log.len() - 4..],
+            [
+                format!("rebase (start): checkout {}", upstream),
+                "rebase (pick): topic 1".to_string(),
+                "rebase (pick): topic 2".to_string(),
+                "rebase (finish): returning to refs/heads/rebase_topic".to_string(),
+            ]
+        );
+        let branch_log = reflog::read_reflog("refs/heads/rebase_topic").unwrap();
+        let finish = branch_log.last().unwrap();
+        assert_eq!((finish.old, finish.new), (old_tip, tip));
+        assert_eq!(
+            finish.message,
+            format!("rebase (finish): refs/heads/rebase_topic onto {}", upstream)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commits_to_replay_across_merges() {
+        test::setup_with_new_libra().await;
+        let base = test::commit_files(&[("replay_base.txt", "base")], "base").await;
+        let tree = Commit::load(&base).tree_id;
+        let commit = |parents: Vec<SHA1>, message: &str| {
+            let commit = Commit::from_tree_id(tree, parents, message);
+            save_object(&commit, &commit.id).unwrap();
+            commit.id
+        };
+        // base <- a <- merge <- c, base <- b <- merge
+        let a = commit(vec![base], "a");
+        let b = commit(vec![base], "b");
+        let merge = commit(vec![a, b], "merge");
+        let c = commit(vec![merge], "c");
+
+        assert_eq!(commits_to_replay(c, base).unwrap(), [a, b, c]);
+        // b is in upstream
+        assert_eq!(commits_to_replay(c, b).unwrap(), [a, c]);
+        assert!(commits_to_replay(base, base).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rebase_conflict_saves_state() {
+        test::setup_with_new_libra().await;
+        let base = test::commit_files(&[("rebase_conflict.txt", "base")], "base").await;
+        let upstream = test::commit_files(&[("rebase_conflict.txt", "upstream")], "upstream").await;
+        switch::execute(SwitchArgs::parse_from([
+            "switch",
+            "--create",
+            "rebase_conflict",
+            &base.to_string(),
+        ]))
+        .await;
+        let topic = test::commit_files(&[("rebase_conflict.txt", "topic")], "topic").await;
+
+        let result = rebase(upstream, upstream).await.unwrap();
+        assert_eq!(
+            result,
+            RebaseResult::Conflicts {
+                commit: topic,
+                conflicts: vec![PathBuf::from("rebase_conflict.txt")],
+            }
+        );
+        let state = path::rebase_merge();
+        let read = |file| fs::read_to_string(state.join(file)).unwrap();
+        assert_eq!(read("head-name"), "refs/heads/rebase_conflict\n");
+        assert_eq!(read("orig-head"), format!("{}\n", topic));
+        assert_eq!(read("onto"), format!("{}\n", upstream));
+        assert_eq!(
+            Branch::find_branch("rebase_conflict", None)
+                .await
+                .unwrap()
+                .commit,
+            topic
+        );
+    }
+}
@@ -25,7 +25,9 @@ pub enum RemoteCmds {
 pub async fn execute(command: RemoteCmds) {
     match command {
         RemoteCmds::Add { name, url } => {
-            Config::insert("remote", Some(&name), "url", &url).await;
+            if let Err(e) = Config::add_remote(&name, &url).await {
+                eprintln!("{}", e);
+            }
         }
         RemoteCmds::Remove { name } => {
             if let Err(e) = Config::remove_remote(&name).await {
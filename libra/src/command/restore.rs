@@ -139,7 +139,7 @@ fn preprocess_blobs(blobs: &[(PathBuf, SHA1)]) -> HashMap<PathBuf, SHA1> {
 /// Restore a blob to file.
 /// If blob is an LFS pointer, download the actual file from LFS server.
 /// - `path` : to workdir
-async fn restore_to_file(hash: &SHA1, path: &PathBuf) -> io::Result<()> {
+pub(crate) async fn restore_to_file(hash: &SHA1, path: &PathBuf) -> io::Result<()> {
     let blob = Blob::load(hash);
     let path_abs = util::workdir_to_absolute(path);
     if let Some(parent) = path_abs.parent() {
@@ -215,6 +215,7 @@ pub async fn restore_worktree(filter: &Vec<PathBuf>, target_blobs: &[(PathBuf, S
     // to workdir path
     let mut file_paths = util::integrate_pathspec(filter);
     file_paths.extend(deleted_files);
+    util::sort_paths(&mut file_paths);
 
     let index = Index::load(path::index()).unwrap();
     for path_wd in &file_paths {
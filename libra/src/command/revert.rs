@@ -0,0 +1,162 @@
+//! Undo an existing commit with a new commit applying its inverse, like `git revert`.
+
+use clap::Parser;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+
+use crate::command::cherry_pick::{apply_diff, commit_applied, print_conflicts, ApplyResult};
+use crate::command::get_target_commit;
+use crate::internal::config::Config;
+use crate::internal::head::Head;
+use crate::utils::object_ext::CommitExt;
+
+#[derive(Parser, Debug)]
+pub struct RevertArgs {
+    /// The commit to revert, e.g. a hash, a branch, `HEAD~2`
+    pub commit: String,
+}
+
+pub async fn execute(args: RevertArgs) {
+    let commit = match get_target_commit(&args.commit).await {
+        Ok(commit) => commit,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    let subject = Commit::load(&commit).format_message();
+    match revert(commit).await {
+        Ok(ApplyResult::Committed(reverted)) => {
+            println!("[{}] Revert \"{}\"", &reverted.to_string()[..7], subject);
+        }
+        Ok(ApplyResult::Conflicts(conflicts)) => {
+            print_conflicts("revert", &commit, &subject, &conflicts)
+        }
+        Err(e) => eprintln!("error: {}", e),
+    }
+}
+
+/// Apply the diff of the parent of `commit` against `commit` to HEAD, the working tree & the
+/// index, then commit it as `Revert "<subject>"`.
+/// A file changed by `commit` which was changed again in HEAD is a conflict, see
+/// [apply_diff]; nothing is applied nor committed then.
+/// - merge commits are not supported, as the parent to go back to is ambiguous
+pub async fn revert(commit_id: SHA1) -> Result<ApplyResult, String> {
+    let head = Head::current_commit()
+        .await
+        .ok_or("cannot revert on a branch without commits")?;
+    let user = Config::user_config().await?;
+    let commit = Commit::load(&commit_id);
+    let parent = match commit.parent_commit_ids[..] {
+        [parent] => parent,
+        [] => return Err(format!("commit {} has no parent to go back to", commit_id)),
+        _ => {
+            return Err(format!(
+                "commit {} is a merge, which can't be reverted",
+                commit_id
+            ))
+        }
+    };
+
+    let parent_tree = Commit::load(&parent).tree_id;
+    let applied = apply_diff(commit.tree_id, parent_tree, head, false).await?;
+    if !applied.conflicts.is_empty() {
+        return Ok(ApplyResult::Conflicts(applied.conflicts));
+    }
+    if !applied.changed {
+        return Err(format!("the changes of {} are not in HEAD", commit_id));
+    }
+    let message = format!(
+        "Revert \"{}\"\n\nThis reverts commit {}.\n",
+        commit.format_message(),
+        commit_id
+    );
+    let reverted = commit_applied(head, &user, None, &message, "revert").await?;
+    Ok(ApplyResult::Committed(reverted))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::command::add::{self, AddArgs};
+    use crate::command::status;
+    use crate::utils::{path, test};
+
+    #[tokio::test]
+    async fn test_revert() {
+        test::setup_with_new_libra().await;
+        let base = test::commit_files(
+            &[
+                ("revert_changed.txt", "v1"),
+                ("revert_removed.txt", "removed"),
+            ],
+            "base",
+        )
+        .await;
+
+        fs::remove_file("revert_removed.txt").unwrap();
+        add::execute(AddArgs {
+            pathspec: vec!["revert_removed.txt".to_string()],
+            all: false,
+            update: false,
+            verbose: false,
+        })
+        .await;
+        let change = test::commit_files(
+            &[("revert_changed.txt", "v2"), ("revert_added.txt", "added")],
+            "change",
+        )
+        .await;
+
+        let reverted = match revert(change).await.unwrap() {
+            ApplyResult::Committed(reverted) => reverted,
+            conflicts => panic!("{:?}", conflicts),
+        };
+        assert_eq!(Head::current_commit().await, Some(reverted));
+        let reverted = Commit::load(&reverted);
+        assert_eq!(reverted.parent_commit_ids, [change]);
+        assert_eq!(reverted.tree_id, Commit::load(&base).tree_id);
+        assert_eq!(reverted.format_message(), "Revert \"change\"");
+        assert_eq!(fs::read_to_string("revert_changed.txt").unwrap(), "v1");
+        assert_eq!(fs::read_to_string("revert_removed.txt").unwrap(), "removed");
+        assert!(!PathBuf::from("revert_added.txt").exists());
+        assert!(status::status().await.staged.is_empty());
+
+        // reverted already
+        assert!(revert(change).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_revert_conflict_changes_nothing() {
+        test::setup_with_new_libra().await;
+        test::commit_files(
+            &[("revert_conflict.txt", "v1"), ("revert_clean.txt", "v1")],
+            "base",
+        )
+        .await;
+        let change = test::commit_files(
+            &[("revert_conflict.txt", "v2"), ("revert_clean.txt", "v2")],
+            "change",
+        )
+        .await;
+        let head = test::commit_files(&[("revert_conflict.txt", "v3")], "again").await;
+        let index_before = fs::read(path::index()).unwrap();
+
+        assert_eq!(
+            revert(change).await,
+            Ok(ApplyResult::Conflicts(vec![PathBuf::from(
+                "revert_conflict.txt"
+            )]))
+        );
+        assert_eq!(Head::current_commit().await, Some(head));
+        assert_eq!(fs::read(path::index()).unwrap(), index_before);
+        // not even the file without conflict is reverted
+        assert_eq!(fs::read_to_string("revert_clean.txt").unwrap(), "v2");
+        assert_eq!(fs::read_to_string("revert_conflict.txt").unwrap(), "v3");
+        let status = status::status().await;
+        assert!(status.staged.is_empty() && status.unstaged.is_empty());
+    }
+}
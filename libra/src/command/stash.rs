@@ -0,0 +1,364 @@
+//! Put the local changes aside and bring them back later, like `git stash`.
+//!
+//! A stash is a commit of the working tree whose parents are the HEAD commit it was made on and
+//! a commit of the index. The stashes are a stack kept as the reflog of [STASH_REF], so the
+//! latest one is `stash@{0}`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use mercury::hash::SHA1;
+use mercury::internal::index::{Index, IndexEntry};
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::Tree;
+
+use crate::command::add::hash_files;
+use crate::command::commit::{create_tree, new_commit};
+use crate::command::restore::{self, RestoreArgs};
+use crate::command::save_object;
+use crate::command::status::{self, changes_to_be_committed, changes_to_be_staged};
+use crate::internal::config::Config;
+use crate::internal::head::Head;
+use crate::internal::reflog::{self, ReflogEntry};
+use crate::utils::object_ext::{CommitExt, TreeExt};
+use crate::utils::{path, util};
+
+/// The reflog holding the stack of stashes
+pub const STASH_REF: &str = "refs/stash";
+
+/// Mode of a file restored by a stash which isn't in the working tree
+const DEFAULT_FILE_MODE: u32 = 0o100644;
+
+#[derive(Subcommand, Debug)]
+pub enum StashCmds {
+    /// Save the local changes and revert the working tree & the index to HEAD
+    Push {
+        /// description of the stash, instead of the HEAD commit
+        #[clap(short, long)]
+        message: Option<String>,
+    },
+    /// Apply the latest stash and remove it from the stack, unless it conflicts
+    Pop,
+    /// List the stashes, latest first
+    List,
+}
+
+pub async fn execute(cmd: StashCmds) {
+    match cmd {
+        StashCmds::Push { message } => match stash_save(message.as_deref()).await {
+            Ok(Some(stash)) => {
+                let message = Commit::load(&stash).format_message();
+                println!("Saved working directory and index state {}", message);
+            }
+            Ok(None) => println!("No local changes to save"),
+            Err(e) => eprintln!("fatal: {}", e),
+        },
+        StashCmds::Pop => match stash_pop().await {
+            Ok(conflicts) if conflicts.is_empty() => println!("Dropped {}@{{0}}", STASH_REF),
+            Ok(conflicts) => {
+                println!("CONFLICT: the stash changes these files, which were changed since:");
+                for path in conflicts {
+                    println!("\t{}", util::workdir_to_current(path).display());
+                }
+                println!("The stash entry is kept in case you need it again.");
+            }
+            Err(e) => eprintln!("error: {}", e),
+        },
+        StashCmds::List => match reflog::read_reflog(STASH_REF) {
+            Ok(entries) => {
+                for (n, entry) in entries.iter().rev().enumerate() {
+                    println!("stash@{{{}}}: {}", n, entry.message);
+                }
+            }
+            Err(e) => eprintln!("fatal: {}", e),
+        },
+    }
+}
+
+/// Save the staged & unstaged changes of the tracked files as a stash, then revert the working
+/// tree & the index to HEAD. Untracked files are left as they are.
+/// - return the stash commit, `None` if there was nothing to save
+pub async fn stash_save(message: Option<&str>) -> Result<Option<SHA1>, String> {
+    let head = Head::current_commit()
+        .await
+        .ok_or("you do not have the initial commit yet")?;
+    let index = Index::load(path::index()).map_err(|e| e.to_string())?;
+    if index.has_conflicts() {
+        return Err("cannot stash with unmerged files in the index".to_string());
+    }
+    let staged = changes_to_be_committed().await;
+    let unstaged = changes_to_be_staged();
+    if staged.is_empty() && unstaged.modified.is_empty() && unstaged.deleted.is_empty() {
+        return Ok(None);
+    }
+    let user = Config::user_config().await?;
+
+    let storage = util::objects_storage();
+    let branch = match Head::current().await {
+        Head::Branch(name) => name,
+        Head::Detached(_) => "(no branch)".to_string(),
+    };
+    let save_commit = |commit: Commit| {
+        save_object(&commit, &commit.id)
+            .map(|_| commit.id)
+            .map_err(|e| e.to_string())
+    };
+    let on_head = format!(
+        "{}: {} {}",
+        branch,
+        &head.to_string()[..7],
+        Commit::load(&head).format_message()
+    );
+
+    let index_tree = create_tree(&index, &storage, "".into()).await;
+    let index_commit = save_commit(new_commit(
+        index_tree.id,
+        vec![head],
+        &format!("index on {}", on_head),
+        &user,
+    ))?;
+
+    // the index updated with the tracked files as they are in the working tree
+    let mut worktree = index;
+    for (file, hash, mode) in hash_files(&unstaged.modified, 1).map_err(|e| e.to_string())? {
+        worktree.stage(&util::path_to_string(&file), hash, mode, None);
+    }
+    for file in &unstaged.deleted {
+        worktree.unstage(&util::path_to_string(file));
+    }
+    let worktree_tree = create_tree(&worktree, &storage, "".into()).await;
+    let message = match message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", on_head),
+    };
+    let stash = save_commit(new_commit(
+        worktree_tree.id,
+        vec![head, index_commit],
+        &message,
+        &user,
+    ))?;
+
+    let previous = reflog::nth_prior_value(STASH_REF, 0).map_err(|e| e.to_string())?;
+    let entry = ReflogEntry::new(previous, stash, &reflog::identity().await, &message);
+    reflog::append_reflog(STASH_REF, &entry).map_err(|e| e.to_string())?;
+
+    restore::execute(RestoreArgs {
+        worktree: true,
+        staged: true,
+        source: Some(head.to_string()),
+        pathspec: vec![util::working_dir_string()],
+    })
+    .await;
+    Ok(Some(stash))
+}
+
+/// Files of the tree of `commit`, to workdir
+fn commit_files(commit: &SHA1) -> HashMap<PathBuf, SHA1> {
+    let tree = Tree::load(&Commit::load(commit).tree_id);
+    tree.get_plain_items().into_iter().collect()
+}
+
+/// Apply the latest stash to the working tree & the index, then drop it.
+/// A file changed both by the stash and by the commits made since it was saved is a conflict:
+/// it is kept as in HEAD and recorded as unmerged in the index, and the stash is not dropped.
+/// - return the conflicted files, to workdir
+/// - fails without changing anything if the stash would overwrite local changes
+pub async fn stash_pop() -> Result<Vec<PathBuf>, String> {
+    let stash = reflog::nth_prior_value(STASH_REF, 0)
+        .map_err(|e| e.to_string())?
+        .ok_or("no stash entries found")?;
+    let (base, index_commit) = match Commit::load(&stash).parent_commit_ids[..] {
+        [base, index_commit] => (base, index_commit),
+        _ => return Err(format!("{} is not a stash commit", stash)),
+    };
+    let base = commit_files(&base);
+    let staged = commit_files(&index_commit);
+    let stashed = commit_files(&stash);
+    let head = match Head::current_commit().await {
+        Some(commit) => commit_files(&commit),
+        None => HashMap::new(),
+    };
+
+    // the files the stash changes, in the index or in the working tree
+    let paths: BTreeSet<&PathBuf> = base
+        .keys()
+        .chain(staged.keys())
+        .chain(stashed.keys())
+        .filter(|path| {
+            base.get(*path) != staged.get(*path) || base.get(*path) != stashed.get(*path)
+        })
+        .collect();
+    let current = status::status().await;
+    let local = current.changed_paths();
+    let overwritten: Vec<String> = paths
+        .iter()
+        .filter(|path| local.contains(*path))
+        .map(|path| util::path_to_string(path))
+        .collect();
+    if !overwritten.is_empty() {
+        return Err(format!(
+            "your local changes to the following files would be overwritten:\n\t{}",
+            overwritten.join("\n\t")
+        ));
+    }
+
+    let workdir = util::working_dir();
+    let mut index = Index::load(path::index()).map_err(|e| e.to_string())?;
+    let mut conflicts = Vec::new();
+    for path in paths {
+        let name = util::path_to_string(path);
+        let in_base = base.get(path);
+        let in_stash = stashed.get(path);
+        let in_head = head.get(path);
+        if in_head == in_stash {
+            continue; // already as stashed
+        }
+        if in_head != in_base {
+            index.add_conflict(&name, in_base.copied(), in_head.copied(), in_stash.copied());
+            conflicts.push(path.clone());
+            continue;
+        }
+
+        let file = util::workdir_to_absolute(path);
+        match in_stash {
+            Some(hash) => restore::restore_to_file(hash, path)
+                .await
+                .map_err(|e| e.to_string())?,
+            None => {
+                fs::remove_file(&file).map_err(|e| e.to_string())?;
+                util::clear_empty_dir(&file);
+            }
+        }
+        match staged.get(path) {
+            // the file matches the index, keep its stat so it isn't seen as modified
+            Some(hash) if in_stash == Some(hash) => {
+                let entry =
+                    IndexEntry::new_from_file(path, *hash, &workdir).map_err(|e| e.to_string())?;
+                index.update(entry);
+            }
+            Some(hash) => {
+                let mode = IndexEntry::new_from_file(path, *hash, &workdir)
+                    .map_or(DEFAULT_FILE_MODE, |entry| entry.mode);
+                index.stage(&name, *hash, mode, None);
+            }
+            None => {
+                index.unstage(&name);
+            }
+        }
+    }
+    index.save(path::index()).map_err(|e| e.to_string())?;
+
+    if conflicts.is_empty() {
+        reflog::drop_reflog_entry(STASH_REF, 0).map_err(|e| e.to_string())?;
+    }
+    Ok(conflicts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::add::{self, AddArgs};
+    use crate::command::status::ChangeKind;
+    use crate::utils::test;
+
+    async fn add(paths: &[&str]) {
+        add::execute(AddArgs {
+            pathspec: paths.iter().map(|path| path.to_string()).collect(),
+            all: false,
+            update: false,
+            verbose: false,
+        })
+        .await;
+    }
+
+    /// the staged & unstaged changes, ignoring the untracked files
+    async fn local_changes() -> Vec<(String, ChangeKind, bool)> {
+        let current = status::status().await;
+        let changes = |changes: Vec<status::FileChange>, staged| {
+            changes
+                .into_iter()
+                .map(move |change| (util::path_to_string(&change.path), change.kind, staged))
+        };
+        changes(current.staged, true)
+            .chain(changes(current.unstaged, false))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stash_save_and_pop() {
+        test::setup_with_new_libra().await;
+        test::commit_files(
+            &[
+                ("stash_modified.txt", "v1"),
+                ("stash_removed.txt", "removed"),
+                ("stash_staged.txt", "staged v1"),
+            ],
+            "base",
+        )
+        .await;
+        assert_eq!(stash_save(None).await, Ok(None));
+
+        fs::write("stash_modified.txt", "v2").unwrap();
+        fs::remove_file("stash_removed.txt").unwrap();
+        fs::write("stash_staged.txt", "staged v2").unwrap();
+        test::ensure_file("stash_new.txt", Some("new"));
+        add(&["stash_staged.txt", "stash_new.txt"]).await;
+        let before = local_changes().await;
+        assert_eq!(before.len(), 4);
+
+        let stash = stash_save(Some("work in progress")).await.unwrap().unwrap();
+        assert!(local_changes().await.is_empty());
+        assert_eq!(fs::read_to_string("stash_modified.txt").unwrap(), "v1");
+        assert_eq!(fs::read_to_string("stash_removed.txt").unwrap(), "removed");
+        assert!(!PathBuf::from("stash_new.txt").exists());
+        let stashes = reflog::read_reflog(STASH_REF).unwrap();
+        assert_eq!(stashes.last().unwrap().new, stash);
+        let author = Commit::load(&stash).author;
+        assert_eq!(
+            (author.name, author.email),
+            ("mega".into(), "admin@mega.org".into())
+        );
+        assert_eq!(
+            stashes.last().unwrap().message,
+            "On master: work in progress"
+        );
+
+        let conflicts = stash_pop().await.unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(local_changes().await, before);
+        assert_eq!(fs::read_to_string("stash_modified.txt").unwrap(), "v2");
+        assert_eq!(fs::read_to_string("stash_staged.txt").unwrap(), "staged v2");
+        assert!(!PathBuf::from("stash_removed.txt").exists());
+        assert_eq!(
+            reflog::read_reflog(STASH_REF).unwrap().len(),
+            stashes.len() - 1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stash_pop_conflict() {
+        test::setup_with_new_libra().await;
+        test::commit_files(&[("stash_conflict.txt", "v1")], "base").await;
+
+        fs::write("stash_conflict.txt", "stashed").unwrap();
+        let stash = stash_save(None).await.unwrap().unwrap();
+        test::commit_files(
+            &[("stash_conflict.txt", "committed")],
+            "change the same file",
+        )
+        .await;
+
+        let conflicts = stash_pop().await.unwrap();
+        assert_eq!(conflicts, [PathBuf::from("stash_conflict.txt")]);
+        assert_eq!(
+            fs::read_to_string("stash_conflict.txt").unwrap(),
+            "committed"
+        );
+        let index = Index::load(path::index()).unwrap();
+        assert_eq!(index.conflicted_paths(), ["stash_conflict.txt"]);
+        // kept to be applied again
+        assert_eq!(reflog::nth_prior_value(STASH_REF, 0).unwrap(), Some(stash));
+    }
+}
@@ -1,7 +1,11 @@
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use clap::Parser;
 use colored::Colorize;
+use wax::{Glob, Pattern};
 
 use mercury::internal::object::commit::Commit;
 use mercury::internal::object::tree::Tree;
@@ -36,75 +40,264 @@ impl Changes {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    New,
+    Modified,
+    Deleted,
+}
+
+impl ChangeKind {
+    /// The letter of the short format
+    pub fn code(self) -> char {
+        match self {
+            ChangeKind::New => 'A',
+            ChangeKind::Modified => 'M',
+            ChangeKind::Deleted => 'D',
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ChangeKind::New => "new file",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Deleted => "deleted",
+        }
+    }
+}
+
+/// path: to workdir
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+/// The whole state of the working tree, each list sorted by path
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// `index` against the last `Commit Tree`, all `New` on an unborn branch
+    pub staged: Vec<FileChange>,
+    /// `workdir` against `index`, only `Modified` & `Deleted`
+    pub unstaged: Vec<FileChange>,
+    /// not in `index` and not ignored by [IGNORE](util::IGNORE)
+    pub untracked: Vec<PathBuf>,
+}
+
+impl Status {
+    pub fn is_clean(&self) -> bool {
+        self.staged.is_empty() && self.unstaged.is_empty() && self.untracked.is_empty()
+    }
+
+    /// The paths with local changes: staged, unstaged or untracked
+    pub fn changed_paths(&self) -> HashSet<&PathBuf> {
+        self.staged
+            .iter()
+            .chain(&self.unstaged)
+            .map(|change| &change.path)
+            .chain(&self.untracked)
+            .collect()
+    }
+
+    /// to relative path(to cur_dir)
+    pub fn to_relative(&self) -> Status {
+        let relative = |changes: &[FileChange]| {
+            changes
+                .iter()
+                .map(|change| FileChange {
+                    path: util::workdir_to_current(&change.path),
+                    kind: change.kind,
+                })
+                .collect()
+        };
+        Status {
+            staged: relative(&self.staged),
+            unstaged: relative(&self.unstaged),
+            untracked: self
+                .untracked
+                .iter()
+                .map(util::workdir_to_current)
+                .collect(),
+        }
+    }
+}
+
+impl From<Changes> for Vec<FileChange> {
+    fn from(changes: Changes) -> Self {
+        let mut files: Vec<FileChange> = [
+            (changes.new, ChangeKind::New),
+            (changes.modified, ChangeKind::Modified),
+            (changes.deleted, ChangeKind::Deleted),
+        ]
+        .into_iter()
+        .flat_map(|(paths, kind)| paths.into_iter().map(move |path| FileChange { path, kind }))
+        .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files
+    }
+}
+
+/// Compute the [Status] of the current repository, paths to workdir
+pub async fn status() -> Status {
+    let staged = changes_to_be_committed().await;
+    let mut unstaged = changes_to_be_staged();
+    let ignored = ignore_patterns();
+    let mut untracked: Vec<PathBuf> = std::mem::take(&mut unstaged.new)
+        .into_iter()
+        .filter(|path| !is_ignored(&ignored, path))
+        .collect();
+    untracked.sort();
+    Status {
+        staged: staged.into(),
+        unstaged: unstaged.into(),
+        untracked,
+    }
+}
+
+/// Globs of the [IGNORE](util::IGNORE) file at the root of workdir, in `.gitignore` syntax:
+/// - blank lines and `#` comments are skipped, negation (`!`) is not supported
+/// - a pattern without a `/`, other than a trailing one, matches at any depth
+/// - a trailing `/` matches everything under the directory
+pub(crate) fn ignore_patterns() -> Vec<Glob<'static>> {
+    let content = fs::read_to_string(path::ignore()).unwrap_or_default();
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let (line, is_dir) = match line.strip_suffix('/') {
+                Some(dir) => (dir, true),
+                None => (line, false),
+            };
+            let mut pattern = match line.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if !line.contains('/') => format!("**/{}", line),
+                None => line.to_string(),
+            };
+            if is_dir {
+                pattern.push_str("/**");
+            }
+            match Glob::new(&pattern) {
+                Ok(glob) => Some(glob.into_owned()),
+                Err(e) => {
+                    tracing::warn!("invalid pattern '{}' in {}: {}", line, util::IGNORE, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// path: to workdir
+pub(crate) fn is_ignored(patterns: &[Glob], path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    patterns.iter().any(|glob| glob.is_match(path.as_ref()))
+}
+
+#[derive(Parser, Debug, Default)]
+pub struct StatusArgs {
+    /// Give the output in the short format, paths relative to the current directory
+    #[arg(short, long)]
+    pub short: bool,
+
+    /// Give the output in a stable format for scripts: `XY path`, paths relative to the repository root
+    #[arg(long)]
+    pub porcelain: bool,
+}
+
 /**
- * 2 parts:
- * 1. unstaged
- * 2. staged to be committed
+ * 3 parts:
+ * 1. staged to be committed
+ * 2. unstaged
+ * 3. untracked
  */
-pub async fn execute() {
+pub async fn execute(args: StatusArgs) {
     if !util::check_repo_exist() {
         return;
     }
-    // TODO .gitignore
-    match Head::current().await {
-        Head::Detached(commit) => {
-            println!("HEAD detached at {}", String::from_utf8_lossy(&commit.0[0..7]));
-        }
-        Head::Branch(branch) => {
-            println!("On branch {}", branch);
-        }
+    let status = status().await;
+    if args.porcelain {
+        print!("{}", format_short(&status));
+        return;
+    }
+    if args.short {
+        print!("{}", format_short(&status.to_relative()));
+        return;
     }
 
+    println!("{}", head_line(&Head::current().await));
+
     if Head::current_commit().await.is_none() {
         println!("\nNo commits yet\n");
     }
-
     // to cur_dir relative path
-    let staged = changes_to_be_committed().await.to_relative();
-    let unstaged = changes_to_be_staged().to_relative();
-    if staged.is_empty() && unstaged.is_empty() {
-        println!("nothing to commit, working tree clean");
-        return;
+    print!("{}", format_long(&status.to_relative()));
+}
+
+/// The first line of the long format, naming the branch or the commit HEAD is detached at
+fn head_line(head: &Head) -> String {
+    match head {
+        Head::Detached(commit) => format!("HEAD detached at {}", &commit.to_string()[..7]),
+        Head::Branch(branch) => format!("On branch {}", branch),
     }
+}
 
-    if !staged.is_empty() {
-        println!("Changes to be committed:");
-        println!("  use \"libra restore --staged <file>...\" to unstage");
-        staged.deleted.iter().for_each(|f| {
-            let str = format!("\tdeleted: {}", f.display());
-            println!("{}", str.bright_green());
-        });
-        staged.modified.iter().for_each(|f| {
-            let str = format!("\tmodified: {}", f.display());
-            println!("{}", str.bright_green());
-        });
-        staged.new.iter().for_each(|f| {
-            let str = format!("\tnew file: {}", f.display());
-            println!("{}", str.bright_green());
-        });
-    }
-
-    if !unstaged.deleted.is_empty() || !unstaged.modified.is_empty() {
-        println!("Changes not staged for commit:");
-        println!("  use \"libra add <file>...\" to update what will be committed");
-        println!("  use \"libra restore <file>...\" to discard changes in working directory");
-        unstaged.deleted.iter().for_each(|f| {
-            let str = format!("\tdeleted: {}", f.display());
-            println!("{}", str.bright_red());
-        });
-        unstaged.modified.iter().for_each(|f| {
-            let str = format!("\tmodified: {}", f.display());
-            println!("{}", str.bright_red());
-        });
-    }
-    if !unstaged.new.is_empty() {
-        println!("Untracked files:");
-        println!("  use \"libra add <file>...\" to include in what will be committed");
-        unstaged.new.iter().for_each(|f| {
-            let str = format!("\t{}", f.display());
-            println!("{}", str.bright_red());
-        });
+/// `XY path` per changed path, like `git status --porcelain`:
+/// - `X` is the staged change and `Y` the unstaged one, ` ` if unchanged
+/// - `??` for untracked files, after the changed paths
+pub fn format_short(status: &Status) -> String {
+    let mut codes: BTreeMap<&Path, [char; 2]> = BTreeMap::new();
+    for (i, changes) in [&status.staged, &status.unstaged].into_iter().enumerate() {
+        for change in changes {
+            codes.entry(&change.path).or_insert([' ', ' '])[i] = change.kind.code();
+        }
+    }
+    let mut out = String::new();
+    for (path, [x, y]) in codes {
+        writeln!(out, "{}{} {}", x, y, path.display()).unwrap();
     }
+    for path in &status.untracked {
+        writeln!(out, "?? {}", path.display()).unwrap();
+    }
+    out
+}
+
+/// The human-readable form, grouped by category, without the branch
+pub fn format_long(status: &Status) -> String {
+    let mut out = String::new();
+    if status.is_clean() {
+        out.push_str("nothing to commit, working tree clean\n");
+        return out;
+    }
+
+    if !status.staged.is_empty() {
+        out.push_str("Changes to be committed:\n");
+        out.push_str("  use \"libra restore --staged <file>...\" to unstage\n");
+        for change in &status.staged {
+            let line = format!("\t{}: {}", change.kind.label(), change.path.display());
+            writeln!(out, "{}", line.bright_green()).unwrap();
+        }
+    }
+
+    if !status.unstaged.is_empty() {
+        out.push_str("Changes not staged for commit:\n");
+        out.push_str("  use \"libra add <file>...\" to update what will be committed\n");
+        out.push_str("  use \"libra restore <file>...\" to discard changes in working directory\n");
+        for change in &status.unstaged {
+            let line = format!("\t{}: {}", change.kind.label(), change.path.display());
+            writeln!(out, "{}", line.bright_red()).unwrap();
+        }
+    }
+
+    if !status.untracked.is_empty() {
+        out.push_str("Untracked files:\n");
+        out.push_str("  use \"libra add <file>...\" to include in what will be committed\n");
+        for path in &status.untracked {
+            let line = format!("\t{}", path.display());
+            writeln!(out, "{}", line.bright_red()).unwrap();
+        }
+    }
+    out
 }
 
 /// Check if the working tree is clean
@@ -180,4 +373,94 @@ pub fn changes_to_be_staged() -> Changes {
         }
     }
     changes
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use mercury::hash::SHA1;
+
+    use super::*;
+    use crate::command::add::{self, AddArgs};
+    use crate::utils::test;
+
+    async fn add(paths: &[&str]) {
+        add::execute(AddArgs {
+            pathspec: paths.iter().map(|path| path.to_string()).collect(),
+            all: false,
+            update: false,
+            verbose: false,
+        })
+        .await;
+    }
+
+    fn change(path: &str, kind: ChangeKind) -> FileChange {
+        FileChange {
+            path: PathBuf::from(path),
+            kind,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_buckets() {
+        test::setup_with_new_libra().await;
+        // unborn branch: everything in the index is new
+        test::ensure_file("tracked.txt", Some("tracked"));
+        add(&["tracked.txt"]).await;
+        let current = status().await;
+        assert_eq!(current.staged, [change("tracked.txt", ChangeKind::New)]);
+        assert!(current.unstaged.is_empty());
+
+        test::commit_files(&[("tracked.txt", "tracked")], "init").await;
+        let current = status().await;
+        assert!(current.staged.is_empty() && current.unstaged.is_empty());
+
+        test::ensure_file("staged.txt", Some("staged"));
+        add(&["staged.txt"]).await;
+        test::ensure_file("tracked.txt", Some("tracked, then modified"));
+        test::ensure_file("untracked.txt", Some("untracked"));
+        test::ensure_file("logs/debug.log", Some("ignored"));
+        test::ensure_file(util::IGNORE, Some("# build output\n*.log\n"));
+
+        let current = status().await;
+        assert_eq!(current.staged, [change("staged.txt", ChangeKind::New)]);
+        assert_eq!(
+            current.unstaged,
+            [change("tracked.txt", ChangeKind::Modified)]
+        );
+        // the test dir is shared, other tests may leave files in it
+        assert!(current.untracked.contains(&PathBuf::from("untracked.txt")));
+        assert!(current.untracked.contains(&PathBuf::from(util::IGNORE)));
+        assert!(!current.untracked.contains(&PathBuf::from("logs/debug.log")));
+        assert!(!current.untracked.contains(&PathBuf::from("staged.txt")));
+    }
+
+    #[test]
+    fn test_head_line() {
+        let commit = SHA1::from_str("8ab686eafeb1f44702738c8b0f24f2567c36da6d").unwrap();
+        assert_eq!(head_line(&Head::Detached(commit)), "HEAD detached at 8ab686e");
+        let branch = Head::Branch("master".to_string());
+        assert_eq!(head_line(&branch), "On branch master");
+    }
+
+    #[test]
+    fn test_porcelain_codes() {
+        let status = Status {
+            staged: vec![
+                change("new.txt", ChangeKind::New),
+                change("both.txt", ChangeKind::New),
+            ],
+            unstaged: vec![
+                change("both.txt", ChangeKind::Modified),
+                change("modified.txt", ChangeKind::Modified),
+                change("removed.txt", ChangeKind::Deleted),
+            ],
+            untracked: vec![PathBuf::from("untracked.txt")],
+        };
+        assert_eq!(
+            format_short(&status),
+            "AM both.txt\n M modified.txt\nA  new.txt\n D removed.txt\n?? untracked.txt\n"
+        );
+    }
+}
@@ -2,14 +2,14 @@ use clap::Parser;
 use mercury::hash::SHA1;
 
 use crate::{
-    command::branch,
-    internal::{branch::Branch, head::Head},
-    utils::util::{self, get_commit_base},
+    command::{branch, get_target_commit},
+    internal::{branch::Branch, head::Head, reflog},
+    utils::util,
 };
 
 use super::{
     restore::{self, RestoreArgs},
-    status,
+    status::{self, StatusArgs},
 };
 
 #[derive(Parser, Debug)]
@@ -22,7 +22,7 @@ pub struct SwitchArgs {
     #[clap(long, short, group = "sub")]
     create: Option<String>,
 
-    /// Switch to a commit
+    /// Switch to a commit, e.g. a hash, `HEAD~2`
     #[clap(long, short, action, default_value = "false", group = "sub")]
     detach: bool,
 }
@@ -31,11 +31,11 @@ pub async fn execute(args: SwitchArgs) {
     // check status
     let unstaged = status::changes_to_be_staged();
     if !unstaged.deleted.is_empty() || !unstaged.modified.is_empty() {
-        status::execute().await;
+        status::execute(StatusArgs::default()).await;
         eprintln!("fatal: uncommitted changes, can't switch branch");
         return;
     } else if !status::changes_to_be_committed().await.is_empty() {
-        status::execute().await;
+        status::execute(StatusArgs::default()).await;
         eprintln!("fatal: unstaged changes, can't switch branch");
         return;
     }
@@ -46,14 +46,10 @@ pub async fn execute(args: SwitchArgs) {
             switch_to_branch(new_branch_name).await;
         }
         None => match args.detach {
-            true => {
-                let commit_base = get_commit_base(&args.branch.unwrap());
-                if commit_base.is_err() {
-                    eprintln!("{}", commit_base.unwrap());
-                    return;
-                }
-                switch_to_commit(commit_base.unwrap()).await;
-            }
+            true => match get_target_commit(&args.branch.unwrap()).await {
+                Ok(commit) => switch_to_commit(commit).await,
+                Err(e) => eprintln!("fatal: {}", e),
+            },
             false => {
                 switch_to_branch(args.branch.unwrap()).await;
             }
@@ -66,7 +62,20 @@ async fn switch_to_commit(commit_hash: SHA1) {
     restore_to_commit(commit_hash).await;
     // update HEAD
     let head = Head::Detached(commit_hash);
+    move_head(head, commit_hash).await;
+}
+
+/// Point HEAD at `head`, which is at `commit`, logging the move in the reflog of HEAD like git
+async fn move_head(head: Head, commit: SHA1) {
+    let name = |head: &Head| match head {
+        Head::Branch(name) => name.clone(),
+        Head::Detached(commit) => commit.to_string(),
+    };
+    let from = Head::current().await;
+    let old_commit = Head::current_commit().await;
+    let message = format!("checkout: moving from {} to {}", name(&from), name(&head));
     Head::update(head, None).await;
+    reflog::log_head(old_commit, commit, &message, false).await;
 }
 
 async fn switch_to_branch(branch_name: String) {
@@ -87,7 +96,7 @@ async fn switch_to_branch(branch_name: String) {
     // update HEAD
     // let mut head: ActiveModel = reference::Model::current_head(db).await.unwrap().into();
     let head = Head::Branch(branch_name);
-    Head::update(head, None).await;
+    move_head(head, commit_id).await;
 }
 
 async fn restore_to_commit(commit_id: SHA1) {
@@ -104,7 +113,8 @@ async fn restore_to_commit(commit_id: SHA1) {
 mod tests {
     use super::*;
     use crate::command::restore::RestoreArgs;
-    use crate::utils::util;
+    use crate::internal::config::Config;
+    use crate::utils::{test, util};
     use std::env;
     use std::str::FromStr;
     #[test]
@@ -121,4 +131,54 @@ mod tests {
         ]);
         println!("{:?}", restore_args);
     }
+
+    #[tokio::test]
+    async fn test_switch_detach_revision() {
+        test::setup_with_new_libra().await;
+        let mut commits = Vec::new();
+        for i in 0..3 {
+            let content = i.to_string();
+            let commit =
+                test::commit_files(&[("switch_detach.txt", &content)], &format!("commit {}", i))
+                    .await;
+            commits.push(commit);
+        }
+
+        execute(SwitchArgs::parse_from(["switch", "--detach", "HEAD"])).await;
+        assert!(matches!(Head::current().await, Head::Detached(id) if id == commits[2]));
+        execute(SwitchArgs::parse_from(["switch", "--detach", "HEAD~1"])).await;
+        assert!(matches!(Head::current().await, Head::Detached(id) if id == commits[1]));
+        assert_eq!(std::fs::read_to_string("switch_detach.txt").unwrap(), "1");
+
+        // out of the 3 commits, HEAD is unchanged
+        execute(SwitchArgs::parse_from(["switch", "--detach", "HEAD~5"])).await;
+        assert!(matches!(Head::current().await, Head::Detached(id) if id == commits[1]));
+
+        // the moves are in the reflog of HEAD, after the commits
+        let branch = Config::default_branch().await;
+        execute(SwitchArgs::parse_from(["switch", &branch])).await;
+        let moves: Vec<(SHA1, SHA1, String)> = reflog::read_reflog(reflog::HEAD)
+            .unwrap()
+            .into_iter()
+            .skip(3)
+            .map(|entry| (entry.old, entry.new, entry.message))
+            .collect();
+        let moving = |from: &str, to: &str| format!("checkout: moving from {} to {}", from, to);
+        let [c1, c2] = [commits[1], commits[2]];
+        assert_eq!(
+            moves,
+            [
+                (c2, c2, moving(&branch, &c2.to_string())),
+                (c2, c1, moving(&c2.to_string(), &c1.to_string())),
+                (c1, c2, moving(&c1.to_string(), &branch)),
+            ]
+        );
+        // the branch didn't move
+        assert_eq!(
+            reflog::read_reflog(&reflog::branch_ref(&branch))
+                .unwrap()
+                .len(),
+            3
+        );
+    }
 }
@@ -0,0 +1,270 @@
+//! Verify the signature of a commit, the `gpgsig` header, against the keys of a [Keyring].
+//!
+//! The signed payload is the commit object without its `gpgsig` header, as `git verify-commit`.
+
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use tempfile::NamedTempFile;
+
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::ObjectTrait;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// signed by a known key, and the commit is unchanged
+    Good,
+    /// the signature doesn't match the commit, it was modified after signing
+    Bad,
+    /// signed by a key which is not in the keyring, or of an unsupported kind
+    Unknown,
+    /// the commit isn't signed
+    None,
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureStatus::Good => write!(f, "Good signature"),
+            SignatureStatus::Bad => write!(f, "BAD signature"),
+            SignatureStatus::Unknown => write!(f, "Can't check signature: no known key"),
+            SignatureStatus::None => write!(f, "No signature"),
+        }
+    }
+}
+
+/// Keys trusted to sign commits
+pub trait Keyring {
+    /// Check the armored `signature` of `payload`: `Good`, `Bad` or `Unknown`
+    fn verify(&self, signature: &str, payload: &[u8]) -> SignatureStatus;
+}
+
+/// Split the data of a commit into the signature of its `gpgsig` header, without the continuation
+/// spaces, and the signed payload. `None` if there is no `gpgsig` header.
+fn split_signature(data: &[u8]) -> Option<(String, Vec<u8>)> {
+    let mut lines = data.split_inclusive(|&b| b == b'\n');
+    let mut signature: Option<Vec<u8>> = None;
+    let mut payload = Vec::with_capacity(data.len());
+    while let Some(line) = lines.next() {
+        if line == b"\n" {
+            // end of the headers, the rest is the message
+            payload.extend(line);
+            break;
+        }
+        match line.strip_prefix(b"gpgsig ") {
+            Some(first) if signature.is_none() => {
+                // git indents the following lines with a space, libra used to not
+                let mut sig = first.to_vec();
+                let mut last = first;
+                while !last.windows(9).any(|w| w == b"-----END ") {
+                    let Some(next) = lines.next() else { break };
+                    last = next.strip_prefix(b" ").unwrap_or(next);
+                    sig.extend(last);
+                }
+                signature = Some(sig);
+            }
+            _ => payload.extend(line),
+        }
+    }
+    lines.for_each(|line| payload.extend(line));
+    Some((String::from_utf8_lossy(&signature?).into_owned(), payload))
+}
+
+/// Verify the signature of `commit` with the keys of `keyring`, [SignatureStatus::None] if unsigned
+pub fn verify_commit_signature(commit: &Commit, keyring: &impl Keyring) -> SignatureStatus {
+    let Ok(data) = commit.to_data() else {
+        return SignatureStatus::Bad;
+    };
+    match split_signature(&data) {
+        Some((signature, payload)) => keyring.verify(&signature, &payload),
+        None => SignatureStatus::None,
+    }
+}
+
+/// The keys of the user's GnuPG, checked by `gpg --verify` like git does.
+/// SSH signatures are `Unknown`, as well as any signature if `gpg` can't be run.
+pub struct GpgKeyring;
+
+impl Keyring for GpgKeyring {
+    fn verify(&self, signature: &str, payload: &[u8]) -> SignatureStatus {
+        if !signature.starts_with("-----BEGIN PGP SIGNATURE-----") {
+            return SignatureStatus::Unknown;
+        }
+        // gpg reads the detached signature from a file, and the payload from stdin.
+        // The file is created only readable by the user, at an unpredictable path
+        let Ok(mut sig_file) = NamedTempFile::with_prefix("libra-sig-") else {
+            return SignatureStatus::Unknown;
+        };
+        if sig_file.write_all(signature.as_bytes()).is_err() {
+            return SignatureStatus::Unknown;
+        }
+        let output = Command::new("gpg")
+            .arg("--status-fd=1")
+            .arg("--verify")
+            .arg(sig_file.path())
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(payload)?;
+                child.wait_with_output()
+            });
+        // removes the file
+        drop(sig_file);
+        match output {
+            Ok(output) => parse_gpg_status(&String::from_utf8_lossy(&output.stdout)),
+            Err(e) => {
+                tracing::debug!("failed to run gpg: {}", e);
+                SignatureStatus::Unknown
+            }
+        }
+    }
+}
+
+/// Status of a signature from the `--status-fd` output of `gpg --verify`
+fn parse_gpg_status(status: &str) -> SignatureStatus {
+    let keywords: Vec<&str> = status
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] "))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    if keywords.contains(&"BADSIG") {
+        SignatureStatus::Bad
+    } else if keywords.contains(&"GOODSIG") {
+        SignatureStatus::Good
+    } else {
+        // ERRSIG, NO_PUBKEY, or gpg failed before checking
+        SignatureStatus::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::utils::format_commit_msg;
+    use mercury::hash::SHA1;
+
+    use super::*;
+
+    /// Signs with the name of its key & the hash of the payload, trusts only `key`
+    struct StubKeyring {
+        key: &'static str,
+    }
+
+    impl StubKeyring {
+        fn sign(key: &str, payload: &[u8]) -> String {
+            format!(
+                "-----BEGIN STUB SIGNATURE-----\n{}:{}\n-----END STUB SIGNATURE-----\n",
+                key,
+                SHA1::new(payload)
+            )
+        }
+    }
+
+    impl Keyring for StubKeyring {
+        fn verify(&self, signature: &str, payload: &[u8]) -> SignatureStatus {
+            let Some((key, hash)) = signature.lines().nth(1).and_then(|l| l.split_once(':')) else {
+                return SignatureStatus::Bad;
+            };
+            if key != self.key {
+                SignatureStatus::Unknown
+            } else if hash == SHA1::new(payload).to_string() {
+                SignatureStatus::Good
+            } else {
+                SignatureStatus::Bad
+            }
+        }
+    }
+
+    /// `unsigned` with the `gpgsig` header of `signature` indented like git
+    fn with_signature(unsigned: &Commit, signature: &str, msg: &str) -> Commit {
+        let header = format!("gpgsig {}", signature.trim_end().replace('\n', "\n "));
+        Commit::new(
+            unsigned.author.clone(),
+            unsigned.committer.clone(),
+            unsigned.tree_id,
+            unsigned.parent_commit_ids.clone(),
+            &format_commit_msg(msg, Some(&header)),
+        )
+    }
+
+    #[test]
+    fn test_verify_commit_signature() {
+        let keyring = StubKeyring { key: "alice" };
+        let unsigned = Commit::from_tree_id(SHA1::new(&[1; 20]), vec![], "\nsigned work");
+        let payload = unsigned.to_data().unwrap();
+
+        let signed = with_signature(
+            &unsigned,
+            &StubKeyring::sign("alice", &payload),
+            "signed work",
+        );
+        assert_eq!(
+            verify_commit_signature(&signed, &keyring),
+            SignatureStatus::Good
+        );
+
+        // the message changed after signing
+        let tampered = with_signature(
+            &unsigned,
+            &StubKeyring::sign("alice", &payload),
+            "evil work",
+        );
+        assert_eq!(
+            verify_commit_signature(&tampered, &keyring),
+            SignatureStatus::Bad
+        );
+
+        let stranger = with_signature(
+            &unsigned,
+            &StubKeyring::sign("bob", &payload),
+            "signed work",
+        );
+        assert_eq!(
+            verify_commit_signature(&stranger, &keyring),
+            SignatureStatus::Unknown
+        );
+
+        assert_eq!(
+            verify_commit_signature(&unsigned, &keyring),
+            SignatureStatus::None
+        );
+    }
+
+    #[test]
+    fn test_split_unindented_signature() {
+        // as written by `format_commit_msg`, the signature lines are not indented
+        let sig = "gpgsig -----BEGIN PGP SIGNATURE-----\n\ncontent\n-----END PGP SIGNATURE-----";
+        let data = format!(
+            "tree {}\nauthor a <a@b> 0 +0000\ncommitter a <a@b> 0 +0000\n{}",
+            SHA1::default(),
+            format_commit_msg("msg", Some(sig))
+        );
+        let (signature, payload) = split_signature(data.as_bytes()).unwrap();
+        assert_eq!(
+            signature,
+            "-----BEGIN PGP SIGNATURE-----\n\ncontent\n-----END PGP SIGNATURE-----\n"
+        );
+        assert_eq!(
+            payload,
+            format!(
+                "tree {}\nauthor a <a@b> 0 +0000\ncommitter a <a@b> 0 +0000\n\nmsg",
+                SHA1::default()
+            )
+            .as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_parse_gpg_status() {
+        let good = "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 1234 alice <a@b>\n[GNUPG:] VALIDSIG 1234";
+        assert_eq!(parse_gpg_status(good), SignatureStatus::Good);
+        let bad = "[GNUPG:] NEWSIG\n[GNUPG:] BADSIG 1234 alice <a@b>\n";
+        assert_eq!(parse_gpg_status(bad), SignatureStatus::Bad);
+        let unknown = "[GNUPG:] ERRSIG 1234 1 8 00 1700000000 9\n[GNUPG:] NO_PUBKEY 1234\n";
+        assert_eq!(parse_gpg_status(unknown), SignatureStatus::Unknown);
+        assert_eq!(parse_gpg_status(""), SignatureStatus::Unknown);
+    }
+}
@@ -0,0 +1,138 @@
+//! Walk the history of a commit, newest first, like `git rev-list <commit>`.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::ObjectTrait;
+
+use crate::utils::client_storage::ClientStorage;
+
+/// Iterator over a commit and its ancestors, each once, by committer time (newest first).
+/// - with `first_parent`, only the first parent of a merge is followed
+/// - a commit failing to load ends the walk with its error
+pub struct CommitWalk {
+    storage: ClientStorage,
+    queue: BinaryHeap<ByCommitTime>,
+    seen: HashSet<SHA1>,
+    first_parent: bool,
+}
+
+/// Commit ordered by committer time, ties broken by id to keep the order stable
+struct ByCommitTime(Commit);
+
+impl Ord for ByCommitTime {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let time = |c: &Self| c.0.committer.timestamp;
+        time(self)
+            .cmp(&time(other))
+            .then_with(|| self.0.id.cmp(&other.0.id))
+    }
+}
+
+impl PartialOrd for ByCommitTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ByCommitTime {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ByCommitTime {}
+
+fn load_commit(storage: &ClientStorage, id: &SHA1) -> Result<Commit, GitError> {
+    Commit::from_bytes(&storage.get(id)?, *id)
+}
+
+impl CommitWalk {
+    /// Start from the commit `start`, which is the first item
+    pub fn new(storage: ClientStorage, start: SHA1) -> Result<Self, GitError> {
+        let commit = load_commit(&storage, &start)?;
+        Ok(CommitWalk {
+            storage,
+            queue: BinaryHeap::from([ByCommitTime(commit)]),
+            seen: HashSet::from([start]),
+            first_parent: false,
+        })
+    }
+
+    /// Follow only the first parent of merge commits
+    pub fn first_parent(mut self, first_parent: bool) -> Self {
+        self.first_parent = first_parent;
+        self
+    }
+}
+
+impl Iterator for CommitWalk {
+    type Item = Result<Commit, GitError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ByCommitTime(commit) = self.queue.pop()?;
+        let parents = match self.first_parent {
+            true => &commit.parent_commit_ids[..commit.parent_commit_ids.len().min(1)],
+            false => &commit.parent_commit_ids[..],
+        };
+        for parent in parents {
+            if !self.seen.insert(*parent) {
+                continue;
+            }
+            match load_commit(&self.storage, parent) {
+                Ok(parent) => self.queue.push(ByCommitTime(parent)),
+                Err(e) => {
+                    self.queue.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+        Some(Ok(commit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// store a commit with the committer time `time`
+    fn save_commit(storage: &ClientStorage, parents: Vec<SHA1>, msg: &str, time: usize) -> SHA1 {
+        let mut commit = Commit::from_tree_id(SHA1::default(), parents, &format!("\n{}", msg));
+        commit.committer.timestamp = time;
+        let data = commit.to_data().unwrap();
+        let id = SHA1::from_type_and_data(commit.get_type(), &data);
+        storage.put(&id, &data, commit.get_type()).unwrap();
+        id
+    }
+
+    fn messages(walk: CommitWalk) -> Vec<String> {
+        walk.map(|commit| commit.unwrap().message().trim().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_walk_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = ClientStorage::init(dir.path().to_path_buf());
+
+        // c1 <- c2 <- c4 <- merge, c1 <- side(3) <- merge
+        let c1 = save_commit(&storage, vec![], "c1", 1);
+        let c2 = save_commit(&storage, vec![c1], "c2", 2);
+        let side = save_commit(&storage, vec![c1], "side", 3);
+        let c4 = save_commit(&storage, vec![c2], "c4", 4);
+        let merge = save_commit(&storage, vec![c4, side], "merge", 5);
+
+        let walk = |start| CommitWalk::new(ClientStorage::init(dir.path().to_path_buf()), start);
+        assert_eq!(
+            messages(walk(merge).unwrap()),
+            ["merge", "c4", "side", "c2", "c1"]
+        );
+        let first_parent = walk(merge).unwrap().first_parent(true);
+        assert_eq!(messages(first_parent), ["merge", "c4", "c2", "c1"]);
+        assert_eq!(messages(walk(side).unwrap()), ["side", "c1"]);
+        assert!(walk(SHA1::new(&[9; 20])).is_err());
+    }
+}
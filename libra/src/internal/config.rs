@@ -1,8 +1,9 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 use std::mem::swap;
 
 use sea_orm::ActiveValue::Set;
 use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter};
+use url::Url;
 
 use crate::internal::db::get_db_conn_instance;
 use crate::internal::head::Head;
@@ -11,12 +12,24 @@ use crate::internal::model::config::Model;
 
 use super::model::config::ActiveModel;
 
+/// Branch HEAD points to in a new repository, unless `init.defaultBranch` is set
+pub const DEFAULT_BRANCH: &str = "master";
+
+/// URL schemes a remote may use, `p2p` addresses a repository shared by a gemini peer
+const REMOTE_SCHEMES: [&str; 6] = ["http", "https", "ssh", "git", "file", "p2p"];
+
 pub struct Config;
 
 pub struct RemoteConfig {
     pub name: String,
     pub url: String,
 }
+/// `user.name` & `user.email`, who the commits of the repository are made by
+pub struct UserConfig {
+    pub name: String,
+    pub email: String,
+}
+
 #[allow(dead_code)]
 pub struct BranchConfig {
     pub name: String,
@@ -55,6 +68,43 @@ impl Config {
         values.first().map(|c| c.value.to_owned())
     }
 
+    /// Set one configuration value, replacing the previous ones
+    pub async fn set(configuration: &str, name: Option<&str>, key: &str, value: &str) {
+        let db = get_db_conn_instance().await;
+        for entry in Self::query(configuration, name, key).await {
+            let entry: ActiveModel = entry.into();
+            entry.delete(db).await.unwrap();
+        }
+        Config::insert(configuration, name, key, value).await;
+    }
+
+    /// Get the identity of new commits, failing like git if `user.name` or `user.email` is unset
+    pub async fn user_config() -> Result<UserConfig, String> {
+        let set = |value: &String| !value.trim().is_empty();
+        let name = Config::get("user", None, "name").await.filter(set);
+        let email = Config::get("user", None, "email").await.filter(set);
+        match (name, email) {
+            (Some(name), Some(email)) => Ok(UserConfig { name, email }),
+            (name, _) => Err(format!(
+                "Author identity unknown\n\n\
+                 *** Please tell me who you are.\n\n\
+                 Run\n\n  \
+                 libra config user.email \"you@example.com\"\n  \
+                 libra config user.name \"Your Name\"\n\n\
+                 to set the identity of this repository.\n\n\
+                 fatal: unable to auto-detect {}",
+                if name.is_none() { "name" } else { "email address" }
+            )),
+        }
+    }
+
+    /// Get the name of the first branch, `init.defaultBranch` or [DEFAULT_BRANCH]
+    pub async fn default_branch() -> String {
+        Config::get("init", None, "defaultBranch")
+            .await
+            .unwrap_or_else(|| DEFAULT_BRANCH.to_owned())
+    }
+
     /// Get remote repo name by branch name
     /// - You may need to `[branch::set-upstream]` if return `None`
     pub async fn get_remote(branch: &str) -> Option<String> {
@@ -101,6 +151,36 @@ impl Config {
             .collect()
     }
 
+    /// Check `url` can be used as a remote: a URL with a known scheme, and a host unless it's a file
+    pub fn validate_remote_url(url: &str) -> Result<(), String> {
+        let parsed = Url::parse(url).map_err(|e| format!("fatal: invalid URL '{}': {}", url, e))?;
+        if !REMOTE_SCHEMES.contains(&parsed.scheme()) {
+            return Err(format!(
+                "fatal: unsupported URL scheme '{}' in '{}'",
+                parsed.scheme(),
+                url
+            ));
+        }
+        if parsed.scheme() != "file" && parsed.host_str().map_or(true, str::is_empty) {
+            return Err(format!("fatal: no host in URL '{}'", url));
+        }
+        Ok(())
+    }
+
+    /// Add remote `name` fetching from `url`, fails if the remote exists or the URL is invalid
+    pub async fn add_remote(name: &str, url: &str) -> Result<(), String> {
+        // `/` separates the remote from the branch in `origin/master`
+        if name.is_empty() || name.contains(['/', ' ']) {
+            return Err(format!("fatal: '{}' is not a valid remote name", name));
+        }
+        if Config::remote_config(name).await.is_some() {
+            return Err(format!("fatal: remote {} already exists.", name));
+        }
+        Config::validate_remote_url(url)?;
+        Config::insert("remote", Some(name), "url", url).await;
+        Ok(())
+    }
+
     pub async fn remove_remote(name: &str) -> Result<(), String> {
         let db = get_db_conn_instance().await;
         let remote = config::Entity::find()
@@ -119,6 +199,7 @@ impl Config {
         Ok(())
     }
 
+    /// Get all remotes, sorted by name
     pub async fn all_remote_configs() -> Vec<RemoteConfig> {
         let db = get_db_conn_instance().await;
         let remotes = config::Entity::find()
@@ -129,7 +210,7 @@ impl Config {
         let remote_names = remotes
             .iter()
             .map(|remote| remote.name.as_ref().unwrap().clone())
-            .collect::<HashSet<String>>();
+            .collect::<BTreeSet<String>>();
 
         remote_names
             .iter()
@@ -162,6 +243,32 @@ impl Config {
         })
     }
 
+    /// Set `branch.<branch>.remote` & `branch.<branch>.merge`, replacing the previous tracking
+    /// - `merge` is the ref name on the remote, e.g. `refs/heads/master`
+    pub async fn set_tracking(branch: &str, remote: &str, merge: &str) {
+        let db = get_db_conn_instance().await;
+        let entries = config::Entity::find()
+            .filter(config::Column::Configuration.eq("branch"))
+            .filter(config::Column::Name.eq(branch))
+            .filter(config::Column::Key.is_in(["remote", "merge"]))
+            .all(db)
+            .await
+            .unwrap();
+        for entry in entries {
+            let entry: ActiveModel = entry.into();
+            entry.delete(db).await.unwrap();
+        }
+        Config::insert("branch", Some(branch), "remote", remote).await;
+        Config::insert("branch", Some(branch), "merge", merge).await;
+    }
+
+    /// Get the remote branch `branch` tracks, as `<remote>/<branch>`, `None` if untracked
+    pub async fn upstream_of(branch: &str) -> Option<String> {
+        Config::branch_config(branch)
+            .await
+            .map(|config| format!("{}/{}", config.remote, config.merge))
+    }
+
     pub async fn branch_config(name: &str) -> Option<BranchConfig> {
         let db = get_db_conn_instance().await;
         let config_entries = config::Entity::find()
@@ -201,3 +308,60 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::test;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_remote_config() {
+        test::setup_with_new_libra().await;
+
+        Config::add_remote("origin", "https://github.com/web3infra-foundation/mega.git")
+            .await
+            .unwrap();
+        Config::add_remote(
+            "peer",
+            "p2p://mrJ46F8gd2sa2Dx3iCYf6DauJ2WpAaepus7PwyZVebgD/mega.git",
+        )
+        .await
+        .unwrap();
+        Config::add_remote("local", "file:///tmp/mega.git")
+            .await
+            .unwrap();
+
+        let remotes: Vec<_> = Config::all_remote_configs()
+            .await
+            .into_iter()
+            .map(|r| r.name)
+            .collect();
+        assert_eq!(remotes, vec!["local", "origin", "peer"]);
+        assert_eq!(
+            Config::get_remote_url("origin").await,
+            "https://github.com/web3infra-foundation/mega.git"
+        );
+
+        // duplicate name, invalid names & urls
+        assert!(Config::add_remote("origin", "https://gitee.com/mega.git")
+            .await
+            .is_err());
+        assert!(Config::add_remote("a/b", "https://gitee.com/mega.git")
+            .await
+            .is_err());
+        assert!(Config::add_remote("ftp", "ftp://example.com/mega.git")
+            .await
+            .is_err());
+        assert!(Config::add_remote("bad", "not a url").await.is_err());
+        assert!(Config::add_remote("nohost", "p2p:///mega.git")
+            .await
+            .is_err());
+        assert_eq!(Config::all_remote_configs().await.len(), 3);
+
+        Config::remove_remote("peer").await.unwrap();
+        assert!(Config::remote_config("peer").await.is_none());
+        assert!(Config::remove_remote("peer").await.is_err());
+        assert_eq!(Config::all_remote_configs().await.len(), 2);
+    }
+}
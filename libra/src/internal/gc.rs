@@ -0,0 +1,299 @@
+//! Garbage collection & counting of loose objects, by reachability from references.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use std::{fs, io};
+
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::index::Index;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tag::Tag;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+
+use crate::internal::db::get_db_conn_instance;
+use crate::internal::model::reference;
+use crate::internal::reflog;
+use crate::utils::client_storage::ClientStorage;
+use crate::utils::{path, util};
+
+/// Loose objects modified within this period are kept, they may belong to a commit in progress
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcReport {
+    /// objects reachable from the refs, loose & packed
+    pub reachable: usize,
+    /// unreachable loose objects kept because they are newer than the grace period
+    pub kept_recent: usize,
+    pub pruned: usize,
+    /// chunks in `objects/chunks` no longer listed by a chunked object
+    pub pruned_chunks: usize,
+    /// bytes freed on disk by pruning, objects & chunks
+    pub pruned_bytes: u64,
+}
+
+/// Loose object statistics, as `git count-objects`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ObjectCount {
+    /// number of loose objects
+    pub count: usize,
+    /// bytes used on disk by loose objects
+    pub size: u64,
+    pub reachable: usize,
+    pub unreachable: usize,
+}
+
+/// Collect the roots of reachability: every reference with a commit (branches, tags and
+/// detached HEADs, local & remote), the old & new values in the reflogs (so the stashes and
+/// the commits HEAD was moved away from are kept), plus all blobs staged in the index.
+pub async fn gc_roots() -> Result<Vec<SHA1>, GitError> {
+    let db_conn = get_db_conn_instance().await;
+    let references = reference::Entity::find()
+        .filter(reference::Column::Commit.is_not_null())
+        .all(db_conn)
+        .await
+        .map_err(|e| GitError::CustomError(e.to_string()))?;
+
+    let mut roots = Vec::new();
+    for commit in references.into_iter().filter_map(|r| r.commit) {
+        roots.push(SHA1::from_str(&commit).map_err(GitError::InvalidHashValue)?);
+    }
+
+    let storage = util::objects_storage();
+    for reference in reflog::list_reflogs()? {
+        for entry in reflog::read_reflog(&reference)? {
+            // zero when the ref was created; an object may be gone, pruned before the reflog
+            // kept it
+            roots.extend(
+                [entry.old, entry.new]
+                    .into_iter()
+                    .filter(|id| *id != SHA1::default() && storage.exist(id)),
+            );
+        }
+    }
+
+    let index_file = path::index();
+    if index_file.exists() {
+        let index = Index::load(&index_file)?;
+        for stage in 0..=3 {
+            roots.extend(index.tracked_entries(stage).iter().map(|e| e.hash));
+        }
+    }
+    Ok(roots)
+}
+
+/// Walk commits → trees → blobs (and tags → targets) from `roots`,
+/// returning every object id found.
+/// - A missing or corrupt object is an error, as the walk can't be trusted without it
+pub fn reachable_objects(storage: &ClientStorage, roots: &[SHA1]) -> Result<HashSet<SHA1>, GitError> {
+    let mut reachable = HashSet::new();
+    let mut stack: Vec<SHA1> = roots.to_vec();
+
+    while let Some(id) = stack.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        match storage.get_object_type(&id)? {
+            ObjectType::Commit => {
+                let commit = Commit::from_bytes(&storage.get(&id)?, id)?;
+                stack.push(commit.tree_id);
+                stack.extend(commit.parent_commit_ids);
+            }
+            ObjectType::Tree => {
+                let tree = Tree::from_bytes(&storage.get(&id)?, id)?;
+                // submodule commits live in another repository
+                stack.extend(
+                    tree.tree_items
+                        .into_iter()
+                        .filter(|item| item.mode != TreeItemMode::Commit)
+                        .map(|item| item.id),
+                );
+            }
+            ObjectType::Tag => {
+                let tag = Tag::from_bytes(&storage.get(&id)?, id)?;
+                stack.push(tag.object_hash);
+            }
+            _ => {}
+        }
+    }
+    Ok(reachable)
+}
+
+/// Time since the file was modified, a modified time in the future counts as new
+fn age(now: SystemTime, meta: &fs::Metadata) -> io::Result<Duration> {
+    Ok(now.duration_since(meta.modified()?).unwrap_or(Duration::ZERO))
+}
+
+/// Delete loose objects that are not reachable from `refs` and older than `grace_period`,
+/// then the chunks older than it which no remaining chunked object lists.
+/// - Objects in PACKs are never deleted
+pub fn gc(storage: &ClientStorage, refs: &[SHA1], grace_period: Duration) -> Result<GcReport, GitError> {
+    let reachable = reachable_objects(storage, refs)?;
+    let mut report = GcReport {
+        reachable: reachable.len(),
+        ..Default::default()
+    };
+
+    let now = SystemTime::now();
+    for id in storage.list_objects_loose() {
+        if reachable.contains(&id) {
+            continue;
+        }
+        let meta = storage.loose_metadata(&id)?;
+        if age(now, &meta)? < grace_period {
+            report.kept_recent += 1;
+            continue;
+        }
+        storage.delete_loose(&id)?;
+        report.pruned += 1;
+        report.pruned_bytes += meta.len();
+    }
+
+    let mut listed = HashSet::new();
+    for id in storage.list_objects_loose() {
+        listed.extend(storage.loose_chunks(&id)?);
+    }
+    for chunk in storage.iter_chunks().collect::<Vec<_>>() {
+        if listed.contains(&chunk) {
+            continue;
+        }
+        let meta = storage.chunk_metadata(&chunk)?;
+        // may be written for an object not saved yet
+        if age(now, &meta)? < grace_period {
+            continue;
+        }
+        storage.delete_chunk(&chunk)?;
+        report.pruned_chunks += 1;
+        report.pruned_bytes += meta.len();
+    }
+    Ok(report)
+}
+
+/// Count loose objects and their size on disk, split by reachability from `refs`
+pub fn count_objects(storage: &ClientStorage, refs: &[SHA1]) -> Result<ObjectCount, GitError> {
+    let reachable = reachable_objects(storage, refs)?;
+    let mut count = ObjectCount::default();
+    for id in storage.list_objects_loose() {
+        count.count += 1;
+        count.size += storage.loose_metadata(&id)?.len();
+        if reachable.contains(&id) {
+            count.reachable += 1;
+        } else {
+            count.unreachable += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use mercury::internal::object::blob::Blob;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::utils::test;
+
+    /// store with a commit → tree → blob chain, returns the storage, commit & chain ids
+    fn setup_storage() -> (TempDir, ClientStorage, SHA1, [SHA1; 3]) {
+        let (dir, storage) = test::temp_storage();
+        let blob = test::save_blob(&storage, b"reachable");
+        let tree = test::save_tree(&storage, vec![("file.txt", TreeItemMode::Blob, blob)]);
+        let commit = Commit::from_tree_id(tree, vec![], "init");
+        storage.put(&commit.id, &commit.to_data().unwrap(), commit.get_type()).unwrap();
+        (dir, storage, commit.id, [blob, tree, commit.id])
+    }
+
+    #[test]
+    fn test_gc_prunes_only_orphans() {
+        let (_dir, storage, commit, chain) = setup_storage();
+        let orphan = test::save_blob(&storage, b"orphan");
+
+        // too recent to prune
+        let report = gc(&storage, &[commit], Duration::from_secs(3600)).unwrap();
+        assert_eq!(report.pruned, 0);
+        assert_eq!(report.kept_recent, 1);
+        assert!(storage.exist(&orphan));
+
+        let report = gc(&storage, &[commit], Duration::ZERO).unwrap();
+        assert_eq!(report.reachable, 3);
+        assert_eq!(report.pruned, 1);
+        assert!(report.pruned_bytes > 0);
+        assert!(!storage.exist(&orphan));
+        for id in chain {
+            assert!(storage.exist(&id));
+        }
+    }
+
+    #[test]
+    fn test_gc_prunes_unlisted_chunks() {
+        let (_dir, storage) = test::temp_storage();
+        let storage = storage.with_chunk_threshold(Some(1 << 20));
+        let content = test::random_bytes(4 << 20, 0x5eed);
+        let mut edited = content.clone();
+        edited[3 << 20..(3 << 20) + 5].copy_from_slice(b"edit!");
+        let kept = test::save_blob(&storage, &content);
+        let orphan = test::save_blob(&storage, &edited);
+        let chunks = |id| storage.loose_chunks(id).unwrap().into_iter().collect::<HashSet<_>>();
+        let (kept_chunks, orphan_chunks) = (chunks(&kept), chunks(&orphan));
+        let unique = orphan_chunks.difference(&kept_chunks).count();
+        assert!(unique > 0 && unique < orphan_chunks.len());
+
+        // too recent to prune, so are their chunks
+        let report = gc(&storage, &[kept], Duration::from_secs(3600)).unwrap();
+        assert_eq!((report.pruned, report.pruned_chunks), (0, 0));
+
+        let report = gc(&storage, &[kept], Duration::ZERO).unwrap();
+        assert_eq!((report.pruned, report.pruned_chunks), (1, unique));
+        let stored: HashSet<SHA1> = storage.iter_chunks().collect();
+        assert_eq!(stored, kept_chunks);
+        assert_eq!(storage.get(&kept).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn test_gc_roots_of_reflogs() {
+        test::setup_with_new_libra().await;
+        let storage = util::objects_storage();
+        let stashed = test::save_blob(&storage, b"stashed");
+        let left = test::save_blob(&storage, b"left by HEAD");
+        let pruned = Blob::from_content("pruned").id;
+        let entry = |old, new| reflog::ReflogEntry::new(old, new, "mega <admin@mega.org>", "");
+        reflog::append_reflog("refs/stash", &entry(None, stashed)).unwrap();
+        reflog::append_reflog(reflog::HEAD, &entry(Some(left), pruned)).unwrap();
+
+        let roots = gc_roots().await.unwrap();
+        assert!(roots.contains(&stashed));
+        assert!(roots.contains(&left));
+        // the object is gone, so can't be walked
+        assert!(!roots.contains(&pruned));
+        assert!(!roots.contains(&SHA1::default()));
+    }
+
+    #[test]
+    fn test_count_objects() {
+        let (_dir, storage, commit, _) = setup_storage();
+        for content in ["orphan 1", "orphan 2"] {
+            test::save_blob(&storage, content.as_bytes());
+        }
+
+        let count = count_objects(&storage, &[commit]).unwrap();
+        assert_eq!(count.count, 5);
+        assert_eq!(count.reachable, 3);
+        assert_eq!(count.unreachable, 2);
+        let size: u64 = storage
+            .list_objects_loose()
+            .iter()
+            .map(|id| storage.loose_metadata(id).unwrap().len())
+            .sum();
+        assert_eq!(count.size, size);
+
+        // nothing is reachable without refs
+        let count = count_objects(&storage, &[]).unwrap();
+        assert_eq!(count.unreachable, 5);
+    }
+}
@@ -0,0 +1,158 @@
+//! Search the content of the files of a tree, like `git grep <pattern> <tree>`.
+
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+
+use regex::{Regex, RegexBuilder};
+
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::tree::TreeItemMode;
+
+use crate::internal::tree_walk::walk_tree;
+use crate::utils::client_storage::ClientStorage;
+
+/// Bytes inspected to tell binary files apart, the same heuristic as git
+const BINARY_CHECK_LEN: usize = 8000;
+
+#[derive(Debug, Clone, Default)]
+pub struct GrepOptions {
+    pub ignore_case: bool,
+    /// stop after this many matches, `None` for all
+    pub max_matches: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub path: PathBuf,
+    /// 1-based
+    pub line_no: usize,
+    pub line: String,
+}
+
+/// A blob is binary if it contains a NUL byte near the start or is not valid UTF-8
+fn as_text(content: &[u8]) -> Option<&str> {
+    if content[..content.len().min(BINARY_CHECK_LEN)].contains(&0) {
+        return None;
+    }
+    std::str::from_utf8(content).ok()
+}
+
+/// Find the lines matching `pattern` in all text files under the tree `root`
+/// - binary files & submodules are skipped
+/// - matches are ordered by path, then line
+pub fn grep_tree(
+    storage: &ClientStorage,
+    root: SHA1,
+    pattern: &Regex,
+    opts: &GrepOptions,
+) -> Result<Vec<Match>, GitError> {
+    let pattern = if opts.ignore_case {
+        RegexBuilder::new(pattern.as_str())
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| GitError::CustomError(e.to_string()))?
+    } else {
+        pattern.clone()
+    };
+    let limit = opts.max_matches.unwrap_or(usize::MAX);
+
+    let mut matches = Vec::new();
+    let mut error = None;
+    walk_tree(storage, root, |path, item| {
+        if !matches!(item.mode, TreeItemMode::Blob | TreeItemMode::BlobExecutable) {
+            return ControlFlow::Continue(());
+        }
+        let content = match storage.get(&item.id) {
+            Ok(content) => content,
+            Err(e) => {
+                error = Some(e);
+                return ControlFlow::Break(());
+            }
+        };
+        if let Some(text) = as_text(&content) {
+            for (idx, line) in text.lines().enumerate() {
+                if matches.len() >= limit {
+                    return ControlFlow::Break(());
+                }
+                if pattern.is_match(line) {
+                    matches.push(Match {
+                        path: path.to_path_buf(),
+                        line_no: idx + 1,
+                        line: line.to_owned(),
+                    });
+                }
+            }
+        }
+        if matches.len() >= limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })?;
+    match error {
+        Some(e) => Err(e),
+        None => Ok(matches),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::{save_blob, save_tree, temp_storage};
+
+    #[test]
+    fn test_grep_tree() {
+        let (_dir, storage) = temp_storage();
+
+        let main = save_blob(&storage, b"fn main() {\n    todo!()\n}\n// TODO: args\n");
+        let lib = save_blob(&storage, b"pub fn lib() {}\n");
+        let image = save_blob(&storage, b"\x89PNG\r\n\x1a\n\0\0todo");
+        let src = save_tree(
+            &storage,
+            vec![
+                ("lib.rs", TreeItemMode::Blob, lib),
+                ("main.rs", TreeItemMode::Blob, main),
+            ],
+        );
+        let root = save_tree(
+            &storage,
+            vec![
+                ("logo.png", TreeItemMode::Blob, image),
+                ("src", TreeItemMode::Tree, src),
+            ],
+        );
+
+        let todo = Regex::new("todo").unwrap();
+        let matches = grep_tree(&storage, root, &todo, &GrepOptions::default()).unwrap();
+        assert_eq!(
+            matches,
+            vec![Match {
+                path: PathBuf::from("src/main.rs"),
+                line_no: 2,
+                line: "    todo!()".to_string(),
+            }]
+        );
+
+        let opts = GrepOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let matches = grep_tree(&storage, root, &todo, &opts).unwrap();
+        let lines: Vec<_> = matches.iter().map(|m| m.line_no).collect();
+        assert_eq!(lines, vec![2, 4]);
+        assert!(matches
+            .iter()
+            .all(|m| m.path == PathBuf::from("src/main.rs")));
+
+        let fns = Regex::new(r"fn \w+").unwrap();
+        let opts = GrepOptions {
+            max_matches: Some(1),
+            ..Default::default()
+        };
+        let matches = grep_tree(&storage, root, &fns, &opts).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].path, PathBuf::from("src/lib.rs"));
+        assert_eq!(matches[0].line_no, 1);
+    }
+}
@@ -1,6 +1,15 @@
 pub mod branch;
+pub mod commit_signature;
+pub mod commit_walk;
 pub mod config;
 pub mod db;
+pub mod gc;
+pub mod grep;
 pub mod head;
 pub mod model;
 pub mod protocol;
+pub mod reflog;
+pub mod rev_parse;
+pub mod tag;
+pub mod tree_diff;
+pub mod tree_walk;
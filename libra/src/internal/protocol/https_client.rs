@@ -1,6 +1,7 @@
+use super::pkt_line::{self, PktLine};
 use super::ProtocolClient;
 use bytes::Bytes;
-use ceres::protocol::smart::{add_pkt_line_string, read_pkt_line};
+use ceres::protocol::smart::add_pkt_line_string;
 use ceres::protocol::ServiceType;
 use ceres::protocol::ServiceType::UploadPack;
 use futures_util::{StreamExt, TryStreamExt};
@@ -9,6 +10,7 @@ use mercury::hash::SHA1;
 use reqwest::header::CONTENT_TYPE;
 use reqwest::{Body, RequestBuilder, Response, StatusCode};
 use std::io::Error as IoError;
+use std::io::Read;
 use std::ops::Deref;
 use std::sync::Mutex;
 use tokio_util::bytes::BytesMut;
@@ -91,6 +93,86 @@ pub struct DiscoveredReference {
 
 type DiscRef = DiscoveredReference;
 
+/// References advertised by a server, see [parse_ref_advertisement]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RefAdvertisement {
+    /// `$servicename` of the `# service=$servicename` line sent over smart HTTP
+    pub service: Option<String>,
+    pub refs: Vec<DiscoveredReference>,
+    /// declared behind a NUL on the first ref
+    pub capabilities: Vec<String>,
+}
+
+/// Read one pkt-line of the ref advertisement, `None` for a flush-pkt
+fn next_pkt_line(reader: &mut impl Read) -> Result<Option<Vec<u8>>, GitError> {
+    match pkt_line::read_pkt_line(reader) {
+        Ok(PktLine::Data(data)) => Ok(Some(data)),
+        Ok(PktLine::Flush) => Ok(None),
+        Ok(PktLine::Delim) => Err(GitError::NetworkError(
+            "unexpected delim-pkt in ref advertisement".to_string(),
+        )),
+        Err(e) => Err(GitError::NetworkError(format!(
+            "malformed pkt-line in ref advertisement: {}",
+            e
+        ))),
+    }
+}
+
+/// Parse the reference advertisement of `git-upload-pack` or `git-receive-pack`, up to its flush-pkt.
+/// - the leading `# service=$servicename` pkt-line & its flush-pkt (smart HTTP) are optional
+/// - an empty repository advertises a zero id named `capabilities^{}`, which is not a ref
+/// - see [http-protocol](https://www.git-scm.com/docs/http-protocol#_smart_clients)
+pub fn parse_ref_advertisement(reader: &mut impl Read) -> Result<RefAdvertisement, GitError> {
+    let invalid = |line: &str| GitError::NetworkError(format!("invalid ref line: {:?}", line));
+    let mut advertisement = RefAdvertisement::default();
+    let mut first = true;
+    while let Some(line) = next_pkt_line(reader)? {
+        let line = String::from_utf8(line)
+            .map_err(|_| GitError::NetworkError("ref advertisement is not UTF-8".to_string()))?;
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        if first && advertisement.service.is_none() {
+            if let Some(service) = line.strip_prefix("# service=") {
+                advertisement.service = Some(service.to_string());
+                // the service line is followed by a flush-pkt, then the refs
+                if next_pkt_line(reader)?.is_some() {
+                    return Err(GitError::NetworkError(
+                        "missing flush-pkt after service line".to_string(),
+                    ));
+                }
+                continue;
+            }
+        }
+
+        let (hash, rest) = line
+            .split_once(' ')
+            .filter(|(hash, _)| hash.len() == 40 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| invalid(line))?;
+        let name = if first {
+            first = false;
+            let (name, caps) = rest.split_once('\0').unwrap_or((rest, ""));
+            advertisement.capabilities = caps
+                .split(' ')
+                .filter(|c| !c.is_empty())
+                .map(String::from)
+                .collect();
+            name
+        } else {
+            rest
+        };
+        if name.is_empty() {
+            return Err(invalid(line));
+        }
+        if name == "capabilities^{}" && hash == SHA1::default().to_string() {
+            continue; // empty repository
+        }
+        advertisement.refs.push(DiscoveredReference {
+            _hash: hash.to_string(),
+            _ref: name.to_string(),
+        });
+    }
+    Ok(advertisement)
+}
+
 // Client communicates with the remote git repository over SMART protocol.
 // protocol details: https://www.git-scm.com/docs/http-protocol
 // capability declarations: https://www.git-scm.com/docs/protocol-capabilities
@@ -134,62 +216,19 @@ impl HttpsClient {
             )));
         }
 
-        let mut response_content = res.bytes().await.unwrap();
+        let response_content = res.bytes().await.unwrap();
         tracing::debug!("{:?}", response_content);
 
-        // the first five bytes of the response entity matches the regex ^[0-9a-f]{4}#.
-        // verify the first pkt-line is # service=$servicename, and ignore LF
-        let (_, first_line) = read_pkt_line(&mut response_content);
-        if first_line[..].ne(format!("# service={}\n", service).as_bytes()) {
+        // verify the first pkt-line is # service=$servicename
+        let advertisement = parse_ref_advertisement(&mut &response_content[..])?;
+        if advertisement.service.as_deref() != Some(service) {
             return Err(GitError::NetworkError(format!(
                 "Error Response format, didn't start with `# service={}`",
                 service
             )));
         }
-
-        let mut ref_list = vec![];
-        let mut read_first_line = false;
-        loop {
-            let (bytes_take, pkt_line) = read_pkt_line(&mut response_content);
-            if bytes_take == 0 {
-                if response_content.is_empty() {
-                    break;
-                } else {
-                    continue;
-                }
-            }
-            let pkt_line = String::from_utf8(pkt_line.to_vec()).unwrap();
-            let (hash, mut refs) = pkt_line.split_at(40); // hex SHA1 string is 40 bytes
-            refs = refs.trim();
-            if !read_first_line {
-                if hash == SHA1::default().to_string() {
-                    break; // empty repo, return empty list // TODO: parse capability
-                }
-                let (head, caps) = refs.split_once('\0').unwrap();
-                if service == UploadPack.to_string() {
-                    // for git-upload-pack, the first line is HEAD
-                    assert_eq!(head, "HEAD");
-                }
-                // default ref named HEAD as the first ref. The stream MUST include capability declarations behind a NUL on the first ref.
-                ref_list.push(DiscoveredReference {
-                    _hash: hash.to_string(),
-                    _ref: head.to_string(),
-                });
-                let caps = caps.split(' ').collect::<Vec<&str>>();
-                tracing::debug!("capability declarations: {:?}", caps);
-                // tracing::warn!(
-                //     "temporary ignore capability declarations:[ {:?} ]",
-                //     refs[4..].to_string()
-                // );
-                read_first_line = true;
-            } else {
-                ref_list.push(DiscoveredReference {
-                    _hash: hash.to_string(),
-                    _ref: refs.to_string(),
-                });
-            }
-        }
-        Ok(ref_list)
+        tracing::debug!("capability declarations: {:?}", advertisement.capabilities);
+        Ok(advertisement.refs)
     }
 
     /// POST $GIT_URL/git-upload-pack HTTP/1.0<br>
@@ -281,6 +320,82 @@ mod tests {
 
     use super::*;
 
+    fn pkt_lines(lines: &[Option<&str>]) -> Bytes {
+        let mut buf = BytesMut::new();
+        for line in lines {
+            match line {
+                Some(line) => add_pkt_line_string(&mut buf, line.to_string()),
+                None => buf.extend(b"0000"),
+            }
+        }
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_parse_ref_advertisement() {
+        let head = "7ef152d43162e28b3177f6df380112f6412f5b42";
+        let tag = "1c05d7f7dd70e38150bfd2d5fb8fb969e2eb9851";
+        let bytes = pkt_lines(&[
+            Some("# service=git-upload-pack\n"),
+            None,
+            Some(&format!(
+                "{} HEAD\0multi_ack side-band-64k ofs-delta symref=HEAD:refs/heads/main agent=git/2.43.0\n",
+                head
+            )),
+            Some(&format!("{} refs/heads/main\n", head)),
+            Some(&format!("{} refs/tags/v1.0\n", tag)),
+            None,
+        ]);
+        let mut reader = &bytes[..];
+        let advertisement = parse_ref_advertisement(&mut reader).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(advertisement.service.as_deref(), Some("git-upload-pack"));
+        assert_eq!(
+            advertisement.capabilities,
+            vec![
+                "multi_ack",
+                "side-band-64k",
+                "ofs-delta",
+                "symref=HEAD:refs/heads/main",
+                "agent=git/2.43.0"
+            ]
+        );
+        let refs: Vec<_> = advertisement
+            .refs
+            .iter()
+            .map(|r| (r._hash.as_str(), r._ref.as_str()))
+            .collect();
+        assert_eq!(
+            refs,
+            vec![
+                (head, "HEAD"),
+                (head, "refs/heads/main"),
+                (tag, "refs/tags/v1.0")
+            ]
+        );
+
+        // empty repository, without the smart HTTP service line
+        let bytes = pkt_lines(&[
+            Some(&format!(
+                "{} capabilities^{{}}\0report-status delete-refs\n",
+                SHA1::default()
+            )),
+            None,
+        ]);
+        let advertisement = parse_ref_advertisement(&mut &bytes[..]).unwrap();
+        assert_eq!(advertisement.service, None);
+        assert!(advertisement.refs.is_empty());
+        assert_eq!(
+            advertisement.capabilities,
+            vec!["report-status", "delete-refs"]
+        );
+
+        // malformed input is an error, not a panic
+        assert!(parse_ref_advertisement(&mut &b"00zz"[..]).is_err());
+        assert!(parse_ref_advertisement(&mut &b"0032abc"[..]).is_err());
+        assert!(parse_ref_advertisement(&mut &pkt_lines(&[Some("not a ref\n")])[..]).is_err());
+    }
+
     #[tokio::test]
     async fn test_discover_reference_upload() {
         init_debug_logger();
@@ -2,6 +2,9 @@ use url::Url;
 
 pub mod https_client;
 pub mod lfs_client;
+pub mod pkt_line;
+pub mod sideband;
+pub mod transport;
 
 #[allow(dead_code)] // todo: unimplemented
 pub trait ProtocolClient {
@@ -0,0 +1,177 @@
+//! pkt-line framing of the git wire protocol: a 4 hex digits length, counting itself, then the data.
+//! - `0000` is a flush-pkt, `0001` a delim-pkt (protocol v2)
+//! - see [protocol-common](https://git-scm.com/docs/protocol-common#_pkt_line_format)
+
+use std::io::{self, Read, Write};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Longest pkt-line, length field included
+pub const MAX_PKT_LEN: usize = 65520;
+
+/// Longest data of a pkt-line
+pub const MAX_PKT_DATA_LEN: usize = MAX_PKT_LEN - 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PktLine {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+}
+
+/// Write `data` as one pkt-line, fails if it doesn't fit in [MAX_PKT_DATA_LEN]
+pub fn write_pkt_line(w: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_PKT_DATA_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "pkt-line data of {} bytes exceeds {} bytes",
+                data.len(),
+                MAX_PKT_DATA_LEN
+            ),
+        ));
+    }
+    write!(w, "{:04x}", data.len() + 4)?;
+    w.write_all(data)
+}
+
+pub fn write_flush_pkt(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(b"0000")
+}
+
+pub fn write_delim_pkt(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(b"0001")
+}
+
+/// Length of the data following the length field `len`, or the special pkt-line it is
+enum PktLen {
+    Data(usize),
+    Special(PktLine),
+}
+
+fn parse_len(len: [u8; 4]) -> io::Result<PktLen> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "invalid pkt-line length {:?}",
+                String::from_utf8_lossy(&len)
+            ),
+        )
+    };
+    if !len.iter().all(u8::is_ascii_hexdigit) {
+        return Err(invalid());
+    }
+    // all hex digits, so valid UTF-8
+    let len = usize::from_str_radix(std::str::from_utf8(&len).unwrap(), 16).unwrap();
+    match len {
+        0 => Ok(PktLen::Special(PktLine::Flush)),
+        1 => Ok(PktLen::Special(PktLine::Delim)),
+        2 | 3 => Err(invalid()),
+        len if len > MAX_PKT_LEN => Err(invalid()),
+        len => Ok(PktLen::Data(len - 4)),
+    }
+}
+
+/// Read one pkt-line
+/// - `InvalidData` if the length isn't 4 hex digits, is reserved (`0002`, `0003`) or too long
+/// - `UnexpectedEof` if the input ends within the pkt-line
+pub fn read_pkt_line(r: &mut impl Read) -> io::Result<PktLine> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    match parse_len(len)? {
+        PktLen::Special(pkt) => Ok(pkt),
+        PktLen::Data(len) => {
+            let mut data = vec![0; len];
+            r.read_exact(&mut data)?;
+            Ok(PktLine::Data(data))
+        }
+    }
+}
+
+/// async version of [read_pkt_line]
+pub async fn read_pkt_line_async(r: &mut (impl AsyncRead + Unpin)) -> io::Result<PktLine> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len).await?;
+    match parse_len(len)? {
+        PktLen::Special(pkt) => Ok(pkt),
+        PktLen::Data(len) => {
+            let mut data = vec![0; len];
+            r.read_exact(&mut data).await?;
+            Ok(PktLine::Data(data))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkt_line_round_trip() {
+        let mut buf = Vec::new();
+        write_pkt_line(&mut buf, b"want 7ef152d43162e28b3177f6df380112f6412f5b42\n").unwrap();
+        write_pkt_line(&mut buf, b"").unwrap();
+        write_delim_pkt(&mut buf).unwrap();
+        write_flush_pkt(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            b"0032want 7ef152d43162e28b3177f6df380112f6412f5b42\n000400010000"
+        );
+
+        let mut reader = &buf[..];
+        assert_eq!(
+            read_pkt_line(&mut reader).unwrap(),
+            PktLine::Data(b"want 7ef152d43162e28b3177f6df380112f6412f5b42\n".to_vec())
+        );
+        assert_eq!(read_pkt_line(&mut reader).unwrap(), PktLine::Data(vec![]));
+        assert_eq!(read_pkt_line(&mut reader).unwrap(), PktLine::Delim);
+        assert_eq!(read_pkt_line(&mut reader).unwrap(), PktLine::Flush);
+        assert_eq!(
+            read_pkt_line(&mut reader).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        let data = vec![b'x'; MAX_PKT_DATA_LEN];
+        let mut buf = Vec::new();
+        write_pkt_line(&mut buf, &data).unwrap();
+        assert_eq!(&buf[..4], b"fff0");
+        assert_eq!(read_pkt_line(&mut &buf[..]).unwrap(), PktLine::Data(data));
+    }
+
+    #[tokio::test]
+    async fn test_read_pkt_line_async() {
+        let mut reader = &b"0009done\n00010000fff1"[..];
+        assert_eq!(
+            read_pkt_line_async(&mut reader).await.unwrap(),
+            PktLine::Data(b"done\n".to_vec())
+        );
+        assert_eq!(
+            read_pkt_line_async(&mut reader).await.unwrap(),
+            PktLine::Delim
+        );
+        assert_eq!(
+            read_pkt_line_async(&mut reader).await.unwrap(),
+            PktLine::Flush
+        );
+        let err = read_pkt_line_async(&mut reader).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_pkt_line_errors() {
+        let oversized = vec![b'x'; MAX_PKT_DATA_LEN + 1];
+        let err = write_pkt_line(&mut Vec::new(), &oversized).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        for input in [&b"00zz"[..], b"+001", b"0002", b"0003", b"fff1xxxx"] {
+            let err = read_pkt_line(&mut &input[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", input);
+        }
+        // truncated length & data
+        let err = read_pkt_line(&mut &b"00"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = read_pkt_line(&mut &b"0010short"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,168 @@
+//! Demux the side-band(-64k) stream of a fetch: each pkt-line starts with its band.
+//! - band 1 is pack data, band 2 progress text for the user, band 3 an error ending the fetch
+//! - see [protocol-capabilities](https://git-scm.com/docs/protocol-capabilities#_side_band_side_band_64k)
+
+use std::fmt;
+use std::io;
+
+use tokio::io::AsyncRead;
+
+use super::pkt_line::{read_pkt_line_async, PktLine};
+
+#[derive(Debug)]
+pub enum SidebandError {
+    Io(io::Error),
+    /// the message sent by the remote on band 3
+    Remote(String),
+    UnknownBand(u8),
+}
+
+impl fmt::Display for SidebandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SidebandError::Io(e) => write!(f, "{}", e),
+            SidebandError::Remote(message) => write!(f, "remote error: {}", message),
+            SidebandError::UnknownBand(band) => write!(f, "unknown side-band-64k code: {}", band),
+        }
+    }
+}
+
+impl std::error::Error for SidebandError {}
+
+impl From<io::Error> for SidebandError {
+    fn from(e: io::Error) -> Self {
+        SidebandError::Io(e)
+    }
+}
+
+/// Reader of the pack data of a side-band stream, handing progress to `progress`
+pub struct SidebandReader<R, P> {
+    reader: R,
+    progress: P,
+}
+
+impl<R, P> SidebandReader<R, P>
+where
+    R: AsyncRead + Unpin,
+    P: FnMut(&str),
+{
+    pub fn new(reader: R, progress: P) -> Self {
+        SidebandReader { reader, progress }
+    }
+
+    /// Next chunk of pack data, `None` at the flush-pkt ending the stream.
+    /// - progress on the way is passed to the progress sink
+    /// - `Remote` on band 3: the fetch must be aborted, nothing more is read
+    /// - the `NAK` / `ACK` lines ending the negotiation, before the multiplexed data, are skipped
+    pub async fn next_pack_data(&mut self) -> Result<Option<Vec<u8>>, SidebandError> {
+        loop {
+            let data = match read_pkt_line_async(&mut self.reader).await? {
+                PktLine::Flush => return Ok(None),
+                PktLine::Delim => {
+                    return Err(SidebandError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected delim-pkt in side-band stream",
+                    )))
+                }
+                PktLine::Data(data) => data,
+            };
+            match data.split_first() {
+                Some((&1, pack)) => return Ok(Some(pack.to_vec())),
+                Some((&2, progress)) => (self.progress)(&String::from_utf8_lossy(progress)),
+                Some((&3, message)) => {
+                    let message = String::from_utf8_lossy(message);
+                    return Err(SidebandError::Remote(message.trim_end().to_owned()));
+                }
+                _ if data.starts_with(b"NAK") || data.starts_with(b"ACK ") => {}
+                Some((&band, _)) => return Err(SidebandError::UnknownBand(band)),
+                None => {}
+            }
+        }
+    }
+
+    /// Read all the pack data, up to the flush-pkt
+    pub async fn read_pack(&mut self) -> Result<Vec<u8>, SidebandError> {
+        let mut pack = Vec::new();
+        while let Some(data) = self.next_pack_data().await? {
+            pack.extend(data);
+        }
+        Ok(pack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::protocol::pkt_line::{write_flush_pkt, write_pkt_line};
+
+    /// A side-band stream of `(band, data)` pkt-lines after a `NAK`, ended by a flush-pkt
+    fn stream(lines: &[(u8, &str)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_pkt_line(&mut buf, b"NAK\n").unwrap();
+        for (band, data) in lines {
+            write_pkt_line(&mut buf, &[&[*band][..], data.as_bytes()].concat()).unwrap();
+        }
+        write_flush_pkt(&mut buf).unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_demux_bands() {
+        let data = stream(&[
+            (2, "Enumerating objects: 3, done.\n"),
+            (1, "PACK\0\0\0\x02"),
+            (2, "Receiving objects: 50% (1/2)\r"),
+            (1, "\0\0\0\x01rest"),
+            (2, "Total 2 (delta 0)\n"),
+        ]);
+        let mut progress = Vec::new();
+        let mut reader =
+            SidebandReader::new(&data[..], |text: &str| progress.push(text.to_owned()));
+        let pack = reader.read_pack().await.unwrap();
+        assert_eq!(pack, b"PACK\0\0\0\x02\0\0\0\x01rest");
+        assert_eq!(
+            progress,
+            [
+                "Enumerating objects: 3, done.\n",
+                "Receiving objects: 50% (1/2)\r",
+                "Total 2 (delta 0)\n"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_band_aborts() {
+        let data = stream(&[
+            (1, "PACK"),
+            (2, "Counting objects: 1\n"),
+            (3, "upload-pack: not our ref\n"),
+            (1, "never read"),
+        ]);
+        let mut progress = Vec::new();
+        let mut reader =
+            SidebandReader::new(&data[..], |text: &str| progress.push(text.to_owned()));
+        assert_eq!(reader.next_pack_data().await.unwrap().unwrap(), b"PACK");
+        let err = reader.read_pack().await.unwrap_err();
+        assert!(
+            matches!(&err, SidebandError::Remote(message) if message == "upload-pack: not our ref"),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.to_string(), "remote error: upload-pack: not our ref");
+        drop(reader);
+        assert_eq!(progress, ["Counting objects: 1\n"]);
+
+        let data = stream(&[(5, "?")]);
+        let mut reader = SidebandReader::new(&data[..], |_: &str| {});
+        assert!(matches!(
+            reader.read_pack().await,
+            Err(SidebandError::UnknownBand(5))
+        ));
+        // the stream ends without its flush-pkt
+        let mut reader = SidebandReader::new(&data[..8], |_: &str| {});
+        assert!(matches!(
+            reader.read_pack().await,
+            Err(SidebandError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+//! Choose how a remote is reached from its URL.
+//!
+//! HTTP(S) remotes are used as they are. A `p2p://` remote, as shared by gemini, is a repository of
+//! another mega peer: it's reached through a ZTM tunnel, whose local end is a plain HTTP server.
+
+use url::Url;
+
+#[cfg(feature = "p2p")]
+use crate::internal::config::Config;
+
+const P2P_SCHEME: &str = "p2p://";
+
+/// Port of the local ZTM agent, unless `ztm.agentPort` is set
+#[cfg(feature = "p2p")]
+const DEFAULT_ZTM_AGENT_PORT: u16 = 7777;
+
+/// Connections to gemini peers, a trait so tests don't need a ZTM agent
+#[cfg(feature = "p2p")]
+pub(crate) trait PeerTunnel {
+    /// Get the local port forwarded to the mega of `peer_id`, creating the tunnel if needed
+    async fn open(&self, peer_id: &str) -> Result<u16, String>;
+
+    /// GET `path` of the mega of `peer_id` through the tunnel
+    async fn get(&self, peer_id: &str, path: &str) -> Result<String, String>;
+}
+
+#[cfg(feature = "p2p")]
+pub(crate) struct ZtmTunnel {
+    pub agent_port: u16,
+}
+
+#[cfg(feature = "p2p")]
+impl PeerTunnel for ZtmTunnel {
+    async fn open(&self, peer_id: &str) -> Result<u16, String> {
+        gemini::ztm::get_or_create_remote_mega_tunnel(self.agent_port, peer_id.to_owned()).await
+    }
+
+    async fn get(&self, peer_id: &str, path: &str) -> Result<String, String> {
+        gemini::ztm::send_get_request_to_peer_by_tunnel(
+            self.agent_port,
+            peer_id.to_owned(),
+            path.to_owned(),
+            None,
+        )
+        .await
+    }
+}
+
+/// Resolve a `p2p://` identifier to the HTTP URL of the repository at the local end of the tunnel
+/// - an alias identifier is first resolved to the repository path by asking the peer
+#[cfg(feature = "p2p")]
+pub(crate) async fn resolve_p2p_url(url: &str, tunnel: &impl PeerTunnel) -> Result<Url, String> {
+    use common::model::CommonResult;
+
+    let identifier = gemini::util::parse_p2p_identifier(url)
+        .map_err(|e| format!("fatal: invalid p2p URL '{}': {}", url, e))?;
+    let path = match identifier.port {
+        Some(_) => identifier.path,
+        None => {
+            let res = tunnel
+                .get(&identifier.peer_id, &alias_query(&identifier.path))
+                .await?;
+            let res: CommonResult<String> =
+                serde_json::from_str(&res).map_err(|e| e.to_string())?;
+            res.data
+                .ok_or_else(|| format!("fatal: peer has no repository '{}'", identifier.path))?
+        }
+    };
+    let port = tunnel.open(&identifier.peer_id).await?;
+    Url::parse(&format!("http://localhost:{}{}.git", port, path)).map_err(|e| e.to_string())
+}
+
+/// Path & query asking a peer for the repository path of `alias`
+#[cfg(feature = "p2p")]
+fn alias_query(alias: &str) -> String {
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("alias", alias)
+        .finish();
+    format!("api/v1/mega/ztm/alias_to_path?{}", query)
+}
+
+/// Get the URL to talk the smart HTTP protocol with for the remote `url`
+pub async fn transport_url(url: &str) -> Result<Url, String> {
+    #[cfg(feature = "p2p")]
+    if url.starts_with(P2P_SCHEME) {
+        let agent_port = Config::get("ztm", None, "agentPort")
+            .await
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(DEFAULT_ZTM_AGENT_PORT);
+        return resolve_p2p_url(url, &ZtmTunnel { agent_port }).await;
+    }
+    #[cfg(not(feature = "p2p"))]
+    if url.starts_with(P2P_SCHEME) {
+        return Err(format!(
+            "fatal: can't fetch '{}', libra is built without the `p2p` feature",
+            url
+        ));
+    }
+    Url::parse(url).map_err(|e| format!("fatal: invalid URL '{}': {}", url, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_http_url_is_used_directly() {
+        let url = transport_url("https://github.com/web3infra-foundation/mega.git")
+            .await
+            .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://github.com/web3infra-foundation/mega.git"
+        );
+        assert!(transport_url("not a url").await.is_err());
+    }
+
+    #[cfg(feature = "p2p")]
+    mod p2p {
+        use std::sync::Mutex;
+
+        use super::super::*;
+
+        const PEER: &str = "mrJ46F8gd2sa2Dx3iCYf6DauJ2WpAaepus7PwyZVebgD";
+
+        /// Records the calls instead of talking to a ZTM agent
+        #[derive(Default)]
+        struct MockTunnel {
+            calls: Mutex<Vec<String>>,
+        }
+
+        impl PeerTunnel for MockTunnel {
+            async fn open(&self, peer_id: &str) -> Result<u16, String> {
+                self.calls.lock().unwrap().push(format!("open {}", peer_id));
+                Ok(9000)
+            }
+
+            async fn get(&self, peer_id: &str, path: &str) -> Result<String, String> {
+                self.calls
+                    .lock()
+                    .unwrap()
+                    .push(format!("get {} {}", peer_id, path));
+                Ok(r#"{"req_result":true,"data":"/third-part/serde","err_message":""}"#.to_owned())
+            }
+        }
+
+        #[tokio::test]
+        async fn test_p2p_url_goes_through_tunnel() {
+            let tunnel = MockTunnel::default();
+            let url = resolve_p2p_url(&format!("p2p://{}/8000/project/mega.git", PEER), &tunnel)
+                .await
+                .unwrap();
+            assert_eq!(url.as_str(), "http://localhost:9000/project/mega.git");
+            assert_eq!(
+                *tunnel.calls.lock().unwrap(),
+                vec![format!("open {}", PEER)]
+            );
+
+            let tunnel = MockTunnel::default();
+            let url = resolve_p2p_url(&format!("p2p://{}/serde", PEER), &tunnel)
+                .await
+                .unwrap();
+            assert_eq!(url.as_str(), "http://localhost:9000/third-part/serde.git");
+            assert_eq!(
+                *tunnel.calls.lock().unwrap(),
+                vec![
+                    format!("get {} api/v1/mega/ztm/alias_to_path?alias=serde", PEER),
+                    format!("open {}", PEER),
+                ]
+            );
+
+            let tunnel = MockTunnel::default();
+            assert!(resolve_p2p_url("p2p://not-base58!/serde", &tunnel)
+                .await
+                .is_err());
+            assert!(tunnel.calls.lock().unwrap().is_empty());
+        }
+
+        #[test]
+        fn test_alias_query_is_encoded() {
+            assert_eq!(
+                alias_query("serde"),
+                "api/v1/mega/ztm/alias_to_path?alias=serde"
+            );
+            assert_eq!(
+                alias_query("my repo&x=1#top"),
+                "api/v1/mega/ztm/alias_to_path?alias=my+repo%26x%3D1%23top"
+            );
+        }
+    }
+}
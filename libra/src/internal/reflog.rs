@@ -0,0 +1,321 @@
+//! The reflog: the successive values of a ref, kept like git in `logs/<ref>` of the storage
+//! directory, one line per update, oldest first:
+//!
+//! `<old> <new> <name> <<email>> <timestamp> <timezone>\t<message>`
+//!
+//! - `HEAD` is logged in `logs/HEAD`, a local branch in `logs/refs/heads/<branch>`
+//! - a ref never updated has no reflog, read as empty
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use mercury::hash::SHA1;
+
+use crate::internal::config::Config;
+use crate::internal::head::Head;
+use crate::utils::util;
+
+/// Name of the reflog of HEAD
+pub const HEAD: &str = "HEAD";
+
+/// Identity of the reflog entries if `user.name` or `user.email` is unset, git logs the updates
+/// even without an identity
+const UNKNOWN_IDENTITY: &str = "unknown <unknown>";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// zero if the ref was created
+    pub old: SHA1,
+    pub new: SHA1,
+    /// `name <email>`
+    pub identity: String,
+    /// seconds since the epoch
+    pub timestamp: i64,
+    pub timezone: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// An update of a ref from `old` to `new` now, by `identity`, see [identity]
+    pub fn new(old: Option<SHA1>, new: SHA1, identity: &str, message: &str) -> Self {
+        let now = chrono::Local::now();
+        ReflogEntry {
+            old: old.unwrap_or_default(),
+            new,
+            identity: identity.to_owned(),
+            timestamp: now.timestamp(),
+            timezone: now.format("%z").to_string(),
+            // a line per entry
+            message: message.lines().next().unwrap_or_default().to_owned(),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {} {}\t{}\n",
+            self.old, self.new, self.identity, self.timestamp, self.timezone, self.message
+        )
+    }
+
+    /// `None` if the line is malformed
+    fn from_line(line: &str) -> Option<Self> {
+        let (info, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old, rest) = info.split_once(' ')?;
+        let (new, rest) = rest.split_once(' ')?;
+        // the name may contain spaces, the time & timezone are after the email
+        let (identity, time) = rest.rsplit_once("> ")?;
+        let (timestamp, timezone) = time.split_once(' ')?;
+        Some(ReflogEntry {
+            old: SHA1::from_str(old).ok()?,
+            new: SHA1::from_str(new).ok()?,
+            identity: format!("{}>", identity),
+            timestamp: timestamp.parse().ok()?,
+            timezone: timezone.to_owned(),
+            message: message.to_owned(),
+        })
+    }
+}
+
+/// `name <email>` of the user, who commits too, see [Config::user_config]
+pub async fn identity() -> String {
+    match Config::user_config().await {
+        Ok(user) => format!("{} <{}>", user.name, user.email),
+        Err(_) => UNKNOWN_IDENTITY.to_owned(),
+    }
+}
+
+/// The reflog of a branch
+pub fn branch_ref(branch: &str) -> String {
+    format!("refs/heads/{}", branch)
+}
+
+/// Record the update of HEAD from `old` to `new` in its reflog, and in the one of the current
+/// branch if it moved with HEAD, see [log_update]
+pub async fn log_head(old: Option<SHA1>, new: SHA1, message: &str, branch_moved: bool) {
+    let entry = ReflogEntry::new(old, new, &identity().await, message);
+    let mut refs = vec![HEAD.to_owned()];
+    if branch_moved {
+        if let Head::Branch(name) = Head::current().await {
+            refs.push(branch_ref(&name));
+        }
+    }
+    for reference in refs {
+        log_update(&reference, &entry);
+    }
+}
+
+/// [append_reflog], only warning if it fails: the update of the ref itself is done
+pub fn log_update(reference: &str, entry: &ReflogEntry) {
+    if let Err(e) = append_reflog(reference, entry) {
+        eprintln!(
+            "warning: failed to update the reflog of {}: {}",
+            reference, e
+        );
+    }
+}
+
+fn reflog_path(reference: &str) -> PathBuf {
+    util::storage_path().join("logs").join(reference)
+}
+
+/// Add `entry` at the end of the reflog of `reference`, creating it if needed
+pub fn append_reflog(reference: &str, entry: &ReflogEntry) -> io::Result<()> {
+    let path = reflog_path(reference);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(entry.to_line().as_bytes())
+}
+
+/// The entries of the reflog of `reference`, oldest first, empty if it has none.
+/// Malformed lines are skipped.
+pub fn read_reflog(reference: &str) -> io::Result<Vec<ReflogEntry>> {
+    let content = match fs::read_to_string(reflog_path(reference)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content.lines().filter_map(ReflogEntry::from_line).collect())
+}
+
+/// The refs which have a reflog, e.g. `HEAD`, `refs/heads/master` & `refs/stash`
+pub fn list_reflogs() -> io::Result<Vec<String>> {
+    let logs = util::storage_path().join("logs");
+    let mut references = Vec::new();
+    let mut dirs = vec![logs.clone()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(reference) = path.strip_prefix(&logs) {
+                let parts: Vec<_> = reference
+                    .iter()
+                    .map(|part| part.to_string_lossy())
+                    .collect();
+                references.push(parts.join("/"));
+            }
+        }
+    }
+    references.sort();
+    Ok(references)
+}
+
+/// The value of `reference` `n` updates ago, as `<ref>@{n}`: `@{0}` is the latest value
+pub fn nth_prior_value(reference: &str, n: usize) -> io::Result<Option<SHA1>> {
+    let entries = read_reflog(reference)?;
+    Ok(entries.iter().rev().nth(n).map(|entry| entry.new))
+}
+
+/// Remove the entries of the reflog of `reference` older than the `older_than` timestamp,
+/// returning how many were removed
+pub fn expire_reflog(reference: &str, older_than: i64) -> io::Result<usize> {
+    let entries = read_reflog(reference)?;
+    let kept: Vec<&ReflogEntry> = entries
+        .iter()
+        .filter(|entry| entry.timestamp >= older_than)
+        .collect();
+    let expired = entries.len() - kept.len();
+    if expired > 0 {
+        let content: String = kept.iter().map(|entry| entry.to_line()).collect();
+        fs::write(reflog_path(reference), content)?;
+    }
+    Ok(expired)
+}
+
+/// Remove the entry `<ref>@{n}` from the reflog of `reference`, returning it
+pub fn drop_reflog_entry(reference: &str, n: usize) -> io::Result<Option<ReflogEntry>> {
+    let mut entries = read_reflog(reference)?;
+    if n >= entries.len() {
+        return Ok(None);
+    }
+    let dropped = entries.remove(entries.len() - 1 - n);
+    let content: String = entries.iter().map(|entry| entry.to_line()).collect();
+    fs::write(reflog_path(reference), content)?;
+    Ok(Some(dropped))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::rev_parse::rev_parse;
+    use crate::utils::test;
+
+    use super::*;
+
+    fn entry(old: u8, new: u8, timestamp: i64, message: &str) -> ReflogEntry {
+        let mut entry = ReflogEntry::new(
+            Some(SHA1::new(&[old])),
+            SHA1::new(&[new]),
+            "mega <admin@mega.org>",
+            message,
+        );
+        entry.timestamp = timestamp;
+        entry.timezone = "+0000".to_owned();
+        entry
+    }
+
+    #[test]
+    fn test_line_round_trip() {
+        let mut entry = entry(1, 2, 1733270400, "commit: add file");
+        entry.identity = "Jane Q. Doe <jane@example.com>".to_owned();
+        let line = entry.to_line();
+        assert!(
+            line.ends_with(" 1733270400 +0000\tcommit: add file\n"),
+            "{}",
+            line
+        );
+        assert_eq!(ReflogEntry::from_line(line.trim_end()), Some(entry));
+        assert_eq!(ReflogEntry::from_line("not a reflog line"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_and_expire_reflog() {
+        test::setup_with_new_libra().await;
+        let reference = branch_ref("reflog_test");
+        // no reflog yet
+        assert!(read_reflog(&reference).unwrap().is_empty());
+        assert_eq!(expire_reflog(&reference, i64::MAX).unwrap(), 0);
+
+        let entries = [
+            entry(0, 1, 100, "commit (initial): one"),
+            entry(1, 2, 200, "commit: two"),
+            entry(2, 3, 300, "commit: three\nbody is not logged"),
+        ];
+        for e in &entries {
+            append_reflog(&reference, e).unwrap();
+        }
+        let read = read_reflog(&reference).unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[..2], entries[..2]);
+        assert_eq!(read[2].message, "commit: three");
+        assert_eq!(
+            nth_prior_value(&reference, 0).unwrap(),
+            Some(SHA1::new(&[3]))
+        );
+        assert_eq!(
+            nth_prior_value(&reference, 2).unwrap(),
+            Some(SHA1::new(&[1]))
+        );
+        assert_eq!(nth_prior_value(&reference, 3).unwrap(), None);
+
+        assert_eq!(expire_reflog(&reference, 200).unwrap(), 1);
+        let read = read_reflog(&reference).unwrap();
+        assert_eq!(
+            read.iter().map(|e| e.timestamp).collect::<Vec<_>>(),
+            [200, 300]
+        );
+        assert_eq!(expire_reflog(&reference, 200).unwrap(), 0);
+
+        let dropped = drop_reflog_entry(&reference, 1).unwrap().unwrap();
+        assert_eq!(dropped.timestamp, 200);
+        assert_eq!(
+            nth_prior_value(&reference, 0).unwrap(),
+            Some(SHA1::new(&[3]))
+        );
+        assert_eq!(drop_reflog_entry(&reference, 1).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_rev_parse_reflog() {
+        test::setup_with_new_libra().await;
+        let storage = util::objects_storage();
+        let save_commit = |parents: Vec<SHA1>, msg: &str| {
+            use mercury::internal::object::{commit::Commit, ObjectTrait};
+            let commit = Commit::from_tree_id(SHA1::default(), parents, &format!("\n{}", msg));
+            storage
+                .put(&commit.id, &commit.to_data().unwrap(), commit.get_type())
+                .unwrap();
+            commit.id
+        };
+        let c1 = save_commit(vec![], "c1");
+        let c2 = save_commit(vec![c1], "c2");
+        let user = identity().await;
+        append_reflog(
+            HEAD,
+            &ReflogEntry::new(None, c1, &user, "commit (initial): c1"),
+        )
+        .unwrap();
+        append_reflog(HEAD, &ReflogEntry::new(Some(c1), c2, &user, "commit: c2")).unwrap();
+        let master = branch_ref("master");
+        append_reflog(
+            &master,
+            &ReflogEntry::new(None, c2, &user, "branch: created"),
+        )
+        .unwrap();
+
+        assert_eq!(rev_parse("HEAD@{0}").await, Ok(c2));
+        assert_eq!(rev_parse("HEAD@{1}").await, Ok(c1));
+        // the current branch
+        assert_eq!(rev_parse("@{0}").await, Ok(c2));
+        assert_eq!(rev_parse("master@{0}~").await, Ok(c1));
+        assert!(rev_parse("HEAD@{2}").await.is_err());
+        assert!(rev_parse("@{1}").await.is_err());
+        assert!(rev_parse("topic@{0}").await.is_err());
+    }
+}
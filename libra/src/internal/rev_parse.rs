@@ -0,0 +1,340 @@
+//! Resolve a revision spec to a commit, like `git rev-parse <rev>^{commit}`.
+//!
+//! Supported forms:
+//! - full or abbreviated (at least 4 chars) object hash
+//! - `HEAD` or `@`
+//! - branch name, local or remote (`origin/master`), and tag name
+//! - `<branch>@{upstream}` or `<branch>@{u}`, the remote branch `<branch>` tracks,
+//!   the current branch if `<branch>` is omitted
+//! - `<ref>@{<n>}`, the value of the branch or `HEAD` `n` updates ago, read from its reflog,
+//!   the current branch if `<ref>` is omitted
+//! - ancestry suffixes, chainable: `~`, `~<n>` (n-th first-parent ancestor),
+//!   `^`, `^<n>` (n-th parent, `^0` is the commit itself)
+
+use std::fmt;
+
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+
+use crate::internal::branch::Branch;
+use crate::internal::config::Config;
+use crate::internal::db::get_db_conn_instance;
+use crate::internal::head::Head;
+use crate::internal::reflog;
+use crate::internal::tag;
+use crate::utils::client_storage::ClientStorage;
+use crate::utils::util;
+
+/// Shortest abbreviated hash accepted, as git
+const MIN_ABBREV_LEN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevParseError {
+    /// the spec names nothing, or an ancestor that doesn't exist
+    NotFound(String),
+    /// the spec matches several branches or objects
+    Ambiguous(String),
+    /// the spec resolves to an object which is not a commit
+    NotACommit(String, ObjectType),
+}
+
+impl fmt::Display for RevParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevParseError::NotFound(spec) => {
+                write!(f, "ambiguous argument '{}': unknown revision", spec)
+            }
+            RevParseError::Ambiguous(spec) => write!(f, "ambiguous argument '{}'", spec),
+            RevParseError::NotACommit(spec, kind) => {
+                write!(f, "'{}' is a {}, not a commit", spec, kind)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RevParseError {}
+
+/// One step of the ancestry suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// `~n`
+    Ancestor(usize),
+    /// `^n`
+    Parent(usize),
+}
+
+/// Split `spec` into its base and ancestry steps, `None` if the suffix is malformed
+fn parse(spec: &str) -> Option<(&str, Vec<Step>)> {
+    // `~` & `^` are not allowed in ref names, the first one starts the suffix
+    let split = spec.find(['~', '^']).unwrap_or(spec.len());
+    let (base, mut suffix) = spec.split_at(split);
+    let mut steps = Vec::new();
+    while let Some(op) = suffix.chars().next() {
+        suffix = &suffix[1..];
+        let digits = suffix
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(suffix.len());
+        let n = match &suffix[..digits] {
+            "" => 1,
+            n => n.parse().ok()?,
+        };
+        suffix = &suffix[digits..];
+        steps.push(match op {
+            '~' => Step::Ancestor(n),
+            '^' => Step::Parent(n),
+            _ => return None,
+        });
+    }
+    Some((base, steps))
+}
+
+/// Get the remote branch tracked by `branch` (the current one if empty or `HEAD`), as `(remote, branch)`
+/// - only `upstream` & `u` selectors are supported
+async fn upstream(branch: &str, selector: &str) -> Option<(String, String)> {
+    if !matches!(selector.to_ascii_lowercase().as_str(), "upstream" | "u") {
+        return None;
+    }
+    let branch = match branch {
+        "" | "HEAD" => match Head::current().await {
+            Head::Branch(name) => name,
+            Head::Detached(_) => return None,
+        },
+        name => name.to_owned(),
+    };
+    let upstream = Config::upstream_of(&branch).await?;
+    let (remote, branch) = upstream.split_once('/')?;
+    Some((remote.to_owned(), branch.to_owned()))
+}
+
+/// Name of the reflog of `<reference>@{n}`
+async fn reflog_ref(reference: &str) -> String {
+    match reference {
+        "HEAD" => reflog::HEAD.to_owned(),
+        "" => match Head::current().await {
+            Head::Branch(name) => reflog::branch_ref(&name),
+            Head::Detached(_) => reflog::HEAD.to_owned(),
+        },
+        branch => reflog::branch_ref(branch),
+    }
+}
+
+fn load_commit(storage: &ClientStorage, id: &SHA1) -> Result<Commit, GitError> {
+    Commit::from_bytes(&storage.get(id)?, *id)
+}
+
+/// Resolve the base of a spec (without suffix) to an object id
+async fn resolve_base(
+    storage: &ClientStorage,
+    spec: &str,
+    base: &str,
+) -> Result<SHA1, RevParseError> {
+    let not_found = || RevParseError::NotFound(spec.to_owned());
+    if let Some((branch, selector)) = base.strip_suffix('}').and_then(|b| b.split_once("@{")) {
+        if let Ok(n) = selector.parse::<usize>() {
+            let reference = reflog_ref(branch).await;
+            let value = reflog::nth_prior_value(&reference, n).ok().flatten();
+            return value.ok_or_else(not_found);
+        }
+        let (remote, branch) = upstream(branch, selector).await.ok_or_else(not_found)?;
+        let branch = Branch::find_branch(&branch, Some(&remote)).await;
+        return branch.map(|b| b.commit).ok_or_else(not_found);
+    }
+    if base == "HEAD" || base == "@" {
+        return Head::current_commit().await.ok_or_else(not_found);
+    }
+    if base.is_empty() {
+        return Err(not_found());
+    }
+
+    let branches = Branch::search_branch(base).await;
+    match branches.len() {
+        0 => {}
+        1 => return Ok(branches[0].commit),
+        _ => return Err(RevParseError::Ambiguous(spec.to_owned())),
+    }
+
+    let db = get_db_conn_instance().await;
+    if let Ok(Some(target)) = tag::resolve_tag(db, storage, base).await {
+        return Ok(target);
+    }
+
+    if base.len() < MIN_ABBREV_LEN || !base.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(not_found());
+    }
+    let objects = storage.search(&base.to_ascii_lowercase());
+    match objects.len() {
+        0 => Err(not_found()),
+        1 => Ok(objects[0]),
+        _ => Err(RevParseError::Ambiguous(spec.to_owned())),
+    }
+}
+
+/// Resolve `spec` to a commit id, see the [module docs](self) for the supported forms
+pub async fn rev_parse(spec: &str) -> Result<SHA1, RevParseError> {
+    let storage = util::objects_storage();
+    let not_found = || RevParseError::NotFound(spec.to_owned());
+    let (base, steps) = parse(spec).ok_or_else(not_found)?;
+
+    let mut id = resolve_base(&storage, spec, base).await?;
+    let kind = storage.get_object_type(&id).map_err(|_| not_found())?;
+    if kind != ObjectType::Commit {
+        return Err(RevParseError::NotACommit(spec.to_owned(), kind));
+    }
+
+    for step in steps {
+        let (n, nth_parent) = match step {
+            Step::Ancestor(n) => (n, 1),
+            Step::Parent(0) => continue,
+            Step::Parent(n) => (1, n),
+        };
+        for _ in 0..n {
+            let commit = load_commit(&storage, &id).map_err(|_| not_found())?;
+            id = *commit
+                .parent_commit_ids
+                .get(nth_parent - 1)
+                .ok_or_else(not_found)?;
+        }
+    }
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use mercury::internal::object::blob::Blob;
+
+    use crate::utils::test;
+
+    use super::*;
+
+    fn save_commit(storage: &ClientStorage, parents: Vec<SHA1>, msg: &str) -> SHA1 {
+        let commit = Commit::from_tree_id(SHA1::default(), parents, &format!("\n{}", msg));
+        storage
+            .put(&commit.id, &commit.to_data().unwrap(), commit.get_type())
+            .unwrap();
+        commit.id
+    }
+
+    #[test]
+    fn test_parse_suffix() {
+        assert_eq!(parse("HEAD"), Some(("HEAD", vec![])));
+        assert_eq!(
+            parse("main~2^^3~"),
+            Some((
+                "main",
+                vec![
+                    Step::Ancestor(2),
+                    Step::Parent(1),
+                    Step::Parent(3),
+                    Step::Ancestor(1)
+                ]
+            ))
+        );
+        assert_eq!(parse("HEAD^0"), Some(("HEAD", vec![Step::Parent(0)])));
+        assert_eq!(parse("HEAD~x"), None);
+    }
+
+    #[tokio::test]
+    async fn test_rev_parse() {
+        test::setup_with_new_libra().await;
+        let storage = util::objects_storage();
+
+        // c1 <- c2 <- c3 (master), side <- merge(c3, side)
+        let c1 = save_commit(&storage, vec![], "c1");
+        let c2 = save_commit(&storage, vec![c1], "c2");
+        let c3 = save_commit(&storage, vec![c2], "c3");
+        let side = save_commit(&storage, vec![c1], "side");
+        let merge = save_commit(&storage, vec![c3, side], "merge");
+        Branch::update_branch("master", &c3.to_string(), None).await;
+        Branch::update_branch("topic", &merge.to_string(), None).await;
+        Branch::update_branch("dev", &c2.to_string(), Some("origin")).await;
+        Head::update(Head::Branch("master".to_owned()), None).await;
+        let db = get_db_conn_instance().await;
+        tag::create_lightweight_tag(db, "v1", c1).await.unwrap();
+
+        let hash = c2.to_string();
+        assert_eq!(rev_parse(&hash).await, Ok(c2));
+        assert_eq!(rev_parse(&hash[..7]).await, Ok(c2));
+        assert_eq!(rev_parse("HEAD").await, Ok(c3));
+        assert_eq!(rev_parse("@").await, Ok(c3));
+        assert_eq!(rev_parse("master").await, Ok(c3));
+        assert_eq!(rev_parse("origin/dev").await, Ok(c2));
+        assert_eq!(rev_parse("v1").await, Ok(c1));
+        assert_eq!(rev_parse("HEAD~").await, Ok(c2));
+        assert_eq!(rev_parse("HEAD~2").await, Ok(c1));
+        assert_eq!(rev_parse("HEAD^^").await, Ok(c1));
+        assert_eq!(rev_parse("HEAD^0").await, Ok(c3));
+        assert_eq!(rev_parse("topic^2").await, Ok(side));
+        assert_eq!(rev_parse("topic^2~").await, Ok(c1));
+        assert_eq!(rev_parse("topic~1").await, Ok(c3));
+
+        // errors
+        assert_eq!(
+            rev_parse("HEAD~3").await,
+            Err(RevParseError::NotFound("HEAD~3".to_owned()))
+        );
+        assert_eq!(
+            rev_parse("master^2").await,
+            Err(RevParseError::NotFound("master^2".to_owned()))
+        );
+        assert_eq!(
+            rev_parse("nope").await,
+            Err(RevParseError::NotFound("nope".to_owned()))
+        );
+        assert_eq!(
+            rev_parse("HEAD~x").await,
+            Err(RevParseError::NotFound("HEAD~x".to_owned()))
+        );
+        // a local branch named like a remote one
+        Branch::update_branch("origin/dev", &c1.to_string(), None).await;
+        assert_eq!(
+            rev_parse("origin/dev").await,
+            Err(RevParseError::Ambiguous("origin/dev".to_owned()))
+        );
+        let blob = Blob::from_content("not a commit");
+        storage.put(&blob.id, &blob.data, blob.get_type()).unwrap();
+        assert_eq!(
+            rev_parse(&blob.id.to_string()).await,
+            Err(RevParseError::NotACommit(
+                blob.id.to_string(),
+                ObjectType::Blob
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rev_parse_upstream() {
+        test::setup_with_new_libra().await;
+        let storage = util::objects_storage();
+        let c1 = save_commit(&storage, vec![], "c1");
+        let c2 = save_commit(&storage, vec![c1], "c2");
+        Branch::update_branch("master", &c1.to_string(), None).await;
+        Branch::update_branch("topic", &c1.to_string(), None).await;
+        Branch::update_branch("master", &c2.to_string(), Some("origin")).await;
+        Head::update(Head::Branch("master".to_owned()), None).await;
+
+        Config::set_tracking("master", "origin", "refs/heads/master").await;
+        assert_eq!(
+            Config::upstream_of("master").await,
+            Some("origin/master".to_owned())
+        );
+        assert_eq!(Config::upstream_of("topic").await, None);
+
+        assert_eq!(rev_parse("master@{upstream}").await, Ok(c2));
+        assert_eq!(rev_parse("@{u}").await, Ok(c2));
+        assert_eq!(rev_parse("HEAD@{U}~").await, Ok(c1));
+        assert_eq!(
+            rev_parse("topic@{u}").await,
+            Err(RevParseError::NotFound("topic@{u}".to_owned()))
+        );
+
+        // tracking is replaced, not duplicated
+        Config::set_tracking("master", "origin", "refs/heads/topic").await;
+        assert_eq!(
+            Config::upstream_of("master").await,
+            Some("origin/topic".to_owned())
+        );
+    }
+}
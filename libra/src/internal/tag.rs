@@ -0,0 +1,182 @@
+//! Tags stored as `Tag` references (`refs/tags`), lightweight or annotated.
+//!
+//! A lightweight tag references the commit directly, an annotated tag references a tag object
+//! which in turn points to the commit, as git does.
+
+use std::str::FromStr;
+
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DbConn, EntityTrait, QueryFilter};
+
+use common::utils::format_commit_msg;
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::signature::Signature;
+use mercury::internal::object::tag::Tag;
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+
+use crate::internal::model::reference;
+use crate::utils::client_storage::ClientStorage;
+
+fn db_error(e: sea_orm::DbErr) -> GitError {
+    GitError::CustomError(e.to_string())
+}
+
+async fn query_tag(db: &DbConn, name: &str) -> Result<Option<reference::Model>, GitError> {
+    reference::Entity::find()
+        .filter(reference::Column::Name.eq(name))
+        .filter(reference::Column::Kind.eq(reference::ConfigKind::Tag))
+        .one(db)
+        .await
+        .map_err(db_error)
+}
+
+/// Point the new tag `name` to `target`, fails if the tag already exists
+async fn insert_tag(db: &DbConn, name: &str, target: SHA1) -> Result<(), GitError> {
+    if query_tag(db, name).await?.is_some() {
+        return Err(GitError::CustomError(format!(
+            "tag '{}' already exists",
+            name
+        )));
+    }
+    reference::ActiveModel {
+        name: Set(Some(name.to_owned())),
+        kind: Set(reference::ConfigKind::Tag),
+        commit: Set(Some(target.to_string())),
+        remote: Set(None), // tags are shared with remotes
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(db_error)?;
+    Ok(())
+}
+
+/// Create tag `name` referencing `commit` directly
+pub async fn create_lightweight_tag(db: &DbConn, name: &str, commit: SHA1) -> Result<(), GitError> {
+    insert_tag(db, name, commit).await
+}
+
+/// Write a tag object for `commit` with `message` & `tagger`, then create tag `name` referencing it
+/// - return the tag object
+pub async fn create_annotated_tag(
+    db: &DbConn,
+    storage: &ClientStorage,
+    name: &str,
+    commit: SHA1,
+    message: &str,
+    tagger: Signature,
+) -> Result<Tag, GitError> {
+    if query_tag(db, name).await?.is_some() {
+        return Err(GitError::CustomError(format!(
+            "tag '{}' already exists",
+            name
+        )));
+    }
+    let tag = Tag::new(
+        commit,
+        ObjectType::Commit,
+        name,
+        tagger,
+        &format_commit_msg(message, None),
+    );
+    storage.put(&tag.id, &tag.to_data()?, tag.get_type())?;
+    insert_tag(db, name, tag.id).await?;
+    Ok(tag)
+}
+
+/// Get the object the tag references: the commit of a lightweight tag, the tag object of an annotated one
+pub async fn find_tag(db: &DbConn, name: &str) -> Result<Option<SHA1>, GitError> {
+    match query_tag(db, name).await? {
+        Some(tag) => {
+            let target = tag
+                .commit
+                .ok_or_else(|| GitError::CustomError(format!("tag '{}' has no target", name)))?;
+            Ok(Some(
+                SHA1::from_str(&target).map_err(GitError::InvalidHashValue)?,
+            ))
+        }
+        None => Ok(None),
+    }
+}
+
+/// List all tag names, sorted
+pub async fn list_tags(db: &DbConn) -> Result<Vec<String>, GitError> {
+    let tags = reference::Entity::find()
+        .filter(reference::Column::Kind.eq(reference::ConfigKind::Tag))
+        .all(db)
+        .await
+        .map_err(db_error)?;
+    let mut names: Vec<String> = tags.into_iter().filter_map(|tag| tag.name).collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Resolve the tag to the commit it points to, peeling (nested) tag objects
+pub async fn resolve_tag(
+    db: &DbConn,
+    storage: &ClientStorage,
+    name: &str,
+) -> Result<Option<SHA1>, GitError> {
+    let mut target = match find_tag(db, name).await? {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+    while storage.get_object_type(&target)? == ObjectType::Tag {
+        target = Tag::from_bytes(&storage.get(&target)?, target)?.object_hash;
+    }
+    Ok(Some(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use mercury::internal::object::commit::Commit;
+    use mercury::internal::object::signature::SignatureType;
+
+    use crate::internal::db::get_db_conn_instance;
+    use crate::utils::{path, test};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_create_and_resolve_tags() {
+        test::setup_with_new_libra().await;
+        let db = get_db_conn_instance().await;
+        let storage = ClientStorage::init(path::objects());
+        let commit = Commit::from_tree_id(SHA1::default(), vec![], "\ninit");
+        storage
+            .put(&commit.id, &commit.to_data().unwrap(), commit.get_type())
+            .unwrap();
+
+        create_lightweight_tag(db, "v0.1", commit.id).await.unwrap();
+        assert_eq!(find_tag(db, "v0.1").await.unwrap(), Some(commit.id));
+        assert_eq!(
+            resolve_tag(db, &storage, "v0.1").await.unwrap(),
+            Some(commit.id)
+        );
+
+        let tagger = Signature::new(
+            SignatureType::Tagger,
+            "mega".to_string(),
+            "admin@mega.org".to_string(),
+        );
+        let tag = create_annotated_tag(db, &storage, "v1.0", commit.id, "release 1.0", tagger)
+            .await
+            .unwrap();
+        // the ref points to the tag object, which points to the commit
+        assert_eq!(find_tag(db, "v1.0").await.unwrap(), Some(tag.id));
+        assert_eq!(storage.get_object_type(&tag.id).unwrap(), ObjectType::Tag);
+        let loaded = Tag::from_bytes(&storage.get(&tag.id).unwrap(), tag.id).unwrap();
+        assert_eq!(loaded.tag_name, "v1.0");
+        assert_eq!(loaded.message.trim(), "release 1.0");
+        assert_eq!(
+            resolve_tag(db, &storage, "v1.0").await.unwrap(),
+            Some(commit.id)
+        );
+
+        assert!(create_lightweight_tag(db, "v1.0", commit.id).await.is_err());
+        assert_eq!(resolve_tag(db, &storage, "missing").await.unwrap(), None);
+        assert_eq!(list_tags(db).await.unwrap(), vec!["v0.1", "v1.0"]);
+    }
+}
@@ -0,0 +1,503 @@
+//! Structural diff between two trees, entry by entry.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use mercury::internal::object::ObjectTrait;
+
+use crate::utils::client_storage::ClientStorage;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    /// content or file mode changed
+    Modified,
+    /// a file replaced by a symlink or a submodule, or the reverse;
+    /// a file replaced by a directory is a deletion & an addition per file instead
+    TypeChanged,
+    /// moved from `from` to the change's `path`, `similarity` is in percent
+    Renamed {
+        from: PathBuf,
+        similarity: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DiffOptions {
+    /// pair deleted & added files with identical content as renames
+    pub detect_renames: bool,
+    /// also pair files whose content similarity (percent) is at least this value,
+    /// only with `detect_renames`, costs a content comparison per deleted & added pair
+    pub rename_similarity: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    /// path relative to the root of the trees
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    pub old: Option<SHA1>,
+    pub new: Option<SHA1>,
+    /// modes of the entry, set with `old` & `new`
+    pub old_mode: Option<TreeItemMode>,
+    pub new_mode: Option<TreeItemMode>,
+}
+
+/// The kind of object an entry points to, a change between kinds is a type change
+#[derive(PartialEq, Eq)]
+enum EntryType {
+    File,
+    Link,
+    Tree,
+    Submodule,
+}
+
+fn entry_type(mode: TreeItemMode) -> EntryType {
+    match mode {
+        TreeItemMode::Blob | TreeItemMode::BlobExecutable => EntryType::File,
+        TreeItemMode::Link => EntryType::Link,
+        TreeItemMode::Tree => EntryType::Tree,
+        TreeItemMode::Commit => EntryType::Submodule,
+    }
+}
+
+fn load_tree(storage: &ClientStorage, id: &SHA1) -> Result<Tree, GitError> {
+    Tree::from_bytes(&storage.get(id)?, *id)
+}
+
+/// Diff tree `old` against tree `new`, returning file-level changes sorted by path.
+/// - Subtrees with the same id on both sides are skipped without loading them
+/// - An added or deleted directory is reported as its files
+/// - A file replaced by a directory, or the reverse, is reported as the deletion of the old side
+///   followed by the addition of the new one
+pub fn diff_trees(
+    storage: &ClientStorage,
+    old: SHA1,
+    new: SHA1,
+) -> Result<Vec<TreeChange>, GitError> {
+    diff_trees_with_options(storage, old, new, &DiffOptions::default())
+}
+
+/// [diff_trees] with optional rename detection, see [DiffOptions]
+pub fn diff_trees_with_options(
+    storage: &ClientStorage,
+    old: SHA1,
+    new: SHA1,
+    options: &DiffOptions,
+) -> Result<Vec<TreeChange>, GitError> {
+    let mut changes = Vec::new();
+    if old != new {
+        let old = load_tree(storage, &old)?;
+        let new = load_tree(storage, &new)?;
+        diff_items(
+            storage,
+            Path::new(""),
+            &old.tree_items,
+            &new.tree_items,
+            &mut changes,
+        )?;
+    }
+    if options.detect_renames {
+        changes = detect_renames(storage, changes, options.rename_similarity)?;
+    }
+    Ok(changes)
+}
+
+/// Replace paired `Deleted` + `Added` changes with `Renamed`, exact matches first
+fn detect_renames(
+    storage: &ClientStorage,
+    changes: Vec<TreeChange>,
+    min_similarity: Option<u8>,
+) -> Result<Vec<TreeChange>, GitError> {
+    let deleted: Vec<usize> = (0..changes.len())
+        .filter(|&i| changes[i].kind == ChangeKind::Deleted)
+        .collect();
+    let added: Vec<usize> = (0..changes.len())
+        .filter(|&i| changes[i].kind == ChangeKind::Added)
+        .collect();
+    // added index -> (deleted index, similarity)
+    let mut pairs: HashMap<usize, (usize, u8)> = HashMap::new();
+    let mut paired_deleted = BTreeSet::new();
+
+    let mut by_hash: HashMap<SHA1, Vec<usize>> = HashMap::new();
+    for &d in deleted.iter().rev() {
+        by_hash.entry(changes[d].old.unwrap()).or_default().push(d);
+    }
+    for &a in &added {
+        if let Some(d) = by_hash
+            .get_mut(&changes[a].new.unwrap())
+            .and_then(|ds| ds.pop())
+        {
+            pairs.insert(a, (d, 100));
+            paired_deleted.insert(d);
+        }
+    }
+
+    if let Some(min_similarity) = min_similarity {
+        let mut old_data = Vec::new();
+        for &d in deleted.iter().filter(|d| !paired_deleted.contains(d)) {
+            old_data.push((d, storage.get(&changes[d].old.unwrap())?));
+        }
+        let mut candidates = Vec::new();
+        for &a in added.iter().filter(|a| !pairs.contains_key(a)) {
+            let new_data = storage.get(&changes[a].new.unwrap())?;
+            for (d, old_data) in &old_data {
+                let (d, score) = (*d, similarity(old_data, &new_data));
+                if score >= min_similarity {
+                    candidates.push((score, a, d));
+                }
+            }
+        }
+        // best matches first, ties keep path order
+        candidates.sort_by(|x, y| y.0.cmp(&x.0).then((x.1, x.2).cmp(&(y.1, y.2))));
+        for (score, a, d) in candidates {
+            if !pairs.contains_key(&a) && !paired_deleted.contains(&d) {
+                pairs.insert(a, (d, score));
+                paired_deleted.insert(d);
+            }
+        }
+    }
+
+    let mut result = Vec::with_capacity(changes.len() - pairs.len());
+    for (i, change) in changes.iter().enumerate() {
+        if paired_deleted.contains(&i) {
+            continue;
+        }
+        let mut change = change.clone();
+        if let Some(&(d, similarity)) = pairs.get(&i) {
+            change.kind = ChangeKind::Renamed {
+                from: changes[d].path.clone(),
+                similarity,
+            };
+            change.old = changes[d].old;
+            change.old_mode = changes[d].old_mode;
+        }
+        result.push(change);
+    }
+    Ok(result)
+}
+
+/// Percentage of content shared by two blobs, compared line by line
+fn similarity(old: &[u8], new: &[u8]) -> u8 {
+    let max_len = old.len().max(new.len());
+    if max_len == 0 {
+        return 100;
+    }
+    let mut old_lines: HashMap<&[u8], usize> = HashMap::new();
+    for line in old.split_inclusive(|&b| b == b'\n') {
+        *old_lines.entry(line).or_default() += 1;
+    }
+    let mut common = 0;
+    for line in new.split_inclusive(|&b| b == b'\n') {
+        if let Some(count) = old_lines.get_mut(line).filter(|c| **c > 0) {
+            *count -= 1;
+            common += line.len();
+        }
+    }
+    (common * 100 / max_len) as u8
+}
+
+fn diff_items(
+    storage: &ClientStorage,
+    prefix: &Path,
+    old: &[TreeItem],
+    new: &[TreeItem],
+    changes: &mut Vec<TreeChange>,
+) -> Result<(), GitError> {
+    let old: BTreeMap<&str, &TreeItem> = old.iter().map(|i| (i.name.as_str(), i)).collect();
+    let new: BTreeMap<&str, &TreeItem> = new.iter().map(|i| (i.name.as_str(), i)).collect();
+    let names: BTreeSet<&str> = old.keys().chain(new.keys()).copied().collect();
+
+    for name in names {
+        let path = prefix.join(name);
+        match (old.get(name), new.get(name)) {
+            (Some(o), None) => one_side(storage, &path, o, ChangeKind::Deleted, changes)?,
+            (None, Some(n)) => one_side(storage, &path, n, ChangeKind::Added, changes)?,
+            (Some(o), Some(n)) => {
+                if o.id == n.id && o.mode == n.mode {
+                    continue;
+                }
+                let (old_type, new_type) = (entry_type(o.mode), entry_type(n.mode));
+                if old_type == EntryType::Tree && new_type == EntryType::Tree {
+                    let (o_tree, n_tree) = (load_tree(storage, &o.id)?, load_tree(storage, &n.id)?);
+                    diff_items(
+                        storage,
+                        &path,
+                        &o_tree.tree_items,
+                        &n_tree.tree_items,
+                        changes,
+                    )?;
+                } else if old_type == EntryType::Tree || new_type == EntryType::Tree {
+                    one_side(storage, &path, o, ChangeKind::Deleted, changes)?;
+                    one_side(storage, &path, n, ChangeKind::Added, changes)?;
+                } else {
+                    changes.push(TreeChange {
+                        path,
+                        kind: if old_type == new_type {
+                            ChangeKind::Modified
+                        } else {
+                            ChangeKind::TypeChanged
+                        },
+                        old: Some(o.id),
+                        new: Some(n.id),
+                        old_mode: Some(o.mode),
+                        new_mode: Some(n.mode),
+                    });
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+/// An entry only present on one side, `kind` is `Added` or `Deleted`
+fn one_side(
+    storage: &ClientStorage,
+    path: &Path,
+    item: &TreeItem,
+    kind: ChangeKind,
+    changes: &mut Vec<TreeChange>,
+) -> Result<(), GitError> {
+    if item.mode == TreeItemMode::Tree {
+        let tree = load_tree(storage, &item.id)?;
+        for sub_item in &tree.tree_items {
+            one_side(
+                storage,
+                &path.join(&sub_item.name),
+                sub_item,
+                kind.clone(),
+                changes,
+            )?;
+        }
+        return Ok(());
+    }
+    let (old, new) = match kind {
+        ChangeKind::Deleted => (Some((item.id, item.mode)), None),
+        _ => (None, Some((item.id, item.mode))),
+    };
+    changes.push(TreeChange {
+        path: path.to_path_buf(),
+        kind,
+        old: old.map(|(id, _)| id),
+        new: new.map(|(id, _)| id),
+        old_mode: old.map(|(_, mode)| mode),
+        new_mode: new.map(|(_, mode)| mode),
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::{save_blob, save_tree, temp_storage};
+
+    fn summary(changes: &[TreeChange]) -> Vec<(&str, ChangeKind)> {
+        changes
+            .iter()
+            .map(|c| (c.path.to_str().unwrap(), c.kind.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_trees() {
+        let (_dir, storage) = temp_storage();
+        let (a, b, c) = (
+            save_blob(&storage, b"a"),
+            save_blob(&storage, b"b"),
+            save_blob(&storage, b"c"),
+        );
+        let src = save_tree(
+            &storage,
+            vec![
+                ("lib.rs", TreeItemMode::Blob, a),
+                ("main.rs", TreeItemMode::Blob, b),
+            ],
+        );
+        let src_new = save_tree(
+            &storage,
+            vec![
+                ("lib.rs", TreeItemMode::Blob, c),
+                ("main.rs", TreeItemMode::Blob, b),
+            ],
+        );
+        let docs = save_tree(&storage, vec![("index.md", TreeItemMode::Blob, a)]);
+
+        let old = save_tree(
+            &storage,
+            vec![
+                ("README.md", TreeItemMode::Blob, a),
+                ("docs", TreeItemMode::Blob, b),
+                ("removed.txt", TreeItemMode::Blob, c),
+                ("src", TreeItemMode::Tree, src),
+            ],
+        );
+        let new = save_tree(
+            &storage,
+            vec![
+                ("README.md", TreeItemMode::Blob, a),
+                ("added.txt", TreeItemMode::Blob, a),
+                ("docs", TreeItemMode::Tree, docs),
+                ("src", TreeItemMode::Tree, src_new),
+            ],
+        );
+
+        let changes = diff_trees(&storage, old, new).unwrap();
+        assert_eq!(
+            summary(&changes),
+            vec![
+                ("added.txt", ChangeKind::Added),
+                ("docs", ChangeKind::Deleted),
+                ("docs/index.md", ChangeKind::Added),
+                ("removed.txt", ChangeKind::Deleted),
+                ("src/lib.rs", ChangeKind::Modified),
+            ]
+        );
+        assert_eq!((changes[1].old, changes[1].new), (Some(b), None));
+        assert_eq!((changes[2].old, changes[2].new), (None, Some(a)));
+        assert_eq!(changes[4].old, Some(a));
+        assert_eq!(changes[4].new, Some(c));
+
+        assert!(diff_trees(&storage, old, old).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rename_detection() {
+        let (_dir, storage) = temp_storage();
+        let content: String = (0..20).map(|i| format!("line {}\n", i)).collect();
+        let (same, old_file, new_file) = (
+            save_blob(&storage, b"same"),
+            save_blob(&storage, content.as_bytes()),
+            save_blob(&storage, (content.clone() + "one more\n").as_bytes()),
+        );
+        let old = save_tree(
+            &storage,
+            vec![
+                ("a.txt", TreeItemMode::Blob, same),
+                ("lib.rs", TreeItemMode::Blob, old_file),
+            ],
+        );
+        let new = save_tree(
+            &storage,
+            vec![
+                ("b.txt", TreeItemMode::Blob, same),
+                ("core.rs", TreeItemMode::Blob, new_file),
+            ],
+        );
+
+        // opt-in, a plain diff is delete + add
+        let changes = diff_trees(&storage, old, new).unwrap();
+        assert_eq!(changes.len(), 4);
+
+        let exact = DiffOptions {
+            detect_renames: true,
+            rename_similarity: None,
+        };
+        let changes = diff_trees_with_options(&storage, old, new, &exact).unwrap();
+        assert_eq!(
+            summary(&changes),
+            vec![
+                (
+                    "b.txt",
+                    ChangeKind::Renamed {
+                        from: "a.txt".into(),
+                        similarity: 100
+                    }
+                ),
+                ("core.rs", ChangeKind::Added),
+                ("lib.rs", ChangeKind::Deleted),
+            ]
+        );
+        assert_eq!((changes[0].old, changes[0].new), (Some(same), Some(same)));
+
+        let similar = DiffOptions {
+            detect_renames: true,
+            rename_similarity: Some(50),
+        };
+        let changes = diff_trees_with_options(&storage, old, new, &similar).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].path, PathBuf::from("core.rs"));
+        match &changes[1].kind {
+            ChangeKind::Renamed { from, similarity } => {
+                assert_eq!(from, &PathBuf::from("lib.rs"));
+                assert!(*similarity >= 90 && *similarity < 100);
+            }
+            kind => panic!("expected a rename, got {:?}", kind),
+        }
+        assert_eq!(
+            (changes[1].old, changes[1].new),
+            (Some(old_file), Some(new_file))
+        );
+
+        // not similar enough
+        let strict = DiffOptions {
+            detect_renames: true,
+            rename_similarity: Some(99),
+        };
+        let changes = diff_trees_with_options(&storage, old, new, &strict).unwrap();
+        assert_eq!(changes.len(), 3);
+    }
+
+    #[test]
+    fn test_diff_trees_whole_dir() {
+        let (_dir, storage) = temp_storage();
+        let a = save_blob(&storage, b"a");
+        let sub = save_tree(&storage, vec![("file.txt", TreeItemMode::Blob, a)]);
+        let dir = save_tree(&storage, vec![("sub", TreeItemMode::Tree, sub)]);
+        let base = save_tree(&storage, vec![("keep.txt", TreeItemMode::Blob, a)]);
+        let with_dir = save_tree(
+            &storage,
+            vec![
+                ("dir", TreeItemMode::Tree, dir),
+                ("keep.txt", TreeItemMode::Blob, a),
+            ],
+        );
+
+        let changes = diff_trees(&storage, base, with_dir).unwrap();
+        assert_eq!(
+            summary(&changes),
+            vec![("dir/sub/file.txt", ChangeKind::Added)]
+        );
+        let changes = diff_trees(&storage, with_dir, base).unwrap();
+        assert_eq!(
+            summary(&changes),
+            vec![("dir/sub/file.txt", ChangeKind::Deleted)]
+        );
+    }
+
+    #[test]
+    fn test_diff_trees_type_change() {
+        let (_dir, storage) = temp_storage();
+        let (a, target) = (save_blob(&storage, b"a"), save_blob(&storage, b"a.txt"));
+        let sub = save_tree(&storage, vec![("file.txt", TreeItemMode::Blob, a)]);
+        let file = save_tree(&storage, vec![("path", TreeItemMode::Blob, a)]);
+        let dir = save_tree(&storage, vec![("path", TreeItemMode::Tree, sub)]);
+        let link = save_tree(&storage, vec![("path", TreeItemMode::Link, target)]);
+
+        // the old side goes first, so a directory is emptied before the file takes its place
+        let changes = diff_trees(&storage, dir, file).unwrap();
+        assert_eq!(
+            summary(&changes),
+            vec![
+                ("path/file.txt", ChangeKind::Deleted),
+                ("path", ChangeKind::Added),
+            ]
+        );
+        let changes = diff_trees(&storage, file, dir).unwrap();
+        assert_eq!(
+            summary(&changes),
+            vec![
+                ("path", ChangeKind::Deleted),
+                ("path/file.txt", ChangeKind::Added),
+            ]
+        );
+
+        let changes = diff_trees(&storage, file, link).unwrap();
+        assert_eq!(summary(&changes), vec![("path", ChangeKind::TypeChanged)]);
+        assert_eq!((changes[0].old, changes[0].new), (Some(a), Some(target)));
+    }
+}
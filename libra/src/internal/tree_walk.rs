@@ -0,0 +1,110 @@
+//! Depth-first walk over the entries of a tree and its subtrees.
+
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use mercury::internal::object::ObjectTrait;
+
+use crate::utils::client_storage::ClientStorage;
+
+/// Walk all entries under the tree `root` in pre-order, a directory is visited before its content.
+/// - `visitor` gets the path relative to `root` & the entry, return `ControlFlow::Break` to stop
+/// - submodules (commit entries) are visited but not entered, they live in another repository
+/// - return `Break` if the visitor stopped the walk
+pub fn walk_tree(
+    storage: &ClientStorage,
+    root: SHA1,
+    mut visitor: impl FnMut(&Path, &TreeItem) -> ControlFlow<()>,
+) -> Result<ControlFlow<()>, GitError> {
+    walk(storage, root, &PathBuf::new(), &mut visitor)
+}
+
+fn walk(
+    storage: &ClientStorage,
+    tree_id: SHA1,
+    prefix: &Path,
+    visitor: &mut impl FnMut(&Path, &TreeItem) -> ControlFlow<()>,
+) -> Result<ControlFlow<()>, GitError> {
+    let tree = Tree::from_bytes(&storage.get(&tree_id)?, tree_id)?;
+    for item in &tree.tree_items {
+        let path = prefix.join(&item.name);
+        if visitor(&path, item).is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+        if item.mode == TreeItemMode::Tree && walk(storage, item.id, &path, visitor)?.is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::{save_tree, temp_storage};
+
+    #[test]
+    fn test_walk_tree() {
+        let (_dir, storage) = temp_storage();
+
+        // blobs are never read, any id will do
+        let blob = SHA1::new(b"blob");
+        let util = save_tree(&storage, vec![("fs.rs", TreeItemMode::Blob, blob)]);
+        let src = save_tree(
+            &storage,
+            vec![
+                ("main.rs", TreeItemMode::Blob, blob),
+                ("util", TreeItemMode::Tree, util),
+            ],
+        );
+        let root = save_tree(
+            &storage,
+            vec![
+                ("README.md", TreeItemMode::Blob, blob),
+                ("src", TreeItemMode::Tree, src),
+                ("vendor", TreeItemMode::Commit, SHA1::new(b"submodule")),
+                ("z.txt", TreeItemMode::Blob, blob),
+            ],
+        );
+
+        let mut paths = Vec::new();
+        let flow = walk_tree(&storage, root, |path, _| {
+            paths.push(path.to_str().unwrap().to_string());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert!(flow.is_continue());
+        assert_eq!(
+            paths,
+            vec![
+                "README.md",
+                "src",
+                "src/main.rs",
+                "src/util",
+                "src/util/fs.rs",
+                "vendor",
+                "z.txt"
+            ]
+        );
+
+        // stop at the first match
+        let mut visited = 0;
+        let mut found = None;
+        let flow = walk_tree(&storage, root, |path, item| {
+            visited += 1;
+            if path == Path::new("src/main.rs") {
+                found = Some(item.clone());
+                return ControlFlow::Break(());
+            }
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert!(flow.is_break());
+        assert_eq!(found.unwrap().name, "main.rs");
+        assert_eq!(visited, 3);
+        assert!(visited < paths.len());
+    }
+}
@@ -0,0 +1,185 @@
+//! Content-defined chunking of large blobs, enabled by `core.chunkThreshold`.
+//!
+//! A blob of at least the threshold is split by [FastCDC](https://www.usenix.org/conference/atc16/technical-sessions/presentation/xia):
+//! a rolling gear hash over the content picks the boundaries, so an edit only changes the chunks
+//! around it and a new version of the blob shares the other chunks with the old one. Each chunk is
+//! stored once in `objects/chunks`, and the loose object is a list of its chunks instead of the
+//! whole content, see [ClientStorage](super::client_storage::ClientStorage).
+//! Git can't read chunked objects, so chunking is disabled by default.
+
+use std::ops::Range;
+use std::sync::RwLock;
+
+/// Bounds of the chunk sizes, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkParams {
+    /// no boundary is searched before, only the last chunk may be smaller
+    pub min_size: usize,
+    /// the expected size, must be a power of 2
+    pub avg_size: usize,
+    /// a chunk is cut here if no boundary was found
+    pub max_size: usize,
+}
+
+impl Default for ChunkParams {
+    fn default() -> Self {
+        ChunkParams {
+            min_size: 16 * 1024,
+            avg_size: 64 * 1024,
+            max_size: 256 * 1024,
+        }
+    }
+}
+
+/// Random values of the gear hash, one per byte, generated by splitmix64 with a fixed seed
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x6d65_6761_6364_6321;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// A mask of the `bits` highest bits, those of the gear hash depending on the most bytes
+const fn high_bits(bits: u32) -> u64 {
+    !(u64::MAX >> bits)
+}
+
+/// Length of the first chunk of `data`.
+/// Normalized chunking: a boundary is harder to match before `avg_size` and easier after,
+/// which keeps most chunks close to the average.
+fn cut_point(data: &[u8], params: &ChunkParams) -> usize {
+    if data.len() <= params.min_size {
+        return data.len();
+    }
+    let end = data.len().min(params.max_size);
+    let normal = end.min(params.avg_size);
+    let bits = params.avg_size.trailing_zeros();
+    let (mask_small, mask_large) = (high_bits(bits + 1), high_bits(bits - 1));
+
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(params.min_size) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { mask_small } else { mask_large };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// The ranges of the chunks of `data`, in order & covering it, none if `data` is empty
+pub fn chunk_ranges(data: &[u8], params: &ChunkParams) -> Vec<Range<usize>> {
+    assert!(params.avg_size.is_power_of_two() && params.avg_size > 2);
+    assert!(params.min_size <= params.avg_size && params.avg_size <= params.max_size);
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = start + cut_point(&data[start..], params);
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
+
+/// Parse a size of `core.chunkThreshold`, in bytes with an optional `k`, `m` or `g` suffix like
+/// git's integers. `0` disables chunking.
+pub fn parse_threshold(s: &str) -> Result<Option<usize>, String> {
+    let s = s.trim();
+    let invalid = || format!("fatal: bad core.chunkThreshold value '{}'", s);
+    let (number, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let unit = match c.to_ascii_lowercase() {
+                'k' => 1 << 10,
+                'm' => 1 << 20,
+                'g' => 1 << 30,
+                _ => return Err(invalid()),
+            };
+            (&s[..i], unit)
+        }
+        _ => (s, 1),
+    };
+    let threshold = number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(invalid)?;
+    Ok((threshold > 0).then_some(threshold))
+}
+
+/// Size from which the blobs written by this process are chunked, set from the config once the
+/// repository is found; `None` never chunks
+static THRESHOLD: RwLock<Option<usize>> = RwLock::new(None);
+
+pub fn configured_threshold() -> Option<usize> {
+    *THRESHOLD.read().unwrap()
+}
+
+pub fn set_configured_threshold(threshold: Option<usize>) {
+    *THRESHOLD.write().unwrap() = threshold;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::random_bytes;
+
+    #[test]
+    fn test_chunk_ranges() {
+        let params = ChunkParams::default();
+        let data = random_bytes(4 * 1024 * 1024, 42);
+        let ranges = chunk_ranges(&data, &params);
+        assert_eq!(ranges.first().unwrap().start, 0);
+        assert_eq!(ranges.last().unwrap().end, data.len());
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        for range in &ranges[..ranges.len() - 1] {
+            assert!(range.len() >= params.min_size && range.len() <= params.max_size);
+        }
+        // around the average
+        assert!(ranges.len() > 4 * 1024 / 256 && ranges.len() < 4 * 1024 / 16);
+
+        assert!(chunk_ranges(&[], &params).is_empty());
+        assert_eq!(chunk_ranges(&data[..100], &params), [0..100]);
+    }
+
+    #[test]
+    fn test_boundaries_survive_an_insertion() {
+        let params = ChunkParams::default();
+        let data = random_bytes(2 * 1024 * 1024, 7);
+        let mut edited = data.clone();
+        edited.splice(1024 * 1024..1024 * 1024, b"inserted".iter().copied());
+
+        let chunks = |data: &[u8]| -> Vec<Vec<u8>> {
+            chunk_ranges(data, &params)
+                .into_iter()
+                .map(|range| data[range].to_vec())
+                .collect()
+        };
+        let (old, new) = (chunks(&data), chunks(&edited));
+        let shared = new.iter().filter(|chunk| old.contains(chunk)).count();
+        // only the chunk of the insertion, maybe its successor, differ
+        assert!(shared + 2 >= new.len(), "{} of {}", shared, new.len());
+        assert!(shared < new.len());
+    }
+
+    #[test]
+    fn test_parse_threshold() {
+        assert_eq!(parse_threshold("1048576"), Ok(Some(1 << 20)));
+        assert_eq!(parse_threshold("512k"), Ok(Some(512 << 10)));
+        assert_eq!(parse_threshold("8M"), Ok(Some(8 << 20)));
+        assert_eq!(parse_threshold("1g"), Ok(Some(1 << 30)));
+        assert_eq!(parse_threshold("0"), Ok(None));
+        assert!(parse_threshold("10x").is_err());
+        assert!(parse_threshold("k").is_err());
+        assert!(parse_threshold("-1").is_err());
+    }
+}
@@ -1,40 +1,86 @@
-use std::{fs, io};
 use std::collections::HashSet;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::{fs, io};
 
 use byteorder::{BigEndian, ReadBytesExt};
-use flate2::Compression;
 use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
 use lru_mem::LruCache;
-use once_cell::sync::Lazy;
-use mercury::internal::pack::cache_object::CacheObject;
-use mercury::internal::pack::Pack;
+use memmap2::Mmap;
 use mercury::errors::GitError;
 use mercury::hash::SHA1;
 use mercury::internal::object::types::ObjectType;
+use mercury::internal::pack::cache_object::CacheObject;
+use mercury::internal::pack::utils as pack_utils;
+use mercury::internal::pack::Pack;
 use mercury::utils::read_sha1;
+use once_cell::sync::Lazy;
 
 use crate::command;
+use crate::utils::chunking::{self, ChunkParams};
+use crate::utils::compression::{self, Codec};
+use crate::utils::fsync::{self, FileSync, Fsync};
 static PACK_OBJ_CACHE: Lazy<Mutex<LruCache<String, CacheObject>>> = Lazy::new(|| {
     // `lazy_static!` may affect IDE's code completion
     Mutex::new(LruCache::new(1024 * 1024 * 200))
 });
 
-#[derive(Default)]
 pub struct ClientStorage {
     base_path: PathBuf,
+    /// compression of the loose objects written, any known one is read
+    codec: Codec,
+    /// blobs of at least this size are written as a list of chunks, see [chunking]
+    chunk_threshold: Option<usize>,
+    /// whether written objects are synced to the disk before `put` returns, by `file_sync`
+    fsync: bool,
+    file_sync: Arc<dyn FileSync>,
 }
 
+/// Start of a loose object stored as a list of chunks, followed by the compressed
+/// `<type> <size>\0` header & the hashes of its chunks; no codec starts like this
+const CHUNK_LIST_MAGIC: &[u8] = b"CHUNKS\0";
+
 impl ClientStorage {
     /// create `base_path` directory
     /// - `base_path` should be ".../objects"
+    /// - objects are written with the codec of `core.compression`, see [compression::configured]
+    /// - blobs are chunked from the size of `core.chunkThreshold`, see [chunking::configured_threshold]
+    /// - objects are synced if `core.fsyncObjectFiles`, see [fsync::configured]
     pub fn init(base_path: PathBuf) -> ClientStorage {
         fs::create_dir_all(&base_path).expect("Create directory failed!");
-        ClientStorage { base_path }
+        ClientStorage {
+            base_path,
+            codec: compression::configured(),
+            chunk_threshold: chunking::configured_threshold(),
+            fsync: fsync::configured(),
+            file_sync: Arc::new(Fsync),
+        }
+    }
+
+    /// Write loose objects with `codec` instead of the configured one
+    pub fn with_codec(self, codec: Codec) -> ClientStorage {
+        ClientStorage { codec, ..self }
+    }
+
+    /// Chunk the blobs from `threshold` instead of the configured one, never if `None`
+    pub fn with_chunk_threshold(self, threshold: Option<usize>) -> ClientStorage {
+        ClientStorage {
+            chunk_threshold: threshold,
+            ..self
+        }
+    }
+
+    /// Sync the written objects or not, instead of the configured behavior
+    pub fn with_fsync(self, fsync: bool) -> ClientStorage {
+        ClientStorage { fsync, ..self }
+    }
+
+    /// Sync the written objects with `file_sync` instead of [Fsync], when syncing is enabled
+    pub fn with_file_sync(self, file_sync: Arc<dyn FileSync>) -> ClientStorage {
+        ClientStorage { file_sync, ..self }
     }
 
     /// e.g. 6ae8a755... -> 6a/e8a755...
@@ -52,10 +98,16 @@ impl ClientStorage {
         Path::new(&self.base_path).join(self.transform_path(obj_id))
     }
 
+    /// `objects/chunks/xx/...`, a chunk is named by the hash of its bytes
+    fn get_chunk_path(&self, chunk_id: &SHA1) -> PathBuf {
+        self.base_path
+            .join("chunks")
+            .join(self.transform_path(chunk_id))
+    }
+
     pub fn get_object_type(&self, obj_id: &SHA1) -> Result<ObjectType, GitError> {
         if self.exist_loosely(obj_id) {
-            let raw_data = self.read_raw_data(obj_id)?;
-            let data = Self::decompress_zlib(&raw_data)?;
+            let data = self.read_loose(obj_id)?;
             let (obj_type, _, _) = Self::parse_header(&data);
             ObjectType::from_string(&obj_type)
         } else {
@@ -84,25 +136,49 @@ impl ClientStorage {
     }
 
     /// list all objects' hash in `objects`
-    fn list_objects_loose(&self) -> Vec<SHA1> {
-        let mut objects = Vec::new();
-        let paths = fs::read_dir(&self.base_path).unwrap();
-        for path in paths {
-            let path = path.unwrap().path();
-            if path.is_dir() && path.file_name().unwrap().len() == 2 { // not very elegant
-                let sub_paths = fs::read_dir(&path).unwrap();
-                for sub_path in sub_paths {
-                    let sub_path = sub_path.unwrap().path();
-                    if sub_path.is_file() {
-                        let parent_name = path.file_name().unwrap().to_str().unwrap().to_string();
-                        let file_name = sub_path.file_name().unwrap().to_str().unwrap().to_string();
-                        let file_name = parent_name + &file_name;
-                        objects.push(SHA1::from_str(&file_name).unwrap()); // this will check format, so don't worry
-                    }
-                }
-            }
+    pub fn list_objects_loose(&self) -> Vec<SHA1> {
+        self.iter_objects().collect()
+    }
+
+    /// Lazily walk the fan-out directories (`objects/xx/`) and yield the hash of every loose object.
+    /// - entries not named like an object (`pack`, `info`, temp files, ...) are skipped
+    /// - unreadable directories are skipped too, the enumeration never fails
+    pub fn iter_objects(&self) -> impl Iterator<Item = SHA1> {
+        Self::iter_fanout(&self.base_path)
+    }
+
+    /// Lazily walk `objects/chunks/xx/` and yield the hash of every stored chunk, as [ClientStorage::iter_objects]
+    pub fn iter_chunks(&self) -> impl Iterator<Item = SHA1> {
+        Self::iter_fanout(&self.base_path.join("chunks"))
+    }
+
+    /// The files named by a hash in the fan-out directories of `dir`
+    fn iter_fanout(dir: &Path) -> impl Iterator<Item = SHA1> {
+        fn is_hex(name: &str, len: usize) -> bool {
+            name.len() == len && name.bytes().all(|b| b.is_ascii_hexdigit())
         }
-        objects
+
+        fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let prefix = entry.file_name().into_string().ok()?;
+                if !is_hex(&prefix, 2) || !entry.file_type().ok()?.is_dir() {
+                    return None;
+                }
+                Some((prefix, fs::read_dir(entry.path()).ok()?))
+            })
+            .flat_map(|(prefix, sub_entries)| {
+                sub_entries.filter_map(move |entry| {
+                    let entry = entry.ok()?;
+                    let rest = entry.file_name().into_string().ok()?;
+                    if !is_hex(&rest, 38) || !entry.file_type().ok()?.is_file() {
+                        return None;
+                    }
+                    SHA1::from_str(&(prefix.clone() + &rest)).ok()
+                })
+            })
     }
 
     /// List all objects' hash in PACKs
@@ -120,27 +196,13 @@ impl ClientStorage {
 }
 
 impl ClientStorage {
-    /// zlib header: 78 9C, but Git is 78 01
-    fn compress_zlib(data: &[u8]) -> io::Result<Vec<u8>> {
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(data)?;
-        let compressed_data = encoder.finish()?;
-        Ok(compressed_data)
-    }
-
-    fn decompress_zlib(data: &[u8]) -> io::Result<Vec<u8>> {
-        let mut decoder = ZlibDecoder::new(data);
-        let mut decompressed_data = Vec::new();
-        decoder.read_to_end(&mut decompressed_data)?;
-        Ok(decompressed_data)
-    }
-
     fn parse_header(data: &[u8]) -> (String, usize, usize) {
-        let end_of_header = data.iter()
+        let end_of_header = data
+            .iter()
             .position(|&b| b == b'\0')
             .expect("Invalid object: no header terminator");
-        let header_str = std::str::from_utf8(&data[..end_of_header])
-            .expect("Invalid UTF-8 in header");
+        let header_str =
+            std::str::from_utf8(&data[..end_of_header]).expect("Invalid UTF-8 in header");
 
         let mut parts = header_str.splitn(2, ' ');
         let obj_type = parts.next().expect("No object type in header").to_string();
@@ -158,10 +220,78 @@ impl ClientStorage {
         Ok(buffer)
     }
 
+    /// Map the file of a loose object to memory
+    /// - `None` if it can't be mapped, e.g. by a file system without mmap
+    fn map_raw_data(&self, obj_id: &SHA1) -> Result<Option<Mmap>, io::Error> {
+        let file = fs::File::open(self.get_obj_path(obj_id))?;
+        // SAFETY: an object file is never modified in place, `put` renames a new file over it,
+        // which leaves the mapped one intact
+        Ok(unsafe { Mmap::map(&file) }.ok())
+    }
+
+    /// Decompress a loose object to `<type> <size>\0<content>`, reassembling its chunks if chunked.
+    /// The object file is inflated from a memory mapping, so only the result is allocated.
+    fn read_loose(&self, obj_id: &SHA1) -> Result<Vec<u8>, io::Error> {
+        self.read_loose_from(obj_id, true)
+    }
+
+    /// [ClientStorage::read_loose], `mmap` false reads the whole file in a buffer first,
+    /// the fallback if it can't be mapped
+    fn read_loose_from(&self, obj_id: &SHA1, mmap: bool) -> Result<Vec<u8>, io::Error> {
+        let mapped = if mmap {
+            self.map_raw_data(obj_id)?
+        } else {
+            None
+        };
+        let buffered;
+        let raw_data: &[u8] = match &mapped {
+            Some(mapped) => mapped,
+            None => {
+                buffered = self.read_raw_data(obj_id)?;
+                &buffered
+            }
+        };
+        let Some(list) = raw_data.strip_prefix(CHUNK_LIST_MAGIC) else {
+            return compression::decompress(raw_data);
+        };
+        let (mut data, chunk_ids) = Self::parse_chunk_list(list)?;
+        for chunk_id in chunk_ids {
+            let chunk = fs::read(self.get_chunk_path(&chunk_id))?;
+            data.extend(compression::decompress(&chunk)?);
+        }
+        Ok(data)
+    }
+
+    /// Decompress a chunk list, after its magic, to the object header & the ids of its chunks
+    fn parse_chunk_list(list: &[u8]) -> Result<(Vec<u8>, Vec<SHA1>), io::Error> {
+        let list = compression::decompress(list)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid chunk list");
+        let end_of_header = list.iter().position(|&b| b == b'\0').ok_or_else(invalid)?;
+        let hashes = &list[end_of_header + 1..];
+        if hashes.len() % 20 != 0 {
+            return Err(invalid());
+        }
+        let chunk_ids = hashes.chunks(20).map(SHA1::from_bytes).collect();
+        Ok((list[..=end_of_header].to_vec(), chunk_ids))
+    }
+
+    /// The chunks of a loose object, none if it isn't chunked
+    pub fn loose_chunks(&self, obj_id: &SHA1) -> Result<Vec<SHA1>, io::Error> {
+        let mut file = fs::File::open(self.get_obj_path(obj_id))?;
+        let mut magic = [0u8; CHUNK_LIST_MAGIC.len()];
+        match file.read_exact(&mut magic) {
+            Ok(()) if magic == CHUNK_LIST_MAGIC => {}
+            Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => return Err(e),
+            _ => return Ok(Vec::new()),
+        }
+        let mut list = Vec::new();
+        file.read_to_end(&mut list)?;
+        Ok(Self::parse_chunk_list(&list)?.1)
+    }
+
     pub fn get(&self, object_id: &SHA1) -> Result<Vec<u8>, GitError> {
         if self.exist_loosely(object_id) {
-            let raw_data = self.read_raw_data(object_id)?;
-            let data = Self::decompress_zlib(&raw_data)?;
+            let data = self.read_loose(object_id)?;
 
             // skip & check header
             let (_, _, end_of_header) = Self::parse_header(&data);
@@ -175,17 +305,79 @@ impl ClientStorage {
     }
 
     /// Save content to `objects`
-    pub fn put(&self, obj_id: &SHA1, content: &[u8], obj_type: ObjectType) -> Result<String, io::Error> {
+    /// - written to a temp file renamed into place, so a concurrent `put` or reader never sees a partial object
+    /// - a blob of at least the chunk threshold is split into chunks, each stored once in `objects/chunks`,
+    ///   the object is the list of them; reads reassemble it
+    pub fn put(
+        &self,
+        obj_id: &SHA1,
+        content: &[u8],
+        obj_type: ObjectType,
+    ) -> Result<String, io::Error> {
         let path = self.get_obj_path(obj_id);
+        let header = format!("{} {}\0", obj_type, content.len());
+        let chunked = obj_type == ObjectType::Blob
+            && self
+                .chunk_threshold
+                .is_some_and(|threshold| content.len() >= threshold);
+
+        let data = if chunked {
+            let mut list = header.into_bytes();
+            for range in chunking::chunk_ranges(content, &ChunkParams::default()) {
+                let chunk = &content[range];
+                let chunk_id = SHA1::new(chunk);
+                let chunk_path = self.get_chunk_path(&chunk_id);
+                if !chunk_path.exists() {
+                    self.write_atomically(&chunk_path, &self.codec.codec().compress(chunk)?)?;
+                }
+                list.extend_from_slice(&chunk_id.0);
+            }
+            [
+                CHUNK_LIST_MAGIC,
+                self.codec.codec().compress(&list)?.as_slice(),
+            ]
+            .concat()
+        } else {
+            let full_content = [header.as_bytes(), content].concat();
+            self.codec.codec().compress(&full_content)?
+        };
+        self.write_atomically(&path, &data)?;
+        Ok(path.to_str().unwrap().to_string())
+    }
+
+    /// Write `data` to a temp file in the directory of `path`, then rename it to `path`
+    /// - if `fsync`, the file is synced before the rename & the directory after, so is the parent
+    ///   of the directory if it's created
+    fn write_atomically(&self, path: &Path, data: &[u8]) -> Result<(), io::Error> {
         let dir = path.parent().unwrap();
+        let new_dir = !dir.exists();
         fs::create_dir_all(dir)?;
+        if self.fsync && new_dir {
+            self.file_sync.sync_dir(dir.parent().unwrap())?;
+        }
 
-        let header = format!("{} {}\0", obj_type, content.len());
-        let full_content = [header.as_bytes().to_vec(), Vec::from(content)].concat();
-
-        let mut file = fs::File::create(&path)?;
-        file.write_all(&Self::compress_zlib(&full_content)?)?;
-        Ok(path.to_str().unwrap().to_string())
+        static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+        let tmp_name = format!(
+            "tmp_obj_{}_{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let tmp_path = dir.join(tmp_name);
+        let write = || -> Result<(), io::Error> {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(data)?;
+            if self.fsync {
+                self.file_sync.sync_file(&file)?;
+            }
+            fs::rename(&tmp_path, path)
+        };
+        write().inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_path);
+        })?;
+        if self.fsync {
+            self.file_sync.sync_dir(dir)?;
+        }
+        Ok(())
     }
 
     /// Check if the object with `obj_id` exists in `objects` or PACKs
@@ -199,6 +391,81 @@ impl ClientStorage {
         let path = self.get_obj_path(obj_id);
         Path::exists(&path)
     }
+
+    /// Size of the object content as declared in its header, loose or in PACKs.
+    /// - Only the header is read & inflated, much cheaper than [ClientStorage::get]
+    /// - `None` if the object doesn't exist or its header is corrupt
+    pub fn object_size(&self, obj_id: &SHA1) -> Option<u64> {
+        if self.exist_loosely(obj_id) {
+            self.loose_object_size(obj_id).ok()
+        } else {
+            self.pack_object_size(obj_id).ok().flatten()
+        }
+    }
+
+    /// Inflate a loose object until the end of `<type> <size>\0`
+    fn loose_object_size(&self, obj_id: &SHA1) -> io::Result<u64> {
+        const MAX_HEADER_LEN: usize = 32;
+        let file = fs::File::open(self.get_obj_path(obj_id))?;
+        let mut reader = io::BufReader::new(file);
+        // the header of a chunk list is its start too
+        if reader.fill_buf()?.starts_with(CHUNK_LIST_MAGIC) {
+            reader.consume(CHUNK_LIST_MAGIC.len());
+        }
+        let mut decoder = compression::decoder(Box::new(reader))?;
+        let mut header = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            decoder.read_exact(&mut byte)?;
+            if byte[0] == b'\0' {
+                break;
+            }
+            header.push(byte[0]);
+            if header.len() > MAX_HEADER_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "object header too long",
+                ));
+            }
+        }
+        std::str::from_utf8(&header)
+            .ok()
+            .and_then(|h| h.split_once(' '))
+            .and_then(|(_, size)| size.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid object header"))
+    }
+
+    /// File metadata of a loose object, for its size on disk & modified time
+    pub fn loose_metadata(&self, obj_id: &SHA1) -> io::Result<fs::Metadata> {
+        fs::metadata(self.get_obj_path(obj_id))
+    }
+
+    /// Delete a loose object, and its fan-out directory if it becomes empty.
+    /// - Objects in PACKs are not affected
+    /// - The chunks of a chunked object are kept, other objects may share them
+    pub fn delete_loose(&self, obj_id: &SHA1) -> io::Result<()> {
+        Self::delete_from_fanout(&self.get_obj_path(obj_id))
+    }
+
+    /// File metadata of a stored chunk, as [ClientStorage::loose_metadata]
+    pub fn chunk_metadata(&self, chunk_id: &SHA1) -> io::Result<fs::Metadata> {
+        fs::metadata(self.get_chunk_path(chunk_id))
+    }
+
+    /// Delete a chunk, and its fan-out directory if it becomes empty.
+    /// - the caller checks no chunked object lists it anymore
+    pub fn delete_chunk(&self, chunk_id: &SHA1) -> io::Result<()> {
+        Self::delete_from_fanout(&self.get_chunk_path(chunk_id))
+    }
+
+    fn delete_from_fanout(path: &Path) -> io::Result<()> {
+        fs::remove_file(path)?;
+        let dir = path.parent().unwrap();
+        if fs::read_dir(dir)?.next().is_none() {
+            fs::remove_dir(dir)?;
+        }
+        Ok(())
+    }
 }
 const FANOUT: u64 = 256 * 4;
 // TODO refactor to `PackReader`
@@ -224,7 +491,8 @@ impl ClientStorage {
         for pack in packs {
             let idx = pack.with_extension("idx");
             if !idx.exists() {
-                command::index_pack::build_index_v1(pack.to_str().unwrap(), idx.to_str().unwrap()).unwrap();
+                command::index_pack::build_index_v1(pack.to_str().unwrap(), idx.to_str().unwrap())
+                    .unwrap();
             }
             idxs.push(idx);
         }
@@ -244,6 +512,41 @@ impl ClientStorage {
         Ok(None)
     }
 
+    fn pack_object_size(&self, obj_id: &SHA1) -> Result<Option<u64>, GitError> {
+        for idx in self.list_all_idx() {
+            if let Some(offset) = Self::read_idx(&idx, obj_id)? {
+                return Ok(Some(Self::read_pack_obj_size(
+                    &idx.with_extension("pack"),
+                    offset,
+                )?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read the object size from its entry header in the pack file, without rebuilding deltas.
+    /// - For deltas, the result size is the 2nd varint of the delta data, only its start is inflated
+    fn read_pack_obj_size(pack_file: &Path, offset: u64) -> io::Result<u64> {
+        let file = fs::File::open(pack_file)?;
+        let mut pack_reader = io::BufReader::new(file);
+        pack_reader.seek(io::SeekFrom::Start(offset))?;
+        let mut offset = offset as usize;
+        let (type_bits, size) =
+            pack_utils::read_type_and_varint_size(&mut pack_reader, &mut offset)?;
+        match ObjectType::from_u8(type_bits) {
+            Ok(ObjectType::OffsetDelta) => {
+                pack_utils::read_offset_encoding(&mut pack_reader)?;
+            }
+            Ok(ObjectType::HashDelta) => {
+                read_sha1(&mut pack_reader)?;
+            }
+            _ => return Ok(size as u64),
+        }
+        let mut delta = ZlibDecoder::new(pack_reader);
+        let (_base_size, result_size) = pack_utils::read_delta_object_size(&mut delta)?;
+        Ok(result_size as u64)
+    }
+
     fn read_idx_fanout(idx_file: &Path) -> Result<[u32; 256], io::Error> {
         let mut idx_file = fs::File::open(idx_file)?;
         // const FANOUT: usize = 256 * 4;
@@ -333,7 +636,7 @@ impl ClientStorage {
                 let base_obj = Self::read_pack_obj(pack_file, base_offset as u64)?;
                 let base_obj = Arc::new(base_obj);
                 Pack::rebuild_delta(obj, base_obj) // new obj
-            },
+            }
             ObjectType::HashDelta => {
                 let base_hash = obj.hash_delta().unwrap();
                 let idx_file = pack_file.with_extension("idx");
@@ -341,11 +644,16 @@ impl ClientStorage {
                 let base_obj = Self::read_pack_obj(pack_file, base_offset)?;
                 let base_obj = Arc::new(base_obj);
                 Pack::rebuild_delta(obj, base_obj) // new obj
-            },
+            }
             _ => obj,
         };
         // write cache
-        if PACK_OBJ_CACHE.lock().unwrap().insert(cache_key, full_obj.clone()).is_err() {
+        if PACK_OBJ_CACHE
+            .lock()
+            .unwrap()
+            .insert(cache_key, full_obj.clone())
+            .is_err()
+        {
             eprintln!("Warn: EntryTooLarge");
         }
         Ok(full_obj)
@@ -354,15 +662,23 @@ impl ClientStorage {
 
 #[cfg(test)]
 mod tests {
-    use std::{env, fs};
-    use std::path::PathBuf;
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::{env, fs, io};
 
+    use mercury::hash::SHA1;
     use mercury::internal::object::blob::Blob;
-    use mercury::internal::object::ObjectTrait;
     use mercury::internal::object::types::ObjectType;
+    use mercury::internal::object::ObjectTrait;
 
     use crate::utils::{test, util};
 
+    use crate::utils::chunking::{self, ChunkParams};
+    use crate::utils::compression::{self, Codec, ObjectCodec, Zlib};
+    use crate::utils::fsync::FileSync;
+
     use super::ClientStorage;
 
     #[test]
@@ -374,7 +690,9 @@ mod tests {
         source.push("tests/objects");
 
         let client_storage = ClientStorage::init(source.clone());
-        assert!(client_storage.put(&blob.id, &blob.data, blob.get_type()).is_ok());
+        assert!(client_storage
+            .put(&blob.id, &blob.data, blob.get_type())
+            .is_ok());
         assert!(client_storage.exist(&blob.id));
 
         let data = client_storage.get(&blob.id).unwrap();
@@ -390,7 +708,9 @@ mod tests {
         source.push("tests/objects");
 
         let client_storage = ClientStorage::init(source.clone());
-        assert!(client_storage.put(&blob.id, &blob.data, blob.get_type()).is_ok());
+        assert!(client_storage
+            .put(&blob.id, &blob.data, blob.get_type())
+            .is_ok());
 
         let objs = client_storage.search("5dd01c177");
 
@@ -399,7 +719,9 @@ mod tests {
 
     #[test]
     fn test_list_objs() {
-        let source = PathBuf::from(test::TEST_DIR).join(util::ROOT_DIR).join("objects");
+        let source = PathBuf::from(test::TEST_DIR)
+            .join(util::ROOT_DIR)
+            .join("objects");
         if !source.exists() {
             return;
         }
@@ -410,6 +732,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_iter_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let client_storage = ClientStorage::init(dir.path().join("objects"));
+        let mut expected = HashSet::new();
+        for i in 0..10 {
+            let blob = Blob::from_content(&format!("object {}", i));
+            client_storage
+                .put(&blob.id, &blob.data, blob.get_type())
+                .unwrap();
+            expected.insert(blob.id);
+        }
+        // garbage that must be skipped
+        let some = expected.iter().next().unwrap().to_string();
+        let fan_out = dir.path().join("objects").join(&some[..2]);
+        fs::write(fan_out.join("tmp_obj_XXXXXX"), b"temp").unwrap();
+        fs::create_dir(fan_out.join("0".repeat(38))).unwrap();
+        fs::create_dir_all(dir.path().join("objects/pack")).unwrap();
+        fs::create_dir_all(dir.path().join("objects/zz")).unwrap();
+        fs::write(dir.path().join("objects/zz").join("0".repeat(38)), b"").unwrap();
+
+        let objects: Vec<SHA1> = client_storage.iter_objects().collect();
+        assert_eq!(objects.len(), expected.len());
+        assert_eq!(objects.into_iter().collect::<HashSet<_>>(), expected);
+    }
+
     #[test]
     fn test_get_obj_type() {
         let blob = Blob::from_content("Hello, world!");
@@ -418,17 +766,42 @@ mod tests {
         source.push("tests/objects");
 
         let client_storage = ClientStorage::init(source.clone());
-        assert!(client_storage.put(&blob.id, &blob.data, blob.get_type()).is_ok());
+        assert!(client_storage
+            .put(&blob.id, &blob.data, blob.get_type())
+            .is_ok());
 
         let obj_type = client_storage.get_object_type(&blob.id).unwrap();
         assert_eq!(obj_type, ObjectType::Blob);
     }
 
+    #[test]
+    fn test_object_size() {
+        let content = "Hello, world!\n".repeat(1024);
+        let blob = Blob::from_content(&content);
+
+        let (_dir, client_storage) = test::temp_storage();
+        assert!(client_storage
+            .put(&blob.id, &blob.data, blob.get_type())
+            .is_ok());
+
+        let size = client_storage.object_size(&blob.id).unwrap();
+        assert_eq!(size, client_storage.get(&blob.id).unwrap().len() as u64);
+        assert_eq!(size, content.len() as u64);
+        // only the header is inflated, a truncated body doesn't matter
+        let path = client_storage.get_obj_path(&blob.id);
+        let raw = fs::read(&path).unwrap();
+        fs::write(&path, &raw[..raw.len() / 2]).unwrap();
+        assert_eq!(client_storage.object_size(&blob.id), Some(size));
+        fs::write(&path, raw).unwrap();
+
+        assert_eq!(client_storage.object_size(&SHA1::new(b"not exist")), None);
+    }
+
     #[test]
     fn test_decompress() {
         let data = b"blob 13\0Hello, world!";
-        let compressed_data = ClientStorage::compress_zlib(data).unwrap();
-        let decompressed_data = ClientStorage::decompress_zlib(&compressed_data).unwrap();
+        let compressed_data = Zlib.compress(data).unwrap();
+        let decompressed_data = compression::decompress(&compressed_data).unwrap();
         assert_eq!(decompressed_data, data);
     }
 
@@ -436,10 +809,185 @@ mod tests {
     fn test_decompress_2() {
         let pack_file = "../tests/data/objects/4b/00093bee9b3ef5afc5f8e3645dc39cfa2f49aa";
         let pack_content = fs::read(pack_file).unwrap();
-        let decompressed_data = ClientStorage::decompress_zlib(&pack_content).unwrap();
+        let decompressed_data = compression::decompress(&pack_content).unwrap();
         println!("{:?}", String::from_utf8(decompressed_data).unwrap());
     }
 
+    #[test]
+    fn test_codecs_read_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let zlib = ClientStorage::init(dir.path().join("objects"));
+        let zstd = ClientStorage::init(dir.path().join("objects")).with_codec(Codec::Zstd);
+        fs::create_dir(dir.path().join("objects/pack")).unwrap();
+
+        let old = Blob::from_content("written by git");
+        let new = Blob::from_content("written with core.compression = zstd");
+        zlib.put(&old.id, &old.data, old.get_type()).unwrap();
+        zstd.put(&new.id, &new.data, new.get_type()).unwrap();
+        let raw = fs::read(zstd.get_obj_path(&new.id)).unwrap();
+        assert!(compression::Zstd.detect(&raw));
+
+        for storage in [&zlib, &zstd] {
+            assert_eq!(storage.get(&old.id).unwrap(), old.data);
+            assert_eq!(storage.get(&new.id).unwrap(), new.data);
+            assert_eq!(storage.get_object_type(&new.id).unwrap(), ObjectType::Blob);
+            assert_eq!(storage.object_size(&new.id), Some(new.data.len() as u64));
+        }
+
+        // an object of an unknown codec is an error, not a panic
+        fs::write(zstd.get_obj_path(&new.id), b"\x04\x22\x4d\x18 lz4 frame").unwrap();
+        assert!(zstd.get(&new.id).is_err());
+        assert!(zstd.get_object_type(&new.id).is_err());
+        assert_eq!(zstd.object_size(&new.id), None);
+    }
+
+    #[test]
+    fn test_chunked_blobs_share_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = dir.path().join("objects");
+        let client_storage =
+            ClientStorage::init(objects.clone()).with_chunk_threshold(Some(1 << 20));
+        fs::create_dir(objects.join("pack")).unwrap();
+
+        // pseudo-random content, like a large binary file
+        let content = test::random_bytes(4 << 20, 0x2545_f491_4f6c_dd1d);
+        let mut edited = content.clone();
+        edited[2 << 20..(2 << 20) + 5].copy_from_slice(b"edit!");
+        let v1 = Blob::from_content_bytes(content);
+        let v2 = Blob::from_content_bytes(edited);
+        let small = Blob::from_content("not chunked");
+        for blob in [&v1, &v2, &small] {
+            client_storage
+                .put(&blob.id, &blob.data, blob.get_type())
+                .unwrap();
+        }
+
+        let chunk_ids = |blob: &Blob| -> HashSet<SHA1> {
+            chunking::chunk_ranges(&blob.data, &ChunkParams::default())
+                .into_iter()
+                .map(|range| SHA1::new(&blob.data[range]))
+                .collect()
+        };
+        let (chunks_v1, chunks_v2) = (chunk_ids(&v1), chunk_ids(&v2));
+        let shared = chunks_v1.intersection(&chunks_v2).count();
+        assert!(shared > 0 && shared < chunks_v2.len());
+        // each shared chunk is stored once
+        let stored = fs::read_dir(objects.join("chunks"))
+            .unwrap()
+            .flat_map(|dir| fs::read_dir(dir.unwrap().path()).unwrap())
+            .count();
+        assert_eq!(stored, chunks_v1.len() + chunks_v2.len() - shared);
+
+        for blob in [&v1, &v2, &small] {
+            assert_eq!(client_storage.get(&blob.id).unwrap(), blob.data);
+            assert_eq!(
+                client_storage.get_object_type(&blob.id).unwrap(),
+                ObjectType::Blob
+            );
+            assert_eq!(
+                client_storage.object_size(&blob.id),
+                Some(blob.data.len() as u64)
+            );
+        }
+        let raw = fs::read(client_storage.get_obj_path(&small.id)).unwrap();
+        assert!(!raw.starts_with(super::CHUNK_LIST_MAGIC));
+        let objs: HashSet<SHA1> = client_storage.iter_objects().collect();
+        assert_eq!(objs, HashSet::from([v1.id, v2.id, small.id]));
+    }
+
+    #[test]
+    fn test_mmap_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let client_storage = ClientStorage::init(dir.path().join("objects"));
+
+        // pseudo-random content doesn't compress, the object file is as large
+        let content = test::random_bytes(16 << 20, 0x9e37_79b9_7f4a_7c15);
+        let blob = Blob::from_content_bytes(content);
+        client_storage
+            .put(&blob.id, &blob.data, blob.get_type())
+            .unwrap();
+        assert!(client_storage.map_raw_data(&blob.id).unwrap().is_some());
+
+        let mapped = client_storage.read_loose_from(&blob.id, true).unwrap();
+        let buffered = client_storage.read_loose_from(&blob.id, false).unwrap();
+        assert_eq!(mapped, buffered);
+        let header = format!("blob {}\0", blob.data.len());
+        assert_eq!(&mapped[..header.len()], header.as_bytes());
+        assert_eq!(&mapped[header.len()..], blob.data);
+
+        assert!(client_storage
+            .map_raw_data(&SHA1::new(b"not exist"))
+            .is_err());
+    }
+
+    /// Counts the syncs, failing them if `fail`
+    #[derive(Default)]
+    struct FaultySync {
+        files: AtomicUsize,
+        dirs: AtomicUsize,
+        fail: bool,
+    }
+
+    impl FileSync for FaultySync {
+        fn sync_file(&self, _file: &fs::File) -> io::Result<()> {
+            self.files.fetch_add(1, Ordering::Relaxed);
+            if self.fail {
+                return Err(io::Error::other("injected fsync failure"));
+            }
+            Ok(())
+        }
+
+        fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+            assert!(dir.is_dir());
+            self.dirs.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_fsync() {
+        let dir = tempfile::tempdir().unwrap();
+        let sync = Arc::new(FaultySync::default());
+        let storage = ClientStorage::init(dir.path().join("objects")).with_file_sync(sync.clone());
+
+        let blob = Blob::from_content("not synced");
+        storage
+            .with_fsync(false)
+            .put(&blob.id, &blob.data, blob.get_type())
+            .unwrap();
+        assert_eq!(sync.files.load(Ordering::Relaxed), 0);
+        assert_eq!(sync.dirs.load(Ordering::Relaxed), 0);
+
+        let storage = ClientStorage::init(dir.path().join("objects"))
+            .with_file_sync(sync.clone())
+            .with_fsync(true);
+        let blob = Blob::from_content("synced");
+        storage.put(&blob.id, &blob.data, blob.get_type()).unwrap();
+        assert_eq!(sync.files.load(Ordering::Relaxed), 1);
+        // the new fan-out directory, and `objects` for it
+        assert_eq!(sync.dirs.load(Ordering::Relaxed), 2);
+        assert_eq!(storage.get(&blob.id).unwrap(), blob.data);
+
+        // a failed sync fails `put`, leaving nothing behind
+        let faulty = Arc::new(FaultySync {
+            fail: true,
+            ..Default::default()
+        });
+        let storage = ClientStorage::init(dir.path().join("objects"))
+            .with_file_sync(faulty.clone())
+            .with_fsync(true);
+        let blob = Blob::from_content("lost");
+        assert!(storage.put(&blob.id, &blob.data, blob.get_type()).is_err());
+        assert_eq!(faulty.files.load(Ordering::Relaxed), 1);
+        assert!(!storage.exist_loosely(&blob.id));
+        let fan_out = storage
+            .get_obj_path(&blob.id)
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        assert_eq!(fs::read_dir(fan_out).unwrap().count(), 0);
+    }
+
     #[test]
     fn test_get_from_pack() {
         unimplemented!();
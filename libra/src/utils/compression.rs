@@ -0,0 +1,183 @@
+//! Compression of loose objects, selected by `core.compression`.
+//!
+//! zlib is what git writes and reads, zstd is an alternative with a better ratio & speed for large
+//! repositories, but git can't read objects compressed with it. Either way the compressed data starts
+//! with the codec's own header, so reading an object picks its decoder without any configuration.
+//! PACKs are always zlib, as the pack format requires.
+
+use std::io::{self, BufRead, Read, Write};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+/// A compression of loose objects
+pub trait ObjectCodec: Sync {
+    /// Whether `data`, the first bytes of an object file, were compressed by this codec
+    fn detect(&self, data: &[u8]) -> bool;
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Stream decompressing `reader`
+    fn decoder<'a>(&self, reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn Read + 'a>>;
+}
+
+pub struct Zlib;
+
+impl ObjectCodec for Zlib {
+    /// CMF `x8` (deflate, window size in `x`) and FCHECK making the first 2 bytes a multiple of 31
+    fn detect(&self, data: &[u8]) -> bool {
+        match data {
+            [cmf, flg, ..] => {
+                cmf & 0x0f == 8 && cmf >> 4 <= 7 && u16::from_be_bytes([*cmf, *flg]) % 31 == 0
+            }
+            _ => false,
+        }
+    }
+
+    /// zlib header: 78 9C, but Git is 78 01
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(ZlibDecoder::new(reader)))
+    }
+}
+
+pub struct Zstd;
+
+impl Zstd {
+    const MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+}
+
+impl ObjectCodec for Zstd {
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(&Self::MAGIC)
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(zstd::Decoder::with_buffer(reader)?))
+    }
+}
+
+/// The codecs `core.compression` can select
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    Zlib,
+    Zstd,
+}
+
+const CODECS: [Codec; 2] = [Codec::Zlib, Codec::Zstd];
+
+impl Codec {
+    pub fn codec(&self) -> &'static dyn ObjectCodec {
+        match self {
+            Codec::Zlib => &Zlib,
+            Codec::Zstd => &Zstd,
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    /// `zlib` or `zstd`, a number is a zlib level in git, so it means zlib
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "zlib" => Ok(Codec::Zlib),
+            "zstd" => Ok(Codec::Zstd),
+            level if level.parse::<i32>().is_ok_and(|l| (-1..=9).contains(&l)) => Ok(Codec::Zlib),
+            _ => Err(format!(
+                "fatal: bad core.compression value '{}', expected zlib or zstd",
+                s
+            )),
+        }
+    }
+}
+
+/// Codec of the objects written by this process, set from the config once the repository is found
+static CONFIGURED: RwLock<Codec> = RwLock::new(Codec::Zlib);
+
+pub fn configured() -> Codec {
+    *CONFIGURED.read().unwrap()
+}
+
+pub fn set_configured(codec: Codec) {
+    *CONFIGURED.write().unwrap() = codec;
+}
+
+fn unknown_codec() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "unknown object compression")
+}
+
+/// Stream decompressing `reader` with the codec it was compressed by
+/// - `InvalidData` if no known codec compressed it
+pub fn decoder<'a>(mut reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+    let header = reader.fill_buf()?;
+    let codec = CODECS
+        .iter()
+        .map(Codec::codec)
+        .find(|codec| codec.detect(header))
+        .ok_or_else(unknown_codec)?;
+    codec.decoder(reader)
+}
+
+/// Decompress `data` with the codec it was compressed by, see [decoder]
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    decoder(Box::new(data))?.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"blob 13\0Hello, world!".repeat(100);
+        for codec in CODECS {
+            let compressed = codec.codec().compress(&data).unwrap();
+            assert!(compressed.len() < data.len(), "{:?}", codec);
+            assert!(codec.codec().detect(&compressed), "{:?}", codec);
+            assert_eq!(decompress(&compressed).unwrap(), data, "{:?}", codec);
+        }
+        // the other codec doesn't claim the data
+        assert!(!Zstd.detect(&Zlib.compress(&data).unwrap()));
+        assert!(!Zlib.detect(&Zstd.compress(&data).unwrap()));
+    }
+
+    #[test]
+    fn test_unknown_codec() {
+        let data = b"blob 13\0Hello, world!";
+        let err = decompress(data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            decompress(b"").unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        // a known header with a corrupt body
+        let mut compressed = Zstd.compress(data).unwrap();
+        compressed.truncate(6);
+        assert!(decompress(&compressed).is_err());
+    }
+
+    #[test]
+    fn test_codec_from_config() {
+        assert_eq!("zstd".parse(), Ok(Codec::Zstd));
+        assert_eq!("ZLIB".parse(), Ok(Codec::Zlib));
+        assert_eq!("9".parse(), Ok(Codec::Zlib));
+        assert_eq!("-1".parse(), Ok(Codec::Zlib));
+        assert!("lz4".parse::<Codec>().is_err());
+        assert!("10".parse::<Codec>().is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Durability of the objects written, selected by `core.fsyncObjectFiles`.
+//!
+//! An object is renamed into place once written, so a reader never sees it partially, but without
+//! a sync a crash may still lose it after the command returned, while refs or the database already
+//! point at it. When enabled, the object file is synced before the rename and its directory after.
+//! Disabled by default, like git, as syncing each object makes writing many of them much slower.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Flushes the written files & directories to the disk
+pub trait FileSync: Send + Sync {
+    fn sync_file(&self, file: &File) -> io::Result<()>;
+
+    /// Make the entries created or renamed in `dir` durable
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+}
+
+/// `fsync`, the sync of the operating system
+pub struct Fsync;
+
+impl FileSync for Fsync {
+    fn sync_file(&self, file: &File) -> io::Result<()> {
+        file.sync_all()
+    }
+
+    #[cfg(unix)]
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        File::open(dir)?.sync_all()
+    }
+
+    /// A directory can't be opened to be synced on Windows, its entries are durable with the files
+    #[cfg(not(unix))]
+    fn sync_dir(&self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether the objects written by this process are synced, set from the config once the repository is found
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+pub fn configured() -> bool {
+    CONFIGURED.load(Ordering::Relaxed)
+}
+
+pub fn set_configured(fsync: bool) {
+    CONFIGURED.store(fsync, Ordering::Relaxed);
+}
+
+/// [Fsync] if the written files are synced, see [configured]
+pub fn configured_sync() -> Option<&'static dyn FileSync> {
+    configured().then_some(&Fsync)
+}
+
+/// Create the file `path` with `data`, for the files not written by `ClientStorage` like PACKs.
+/// With `sync`, the file is synced then its directory, so it's durable once this returns.
+pub fn write_file(path: &Path, data: &[u8], sync: Option<&dyn FileSync>) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    if let Some(sync) = sync {
+        sync.sync_file(&file)?;
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        sync.sync_dir(dir.unwrap_or(Path::new(".")))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Records what is synced
+    #[derive(Default)]
+    struct RecordingSync {
+        files: Mutex<usize>,
+        dirs: Mutex<Vec<std::path::PathBuf>>,
+    }
+
+    impl FileSync for RecordingSync {
+        fn sync_file(&self, _file: &File) -> io::Result<()> {
+            *self.files.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+            self.dirs.lock().unwrap().push(dir.to_path_buf());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let sync = RecordingSync::default();
+        let path = dir.path().join("pack-1.pack");
+        write_file(&path, b"not synced", None).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"not synced");
+
+        write_file(&path, b"synced", Some(&sync)).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"synced");
+        assert_eq!(*sync.files.lock().unwrap(), 1);
+        assert_eq!(*sync.dirs.lock().unwrap(), [dir.path().to_path_buf()]);
+    }
+}
@@ -38,7 +38,10 @@ where
         return false;
     }
 
-    let path = util::to_workdir_path(path);
+    // a path with no relative form is outside the repository, so not tracked
+    let Ok(path) = util::try_to_workdir_path(path) else {
+        return false;
+    };
     let glob = wax::any(LFS_PATTERNS.iter().map(|s| s.as_str()).collect::<Vec<_>>()).unwrap();
     glob.is_match(path.to_str().unwrap())
 }
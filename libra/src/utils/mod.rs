@@ -4,4 +4,7 @@ pub(crate) mod path;
 pub(crate) mod object_ext;
 pub(crate) mod path_ext;
 pub(crate) mod client_storage;
+pub(crate) mod chunking;
+pub(crate) mod compression;
+pub(crate) mod fsync;
 pub mod lfs;
\ No newline at end of file
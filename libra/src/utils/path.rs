@@ -9,10 +9,19 @@ pub fn objects() -> PathBuf {
     util::storage_path().join("objects")
 }
 
+/// the state of a rebase stopped by a conflict, as git's `.git/rebase-merge`
+pub fn rebase_merge() -> PathBuf {
+    util::storage_path().join("rebase-merge")
+}
+
 pub fn database() -> PathBuf {
     util::storage_path().join(util::DATABASE)
 }
 
 pub fn attributes() -> PathBuf {
     util::working_dir().join(util::ATTRIBUTES)
+}
+
+pub fn ignore() -> PathBuf {
+    util::working_dir().join(util::IGNORE)
 }
\ No newline at end of file
@@ -8,8 +8,18 @@ use std::io::Write;
 use std::path::Path;
 use std::{env, fs, path::PathBuf};
 
+use mercury::hash::SHA1;
+use mercury::internal::object::blob::Blob;
+use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use mercury::internal::object::ObjectTrait;
+use mercury::utils::xorshift64;
+use tempfile::TempDir;
+
+use crate::utils::client_storage::ClientStorage;
 use crate::utils::util;
 use crate::command;
+use crate::internal::config::Config;
+use crate::internal::head::Head;
 
 pub const TEST_DIR: &str = "libra_test_repo";
 
@@ -81,7 +91,7 @@ pub fn setup_clean_testing_env() {
     let mut path = util::cur_dir();
 
     // Append the Libra root directory to the current directory
-    path.push(util::ROOT_DIR);
+    path.push(util::root_dir_name());
 
     // If the Libra root directory exists, remove it
     if path.exists() {
@@ -89,10 +99,12 @@ pub fn setup_clean_testing_env() {
     }
 }
 
-/// switch to test dir and create a new .libra
+/// switch to test dir and create a new .libra, with the identity commits need
 pub async fn setup_with_new_libra() {
     setup_clean_testing_env();
     command::init::init().await.unwrap();
+    Config::set("user", None, "name", "mega").await;
+    Config::set("user", None, "email", "admin@mega.org").await;
 }
 
 pub fn init_debug_logger() {
@@ -127,3 +139,59 @@ pub fn ensure_file(path: impl AsRef<Path>, content: Option<&str>) {
             .unwrap();
     }
 }
+
+/// write `files` as `(path, content)`, add & commit them, returns the new HEAD commit
+pub async fn commit_files(files: &[(&str, &str)], message: &str) -> SHA1 {
+    for (file, content) in files {
+        ensure_file(file, Some(content));
+    }
+    command::add::execute(command::add::AddArgs {
+        pathspec: files.iter().map(|(file, _)| file.to_string()).collect(),
+        all: false,
+        update: false,
+        verbose: false,
+    })
+    .await;
+    command::commit::execute(command::commit::CommitArgs {
+        message: message.to_string(),
+        allow_empty: false,
+        conventional: false,
+        verify: false,
+    })
+    .await;
+    Head::current_commit().await.unwrap()
+}
+
+/// Deterministic pseudo-random bytes, content without repetition like a binary file
+pub fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+    xorshift64(seed).take(len).map(|value| value as u8).collect()
+}
+
+/// An empty object storage in a new temporary directory, outside of any repo.
+/// The directory is removed when the returned [TempDir] is dropped.
+pub fn temp_storage() -> (TempDir, ClientStorage) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = ClientStorage::init(dir.path().join("objects"));
+    fs::create_dir(dir.path().join("objects/pack")).unwrap();
+    (dir, storage)
+}
+
+/// store a blob of `content`
+pub fn save_blob(storage: &ClientStorage, content: &[u8]) -> SHA1 {
+    let blob = Blob::from_content_bytes(content.to_vec());
+    storage.put(&blob.id, &blob.data, blob.get_type()).unwrap();
+    blob.id
+}
+
+/// store a tree of `items` as `(name, mode, id)`
+pub fn save_tree(storage: &ClientStorage, items: Vec<(&str, TreeItemMode, SHA1)>) -> SHA1 {
+    let items = items
+        .into_iter()
+        .map(|(name, mode, id)| TreeItem::new(mode, id, name.to_string()))
+        .collect();
+    let tree = Tree::from_tree_items(items).unwrap();
+    storage
+        .put(&tree.id, &tree.to_data().unwrap(), tree.get_type())
+        .unwrap();
+    tree.id
+}
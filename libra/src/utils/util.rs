@@ -1,19 +1,25 @@
 use std::collections::HashSet;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
 use path_absolutize::*;
-use std::{env, fs, io};
+use std::{env, fmt, fs, io};
 use indicatif::{ProgressBar, ProgressStyle};
 use mercury::hash::SHA1;
 use mercury::internal::object::types::ObjectType;
+use wax::{Glob, Pattern};
 
 use crate::utils::client_storage::ClientStorage;
 use crate::utils::path;
 use crate::utils::path_ext::PathExt;
 
 pub const ROOT_DIR: &str = ".libra";
+/// Environment variable overriding the name of the repository directory, [ROOT_DIR] by default
+/// - e.g. to keep several repositories side by side in one working directory
+pub const ROOT_DIR_ENV: &str = "LIBRA_DIR";
 pub const DATABASE: &str = "libra.db";
 pub const ATTRIBUTES: &str = ".libra_attributes";
+pub const IGNORE: &str = ".libraignore";
 
 /// Returns the current working directory as a `PathBuf`.
 ///
@@ -29,13 +35,23 @@ pub fn cur_dir() -> PathBuf {
     env::current_dir().unwrap()
 }
 
+/// Name of the repository directory: [ROOT_DIR_ENV] if set, else [ROOT_DIR]
+pub fn root_dir_name() -> String {
+    match env::var(ROOT_DIR_ENV) {
+        Ok(name) if !name.is_empty() => name,
+        _ => ROOT_DIR.to_string(),
+    }
+}
+
 /// Try to get the storage path of the repository, which is the path of the `.libra` directory
+/// (or the one named by [ROOT_DIR_ENV])
 /// - if the current directory is not a repository, return an error
 pub fn try_get_storage_path() -> Result<PathBuf, io::Error> {
+    let root_dir = root_dir_name();
     let mut cur_dir = env::current_dir()?;
     loop {
         let mut libra = cur_dir.clone();
-        libra.push(ROOT_DIR);
+        libra.push(&root_dir);
         if libra.exists() {
             return Ok(libra);
         }
@@ -53,13 +69,34 @@ pub fn try_get_storage_path() -> Result<PathBuf, io::Error> {
 pub fn storage_path() -> PathBuf {
     try_get_storage_path().unwrap()
 }
-/// Check if libra repo exists
+/// Whether the current directory is in a repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoStatus {
+    /// with the path of its `.libra` directory
+    Present(PathBuf),
+    Absent,
+}
+
+/// Find the repository of the current directory, without printing anything
+pub fn repo_status() -> RepoStatus {
+    match try_get_storage_path() {
+        Ok(storage) => RepoStatus::Present(storage),
+        Err(_) => RepoStatus::Absent,
+    }
+}
+
+/// Check if libra repo exists, printing an error to stderr if not
 pub fn check_repo_exist() -> bool {
-    if try_get_storage_path().is_err() {
-        eprintln!("fatal: not a libra repository (or any of the parent directories): .libra");
-        return false;
+    match repo_status() {
+        RepoStatus::Present(_) => true,
+        RepoStatus::Absent => {
+            eprintln!(
+                "fatal: not a libra repository (or any of the parent directories): {}",
+                root_dir_name()
+            );
+            false
+        }
     }
-    true
 }
 
 /// Get `ClientStorage` for the `objects` directory
@@ -67,25 +104,72 @@ pub fn objects_storage() -> ClientStorage {
     ClientStorage::init(path::objects())
 }
 
+/// Storage paths of the repositories with `core.bare` set, see [set_bare]
+static BARE_REPOS: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+/// Record the `core.bare` config of the repository stored at `storage`
+/// - a bare repository has no working directory, only objects and refs
+pub fn set_bare(storage: &Path, bare: bool) {
+    let mut bare_repos = BARE_REPOS.write().unwrap();
+    bare_repos.retain(|path| path != storage);
+    if bare {
+        bare_repos.push(storage.to_path_buf());
+    }
+}
+
+/// Check if the current repository is bare, see [set_bare]
+pub fn is_bare() -> bool {
+    match try_get_storage_path() {
+        Ok(storage) => BARE_REPOS.read().unwrap().contains(&storage),
+        Err(_) => false,
+    }
+}
+
+/// Try to get the working directory of the repository
+/// - error if the current directory is not a repository, or the repository is bare
+pub fn try_working_dir() -> Result<PathBuf, io::Error> {
+    let mut storage_path = try_get_storage_path()?;
+    if BARE_REPOS.read().unwrap().contains(&storage_path) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this operation must be run in a work tree",
+        ));
+    }
+    storage_path.pop();
+    Ok(storage_path)
+}
+
 /// Get the working directory of the repository
-/// - panics if the current directory is not a repository
+/// - panics if the current directory is not a repository, or the repository is bare
 pub fn working_dir() -> PathBuf {
-    let mut storage_path = storage_path();
-    storage_path.pop();
-    storage_path
+    try_working_dir().unwrap_or_else(|e| panic!("fatal: {}", e))
 }
 
-/// Get the working directory of the repository as a string, panics if the path is not valid utf-8
+/// Get the working directory of the repository as a string, for paths that must round-trip
+/// (e.g. passed on as a pathspec)
+/// - panics if the path is not valid utf-8, which is possible on Linux; use
+///   [working_dir_string_lossy] to only display it
 pub fn working_dir_string() -> String {
     working_dir().to_str().unwrap().to_string()
 }
 
+/// Get the working directory of the repository as a string to display, invalid utf-8 is replaced
+/// with `U+FFFD`
+pub fn working_dir_string_lossy() -> String {
+    working_dir().to_string_lossy().into_owned()
+}
+
 /// Turn a path to a relative path to the working directory
 /// - not check existence
 pub fn to_workdir_path(path: impl AsRef<Path>) -> PathBuf {
     to_relative(path, working_dir())
 }
 
+/// Same as [to_workdir_path], but fails instead of panicking if the path has no relative form
+pub fn try_to_workdir_path(path: impl AsRef<Path>) -> Result<PathBuf, RelativeError> {
+    try_to_relative(path, working_dir())
+}
+
 /// Turn a workdir path to absolute path
 pub fn workdir_to_absolute(path: impl AsRef<Path>) -> PathBuf {
     working_dir().join(path.as_ref())
@@ -101,8 +185,8 @@ where
 {
     // to absolute, just for clear redundant `..` `.` in the path
     // may generate wrong intermediate path, but the final result is correct (after `starts_with`)
-    let path_abs = path.as_ref().absolutize().unwrap();
-    let parent_abs = parent.as_ref().absolutize().unwrap();
+    let path_abs = to_absolute(path);
+    let parent_abs = to_absolute(parent);
     path_abs.starts_with(parent_abs)
 }
 
@@ -141,9 +225,28 @@ where
         .collect()
 }
 
+/// `path` has no relative form based on `base`, e.g. they are on different Windows drives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelativeError {
+    pub path: PathBuf,
+    pub base: PathBuf,
+}
+
+impl fmt::Display for RelativeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "path {:?} cannot convert to relative based on {:?}",
+            self.path, self.base
+        )
+    }
+}
+
+impl std::error::Error for RelativeError {}
+
 /// `path` & `base` must be absolute or relative (to current dir)
 /// <br> return "." if `path` == `base`
-pub fn to_relative<P, B>(path: P, base: B) -> PathBuf
+pub fn try_to_relative<P, B>(path: P, base: B) -> Result<PathBuf, RelativeError>
 where
     P: AsRef<Path>,
     B: AsRef<Path>,
@@ -151,24 +254,65 @@ where
     // × crate `PathAbs` is NOT good enough
     // 1. `PathAbs::new` can not handle `.` or `./`, all return ""
     // 2. `PathAbs::new` generate prefix: '\\?\' on Windows
-    // So, we replace it with `path_absolutize` √
-    let path_abs = path.as_ref().absolutize().unwrap();
-    let base_abs = base.as_ref().absolutize().unwrap();
-    if let Some(rel_path) = pathdiff::diff_paths(path_abs, base_abs) {
-        if rel_path.to_string_lossy() == "" {
-            PathBuf::from(".")
-        } else {
-            rel_path
+    // So, we normalize lexically with [simplify_path], keeping the Windows prefix √
+    let path_abs = to_absolute(&path);
+    let base_abs = to_absolute(&base);
+    match pathdiff::diff_paths(path_abs, base_abs) {
+        Some(rel_path) if rel_path.to_string_lossy() == "" => Ok(PathBuf::from(".")),
+        Some(rel_path) => Ok(rel_path),
+        None => Err(RelativeError {
+            path: path.as_ref().to_path_buf(),
+            base: base.as_ref().to_path_buf(),
+        }),
+    }
+}
+
+/// Same as [try_to_relative], but panics if there is no relative path
+pub fn to_relative<P, B>(path: P, base: B) -> PathBuf
+where
+    P: AsRef<Path>,
+    B: AsRef<Path>,
+{
+    try_to_relative(path, base).unwrap_or_else(|e| panic!("fatal: {}", e))
+}
+
+/// Lexically normalize a path: drop `.` and resolve `..` against the component before it
+/// - keeps the prefix (`C:`, `\\server\share` on Windows) and the root of absolute paths,
+///   `..` never goes above the root
+/// - keeps leading `..` of relative paths
+/// - return "." if nothing is left
+/// - not check existence, nor follow symlinks
+pub fn simplify_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut simplified = PathBuf::new();
+    let mut names = 0; // `Normal` components a `..` can remove
+    for component in path.as_ref().components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => simplified.push(component.as_os_str()),
+            Component::CurDir => {}
+            Component::ParentDir if names > 0 => {
+                simplified.pop();
+                names -= 1;
+            }
+            Component::ParentDir if simplified.has_root() => {}
+            Component::ParentDir => simplified.push(".."),
+            Component::Normal(name) => {
+                simplified.push(name);
+                names += 1;
+            }
         }
+    }
+    if simplified.as_os_str().is_empty() {
+        PathBuf::from(".")
     } else {
-        panic!(
-            "fatal: path {:?} cannot convert to relative based on {:?}",
-            path.as_ref(),
-            base.as_ref()
-        );
+        simplified
     }
 }
 
+/// `path` joined to the current directory if relative, then [simplify_path]ed
+fn to_absolute(path: impl AsRef<Path>) -> PathBuf {
+    simplify_path(cur_dir().join(path))
+}
+
 #[allow(dead_code)]
 /// Convert a path to relative path to the current directory
 /// - `path` must be absolute or relative (to current dir)
@@ -201,24 +345,46 @@ where
 /// List all files in the given dir and its sub_dir, except `.libra`
 /// - input `path`: absolute path or relative path to the current dir
 /// - output: to workdir path
+/// - symlinks to directories are listed as files (like git), not followed
 pub fn list_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    list_files_with_options(path, false)
+}
+
+/// Same as [list_files], but can descend into symlinked directories
+/// - `follow_symlinks`: if `true`, symlinked directories are listed recursively;
+///   each real directory is visited only once, so symlink cycles terminate
+pub fn list_files_with_options(path: &Path, follow_symlinks: bool) -> io::Result<Vec<PathBuf>> {
     let mut files = Vec::new();
-    if path.is_dir() {
-        if path.file_name().unwrap_or_default() == ROOT_DIR {
-            // ignore `.libra`
-            return Ok(files);
-        }
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                files.extend(list_files(&path)?);
-            } else {
-                files.push(to_workdir_path(&path));
-            }
+    let mut visited = HashSet::new();
+    list_files_recursive(path, follow_symlinks, &mut visited, &mut files)?;
+    Ok(files)
+}
+
+fn list_files_recursive(
+    path: &Path,
+    follow_symlinks: bool,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !path.is_dir() || path.file_name().unwrap_or_default() == root_dir_name().as_str() {
+        // ignore `.libra`
+        return Ok(());
+    }
+    if !visited.insert(path.canonicalize()?) {
+        // already listed, reached again through a symlink
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_symlink = entry.file_type()?.is_symlink();
+        if path.is_dir() && (!is_symlink || follow_symlinks) {
+            list_files_recursive(&path, follow_symlinks, visited, files)?;
+        } else {
+            files.push(to_workdir_path(&path));
         }
     }
-    Ok(files)
+    Ok(())
 }
 
 /// list all files in the working dir(include sub_dir)
@@ -229,19 +395,68 @@ pub fn list_workdir_files() -> io::Result<Vec<PathBuf>> {
 
 /// Integrate the input paths (relative, absolute, file, dir) to workdir paths
 /// - only include existing files
-pub fn integrate_pathspec(paths: &Vec<PathBuf>) -> HashSet<PathBuf> {
-    let mut workdir_paths = HashSet::new();
+/// - output: deduplicated, sorted in git's path order, see [sort_paths]
+pub fn integrate_pathspec(paths: &Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut workdir_paths = Vec::new();
     for path in paths {
         if path.is_dir() {
             let files = list_files(path).unwrap(); // to workdir
             workdir_paths.extend(files);
         } else {
-            workdir_paths.insert(path.to_workdir());
+            workdir_paths.push(path.to_workdir());
         }
     }
+    sort_paths(&mut workdir_paths);
     workdir_paths
 }
 
+/// Sort paths in git's order and remove duplicates
+/// - git compares the bytes of the whole path with `/` as separator, so `a.txt` < `a/b` < `a0`,
+///   which differs from the component-wise order of [PathBuf]
+pub fn sort_paths(paths: &mut Vec<PathBuf>) {
+    paths.sort_by_cached_key(|p| {
+        p.components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/")
+    });
+    paths.dedup();
+}
+
+/// Expand shell-style globs (e.g. `src/**/*.rs`) against the working tree, except `.libra`
+/// - `patterns`: relative to the working dir, literal paths (or invalid globs) pass through unchanged
+/// - output: to workdir path, the matches of each glob are sorted in git's path order
+pub fn expand_globs(patterns: Vec<String>) -> Vec<PathBuf> {
+    let workdir = working_dir();
+    let root_dir = root_dir_name();
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let glob = match Glob::new(&pattern) {
+            Ok(glob) if glob.variance().is_variant() => glob,
+            _ => {
+                paths.push(PathBuf::from(&pattern));
+                continue;
+            }
+        };
+        let mut matches: Vec<PathBuf> = glob
+            .walk(&workdir)
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| {
+                entry
+                    .path()
+                    .strip_prefix(&workdir)
+                    .ok()
+                    .map(Path::to_path_buf)
+            })
+            .filter(|path| !path.starts_with(&root_dir))
+            .collect();
+        sort_paths(&mut matches);
+        paths.extend(matches);
+    }
+    paths
+}
+
 /// write content to file
 /// - create parent directory if not exist
 pub fn write_file(content: &[u8], file: &PathBuf) -> io::Result<()> {
@@ -291,6 +506,7 @@ pub fn path_to_string(path: &Path) -> String {
 }
 
 /// extend hash, panic if not valid or ambiguous
+/// - only hashes, use [rev_parse](crate::internal::rev_parse::rev_parse) for `HEAD~n` & refs
 pub fn get_commit_base(commit_base: &str) -> Result<SHA1, String> {
     let storage = objects_storage();
 
@@ -363,12 +579,192 @@ mod test {
         assert!(is_sub_path("src/main.rs", "src/"));
         assert!(is_sub_path("src/main.rs", "src/main.rs"));
         assert!(is_sub_path("src/main.rs", "."));
+        assert!(is_sub_path("lib/../src/./main.rs", "src"));
+        assert!(!is_sub_path("src/../main.rs", "src"));
     }
 
     #[test]
     fn test_to_relative() {
         assert_eq!(to_relative("src/main.rs", "src"), PathBuf::from("main.rs"));
         assert_eq!(to_relative(".", "src"), PathBuf::from(".."));
+        assert_eq!(
+            to_relative("src/./lib/../main.rs", "docs/.."),
+            PathBuf::from("src/main.rs")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_try_to_relative_unrelated() {
+        // absolute paths on unix always share the root
+        assert_eq!(
+            try_to_relative("/tmp/a", "/usr/lib"),
+            Ok(PathBuf::from("../../tmp/a"))
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_try_to_relative_unrelated() {
+        let err = try_to_relative(r"C:\repo\a.txt", r"D:\work").unwrap_err();
+        assert_eq!(err.path, PathBuf::from(r"C:\repo\a.txt"));
+        assert_eq!(err.base, PathBuf::from(r"D:\work"));
+        assert!(err.to_string().contains("cannot convert to relative"));
+    }
+
+    #[test]
+    fn test_simplify_path() {
+        assert_eq!(simplify_path("a/./b/../c"), PathBuf::from("a/c"));
+        assert_eq!(simplify_path("../a/b/../.."), PathBuf::from(".."));
+        assert_eq!(simplify_path("a/.."), PathBuf::from("."));
+        assert_eq!(simplify_path(""), PathBuf::from("."));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_simplify_absolute_path() {
+        assert_eq!(simplify_path("/a/../../b/./c"), PathBuf::from("/b/c"));
+        assert_eq!(simplify_path("/.."), PathBuf::from("/"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_simplify_windows_path() {
+        assert_eq!(simplify_path(r"C:\a\..\b"), PathBuf::from(r"C:\b"));
+        assert_eq!(simplify_path(r"C:\.."), PathBuf::from(r"C:\"));
+        assert_eq!(
+            simplify_path(r"\\server\share\a\.\..\b"),
+            PathBuf::from(r"\\server\share\b")
+        );
+        // the prefix survives, so the result is still under the same absolute base
+        let base = PathBuf::from(r"C:\repo");
+        let path = simplify_path(r"C:\repo\src\..\lib.rs");
+        assert_eq!(path.strip_prefix(&base).unwrap(), Path::new("lib.rs"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_working_dir_string_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        test::setup_with_new_libra().await;
+        let test_dir = cur_dir();
+        let repo = test_dir.join(OsStr::from_bytes(b"non_utf8_\xff"));
+        fs::create_dir_all(repo.join(ROOT_DIR)).unwrap();
+        env::set_current_dir(&repo).unwrap();
+
+        let lossy = working_dir_string_lossy();
+        let strict = std::panic::catch_unwind(working_dir_string);
+        env::set_current_dir(&test_dir).unwrap();
+        fs::remove_dir_all(&repo).unwrap();
+
+        assert!(lossy.ends_with("non_utf8_\u{FFFD}"), "{}", lossy);
+        assert!(strict.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_repo_status() {
+        test::setup_with_new_libra().await;
+        let test_dir = cur_dir();
+        fs::create_dir_all(test_dir.join("repo_status/sub")).unwrap();
+        env::set_current_dir(test_dir.join("repo_status/sub")).unwrap();
+        let inside = repo_status();
+
+        let outside_dir = tempfile::tempdir().unwrap();
+        env::set_current_dir(outside_dir.path()).unwrap();
+        let outside = repo_status();
+        env::set_current_dir(&test_dir).unwrap();
+
+        assert_eq!(inside, RepoStatus::Present(test_dir.join(ROOT_DIR)));
+        assert_eq!(outside, RepoStatus::Absent);
+    }
+
+    #[tokio::test]
+    async fn test_root_dir_override() {
+        test::setup_clean_testing_env();
+        let test_dir = cur_dir();
+        let repo = test_dir.join("root_dir_override");
+        fs::create_dir_all(repo.join("src")).unwrap();
+        env::set_current_dir(&repo).unwrap();
+
+        env::set_var(ROOT_DIR_ENV, ".libra_custom");
+        crate::command::init::init().await.unwrap();
+        env::set_current_dir(repo.join("src")).unwrap();
+        let storage = try_get_storage_path();
+        let workdir = working_dir();
+        env::remove_var(ROOT_DIR_ENV);
+        env::set_current_dir(&test_dir).unwrap();
+
+        assert!(repo.join(".libra_custom").join(DATABASE).exists());
+        assert!(!repo.join(ROOT_DIR).exists());
+        assert_eq!(storage.unwrap(), repo.join(".libra_custom"));
+        assert_eq!(workdir, repo);
+        assert_eq!(root_dir_name(), ROOT_DIR);
+        fs::remove_dir_all(&repo).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_integrate_pathspec_order() {
+        test::setup_with_new_libra().await;
+        let dir = PathBuf::from("integrate_pathspec");
+        for file in ["b.txt", "a0", "a.txt", "a/b.txt", "a-b.txt"] {
+            test::ensure_file(dir.join(file), None);
+        }
+
+        let paths = integrate_pathspec(&vec![
+            dir.join("a/b.txt"),
+            dir.clone(),
+            dir.join("b.txt"),
+        ]);
+        let expected: Vec<PathBuf> = ["a-b.txt", "a.txt", "a/b.txt", "a0", "b.txt"]
+            .iter()
+            .map(|f| dir.join(f))
+            .collect();
+        assert_eq!(paths, expected);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_list_files_symlink_loop() {
+        test::setup_with_new_libra().await;
+        let dir = PathBuf::from("list_files_symlink_loop");
+        test::ensure_file(dir.join("sub/file.txt"), None);
+        let link = dir.join("sub/loop");
+        if fs::symlink_metadata(&link).is_err() {
+            std::os::unix::fs::symlink("..", &link).unwrap(); // sub/loop -> list_files_symlink_loop
+        }
+
+        let mut files = list_files(&dir).unwrap();
+        files.sort();
+        assert_eq!(files, vec![dir.join("sub/file.txt"), dir.join("sub/loop")]);
+
+        let files = list_files_with_options(&dir, true).unwrap();
+        assert_eq!(files, vec![dir.join("sub/file.txt")]);
+    }
+
+    #[tokio::test]
+    async fn test_expand_globs() {
+        test::setup_with_new_libra().await;
+        let dir = PathBuf::from("expand_globs");
+        for file in ["top.rs", "README.md", "src/main.rs", "src/lib/mod.rs"] {
+            test::ensure_file(dir.join(file), None);
+        }
+
+        assert_eq!(
+            expand_globs(vec!["expand_globs/*.rs".to_string()]),
+            vec![dir.join("top.rs")]
+        );
+        assert_eq!(
+            expand_globs(vec!["expand_globs/src/**/*".to_string()]),
+            vec![dir.join("src/lib/mod.rs"), dir.join("src/main.rs")]
+        );
+        assert_eq!(
+            expand_globs(vec!["expand_globs/README.md".to_string()]),
+            vec![dir.join("README.md")]
+        );
+        // `.libra` is never matched
+        assert!(expand_globs(vec!["**/libra.db".to_string()]).is_empty());
     }
 
     #[tokio::test]
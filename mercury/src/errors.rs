@@ -38,6 +38,9 @@ pub enum GitError {
     #[error("The `{0}` is not a valid pack header.")]
     InvalidPackHeader(String),
 
+    #[error("The object is too large: {0}")]
+    ObjectTooLarge(String),
+
     #[error("The `{0}` is not a valid index file.")]
     InvalidIndexFile(String),
 
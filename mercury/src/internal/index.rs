@@ -351,6 +351,61 @@ impl Index {
         self.entries.insert((entry.name.clone(), entry.flags.stage), entry);
     }
 
+    /// Stage `path` (to workdir path) at stage 0 with the blob `hash`, replacing the existing
+    /// entry & any merge conflict stages of the path. Only the in-memory index changes,
+    /// use [Index::save] to write it.
+    /// - `mode`: e.g. `0o100644`, `0o100755`, `0o120000`
+    /// - `stat`: metadata of the file, got by [fs::symlink_metadata]. Without it, times & size
+    ///   are zero so the file is treated as modified until staged again with a stat
+    /// - return the replaced stage 0 entry, if any
+    pub fn stage(&mut self, path: &str, hash: SHA1, mode: u32, stat: Option<&fs::Metadata>) -> Option<IndexEntry> {
+        let mut entry = match stat {
+            Some(meta) => IndexEntry::new(meta, hash, path.to_string()),
+            None => IndexEntry::new_from_blob(path.to_string(), hash, 0),
+        };
+        entry.mode = mode;
+        for stage in 1..=3 {
+            self.remove(path, stage);
+        }
+        self.entries.insert((path.to_string(), 0), entry)
+    }
+
+    /// Remove `path` from the index at all stages, return whether it was tracked
+    pub fn unstage(&mut self, path: &str) -> bool {
+        (0..=3).fold(false, |removed, stage| self.remove(path, stage).is_some() || removed)
+    }
+
+    /// Record an unresolved merge conflict of `path`, replacing its stage 0 entry.
+    /// Each side is put at its stage: `base` at 1, `ours` at 2, `theirs` at 3,
+    /// a side is `None` if the file doesn't exist there (e.g. added by both, deleted by them).
+    /// - resolve it by [Index::stage] (or [Index::unstage] to delete the file)
+    pub fn add_conflict(&mut self, path: &str, base: Option<SHA1>, ours: Option<SHA1>, theirs: Option<SHA1>) {
+        self.unstage(path);
+        for (stage, hash) in [(1, base), (2, ours), (3, theirs)] {
+            if let Some(hash) = hash {
+                let mut entry = IndexEntry::new_from_blob(path.to_string(), hash, 0);
+                entry.flags.stage = stage;
+                self.add(entry);
+            }
+        }
+    }
+
+    /// Paths with unresolved conflicts (entries at stage 1/2/3), sorted & deduplicated
+    pub fn conflicted_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|(_, stage)| *stage != 0)
+            .map(|(name, _)| name.clone())
+            .collect();
+        paths.dedup(); // keys are sorted by name first
+        paths
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.keys().any(|(_, stage)| *stage != 0)
+    }
+
     pub fn remove(&mut self, name: &str, stage: u8) -> Option<IndexEntry> {
         self.entries.remove(&(name.to_string(), stage))
     }
@@ -435,8 +490,16 @@ impl Index {
     }
 
     /// saved to index file
+    /// - written to `index.lock` then renamed, so readers never see a partial index
     pub fn save(&self, index_file: impl AsRef<Path>) -> Result<(), GitError> {
-        self.to_file(index_file)
+        let index_file = index_file.as_ref();
+        let lock_file = index_file.with_extension("lock");
+        if let Err(e) = self.to_file(&lock_file) {
+            let _ = fs::remove_file(&lock_file);
+            return Err(e);
+        }
+        fs::rename(&lock_file, index_file)?;
+        Ok(())
     }
 }
 
@@ -479,6 +542,58 @@ mod tests {
         assert_eq!(index.size(), new_index.size());
     }
 
+    #[test]
+    fn test_stage_and_unstage() {
+        let mut index = Index::new();
+        let (v1, v2) = (SHA1::new(b"v1"), SHA1::new(b"v2"));
+
+        // new entry, with stat
+        let meta = fs::symlink_metadata("Cargo.toml").unwrap();
+        assert!(index.stage("src/main.rs", v1, 0o100644, Some(&meta)).is_none());
+        let entry = index.get("src/main.rs", 0).unwrap();
+        assert_eq!(entry.hash, v1);
+        assert_eq!(entry.size, meta.len() as u32);
+
+        // replace, also resolves conflict stages
+        let mut theirs = IndexEntry::new_from_blob("src/main.rs".to_string(), v1, 2);
+        theirs.flags.stage = 3;
+        index.add(theirs);
+        let replaced = index.stage("src/main.rs", v2, 0o100755, None).unwrap();
+        assert_eq!(replaced.hash, v1);
+        assert_eq!(index.size(), 1);
+        let entry = index.get("src/main.rs", 0).unwrap();
+        assert_eq!((entry.hash, entry.mode, entry.size), (v2, 0o100755, 0));
+
+        assert!(index.unstage("src/main.rs"));
+        assert!(!index.unstage("src/main.rs"));
+        assert_eq!(index.size(), 0);
+    }
+
+    #[test]
+    fn test_conflict_stages() {
+        let mut index = Index::new();
+        let (base, ours, theirs) = (SHA1::new(b"base"), SHA1::new(b"ours"), SHA1::new(b"theirs"));
+        index.stage("a.txt", base, 0o100644, None);
+        index.stage("b.txt", base, 0o100644, None);
+
+        index.add_conflict("a.txt", Some(base), Some(ours), Some(theirs));
+        // added by both, no base
+        index.add_conflict("c.txt", None, Some(ours), Some(theirs));
+        assert!(index.has_conflicts());
+        assert_eq!(index.conflicted_paths(), vec!["a.txt", "c.txt"]);
+        assert!(!index.tracked("a.txt", 0));
+        assert_eq!(index.get_hash("a.txt", 1), Some(base));
+        assert_eq!(index.get_hash("a.txt", 2), Some(ours));
+        assert_eq!(index.get("a.txt", 3).unwrap().flags.stage, 3);
+        assert!(!index.tracked("c.txt", 1));
+
+        index.stage("a.txt", ours, 0o100644, None);
+        assert_eq!(index.conflicted_paths(), vec!["c.txt"]);
+        index.unstage("c.txt");
+        assert!(!index.has_conflicts());
+        assert_eq!(index.tracked_entries(0).len(), 2);
+    }
+
     #[test]
     fn test_index_entry_create() {
         let file = Path::new("Cargo.toml"); // use as a normal file
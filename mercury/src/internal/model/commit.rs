@@ -8,41 +8,63 @@ use crate::{
     internal::{object::{commit::Commit, signature::Signature, ObjectTrait}, pack::entry::Entry},
 };
 
+/// The `content` of a message whose bytes aren't UTF-8 is their hex after this prefix, so the
+/// commit is read back with the same bytes & id. A UTF-8 message starting with the prefix is
+/// stored the same way, any `content` with it is hex then.
+const HEX_CONTENT_PREFIX: &str = "hex:";
+
+fn message_to_content(commit: &Commit) -> String {
+    let message = commit.message();
+    if commit.message_bytes() == message.as_bytes() && !message.starts_with(HEX_CONTENT_PREFIX) {
+        message.to_owned()
+    } else {
+        let encoded = hex::encode(commit.message_bytes());
+        format!("{}{}", HEX_CONTENT_PREFIX, encoded)
+    }
+}
+
+fn content_to_message(content: String) -> Vec<u8> {
+    match content.strip_prefix(HEX_CONTENT_PREFIX) {
+        Some(encoded) => hex::decode(encoded).expect("invalid hex content of a commit"),
+        None => content.into_bytes(),
+    }
+}
+
 impl From<mega_commit::Model> for Commit {
     fn from(value: mega_commit::Model) -> Self {
-        Commit {
-            id: SHA1::from_str(&value.commit_id).unwrap(),
-            tree_id: SHA1::from_str(&value.tree).unwrap(),
-            parent_commit_ids: value
+        Commit::from_parts(
+            SHA1::from_str(&value.commit_id).unwrap(),
+            SHA1::from_str(&value.tree).unwrap(),
+            value
                 .parents_id
                 .as_array()
                 .unwrap()
                 .iter()
                 .map(|id| SHA1::from_str(id.as_str().unwrap()).unwrap())
                 .collect(),
-            author: Signature::from_data(value.author.unwrap().into()).unwrap(),
-            committer: Signature::from_data(value.committer.unwrap().into()).unwrap(),
-            message: value.content.unwrap(),
-        }
+            Signature::from_data(value.author.unwrap().into()).unwrap(),
+            Signature::from_data(value.committer.unwrap().into()).unwrap(),
+            content_to_message(value.content.unwrap()),
+        )
     }
 }
 
 impl From<git_commit::Model> for Commit {
     fn from(value: git_commit::Model) -> Self {
-        Commit {
-            id: SHA1::from_str(&value.commit_id).unwrap(),
-            tree_id: SHA1::from_str(&value.tree).unwrap(),
-            parent_commit_ids: value
+        Commit::from_parts(
+            SHA1::from_str(&value.commit_id).unwrap(),
+            SHA1::from_str(&value.tree).unwrap(),
+            value
                 .parents_id
                 .as_array()
                 .unwrap()
                 .iter()
                 .map(|id| SHA1::from_str(id.as_str().unwrap()).unwrap())
                 .collect(),
-            author: Signature::from_data(value.author.unwrap().into()).unwrap(),
-            committer: Signature::from_data(value.committer.unwrap().into()).unwrap(),
-            message: value.content.unwrap(),
-        }
+            Signature::from_data(value.author.unwrap().into()).unwrap(),
+            Signature::from_data(value.committer.unwrap().into()).unwrap(),
+            content_to_message(value.content.unwrap()),
+        )
     }
 }
 
@@ -61,7 +83,7 @@ impl From<Commit> for mega_commit::Model {
             committer: Some(
                 String::from_utf8_lossy(&value.committer.to_data().unwrap()).to_string(),
             ),
-            content: Some(value.message.clone()),
+            content: Some(message_to_content(&value)),
             created_at: chrono::Utc::now().naive_utc(),
         }
     }
@@ -83,7 +105,7 @@ impl From<Commit> for git_commit::Model {
             committer: Some(
                 String::from_utf8_lossy(&value.committer.to_data().unwrap()).to_string(),
             ),
-            content: Some(value.message.clone()),
+            content: Some(message_to_content(&value)),
             created_at: chrono::Utc::now().naive_utc(),
         }
     }
@@ -94,3 +116,39 @@ impl From<Entry> for Commit {
         Commit::from_bytes(&value.data, value.hash).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature(kind: &str) -> Signature {
+        let data = format!("{} mega <admin@mega.org> 1733270400 +0800", kind);
+        Signature::from_data(data.into_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_model_keeps_message_bytes() {
+        let latin1 = Commit::new_with_encoding(
+            signature("author"),
+            signature("committer"),
+            SHA1::default(),
+            vec![],
+            "ISO-8859-1",
+            b"\ncaf\xe9\n",
+        );
+        let prefixed = Commit::from_tree_id(SHA1::default(), vec![], "hex:00");
+        let plain = Commit::from_tree_id(SHA1::default(), vec![], "\ncafé\n");
+        for commit in [latin1, prefixed, plain] {
+            let data = commit.to_data().unwrap();
+            let mega: Commit = mega_commit::Model::from(commit.clone()).into();
+            assert_eq!(mega.to_data().unwrap(), data);
+            assert_eq!(mega.message(), commit.message());
+            let git: Commit = git_commit::Model::from(commit).into();
+            assert_eq!(git.to_data().unwrap(), data);
+        }
+
+        // readable as is unless it can't be
+        let plain = mega_commit::Model::from(Commit::from_tree_id(SHA1::default(), vec![], "\nok"));
+        assert_eq!(plain.content.as_deref(), Some("\nok"));
+    }
+}
@@ -33,6 +33,10 @@ use crate::internal::object::ObjectType;
 ///   history of a repository with a single commit object at its root.
 /// - The author and committer fields contain the name, email address, timestamp and timezone.
 /// - The message field contains the commit message, which maybe include signed or DCO.
+/// - A message in another encoding than UTF-8 starts with an `encoding` header, see [Commit::encoding].
+///   If its bytes aren't valid UTF-8, they are kept in `raw_message` and `message` is their lossy
+///   decoding, so the commit is written back with the same bytes and id. Both are private, set
+///   together, and read through [Commit::message] & [Commit::message_bytes].
 #[derive(Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Commit {
     pub id: SHA1,
@@ -40,7 +44,9 @@ pub struct Commit {
     pub parent_commit_ids: Vec<SHA1>,
     pub author: Signature,
     pub committer: Signature,
-    pub message: String,
+    message: String,
+    #[serde(default)]
+    raw_message: Option<Vec<u8>>,
 }
 impl PartialEq for Commit {
     fn eq(&self, other: &Self) -> bool {
@@ -75,12 +81,80 @@ impl Commit {
             author,
             committer,
             message: message.to_string(),
+            raw_message: None,
         };
         let hash = SHA1::from_type_and_data(ObjectType::Commit, &commit.to_data().unwrap());
         commit.id = hash;
         commit
     }
 
+    /// Create a commit with a message in `encoding`, written as an `encoding` header before it.
+    /// - `message` is the bytes after the header, starting with the blank line like [Commit::new]
+    pub fn new_with_encoding(
+        author: Signature,
+        committer: Signature,
+        tree_id: SHA1,
+        parent_commit_ids: Vec<SHA1>,
+        encoding: &str,
+        message: &[u8],
+    ) -> Commit {
+        let mut raw = format!("encoding {}\n", encoding).into_bytes();
+        raw.extend(message);
+        let mut commit = Commit::new(author, committer, tree_id, parent_commit_ids, "");
+        (commit.message, commit.raw_message) = decode_message(raw);
+        commit.id = SHA1::from_type_and_data(ObjectType::Commit, &commit.to_data().unwrap());
+        commit
+    }
+
+    /// A commit read back by its `id`, e.g. from the database, `message` are the bytes after the
+    /// committer as [Commit::message_bytes] returns them
+    pub(crate) fn from_parts(
+        id: SHA1,
+        tree_id: SHA1,
+        parent_commit_ids: Vec<SHA1>,
+        author: Signature,
+        committer: Signature,
+        message: Vec<u8>,
+    ) -> Commit {
+        let (message, raw_message) = decode_message(message);
+        Commit {
+            id,
+            tree_id,
+            parent_commit_ids,
+            author,
+            committer,
+            message,
+            raw_message,
+        }
+    }
+
+    /// The message with the headers after the committer, lossy if its bytes aren't UTF-8
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The encoding declared by the `encoding` header, `None` for the default UTF-8.
+    /// Git writes the header right after the committer, so it's the first line of `message`.
+    pub fn encoding(&self) -> Option<&str> {
+        let first_line = self.message.lines().next()?;
+        first_line.strip_prefix("encoding ").map(str::trim)
+    }
+
+    /// `message` without the `encoding` header, starting with the signature or the blank line
+    pub fn message_without_encoding(&self) -> &str {
+        match self.encoding() {
+            Some(_) => self.message.split_once('\n').map_or("", |(_, rest)| rest),
+            None => &self.message,
+        }
+    }
+
+    /// The bytes of the message as stored, see [Commit::raw_message]
+    pub fn message_bytes(&self) -> &[u8] {
+        self.raw_message
+            .as_deref()
+            .unwrap_or(self.message.as_bytes())
+    }
+
     pub fn from_tree_id(tree_id: SHA1, parent_commit_ids: Vec<SHA1>, message: &str) -> Commit {
         let author = Signature::from_data(
             format!(
@@ -104,8 +178,9 @@ impl Commit {
     }
 
     pub fn format_message(&self) -> String {
+        let message = self.message_without_encoding();
         let mut has_signature = false;
-        for line in self.message.lines() {
+        for line in message.lines() {
             if has_signature && !line.trim().is_empty() {
                 return line.to_owned();
             }
@@ -114,12 +189,23 @@ impl Commit {
             }
         }
         // does not have pgp, find first line has data
-        for line in self.message.lines() {
+        for line in message.lines() {
             if !line.trim().is_empty() {
                 return line.to_owned();
             }
         }
-        self.message.clone()
+        message.to_owned()
+    }
+}
+
+/// `(message, raw_message)` of the bytes of a message, the raw bytes are only kept if not UTF-8
+fn decode_message(bytes: Vec<u8>) -> (String, Option<Vec<u8>>) {
+    match String::from_utf8(bytes) {
+        Ok(message) => (message, None),
+        Err(e) => {
+            let bytes = e.into_bytes();
+            (String::from_utf8_lossy(&bytes).into_owned(), Some(bytes))
+        }
     }
 }
 
@@ -165,10 +251,9 @@ impl ObjectTrait for Commit {
         let committer =
             Signature::from_data(commit[..commit.find_byte(0x0a).unwrap()].to_vec()).unwrap();
 
-        // The rest is the message
-        let message = unsafe {
-            String::from_utf8_unchecked(commit[commit.find_byte(0x0a).unwrap() + 1..].to_vec())
-        };
+        // The rest is the message, with the headers after the committer
+        let (message, raw_message) =
+            decode_message(commit[commit.find_byte(0x0a).unwrap() + 1..].to_vec());
 
         Ok(Commit {
             id: hash,
@@ -177,6 +262,7 @@ impl ObjectTrait for Commit {
             author,
             committer,
             message,
+            raw_message,
         })
     }
 
@@ -209,8 +295,58 @@ impl ObjectTrait for Commit {
         // Important! or Git Server can't parse & reply: unpack-objects abnormal exit
         // We can move [0x0a] to message instead here.
         // data.extend(&[0x0a]);
-        data.extend(self.message.as_bytes());
+        data.extend(self.message_bytes());
 
         Ok(data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature(kind: &str) -> Signature {
+        let data = format!("{} mega <admin@mega.org> 1733270400 +0800", kind);
+        Signature::from_data(data.into_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        // "café" in ISO-8859-1, not valid UTF-8
+        let message = b"\ncaf\xe9\n";
+        let commit = Commit::new_with_encoding(
+            signature("author"),
+            signature("committer"),
+            SHA1::default(),
+            vec![],
+            "ISO-8859-1",
+            message,
+        );
+        let data = commit.to_data().unwrap();
+        assert!(data.ends_with(b"\nencoding ISO-8859-1\n\ncaf\xe9\n"));
+        assert_eq!(
+            commit.id,
+            SHA1::from_type_and_data(ObjectType::Commit, &data)
+        );
+
+        let read = Commit::from_bytes(&data, commit.id).unwrap();
+        assert_eq!(read.encoding(), Some("ISO-8859-1"));
+        assert_eq!(read.message_bytes(), b"encoding ISO-8859-1\n\ncaf\xe9\n");
+        assert_eq!(read.to_data().unwrap(), data);
+        assert_eq!(read.message_without_encoding(), "\ncaf\u{FFFD}\n");
+        assert_eq!(read.format_message(), "caf\u{FFFD}");
+
+        // UTF-8 is the default, without header
+        let utf8 = Commit::new(
+            signature("author"),
+            signature("committer"),
+            SHA1::default(),
+            vec![],
+            "\ncafé\n",
+        );
+        let read = Commit::from_bytes(&utf8.to_data().unwrap(), utf8.id).unwrap();
+        assert_eq!(read.encoding(), None);
+        assert_eq!(read.raw_message, None);
+        assert_eq!(read.format_message(), "café");
+    }
+}
@@ -178,32 +178,24 @@ impl Signature {
         Ok(sign)
     }
 
-/// Represents a signature with author, email, timestamp, and timezone information.
-pub fn new(sign_type: SignatureType, author: String, email: String) -> Signature {
-    // Get the current local time (with timezone)
-    let local_time = chrono::Local::now();
-    
-    // Get the offset from UTC in minutes (local time - UTC time)
-    let offset = local_time.offset().fix().local_minus_utc();
-    
-    // Calculate the hours part of the offset (divide by 3600 to convert from seconds to hours)
-    let hours = offset / 60 / 60;
-    
-    // Calculate the minutes part of the offset (remaining minutes after dividing by 60)
-    let minutes = offset / 60 % 60;
-
-    // Format the offset as a string (e.g., "+0800", "-0300", etc.)
-    let offset_str = format!("{:+03}{:02}", hours, minutes);
-    
-    // Return the Signature struct with the provided information
-    Signature {
-        signature_type: sign_type,    // The type of signature (e.g., commit, merge)
-        name: author,                 // The author's name
-        email,                        // The author's email
-        timestamp: chrono::Utc::now().timestamp() as usize, // The timestamp of the signature (seconds since Unix epoch)
-        timezone: offset_str,         // The timezone offset (e.g., "+0800")
+    /// A signature of `author` at the current time, in the local timezone
+    pub fn new(sign_type: SignatureType, author: String, email: String) -> Signature {
+        let local_time = chrono::Local::now();
+        Signature {
+            signature_type: sign_type,
+            name: author,
+            email,
+            timestamp: local_time.timestamp() as usize,
+            timezone: format_timezone(local_time.offset().fix().local_minus_utc()),
+        }
     }
 }
+
+/// Format an offset from UTC in seconds like git, `+HHMM` or `-HHMM`
+fn format_timezone(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.unsigned_abs() / 60;
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
 }
 
 #[cfg(test)]
@@ -212,7 +204,7 @@ mod tests {
 
     use chrono::DateTime;
 
-    use crate::internal::object::signature::{Signature, SignatureType};
+    use crate::internal::object::signature::{format_timezone, Signature, SignatureType};
 
     #[test]
     fn test_signature_type_from_str() {
@@ -294,9 +286,21 @@ mod tests {
         assert_eq!(sign.signature_type, SignatureType::Author);
         assert_eq!(sign.name, "MEGA");
         assert_eq!(sign.email, "admin@mega.com");
-        assert_eq!(sign.timezone, "+0800");
-        
+        // the timezone of the machine running the test
+        assert_eq!(sign.timezone, chrono::Local::now().format("%z").to_string());
+
         let naive_datetime = DateTime::from_timestamp(sign.timestamp as i64, 0).unwrap();
         println!("Formatted DateTime: {}", naive_datetime.naive_local());
     }
+
+    #[test]
+    fn test_format_timezone() {
+        assert_eq!(format_timezone(8 * 3600), "+0800");
+        assert_eq!(format_timezone(0), "+0000");
+        assert_eq!(format_timezone(-5 * 3600), "-0500");
+        // half & quarter hours, west of UTC
+        assert_eq!(format_timezone(-(3 * 3600 + 30 * 60)), "-0330");
+        assert_eq!(format_timezone(-30 * 60), "-0030");
+        assert_eq!(format_timezone(5 * 3600 + 45 * 60), "+0545");
+    }
 }
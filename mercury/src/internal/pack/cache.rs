@@ -41,8 +41,14 @@ pub struct Caches {
     // and `CacheObjects` will be killed by OS after Process ends abnormally
     // Solution: use `mimalloc`
     lru_cache: Mutex<LruCache<SHA1, ArcWrapper<CacheObject>>>,
+    // objects kept in memory whatever the LRU evicts, with the count of `pin` not yet unpinned
+    pinned: DashMap<SHA1, (Arc<CacheObject>, usize)>,
     mem_size: Option<usize>,
     tmp_path: PathBuf,
+    // whether the objects evicted from the lru are written to `tmp_path`, or dropped
+    spill: bool,
+    // whether the files written to `tmp_path` are removed on drop
+    clean_tmp: bool,
     path_prefixes: [Once; 256],
     pool: Arc<ThreadPool>,
     complete_signal: Arc<AtomicBool>,
@@ -51,6 +57,9 @@ pub struct Caches {
 impl Caches {
     /// only get object from memory, not from tmp file
     fn try_get(&self, hash: SHA1) -> Option<Arc<CacheObject>> {
+        if let Some(pinned) = self.pinned.get(&hash) {
+            return Some(pinned.0.clone());
+        }
         let mut map = self.lru_cache.lock().unwrap();
         map.get(&hash).map(|x| x.data.clone())
     }
@@ -107,6 +116,39 @@ impl Caches {
         Ok(obj)
     }
 
+    /// Keep the object in memory until [Caches::unpin], while it's the base of a delta being
+    /// rebuilt, so the deltas coming meanwhile don't read it back from its tmp file after the LRU
+    /// evicted it.
+    /// - pins are counted, the object is released after as many `unpin`
+    /// - pinned objects are not limited by `mem_size`
+    pub fn pin(&self, obj: &Arc<CacheObject>) {
+        let hash = obj.base_object_hash().unwrap();
+        self.pinned.entry(hash).or_insert_with(|| (obj.clone(), 0)).1 += 1;
+    }
+
+    pub fn unpin(&self, hash: SHA1) {
+        self.pinned.remove_if_mut(&hash, |_, (_, count)| {
+            *count -= 1;
+            *count == 0
+        });
+    }
+
+    /// memory used by the objects in the lru cache, kept under `mem_size` by evicting
+    /// the least recently used ones to tmp files
+    pub fn current_mem(&self) -> usize {
+        self.lru_cache.lock().unwrap().current_size()
+    }
+
+    /// The base of the delta object `delta_obj`, by its offset for an offset delta or its hash
+    /// for a ref delta, to [apply](crate::internal::pack::delta::apply_delta) the delta to
+    /// - `None` if `delta_obj` isn't a delta, or its base isn't cached
+    pub fn delta_base(&self, delta_obj: &CacheObject) -> Option<Arc<CacheObject>> {
+        match delta_obj.offset_delta() {
+            Some(offset) => self.get_by_offset(offset),
+            None => delta_obj.hash_delta().and_then(|hash| self.get_by_hash(hash)),
+        }
+    }
+
     pub fn queued_tasks(&self) -> usize {
         self.pool.queued_count()
     }
@@ -117,6 +159,12 @@ impl Caches {
         + self.hash_set.capacity() * (std::mem::size_of::<SHA1>())
     }
 
+    /// Whether the tmp files are removed when the cache is dropped, `true` by default
+    pub fn with_clean_tmp(mut self, clean_tmp: bool) -> Self {
+        self.clean_tmp = clean_tmp;
+        self
+    }
+
     /// remove the tmp dir
     pub fn remove_tmp_dir(&self) {
         time_it!("Remove tmp dir", {
@@ -129,13 +177,16 @@ impl Caches {
 
 impl _Cache for Caches {
     /// @param size: the size of the memory lru cache. **None means no limit**
-    /// @param tmp_path: the path to store the cache object in the tmp file
+    /// @param tmp_path: the path to store the cache object in the tmp file.
+    /// An empty path disables the tmp files: the objects evicted from memory are dropped, and
+    /// `get_by_*` returns `None` for them
     fn new(mem_size: Option<usize>, tmp_path: PathBuf, thread_num: usize) -> Self
     where
         Self: Sized,
     {
         // `None` means no limit, so no need to create the tmp dir
-        if mem_size.is_some() {
+        let spill = mem_size.is_some() && !tmp_path.as_os_str().is_empty();
+        if spill {
             fs::create_dir_all(&tmp_path).unwrap();
         }
 
@@ -143,8 +194,11 @@ impl _Cache for Caches {
             map_offset: DashMap::new(),
             hash_set: DashSet::new(),
             lru_cache: Mutex::new(LruCache::new(mem_size.unwrap_or(usize::MAX))),
+            pinned: DashMap::new(),
             mem_size,
             tmp_path,
+            spill,
+            clean_tmp: true,
             path_prefixes: [const { Once::new() }; 256],
             pool: Arc::new(ThreadPool::new(thread_num)),
             complete_signal: Arc::new(AtomicBool::new(false)),
@@ -165,7 +219,7 @@ impl _Cache for Caches {
                 self.complete_signal.clone(),
                 Some(self.pool.clone()),
             );
-            if self.mem_size.is_some() {
+            if self.spill {
                 a_obj.set_store_path(self.generate_temp_path(&self.tmp_path, hash));
             }
             let _ = map.insert(hash, a_obj);
@@ -193,6 +247,9 @@ impl _Cache for Caches {
                     if self.mem_size.is_none() {
                         panic!("should not be here when mem_size is not set")
                     }
+                    if !self.spill {
+                        return None; // evicted & dropped
+                    }
                     match self.get_fallback(hash) {
                         Ok(x) => Some(x),
                         Err(_) => None,
@@ -216,6 +273,7 @@ impl _Cache for Caches {
             self.complete_signal.store(true, Ordering::Release);
             self.pool.join();
             self.lru_cache.lock().unwrap().clear();
+            self.pinned.clear();
             self.hash_set.clear();
             self.hash_set.shrink_to_fit();
             self.map_offset.clear();
@@ -228,12 +286,33 @@ impl _Cache for Caches {
     }
 }
 
+impl Drop for Caches {
+    /// Remove the tmp files of the evicted objects, with the tmp dir if nothing else is in it,
+    /// unless they are kept by [Caches::with_clean_tmp]
+    fn drop(&mut self) {
+        if !self.spill || !self.clean_tmp {
+            return;
+        }
+        // stop writing the objects still evicted, e.g. when the lru is dropped after this
+        self.complete_signal.store(true, Ordering::Release);
+        self.pool.join();
+        for (prefix, created) in self.path_prefixes.iter().enumerate() {
+            if created.is_completed() {
+                let _ = fs::remove_dir_all(self.tmp_path.join(format!("{:02x}", prefix)));
+            }
+        }
+        let _ = fs::remove_dir(&self.tmp_path);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::env;
 
     use super::*;
     use crate::{hash::SHA1, internal::{object::types::ObjectType, pack::cache_object::CacheObjectInfo}};
+    use crate::internal::pack::delta::apply_delta;
+    use crate::internal::pack::utils;
 
     #[test]
     fn test_cache_single_thread() {
@@ -283,4 +362,167 @@ mod test {
         assert!(cache.try_get(c_hash).is_none());
         assert!(cache.get_by_hash(c_hash).is_some());
     }
+
+    #[test]
+    fn test_get_by_offset_and_hash() {
+        // no memory limit, so nothing goes to the tmp dir
+        let cache = Caches::new(None, PathBuf::from("unused"), 1);
+        let hash = SHA1::new(String::from("obj").as_bytes());
+        let obj = CacheObject {
+            info: CacheObjectInfo::BaseObject(ObjectType::Blob, hash),
+            data_decompressed: b"obj".to_vec(),
+            mem_recorder: None,
+            offset: 42,
+        };
+        let inserted = cache.insert(obj.offset, hash, obj.clone());
+        assert_eq!(cache.total_inserted(), 1);
+        assert_eq!(cache.get_hash(42), Some(hash));
+
+        let by_hash = cache.get_by_hash(hash).unwrap();
+        let by_offset = cache.get_by_offset(42).unwrap();
+        assert!(Arc::ptr_eq(&by_hash, &inserted));
+        assert!(Arc::ptr_eq(&by_offset, &inserted));
+        assert_eq!(by_offset.data_decompressed, b"obj");
+
+        assert!(cache.get_by_offset(0).is_none());
+        assert!(cache.get_by_hash(SHA1::new(String::from("other").as_bytes())).is_none());
+    }
+
+    #[test]
+    fn test_delta_base() {
+        let cache = Caches::new(None, PathBuf::from("unused"), 1);
+        let base_data = b"Hello, world!".to_vec();
+        let hash = utils::calculate_object_hash(ObjectType::Blob, &base_data);
+        let base = CacheObject {
+            info: CacheObjectInfo::BaseObject(ObjectType::Blob, hash),
+            data_decompressed: base_data,
+            mem_recorder: None,
+            offset: 12,
+        };
+        cache.insert(base.offset, hash, base);
+
+        // base size 13, result size 6: copy 5 bytes at 0, insert "!"
+        let delta_data = vec![13, 6, 0x90, 0x05, 0x01, b'!'];
+        let delta = |info| CacheObject {
+            info,
+            data_decompressed: delta_data.clone(),
+            mem_recorder: None,
+            offset: 100,
+        };
+        for info in [
+            CacheObjectInfo::OffsetDelta(12, 6),
+            CacheObjectInfo::HashDelta(hash, 6),
+        ] {
+            let delta_obj = delta(info);
+            let base = cache.delta_base(&delta_obj).unwrap();
+            assert_eq!(
+                apply_delta(&base.data_decompressed, &delta_obj.data_decompressed).unwrap(),
+                b"Hello!"
+            );
+        }
+        assert!(cache.delta_base(&delta(CacheObjectInfo::OffsetDelta(0, 6))).is_none());
+        let other = SHA1::new(String::from("other").as_bytes());
+        assert!(cache.delta_base(&delta(CacheObjectInfo::HashDelta(other, 6))).is_none());
+        let base = cache.get_by_hash(hash).unwrap();
+        assert!(cache.delta_base(&base).is_none());
+    }
+
+    #[test]
+    fn test_pinned_not_evicted() {
+        let source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
+        let cache = Caches::new(Some(2048), source.join("tests/.cache_tmp_pin"), 1);
+        let object = |name: &str, size: usize| {
+            let hash = SHA1::new(name.as_bytes());
+            let obj = CacheObject {
+                info: CacheObjectInfo::BaseObject(ObjectType::Blob, hash),
+                data_decompressed: vec![0; size],
+                mem_recorder: None,
+                offset: 0,
+            };
+            (hash, obj)
+        };
+        let (a_hash, a) = object("a", 1024);
+        let (b_hash, b) = object("b", 1636);
+
+        let pinned = cache.insert(0, a_hash, a);
+        cache.pin(&pinned);
+        cache.pin(&pinned);
+        // b evicts a from the lru, but the pinned a stays in memory
+        cache.insert(1, b_hash, b);
+        assert!(cache.current_mem() <= 2048);
+        assert!(Arc::ptr_eq(&cache.try_get(a_hash).unwrap(), &pinned));
+
+        // released after as many unpin as pin
+        cache.unpin(a_hash);
+        assert!(cache.try_get(a_hash).is_some());
+        cache.unpin(a_hash);
+        assert!(cache.try_get(a_hash).is_none());
+        assert!(cache.get_by_hash(a_hash).is_some());
+        cache.unpin(a_hash); // not pinned, no-op
+    }
+
+    #[test]
+    fn test_spill_to_tmp_path() {
+        let source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
+        let tmp_path = source.join("tests/.cache_tmp_spill");
+        let cache = Caches::new(Some(2048), tmp_path.clone(), 1);
+        let object = |name: &str, size: usize| {
+            let hash = SHA1::new(name.as_bytes());
+            let obj = CacheObject {
+                info: CacheObjectInfo::BaseObject(ObjectType::Blob, hash),
+                data_decompressed: vec![name.as_bytes()[0]; size],
+                mem_recorder: None,
+                offset: size,
+            };
+            (hash, obj)
+        };
+        let (a_hash, a) = object("a", 1024);
+        let (b_hash, b) = object("b", 1636);
+        cache.insert(a.offset, a_hash, a);
+        // b evicts a to its tmp file, read back on a miss
+        cache.insert(b.offset, b_hash, b);
+        assert!(cache.try_get(a_hash).is_none());
+        let reloaded = cache.get_by_offset(1024).unwrap();
+        assert_eq!(reloaded.base_object_hash(), Some(a_hash));
+        assert_eq!(reloaded.data_decompressed, vec![b'a'; 1024]);
+        let spilled = tmp_path
+            .join(&a_hash._to_string()[..2])
+            .join(a_hash._to_string());
+        assert!(spilled.exists());
+
+        drop(cache);
+        assert!(!tmp_path.exists());
+
+        // kept as asked
+        let cache = Caches::new(Some(2048), tmp_path.clone(), 1).with_clean_tmp(false);
+        let (a_hash, a) = object("a", 1024);
+        let (b_hash, b) = object("b", 1636);
+        cache.insert(a.offset, a_hash, a);
+        cache.insert(b.offset, b_hash, b);
+        cache.get_by_offset(1024).unwrap(); // the tmp file is written
+        drop(cache);
+        assert!(spilled.exists());
+        fs::remove_dir_all(&tmp_path).unwrap();
+    }
+
+    #[test]
+    fn test_spill_disabled_by_empty_tmp_path() {
+        let cache = Caches::new(Some(2048), PathBuf::new(), 1);
+        let a_hash = SHA1::new(String::from("a").as_bytes());
+        let b_hash = SHA1::new(String::from("b").as_bytes());
+        for (offset, hash, size) in [(0, a_hash, 1024), (1, b_hash, 1636)] {
+            let obj = CacheObject {
+                info: CacheObjectInfo::BaseObject(ObjectType::Blob, hash),
+                data_decompressed: vec![0; size],
+                mem_recorder: None,
+                offset,
+            };
+            cache.insert(offset, hash, obj);
+        }
+        // a was evicted & dropped, not written anywhere
+        assert!(cache.get_by_hash(a_hash).is_none());
+        assert!(cache.get_by_hash(b_hash).is_some());
+        assert!(!PathBuf::from(&a_hash._to_string()[..2]).exists());
+        assert_eq!(cache.total_inserted(), 2);
+    }
 }
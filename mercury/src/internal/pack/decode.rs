@@ -1,4 +1,4 @@
-use std::io::{self, BufRead, Cursor, ErrorKind, Read};
+use std::io::{self, BufRead, Cursor, Read};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, mpsc};
@@ -23,7 +23,7 @@ use crate::internal::pack::cache::_Cache;
 use crate::internal::pack::cache_object::{CacheObject, MemSizeRecorder};
 use crate::internal::pack::waitlist::Waitlist;
 use crate::internal::pack::wrapper::Wrapper;
-use crate::internal::pack::{utils, Pack, DEFAULT_TMP_DIR};
+use crate::internal::pack::{delta, utils, Pack, DEFAULT_TMP_DIR};
 use crate::internal::pack::channel_reader::ChannelReader;
 use crate::internal::pack::entry::Entry;
 
@@ -56,6 +56,9 @@ impl Pack {
     /// - `temp_path`: The path to a directory for temporary files, default is "./.cache_temp" <br>
     ///   For example, thread_num = 4 will use up to 8 threads (4 for decoding and 4 for cache) <br>
     /// - `clean_tmp`: whether to remove temp directory when Pack is dropped
+    ///
+    /// The size of a single object and of the whole pack is unlimited by default,
+    /// set `max_object_size` and `max_total_size` to guard against zip bombs.
     pub fn new(thread_num: Option<usize>, mem_limit: Option<usize>, temp_path: Option<PathBuf>, clean_tmp: bool) -> Self {
         let mut temp_path = temp_path.unwrap_or(PathBuf::from(DEFAULT_TMP_DIR));
         // add 8 random characters as subdirectory, check if the directory exists
@@ -75,10 +78,23 @@ impl Pack {
             objects: Vec::new(),
             pool: Arc::new(ThreadPool::new(thread_num)),
             waitlist: Arc::new(Waitlist::new()),
-            caches:  Arc::new(Caches::new(cache_mem_size, temp_path, thread_num)),
+            caches:  Arc::new(Caches::new(cache_mem_size, temp_path, thread_num).with_clean_tmp(clean_tmp)),
             mem_limit,
             cache_objs_mem: Arc::new(AtomicUsize::default()),
             clean_tmp,
+            max_object_size: None,
+            max_total_size: None,
+        }
+    }
+
+    /// Check the (declared or inflated) size of an object against `max_object_size`.
+    fn check_object_size(&self, size: usize) -> Result<(), GitError> {
+        match self.max_object_size {
+            Some(limit) if size > limit => Err(GitError::ObjectTooLarge(format!(
+                "{} bytes exceeds the limit of {} bytes",
+                size, limit
+            ))),
+            _ => Ok(()),
         }
     }
 
@@ -197,14 +213,16 @@ impl Pack {
     /// * A tuple with a `Vec<u8>` of the decompressed data and the total number of input bytes processed,
     /// * Or a `GitError` in case of a mismatch in expected size or any other reading error.
     ///
+    /// At most `expected_size + 1` bytes are inflated, so an object lying about its size can't exhaust memory.
     pub fn decompress_data(&mut self, pack: &mut (impl BufRead + Send), expected_size: usize) -> Result<(Vec<u8>, usize), GitError> {
         // Create a buffer with the expected size for the decompressed data
         let mut buf = Vec::with_capacity(expected_size);
         // Create a new Zlib decoder with the original data
         let mut deflate = ZlibDecoder::new(pack);
 
-        // Attempt to read data to the end of the buffer
-        match deflate.read_to_end(&mut buf) {
+        // Attempt to read data to the end of the buffer, one extra byte is enough to detect an oversize object
+        let read_result = (&mut deflate).take(expected_size as u64 + 1).read_to_end(&mut buf);
+        match read_result {
             Ok(_) => {
                 // Check if the length of the buffer matches the expected size
                 if buf.len() != expected_size {
@@ -252,6 +270,8 @@ impl Pack {
 
         // Check if the object type is valid
         let t = ObjectType::from_u8(type_bits)?;
+        // Refuse oversize objects before inflating them
+        self.check_object_size(size)?;
 
         match t {
             ObjectType::Commit | ObjectType::Tree | ObjectType::Blob | ObjectType::Tag => {
@@ -260,7 +280,7 @@ impl Pack {
                 Ok(CacheObject::new_for_undeltified(t, data, init_offset))
             },
             ObjectType::OffsetDelta => {
-                let (delta_offset, bytes) = utils::read_offset_encoding(pack).unwrap();
+                let (delta_offset, bytes) = utils::read_offset_encoding(pack)?;
                 *offset += bytes;
 
                 let (data, raw_size) = self.decompress_data(pack, size)?;
@@ -271,11 +291,11 @@ impl Pack {
                     .checked_sub(delta_offset as usize)
                     .ok_or_else(|| {
                         GitError::InvalidObjectInfo("Invalid OffsetDelta offset".to_string())
-                    })
-                    .unwrap();
+                    })?;
 
                 let mut reader = Cursor::new(&data);
                 let (_, final_size) = utils::read_delta_object_size(&mut reader)?;
+                self.check_object_size(final_size)?;
 
                 Ok(CacheObject {
                     info: CacheObjectInfo::OffsetDelta(base_offset, final_size),
@@ -286,7 +306,7 @@ impl Pack {
             },
             ObjectType::HashDelta => {
                 // Read 20 bytes to get the reference object SHA1 hash
-                let ref_sha1 = SHA1::from_stream(pack).unwrap();
+                let ref_sha1 = SHA1::from_stream(pack)?;
                 // Offset is incremented by 20 bytes
                 *offset += SHA1::SIZE;
 
@@ -295,6 +315,7 @@ impl Pack {
                 
                 let mut reader = Cursor::new(&data);
                 let (_, final_size) = utils::read_delta_object_size(&mut reader)?;
+                self.check_object_size(final_size)?;
 
                 Ok(CacheObject {
                     info: CacheObjectInfo::HashDelta(ref_sha1, final_size),
@@ -336,6 +357,7 @@ impl Pack {
         }
         tracing::info!("The pack file has {} objects", self.number);
         let mut offset: usize = 12;
+        let mut total_size: usize = 0; // inflated bytes of all objects so far
         let mut i = 0;
         while i < self.number {
             // log per 1000 objects and 1 second
@@ -355,6 +377,21 @@ impl Pack {
             let r: Result<CacheObject, GitError> = self.decode_pack_object(&mut reader, &mut offset);
             match r {
                 Ok(mut obj) => {
+                    // a delta counts as the object it expands to, its payload may be tiny
+                    total_size += match obj.info {
+                        CacheObjectInfo::OffsetDelta(_, final_size)
+                        | CacheObjectInfo::HashDelta(_, final_size) => final_size,
+                        CacheObjectInfo::BaseObject(_, _) => obj.data_decompressed.len(),
+                    };
+                    if let Some(limit) = self.max_total_size {
+                        if total_size > limit {
+                            return Err(GitError::ObjectTooLarge(format!(
+                                "the inflated size of the pack exceeds the limit of {} bytes",
+                                limit
+                            )));
+                        }
+                    }
+
                     obj.set_mem_recorder(self.cache_objs_mem.clone());
                     obj.record_mem_size();
 
@@ -400,17 +437,38 @@ impl Pack {
                         }
                     });
                 },
-                Err(e) => {
+                // a failure of the source, e.g. the network, isn't a corrupt pack
+                Err(e @ (GitError::ObjectTooLarge(_) | GitError::IOError(_))) => {
                     return Err(e);
                 }
+                Err(e) => {
+                    // A truncated pack, or a header declaring more objects than the pack has,
+                    // runs into the trailer (or EOF) here
+                    return Err(GitError::InvalidPackFile(format!(
+                        "Failed to decode object {} of the {} declared in the header: {}",
+                        i + 1,
+                        self.number,
+                        e
+                    )));
+                }
             }
             i += 1;
         }
         log_info(i, self);
         let render_hash = reader.final_hash();
-        self.signature = SHA1::from_stream(&mut reader).unwrap();
+        self.signature = SHA1::from_stream(&mut reader).map_err(|e| {
+            GitError::InvalidPackFile(format!("Error reading the trailer hash: {}", e))
+        })?;
+        let end = utils::is_eof(&mut reader);
 
         if render_hash != self.signature {
+            if !end {
+                // the header declares fewer objects than the pack has
+                return Err(GitError::InvalidPackFile(format!(
+                    "The pack file has more data than the {} objects declared in the header",
+                    self.number
+                )));
+            }
             return Err(GitError::InvalidPackFile(format!(
                 "The pack file hash {} does not match the trailer hash {}",
                 render_hash,
@@ -418,7 +476,6 @@ impl Pack {
             )));
         }
 
-        let end = utils::is_eof(&mut reader);
         if !end {
             return Err(GitError::InvalidPackFile(
                 "The pack file is not at the end".to_string()
@@ -501,8 +558,12 @@ impl Pack {
     /// Rebuild the Delta Object in a new thread & process the objects waiting for it recursively.
     /// <br> This function must be *static*, because [&self] can't be moved into a new thread.
     fn process_delta(shared_params: Arc<SharedParams>, delta_obj: CacheObject, base_obj: Arc<CacheObject>) {
+        // the base stays in memory for the deltas of it coming during the rebuild
+        shared_params.caches.pin(&base_obj);
         shared_params.pool.clone().execute(move || {
+            let base_hash = base_obj.base_object_hash().unwrap();
             let mut new_obj = Pack::rebuild_delta(delta_obj, base_obj);
+            shared_params.caches.unpin(base_hash);
             new_obj.set_mem_recorder(shared_params.cache_objs_mem_size.clone());
             new_obj.record_mem_size();
             Self::cache_obj_and_process_waitlist(shared_params, new_obj); //Indirect Recursion
@@ -525,85 +586,26 @@ impl Pack {
     }
 
     /// Reconstruct the Delta Object based on the "base object"
-    /// and return the new object.
+    /// and return the new object, see [delta::apply_delta].
+    /// - panics if the delta is invalid, see [Pack::try_rebuild_delta]
     pub fn rebuild_delta(delta_obj: CacheObject, base_obj: Arc<CacheObject>) -> CacheObject {
-        const COPY_INSTRUCTION_FLAG: u8 = 1 << 7;
-        const COPY_OFFSET_BYTES: u8 = 4;
-        const COPY_SIZE_BYTES: u8 = 3;
-        const COPY_ZERO_SIZE: usize = 0x10000;
-
-        let mut stream = Cursor::new(&delta_obj.data_decompressed);
-
-        // Read the base object size
-        // (Size Encoding)
-        let (base_size, result_size) = utils::read_delta_object_size(&mut stream).unwrap();
-
-        // Get the base object data
-        let base_info = &base_obj.data_decompressed;
-        assert_eq!(base_info.len(), base_size, "Base object size mismatch");
-
-        let mut result = Vec::with_capacity(result_size);
-
-        loop {
-            // Check if the stream has ended, meaning the new object is done
-            let instruction = match utils::read_bytes(&mut stream) {
-                Ok([instruction]) => instruction,
-                Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
-                Err(err) => {
-                    panic!(
-                        "{}",
-                        GitError::DeltaObjectError(format!("Wrong instruction in delta :{}", err))
-                    );
-                }
-            };
-
-            if instruction & COPY_INSTRUCTION_FLAG == 0 {
-                // Data instruction; the instruction byte specifies the number of data bytes
-                if instruction == 0 {
-                    // Appending 0 bytes doesn't make sense, so git disallows it
-                    panic!(
-                        "{}",
-                        GitError::DeltaObjectError(String::from("Invalid data instruction"))
-                    );
-                }
+        Self::try_rebuild_delta(delta_obj, &base_obj)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
 
-                // Append the provided bytes
-                let mut data = vec![0; instruction as usize];
-                stream.read_exact(&mut data).unwrap();
-                result.extend_from_slice(&data);
-            } else {
-                // Copy instruction
-                // +----------+---------+---------+---------+---------+-------+-------+-------+
-                // | 1xxxxxxx | offset1 | offset2 | offset3 | offset4 | size1 | size2 | size3 |
-                // +----------+---------+---------+---------+---------+-------+-------+-------+
-                let mut nonzero_bytes = instruction;
-                let offset = utils::read_partial_int(&mut stream, COPY_OFFSET_BYTES, &mut nonzero_bytes).unwrap();
-                let mut size = utils::read_partial_int(&mut stream, COPY_SIZE_BYTES, &mut nonzero_bytes).unwrap();
-                if size == 0 {
-                    // Copying 0 bytes doesn't make sense, so git assumes a different size
-                    size = COPY_ZERO_SIZE;
-                }
-                // Copy bytes from the base object
-                let base_data = base_info.get(offset..(offset + size)).ok_or_else(|| {
-                    GitError::DeltaObjectError("Invalid copy instruction".to_string())
-                });
-
-                match base_data {
-                    Ok(data) => result.extend_from_slice(data),
-                    Err(e) => panic!("{}", e),
-                }
-            }
-        }
-        assert_eq!(result_size, result.len(), "Result size mismatch");
+    /// [Pack::rebuild_delta], failing with [GitError::DeltaObjectError] if the delta is invalid
+    pub fn try_rebuild_delta(delta_obj: CacheObject, base_obj: &CacheObject) -> Result<CacheObject, GitError> {
+        let result = delta::apply_delta(&base_obj.data_decompressed, &delta_obj.data_decompressed)
+            .map_err(|e| GitError::DeltaObjectError(e.to_string()))?;
 
         let hash = utils::calculate_object_hash(base_obj.object_type(), &result);
         // create new obj from `delta_obj` & `result` instead of modifying `delta_obj` for heap-size recording
-        CacheObject {
+        Ok(CacheObject {
             info: CacheObjectInfo::BaseObject(base_obj.object_type(), hash),
             offset: delta_obj.offset,
             data_decompressed: result,
             mem_recorder: None,
-        } // Canonical form (Complete Object)
+        }) // Canonical form (Complete Object)
         // Memory recording will happen after this function returns. See `process_delta`
     }
 }
@@ -620,12 +622,32 @@ mod tests {
 
     use flate2::write::ZlibEncoder;
     use flate2::Compression;
+    use sha1::{Digest, Sha1};
     use tokio_util::io::ReaderStream;
 
+    use crate::errors::GitError;
     use crate::internal::pack::tests::init_logger;
     use crate::internal::pack::Pack;
     use futures_util::TryStreamExt;
 
+    /// Encode the type and size header of an object in pack
+    fn encode_object_header(type_bits: u8, size: usize) -> Vec<u8> {
+        let mut header = vec![(type_bits << 4) | (size & 0x0f) as u8];
+        let mut size = size >> 4;
+        while size > 0 {
+            *header.last_mut().unwrap() |= 0x80;
+            header.push((size & 0x7f) as u8);
+            size >>= 7;
+        }
+        header
+    }
+
+    fn zlib_compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
     #[test]
     fn test_pack_check_header() {
         let mut source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
@@ -662,6 +684,161 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_decode_object_within_size_limit() {
+        let data = b"Hello, world!";
+        let mut raw = encode_object_header(3, data.len()); // blob
+        raw.extend(zlib_compress(data));
+
+        let mut p = Pack::new(None, None, None, true);
+        p.max_object_size = Some(data.len());
+        let mut offset = 0;
+        let obj = p.decode_pack_object(&mut Cursor::new(raw.clone()), &mut offset).unwrap();
+        assert_eq!(obj.data_decompressed, data);
+        assert_eq!(offset, raw.len());
+    }
+
+    #[test]
+    fn test_decode_object_exceeds_size_limit() {
+        // declare a huge object, but only a small payload follows
+        let declared_size = 1024 * 1024 * 1024;
+        let header = encode_object_header(3, declared_size);
+        let mut raw = header.clone();
+        raw.extend(zlib_compress(b"tiny"));
+
+        let mut p = Pack::new(None, None, None, true);
+        p.max_object_size = Some(1024);
+        let mut cursor = Cursor::new(raw);
+        let mut offset = 0;
+        let result = p.decode_pack_object(&mut cursor, &mut offset);
+        assert!(matches!(result, Err(GitError::ObjectTooLarge(_))));
+        // refused right after the header, the payload is never inflated
+        assert_eq!(cursor.position() as usize, header.len());
+    }
+
+    #[test]
+    fn test_decompress_data_larger_than_declared() {
+        let data = vec![0u8; 1024 * 1024];
+        let mut cursor = Cursor::new(zlib_compress(&data));
+
+        let mut p = Pack::new(None, None, None, true);
+        let result = p.decompress_data(&mut cursor, 16);
+        assert!(matches!(result, Err(GitError::InvalidPackFile(_))));
+    }
+
+    /// Build a pack of blobs, with `declared_num` written as the object count in the header
+    fn build_blob_pack(declared_num: u32, blobs: &[&[u8]]) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend(declared_num.to_be_bytes());
+        for blob in blobs {
+            pack.extend(encode_object_header(3, blob.len()));
+            pack.extend(zlib_compress(blob));
+        }
+        let mut hasher = Sha1::new();
+        hasher.update(&pack);
+        let trailer: [u8; 20] = hasher.finalize().into();
+        pack.extend(trailer);
+        pack
+    }
+
+    #[test]
+    fn test_pack_decode_object_count() {
+        let blobs: [&[u8]; 2] = [b"first blob", b"second blob"];
+        let tmp = PathBuf::from("/tmp/.cache_temp");
+
+        let pack = build_blob_pack(2, &blobs);
+        let mut p = Pack::new(None, None, Some(tmp.clone()), true);
+        p.decode(&mut Cursor::new(pack), |_, _| {}).unwrap();
+        assert_eq!(p.number, 2);
+    }
+
+    #[test]
+    fn test_pack_decode_object_count_too_high() {
+        let blobs: [&[u8]; 2] = [b"first blob", b"second blob"];
+        let tmp = PathBuf::from("/tmp/.cache_temp");
+
+        let pack = build_blob_pack(3, &blobs);
+        let mut p = Pack::new(None, None, Some(tmp), true);
+        let result = p.decode(&mut Cursor::new(pack), |_, _| {});
+        assert!(matches!(result, Err(GitError::InvalidPackFile(_))));
+    }
+
+    #[test]
+    fn test_pack_decode_object_count_too_low() {
+        let blobs: [&[u8]; 2] = [b"first blob", b"second blob"];
+        let tmp = PathBuf::from("/tmp/.cache_temp");
+
+        let pack = build_blob_pack(1, &blobs);
+        let mut p = Pack::new(None, None, Some(tmp), true);
+        let result = p.decode(&mut Cursor::new(pack), |_, _| {});
+        match &result {
+            Err(GitError::InvalidPackFile(msg)) => assert!(msg.contains("more data than the 1 objects")),
+            _ => panic!("expected an object count mismatch, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_pack_decode_exceeds_total_size() {
+        let blobs: [&[u8]; 2] = [b"first blob", b"second blob"];
+        let tmp = PathBuf::from("/tmp/.cache_temp");
+
+        let pack = build_blob_pack(2, &blobs);
+        let mut p = Pack::new(None, None, Some(tmp), true);
+        p.max_total_size = Some(blobs[0].len() + 1);
+        let result = p.decode(&mut Cursor::new(pack), |_, _| {});
+        assert!(matches!(result, Err(GitError::ObjectTooLarge(_))));
+    }
+
+    #[test]
+    fn test_pack_decode_delta_result_exceeds_total_size() {
+        fn varint(mut value: usize) -> Vec<u8> {
+            let mut bytes = Vec::new();
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    bytes.push(byte);
+                    return bytes;
+                }
+                bytes.push(byte | 0x80);
+            }
+        }
+        // a delta of a few bytes, copying the whole base 16 times
+        let base = vec![b'a'; 0x1000];
+        let mut delta = varint(base.len());
+        delta.extend(varint(16 * base.len()));
+        for _ in 0..16 {
+            delta.extend([0x80 | 0x20, 0x10]); // copy 0x1000 bytes from offset 0
+        }
+
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend(2u32.to_be_bytes());
+        let base_offset = pack.len();
+        pack.extend(encode_object_header(3, base.len()));
+        pack.extend(zlib_compress(&base));
+        let distance = pack.len() - base_offset;
+        assert!(distance < 0x80, "one byte of negative offset");
+        pack.extend(encode_object_header(6, delta.len())); // ofs delta
+        pack.push(distance as u8);
+        pack.extend(zlib_compress(&delta));
+        let mut hasher = Sha1::new();
+        hasher.update(&pack);
+        let trailer: [u8; 20] = hasher.finalize().into();
+        pack.extend(trailer);
+
+        let tmp = PathBuf::from("/tmp/.cache_temp");
+        let mut p = Pack::new(None, None, Some(tmp.clone()), true);
+        p.decode(&mut Cursor::new(pack.clone()), |_, _| {}).unwrap();
+
+        // the payloads fit, the expanded delta doesn't
+        let mut p = Pack::new(None, None, Some(tmp), true);
+        p.max_total_size = Some(base.len() + delta.len() + 1024);
+        let result = p.decode(&mut Cursor::new(pack), |_, _| {});
+        assert!(matches!(result, Err(GitError::ObjectTooLarge(_))));
+    }
+
     #[test]
     fn test_pack_decode_without_delta() {
         let mut source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
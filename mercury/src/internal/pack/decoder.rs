@@ -0,0 +1,357 @@
+//! A sequential pack decoder, yielding the objects of a pack one by one as it's read.
+//!
+//! Unlike [Pack::decode], which rebuilds the deltas on a thread pool and hands the objects to a
+//! callback, [PackDecoder] is an [Iterator] pulled by its caller, so a huge pack can be processed
+//! at the caller's pace without holding more than the [Caches](super::cache::Caches) keep for the
+//! delta bases.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+
+use flate2::CrcReader;
+
+use crate::errors::GitError;
+use crate::hash::SHA1;
+use crate::internal::pack::cache::_Cache;
+use crate::internal::pack::cache_object::CacheObject;
+use crate::internal::pack::wrapper::Wrapper;
+use crate::internal::pack::{utils, Pack};
+
+/// Iterator over the objects of a pack read from `R`, deltas resolved to complete objects.
+/// - objects come in pack order, except a delta whose base comes later, which follows its base
+/// - the first error ends the iteration: an invalid object, a delta whose base isn't in the
+///   pack, or a trailer not matching the pack's hash
+pub struct PackDecoder<R> {
+    /// the CRC32 is reset at the start of each object, for the pack index
+    reader: CrcReader<Wrapper<R>>,
+    /// the caches of the delta bases & the waitlist of the deltas read before their base
+    pack: Pack,
+    /// objects left to read, of the number declared in the header
+    remaining: usize,
+    offset: usize,
+    /// deltas resolved by the last object read, yielded before reading the next one
+    resolved: VecDeque<CacheObject>,
+    /// CRC32 of the packed data of the objects read, by offset
+    crc32s: HashMap<usize, u32>,
+    /// the hash of the pack, once the trailer is checked
+    signature: Option<SHA1>,
+    finished: bool,
+}
+
+fn invalid_data(e: GitError) -> io::Error {
+    match e {
+        GitError::IOError(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+impl<R: BufRead + Send> PackDecoder<R> {
+    /// Check the header of the pack: the `PACK` magic & version 2, then read the objects lazily
+    /// - `mem_limit` & `temp_path`: the delta bases over `mem_limit` are spilled to `temp_path`,
+    ///   see [Pack::new]
+    pub fn new(
+        reader: R,
+        mem_limit: Option<usize>,
+        temp_path: Option<PathBuf>,
+    ) -> io::Result<Self> {
+        let mut reader = CrcReader::new(Wrapper::new(reader));
+        let (number, _) = Pack::check_header(&mut reader).map_err(invalid_data)?;
+        let mut pack = Pack::new(Some(1), mem_limit, temp_path, true);
+        pack.number = number as usize;
+        Ok(PackDecoder {
+            reader,
+            pack,
+            remaining: number as usize,
+            offset: 12,
+            resolved: VecDeque::new(),
+            crc32s: HashMap::new(),
+            signature: None,
+            finished: false,
+        })
+    }
+
+    /// Number of objects in the pack, as declared in its header
+    pub fn number(&self) -> usize {
+        self.pack.number
+    }
+
+    /// CRC32 of the packed data of the object at `offset`, as a [pack index](super::index) records
+    /// - `None` if no object at `offset` has been read
+    pub fn crc32(&self, offset: usize) -> Option<u32> {
+        self.crc32s.get(&offset).copied()
+    }
+
+    /// The hash of the pack, in its trailer
+    /// - `None` until all the objects are read & the trailer is checked
+    pub fn signature(&self) -> Option<SHA1> {
+        self.signature
+    }
+
+    /// Rebuild `obj` if it's a delta whose base is cached
+    /// - `None` if its base hasn't been read yet, `obj` waits for it
+    fn resolve(&self, obj: CacheObject) -> Result<Option<CacheObject>, GitError> {
+        if obj.base_object_hash().is_some() {
+            return Ok(Some(obj));
+        }
+        if let Some(base) = self.pack.caches.delta_base(&obj) {
+            return Pack::try_rebuild_delta(obj, &base).map(Some);
+        }
+        if let Some(offset) = obj.offset_delta() {
+            self.pack.waitlist.insert_offset(offset, obj);
+        } else if let Some(hash) = obj.hash_delta() {
+            self.pack.waitlist.insert_ref(hash, obj);
+        }
+        Ok(None)
+    }
+
+    /// Cache `obj` as a base, then rebuild the deltas waiting for it, & for those, recursively
+    fn cache(&mut self, obj: &CacheObject) -> Result<(), GitError> {
+        let mut bases = vec![obj.clone()];
+        while let Some(base) = bases.pop() {
+            let hash = base.base_object_hash().unwrap();
+            let base = self.pack.caches.insert(base.offset, hash, base);
+            for delta in self.pack.waitlist.take(base.offset, hash) {
+                let rebuilt = Pack::try_rebuild_delta(delta, &base)?;
+                self.resolved.push_back(rebuilt.clone());
+                bases.push(rebuilt);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the trailer once all the objects are read
+    fn finish(&mut self) -> Result<(), GitError> {
+        let waitlist = &self.pack.waitlist;
+        let waiting: usize = waitlist
+            .map_offset
+            .iter()
+            .map(|deltas| deltas.len())
+            .sum::<usize>()
+            + waitlist
+                .map_ref
+                .iter()
+                .map(|deltas| deltas.len())
+                .sum::<usize>();
+        if waiting > 0 {
+            return Err(GitError::InvalidPackFile(format!(
+                "{} delta objects have no base in the pack",
+                waiting
+            )));
+        }
+        let reader = self.reader.get_mut();
+        let hash = reader.final_hash();
+        let signature = SHA1::from_stream(reader).map_err(|e| {
+            GitError::InvalidPackFile(format!("Error reading the trailer hash: {}", e))
+        })?;
+        if hash != signature {
+            return Err(GitError::InvalidPackFile(format!(
+                "The pack file hash {} does not match the trailer hash {}",
+                hash, signature
+            )));
+        }
+        if !utils::is_eof(reader) {
+            return Err(GitError::InvalidPackFile(
+                "The pack file is not at the end".to_string(),
+            ));
+        }
+        self.signature = Some(signature);
+        Ok(())
+    }
+
+    fn next_object(&mut self) -> Result<Option<CacheObject>, GitError> {
+        loop {
+            if let Some(obj) = self.resolved.pop_front() {
+                return Ok(Some(obj));
+            }
+            if self.remaining == 0 {
+                self.finish()?;
+                return Ok(None);
+            }
+            self.remaining -= 1;
+            self.reader.reset();
+            let obj = self
+                .pack
+                .decode_pack_object(&mut self.reader, &mut self.offset)?;
+            self.crc32s.insert(obj.offset, self.reader.crc().sum());
+            if let Some(obj) = self.resolve(obj)? {
+                self.cache(&obj)?;
+                return Ok(Some(obj));
+            }
+        }
+    }
+}
+
+impl<R: BufRead + Send> Iterator for PackDecoder<R> {
+    type Item = io::Result<CacheObject>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let next = self.next_object().map_err(invalid_data).transpose();
+        if !matches!(next, Some(Ok(_))) {
+            self.finished = true;
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::internal::object::types::ObjectType;
+
+    /// A commit changing a line of a file, `git pack-objects --delta-base-offset`:
+    /// the second version of the file is an offset delta of the first
+    pub(crate) const OFS_DELTA_PACK: &[u8] = &[
+        0x50, 0x41, 0x43, 0x4b, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x06, 0x9a, 0x0a, 0x78,
+        0x9c, 0x7d, 0xca, 0x4b, 0x0a, 0x02, 0x31, 0x0c, 0x00, 0xd0, 0x7d, 0x4f, 0xd1, 0xbd, 0x20,
+        0x49, 0xfa, 0x4b, 0x41, 0xc4, 0xab, 0x24, 0x6d, 0x82, 0x2e, 0xc6, 0x91, 0xa1, 0xe2, 0xf5,
+        0xf5, 0x04, 0xbe, 0xf5, 0x5b, 0x87, 0x59, 0x54, 0x71, 0xca, 0xbd, 0x13, 0x33, 0xf5, 0x4a,
+        0xea, 0xb5, 0x43, 0xe3, 0xc9, 0x43, 0x5d, 0x3d, 0xb1, 0x18, 0x72, 0xe3, 0x94, 0xab, 0x87,
+        0x97, 0x1c, 0xf6, 0x5c, 0x51, 0xc9, 0x72, 0x46, 0x17, 0xc4, 0xd4, 0xd3, 0x1c, 0xb3, 0x0f,
+        0x30, 0xab, 0x3c, 0x95, 0xb4, 0x31, 0x15, 0x02, 0x72, 0x29, 0x3a, 0x31, 0xc8, 0x7b, 0xdd,
+        0xf7, 0x23, 0x4a, 0xbc, 0xc8, 0x4d, 0xcf, 0xe3, 0x1a, 0xb1, 0x41, 0xc6, 0x92, 0x2a, 0x40,
+        0x3c, 0xc1, 0x4f, 0x18, 0xfb, 0xb6, 0x3d, 0xd6, 0xb2, 0xbf, 0x29, 0xac, 0xcf, 0x1e, 0xbe,
+        0x1a, 0x7a, 0x2e, 0x49, 0x9a, 0x07, 0x78, 0x9c, 0x7d, 0xca, 0x4d, 0x0a, 0x80, 0x20, 0x10,
+        0x40, 0xe1, 0xbd, 0xa7, 0x98, 0x7d, 0x10, 0xe3, 0xef, 0x28, 0x44, 0x74, 0x15, 0x9d, 0x26,
+        0x6a, 0x61, 0x82, 0xd8, 0xfd, 0xeb, 0x04, 0xbd, 0xe5, 0xc7, 0x1b, 0x5d, 0x04, 0xc4, 0x26,
+        0xe3, 0x58, 0xe8, 0x88, 0xd9, 0x5b, 0x62, 0x74, 0x46, 0x27, 0xf6, 0x54, 0x4a, 0xd8, 0x43,
+        0xe4, 0x8f, 0x8f, 0x48, 0xde, 0x46, 0xaf, 0xf2, 0x33, 0xce, 0xd6, 0x21, 0xc3, 0x92, 0xb7,
+        0x32, 0xf3, 0x0a, 0x9a, 0xd0, 0x61, 0x20, 0x83, 0x08, 0x13, 0x7e, 0x29, 0x6e, 0xb5, 0x5e,
+        0x63, 0xc8, 0xef, 0xa4, 0xda, 0x2d, 0xea, 0x05, 0x17, 0x57, 0x20, 0x0a, 0xa4, 0x02, 0x78,
+        0x9c, 0x33, 0x34, 0x30, 0x30, 0x33, 0x31, 0x51, 0x48, 0xcb, 0xcc, 0x49, 0xd5, 0x2b, 0xa9,
+        0x28, 0x61, 0x48, 0x0f, 0x98, 0x96, 0xfe, 0x77, 0xbf, 0xdf, 0xab, 0x1b, 0xd3, 0xef, 0x5c,
+        0x2d, 0xe0, 0xe9, 0xbf, 0x79, 0x5c, 0x2a, 0x27, 0x1d, 0x00, 0xf7, 0x94, 0x0f, 0xde, 0xba,
+        0x45, 0x78, 0x9c, 0x7d, 0xd3, 0x3d, 0x0e, 0xc2, 0x30, 0x10, 0x44, 0xe1, 0x9e, 0x53, 0xf8,
+        0x08, 0xcc, 0x0e, 0xf9, 0xe1, 0x40, 0x8e, 0x88, 0x64, 0x25, 0x52, 0x64, 0x44, 0xb8, 0x3d,
+        0x4a, 0xcf, 0xbe, 0xca, 0xc5, 0xab, 0xf6, 0xd3, 0xb8, 0xad, 0x5b, 0x2d, 0xf7, 0xb2, 0x2f,
+        0xa5, 0xbf, 0x6a, 0x59, 0xd6, 0xb3, 0xbf, 0x8f, 0xeb, 0x6d, 0xf5, 0xd6, 0xae, 0xa4, 0x3c,
+        0x45, 0x9e, 0x9c, 0xa7, 0x47, 0x9e, 0x86, 0x3c, 0x8d, 0x79, 0x9a, 0xf2, 0x34, 0xe7, 0xe9,
+        0x09, 0x27, 0x13, 0x07, 0x78, 0x08, 0x40, 0x04, 0x22, 0x02, 0x12, 0x81, 0x89, 0x00, 0x45,
+        0xa0, 0x22, 0x60, 0x11, 0xb8, 0xf4, 0x4f, 0xdd, 0xfa, 0x17, 0xf6, 0x40, 0x5b, 0x01, 0x9b,
+        0x00, 0x9b, 0x00, 0x9b, 0x00, 0x9b, 0x00, 0x9b, 0x00, 0x9b, 0x00, 0x9b, 0x00, 0x1b, 0xc3,
+        0x66, 0x0c, 0x2e, 0xa6, 0x4f, 0x04, 0x2e, 0x06, 0x17, 0x83, 0x8b, 0xc1, 0xc5, 0xe0, 0x62,
+        0x70, 0xf1, 0x7f, 0x97, 0x1f, 0xd1, 0xf8, 0x7a, 0xc1, 0xa4, 0x02, 0x78, 0x9c, 0x33, 0x34,
+        0x30, 0x30, 0x33, 0x31, 0x51, 0x48, 0xcb, 0xcc, 0x49, 0xd5, 0x2b, 0xa9, 0x28, 0x61, 0x78,
+        0xa8, 0x31, 0xf1, 0xd3, 0xbe, 0xab, 0xff, 0xfe, 0xd5, 0xb4, 0x4d, 0xfd, 0xda, 0x1a, 0x2b,
+        0xa5, 0xae, 0x74, 0x9e, 0xe7, 0x38, 0x00, 0x04, 0x33, 0x10, 0x0c, 0x6f, 0x80, 0x40, 0x78,
+        0x9c, 0xbb, 0xc5, 0x71, 0x8d, 0x63, 0x83, 0x36, 0x13, 0x93, 0x91, 0xc1, 0x66, 0x43, 0x26,
+        0x4d, 0x26, 0x00, 0x28, 0xfe, 0x04, 0x13, 0xe0, 0x8d, 0xda, 0xf9, 0xef, 0xb6, 0x88, 0x89,
+        0x43, 0xbd, 0x39, 0x48, 0x9e, 0x09, 0xd5, 0x80, 0x29, 0x1c, 0x01, 0x52,
+    ];
+
+    /// The same objects without `--delta-base-offset`, the delta refers to its base by hash
+    const REF_DELTA_PACK: &[u8] = &[
+        0x50, 0x41, 0x43, 0x4b, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x06, 0x9a, 0x0a, 0x78,
+        0x9c, 0x7d, 0xca, 0x4b, 0x0a, 0x02, 0x31, 0x0c, 0x00, 0xd0, 0x7d, 0x4f, 0xd1, 0xbd, 0x20,
+        0x49, 0xfa, 0x4b, 0x41, 0xc4, 0xab, 0x24, 0x6d, 0x82, 0x2e, 0xc6, 0x91, 0xa1, 0xe2, 0xf5,
+        0xf5, 0x04, 0xbe, 0xf5, 0x5b, 0x87, 0x59, 0x54, 0x71, 0xca, 0xbd, 0x13, 0x33, 0xf5, 0x4a,
+        0xea, 0xb5, 0x43, 0xe3, 0xc9, 0x43, 0x5d, 0x3d, 0xb1, 0x18, 0x72, 0xe3, 0x94, 0xab, 0x87,
+        0x97, 0x1c, 0xf6, 0x5c, 0x51, 0xc9, 0x72, 0x46, 0x17, 0xc4, 0xd4, 0xd3, 0x1c, 0xb3, 0x0f,
+        0x30, 0xab, 0x3c, 0x95, 0xb4, 0x31, 0x15, 0x02, 0x72, 0x29, 0x3a, 0x31, 0xc8, 0x7b, 0xdd,
+        0xf7, 0x23, 0x4a, 0xbc, 0xc8, 0x4d, 0xcf, 0xe3, 0x1a, 0xb1, 0x41, 0xc6, 0x92, 0x2a, 0x40,
+        0x3c, 0xc1, 0x4f, 0x18, 0xfb, 0xb6, 0x3d, 0xd6, 0xb2, 0xbf, 0x29, 0xac, 0xcf, 0x1e, 0xbe,
+        0x1a, 0x7a, 0x2e, 0x49, 0x9a, 0x07, 0x78, 0x9c, 0x7d, 0xca, 0x4d, 0x0a, 0x80, 0x20, 0x10,
+        0x40, 0xe1, 0xbd, 0xa7, 0x98, 0x7d, 0x10, 0xe3, 0xef, 0x28, 0x44, 0x74, 0x15, 0x9d, 0x26,
+        0x6a, 0x61, 0x82, 0xd8, 0xfd, 0xeb, 0x04, 0xbd, 0xe5, 0xc7, 0x1b, 0x5d, 0x04, 0xc4, 0x26,
+        0xe3, 0x58, 0xe8, 0x88, 0xd9, 0x5b, 0x62, 0x74, 0x46, 0x27, 0xf6, 0x54, 0x4a, 0xd8, 0x43,
+        0xe4, 0x8f, 0x8f, 0x48, 0xde, 0x46, 0xaf, 0xf2, 0x33, 0xce, 0xd6, 0x21, 0xc3, 0x92, 0xb7,
+        0x32, 0xf3, 0x0a, 0x9a, 0xd0, 0x61, 0x20, 0x83, 0x08, 0x13, 0x7e, 0x29, 0x6e, 0xb5, 0x5e,
+        0x63, 0xc8, 0xef, 0xa4, 0xda, 0x2d, 0xea, 0x05, 0x17, 0x57, 0x20, 0x0a, 0xa4, 0x02, 0x78,
+        0x9c, 0x33, 0x34, 0x30, 0x30, 0x33, 0x31, 0x51, 0x48, 0xcb, 0xcc, 0x49, 0xd5, 0x2b, 0xa9,
+        0x28, 0x61, 0x48, 0x0f, 0x98, 0x96, 0xfe, 0x77, 0xbf, 0xdf, 0xab, 0x1b, 0xd3, 0xef, 0x5c,
+        0x2d, 0xe0, 0xe9, 0xbf, 0x79, 0x5c, 0x2a, 0x27, 0x1d, 0x00, 0xf7, 0x94, 0x0f, 0xde, 0xba,
+        0x45, 0x78, 0x9c, 0x7d, 0xd3, 0x3d, 0x0e, 0xc2, 0x30, 0x10, 0x44, 0xe1, 0x9e, 0x53, 0xf8,
+        0x08, 0xcc, 0x0e, 0xf9, 0xe1, 0x40, 0x8e, 0x88, 0x64, 0x25, 0x52, 0x64, 0x44, 0xb8, 0x3d,
+        0x4a, 0xcf, 0xbe, 0xca, 0xc5, 0xab, 0xf6, 0xd3, 0xb8, 0xad, 0x5b, 0x2d, 0xf7, 0xb2, 0x2f,
+        0xa5, 0xbf, 0x6a, 0x59, 0xd6, 0xb3, 0xbf, 0x8f, 0xeb, 0x6d, 0xf5, 0xd6, 0xae, 0xa4, 0x3c,
+        0x45, 0x9e, 0x9c, 0xa7, 0x47, 0x9e, 0x86, 0x3c, 0x8d, 0x79, 0x9a, 0xf2, 0x34, 0xe7, 0xe9,
+        0x09, 0x27, 0x13, 0x07, 0x78, 0x08, 0x40, 0x04, 0x22, 0x02, 0x12, 0x81, 0x89, 0x00, 0x45,
+        0xa0, 0x22, 0x60, 0x11, 0xb8, 0xf4, 0x4f, 0xdd, 0xfa, 0x17, 0xf6, 0x40, 0x5b, 0x01, 0x9b,
+        0x00, 0x9b, 0x00, 0x9b, 0x00, 0x9b, 0x00, 0x9b, 0x00, 0x9b, 0x00, 0x9b, 0x00, 0x1b, 0xc3,
+        0x66, 0x0c, 0x2e, 0xa6, 0x4f, 0x04, 0x2e, 0x06, 0x17, 0x83, 0x8b, 0xc1, 0xc5, 0xe0, 0x62,
+        0x70, 0xf1, 0x7f, 0x97, 0x1f, 0xd1, 0xf8, 0x7a, 0xc1, 0xa4, 0x02, 0x78, 0x9c, 0x33, 0x34,
+        0x30, 0x30, 0x33, 0x31, 0x51, 0x48, 0xcb, 0xcc, 0x49, 0xd5, 0x2b, 0xa9, 0x28, 0x61, 0x78,
+        0xa8, 0x31, 0xf1, 0xd3, 0xbe, 0xab, 0xff, 0xfe, 0xd5, 0xb4, 0x4d, 0xfd, 0xda, 0x1a, 0x2b,
+        0xa5, 0xae, 0x74, 0x9e, 0xe7, 0x38, 0x00, 0x04, 0x33, 0x10, 0x0c, 0x7f, 0x67, 0x50, 0x96,
+        0x67, 0xfd, 0xbf, 0x4e, 0xea, 0xd8, 0x97, 0xdc, 0xd5, 0x70, 0x0c, 0x8f, 0xd9, 0xc7, 0x1a,
+        0x6c, 0x67, 0x78, 0x9c, 0xbb, 0xc5, 0x71, 0x8d, 0x63, 0x83, 0x36, 0x13, 0x93, 0x91, 0xc1,
+        0x66, 0x43, 0x26, 0x4d, 0x26, 0x00, 0x28, 0xfe, 0x04, 0x13, 0xa9, 0x8c, 0x14, 0x3d, 0xb3,
+        0x5c, 0x64, 0x84, 0x9e, 0xec, 0x5f, 0xdc, 0xfb, 0x3a, 0x5f, 0x8a, 0x33, 0x5a, 0xcb, 0x1c,
+    ];
+
+    /// The objects of both packs, in pack order, as listed by `git verify-pack -v`
+    pub(crate) const OBJECTS: [(&str, ObjectType); 6] = [
+        (
+            "4e0ea1ff68682cc24a2792be8d3c2a21d6f1d6b0",
+            ObjectType::Commit,
+        ),
+        (
+            "b2e441fa11393dcd9c0ee68db2b7825202fa5bd1",
+            ObjectType::Commit,
+        ),
+        ("baf24992882962bf69078d8cbfbf38ae1878346f", ObjectType::Tree),
+        ("67509667fdbf4eead897dcd5700c8fd9c71a6c67", ObjectType::Blob),
+        ("e3924ce7f8a537c04219c57bb6d68c7f8f875385", ObjectType::Tree),
+        ("e12891f2bed5fefe7c8695f5855d1a2722cf0cc7", ObjectType::Blob),
+    ];
+
+    fn decode(pack: &[u8]) -> io::Result<Vec<CacheObject>> {
+        PackDecoder::new(Cursor::new(pack), None, None)?.collect()
+    }
+
+    #[test]
+    fn test_decode_pack() {
+        for pack in [OFS_DELTA_PACK, REF_DELTA_PACK] {
+            let decoder = PackDecoder::new(Cursor::new(pack), None, None).unwrap();
+            assert_eq!(decoder.number(), OBJECTS.len());
+            let objects = decoder.collect::<io::Result<Vec<_>>>().unwrap();
+            assert_eq!(objects.len(), OBJECTS.len());
+            for (obj, (hash, obj_type)) in objects.iter().zip(OBJECTS) {
+                assert_eq!(obj.base_object_hash(), Some(SHA1::from_str(hash).unwrap()));
+                assert_eq!(obj.object_type(), obj_type);
+            }
+            // the delta is rebuilt
+            let file = String::from_utf8(objects[5].data_decompressed.clone()).unwrap();
+            assert!(file.contains("line twenty of the fixture file"), "{}", file);
+        }
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut pack = OFS_DELTA_PACK.to_vec();
+        pack[..4].copy_from_slice(b"KCAP");
+        assert!(PackDecoder::new(Cursor::new(&pack), None, None).is_err());
+
+        let mut pack = OFS_DELTA_PACK.to_vec();
+        pack[7] = 3; // version 3
+        assert!(PackDecoder::new(Cursor::new(&pack), None, None).is_err());
+
+        assert!(PackDecoder::new(Cursor::new(&OFS_DELTA_PACK[..10]), None, None).is_err());
+    }
+
+    #[test]
+    fn test_invalid_pack() {
+        // corrupt trailer
+        let mut pack = OFS_DELTA_PACK.to_vec();
+        *pack.last_mut().unwrap() ^= 0xff;
+        let err = decode(&pack).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // truncated, the error ends the iteration
+        let pack = &OFS_DELTA_PACK[..OFS_DELTA_PACK.len() - 100];
+        let mut decoder = PackDecoder::new(Cursor::new(pack), None, None).unwrap();
+        assert!(decoder.by_ref().any(|obj| obj.is_err()));
+        assert!(decoder.next().is_none());
+
+        // more objects declared than the pack has
+        let mut pack = OFS_DELTA_PACK.to_vec();
+        pack[11] = 7;
+        assert!(decode(&pack).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Reconstruction of the delta objects of a pack, `OBJ_OFS_DELTA` & `OBJ_REF_DELTA`.
+//!
+//! A delta is the size of its base & the size of the result, both as [varint](utils::read_varint_le),
+//! then instructions building the result in order:
+//! - copy: `1xxxxxxx`, the 7 bits telling which of the 4 offset & 3 size bytes follow, copying
+//!   `base[offset..offset + size]`; a size of 0 means `0x10000`
+//! - insert: `0xxxxxxx`, appending the next `xxxxxxx` bytes of the delta, 0 is reserved
+//!
+//! The two kinds differ only by how the base is found in the pack, see
+//! [Caches::delta_base](super::cache::Caches::delta_base).
+
+use std::io::{self, Cursor, ErrorKind, Read};
+
+use crate::internal::pack::utils;
+
+const COPY_INSTRUCTION_FLAG: u8 = 1 << 7;
+const COPY_OFFSET_BYTES: u8 = 4;
+const COPY_SIZE_BYTES: u8 = 3;
+const COPY_ZERO_SIZE: usize = 0x10000;
+
+fn invalid_delta(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Apply the instructions of `delta` to `base`, returning the reconstructed object
+/// - `InvalidData` if `base` isn't of the size the delta expects, an instruction is invalid or
+///   out of `base`, or the result isn't of the declared size
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = Cursor::new(delta);
+    let (base_size, result_size) = utils::read_delta_object_size(&mut stream)?;
+    if base.len() != base_size {
+        return Err(invalid_delta(format!(
+            "base object size mismatch: {} instead of {}",
+            base.len(),
+            base_size
+        )));
+    }
+
+    // the declared size isn't trusted for the allocation, the delta may be corrupt
+    let mut result = Vec::with_capacity(result_size.min(delta.len() + base.len()));
+    while (stream.position() as usize) < delta.len() {
+        let [instruction] = utils::read_bytes(&mut stream)?;
+        if instruction & COPY_INSTRUCTION_FLAG == 0 {
+            // Data instruction; the instruction byte specifies the number of data bytes
+            if instruction == 0 {
+                // Appending 0 bytes doesn't make sense, so git disallows it
+                return Err(invalid_delta("invalid data instruction 0".to_string()));
+            }
+            let start = result.len();
+            result.resize(start + instruction as usize, 0);
+            stream
+                .read_exact(&mut result[start..])
+                .map_err(|_| invalid_delta("truncated data instruction".to_string()))?;
+        } else {
+            // Copy instruction
+            // +----------+---------+---------+---------+---------+-------+-------+-------+
+            // | 1xxxxxxx | offset1 | offset2 | offset3 | offset4 | size1 | size2 | size3 |
+            // +----------+---------+---------+---------+---------+-------+-------+-------+
+            let mut nonzero_bytes = instruction;
+            let offset =
+                utils::read_partial_int(&mut stream, COPY_OFFSET_BYTES, &mut nonzero_bytes)?;
+            let mut size =
+                utils::read_partial_int(&mut stream, COPY_SIZE_BYTES, &mut nonzero_bytes)?;
+            if size == 0 {
+                // Copying 0 bytes doesn't make sense, so git assumes a different size
+                size = COPY_ZERO_SIZE;
+            }
+            let data = offset
+                .checked_add(size)
+                .and_then(|end| base.get(offset..end))
+                .ok_or_else(|| {
+                    invalid_delta(format!(
+                        "copy of {} bytes at {} is out of the base of {} bytes",
+                        size, offset, base_size
+                    ))
+                })?;
+            result.extend_from_slice(data);
+        }
+        if result.len() > result_size {
+            break; // reported below, without building the rest
+        }
+    }
+
+    if result.len() != result_size {
+        return Err(invalid_delta(format!(
+            "result size mismatch: {} instead of {}",
+            result.len(),
+            result_size
+        )));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A delta header for `base_size` & `result_size` followed by `instructions`
+    fn delta(base_size: usize, result_size: usize, instructions: &[u8]) -> Vec<u8> {
+        let mut delta = Vec::new();
+        utils::write_varint_le(&mut delta, base_size as u64).unwrap();
+        utils::write_varint_le(&mut delta, result_size as u64).unwrap();
+        delta.extend_from_slice(instructions);
+        delta
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let base = b"Hello, world! This is the base object.";
+        let cases: [(&[u8], &[u8]); 4] = [
+            // copy 5 bytes at 0, insert "!"
+            (&[0x90, 0x05, 0x01, b'!'], b"Hello!"),
+            // insert "Bye", copy 6 bytes at 5: offset1 & size1 present
+            (&[0x03, b'B', b'y', b'e', 0x91, 0x05, 0x06], b"Bye, worl"),
+            // copy the whole base with the offset omitted
+            (&[0x90, base.len() as u8], base),
+            // copy from 2 offset bytes: 0x0007
+            (&[0x93, 0x07, 0x00, 0x05], b"world"),
+        ];
+        for (instructions, expected) in cases {
+            let delta = delta(base.len(), expected.len(), instructions);
+            assert_eq!(apply_delta(base, &delta).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_copy_zero_size() {
+        let base: Vec<u8> = (0..0x10000 + 10).map(|i| i as u8).collect();
+        // no size byte: 0x10000 bytes at 3
+        let delta = delta(base.len(), 0x10000, &[0x81, 0x03]);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), &base[3..0x10003]);
+    }
+
+    #[test]
+    fn test_invalid_delta() {
+        let base = b"0123456789";
+        let invalid = [
+            // base size mismatch
+            delta(9, 5, &[0x90, 0x05]),
+            // result size mismatch
+            delta(10, 6, &[0x90, 0x05]),
+            delta(10, 4, &[0x90, 0x05]),
+            // copy out of the base
+            delta(10, 5, &[0x91, 0x08, 0x05]),
+            // reserved instruction
+            delta(10, 0, &[0x00]),
+            // truncated data & copy
+            delta(10, 3, &[0x03, b'a']),
+            delta(10, 5, &[0x91, 0x08]),
+            // truncated header
+            vec![0x8a],
+        ];
+        for delta in invalid {
+            let err = apply_delta(base, &delta).unwrap_err();
+            assert!(
+                matches!(
+                    err.kind(),
+                    ErrorKind::InvalidData | ErrorKind::UnexpectedEof
+                ),
+                "{:?}: {}",
+                delta,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! The index of a pack, a `.idx` file of version 2, to find an object of the pack by its hash.
+//!
+//! - header: the magic `\377tOc` & the version, 2
+//! - fan-out: 256 big-endian `u32`, the `n`th is the number of objects whose hash starts with a
+//!   byte `<= n`
+//! - the hashes of the objects, sorted
+//! - the CRC32 of the packed data of each object, in the same order
+//! - the offset of each object in the pack, as 31 bits; if the MSB is set, the other bits are the
+//!   position of the offset in the next table instead
+//! - the offsets of 2 GiB or more, as `u64`
+//! - the hash of the pack, then the hash of all the above
+//!
+//! See [pack-format](https://git-scm.com/docs/pack-format#_version_2_pack_idx_files_support_packs_larger_than_4_gib_and)
+
+use std::cmp::Ordering;
+use std::io::{self, Write};
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use sha1::{Digest, Sha1};
+
+use crate::hash::SHA1;
+use crate::internal::pack::cache_object::CacheObject;
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+const IDX_VERSION: u32 = 2;
+const HEADER_SIZE: usize = 8;
+const FANOUT_SIZE: usize = 256 * 4;
+/// Offsets from this one are in the 64-bit table
+const LARGE_OFFSET_FLAG: u32 = 1 << 31;
+
+/// An object of a pack, as recorded in its index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdxEntry {
+    pub hash: SHA1,
+    /// of the start of the object in the pack
+    pub offset: u64,
+    /// of the packed data of the object, its header included
+    pub crc32: u32,
+}
+
+impl IdxEntry {
+    /// The entry of a decoded object, which must be complete
+    pub fn new(obj: &CacheObject, crc32: u32) -> Self {
+        IdxEntry {
+            hash: obj.base_object_hash().expect("a delta has no hash"),
+            offset: obj.offset as u64,
+            crc32,
+        }
+    }
+}
+
+/// Build the `.idx` of the pack of hash `pack_hash` made of the objects `entries`, in any order
+/// - `InvalidData` if two entries are of the same object
+pub fn build_index(
+    entries: impl IntoIterator<Item = IdxEntry>,
+    pack_hash: SHA1,
+) -> io::Result<Vec<u8>> {
+    let mut entries: Vec<IdxEntry> = entries.into_iter().collect();
+    entries.sort_unstable_by_key(|entry| entry.hash);
+    if let Some(pair) = entries.windows(2).find(|pair| pair[0].hash == pair[1].hash) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("object {} is twice in the pack", pair[0].hash),
+        ));
+    }
+
+    let mut idx = Vec::with_capacity(HEADER_SIZE + FANOUT_SIZE + entries.len() * 28 + 40);
+    idx.write_all(&IDX_MAGIC)?;
+    idx.write_u32::<BigEndian>(IDX_VERSION)?;
+
+    let mut count = 0;
+    for byte in 0..=u8::MAX {
+        while count < entries.len() && entries[count].hash.0[0] == byte {
+            count += 1;
+        }
+        idx.write_u32::<BigEndian>(count as u32)?;
+    }
+    for entry in &entries {
+        idx.write_all(&entry.hash.0)?;
+    }
+    for entry in &entries {
+        idx.write_u32::<BigEndian>(entry.crc32)?;
+    }
+    let mut large_offsets = Vec::new();
+    for entry in &entries {
+        if entry.offset < LARGE_OFFSET_FLAG as u64 {
+            idx.write_u32::<BigEndian>(entry.offset as u32)?;
+        } else {
+            idx.write_u32::<BigEndian>(LARGE_OFFSET_FLAG | large_offsets.len() as u32)?;
+            large_offsets.push(entry.offset);
+        }
+    }
+    for offset in large_offsets {
+        idx.write_u64::<BigEndian>(offset)?;
+    }
+
+    idx.write_all(&pack_hash.0)?;
+    let checksum: [u8; 20] = Sha1::digest(&idx).into();
+    idx.write_all(&checksum)?;
+    Ok(idx)
+}
+
+/// The offset in the pack of the object `hash`, looked up in the `.idx` of the pack
+/// - `None` if the object isn't in the pack
+/// - `InvalidData` if `idx` isn't an index of version 2, or is truncated
+pub fn find_offset(idx: &[u8], hash: &SHA1) -> io::Result<Option<u64>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    if idx.len() < HEADER_SIZE + FANOUT_SIZE
+        || idx[..4] != IDX_MAGIC
+        || BigEndian::read_u32(&idx[4..8]) != IDX_VERSION
+    {
+        return Err(invalid("not a pack index of version 2"));
+    }
+    let fanout = |byte: usize| BigEndian::read_u32(&idx[HEADER_SIZE + byte * 4..]) as usize;
+    let number = fanout(255);
+    let hashes = HEADER_SIZE + FANOUT_SIZE;
+    let crc32s = hashes + number * 20;
+    let offsets = crc32s + number * 4;
+    let large_offsets = offsets + number * 4;
+    if idx.len() < large_offsets + 40 {
+        return Err(invalid("truncated pack index"));
+    }
+
+    // the objects starting with the same byte as `hash`
+    let first = hash.0[0] as usize;
+    let start = if first == 0 { 0 } else { fanout(first - 1) };
+    let end = fanout(first);
+    if start > end || end > number {
+        return Err(invalid("corrupt fan-out table"));
+    }
+    let hash_at = |i: usize| &idx[hashes + i * 20..hashes + (i + 1) * 20];
+    let (mut low, mut high) = (start, end);
+    let found = loop {
+        if low >= high {
+            return Ok(None);
+        }
+        let mid = low + (high - low) / 2;
+        match hash_at(mid).cmp(&hash.0[..]) {
+            Ordering::Less => low = mid + 1,
+            Ordering::Greater => high = mid,
+            Ordering::Equal => break mid,
+        }
+    };
+
+    let offset = BigEndian::read_u32(&idx[offsets + found * 4..]);
+    if offset & LARGE_OFFSET_FLAG == 0 {
+        return Ok(Some(offset as u64));
+    }
+    let position = large_offsets + (offset & !LARGE_OFFSET_FLAG) as usize * 8;
+    if idx.len() < position + 8 + 40 {
+        return Err(invalid("truncated pack index"));
+    }
+    Ok(Some(BigEndian::read_u64(&idx[position..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::internal::pack::decoder::tests::{OBJECTS, OFS_DELTA_PACK};
+    use crate::internal::pack::decoder::PackDecoder;
+
+    #[test]
+    fn test_build_index() {
+        let mut decoder = PackDecoder::new(Cursor::new(OFS_DELTA_PACK), None, None).unwrap();
+        let objects = decoder
+            .by_ref()
+            .collect::<io::Result<Vec<CacheObject>>>()
+            .unwrap();
+        let entries: Vec<IdxEntry> = objects
+            .iter()
+            .map(|obj| IdxEntry::new(obj, decoder.crc32(obj.offset).unwrap()))
+            .collect();
+        let pack_hash = decoder.signature().unwrap();
+        let idx = build_index(entries, pack_hash).unwrap();
+
+        assert_eq!(idx[..8], [0xff, b't', b'O', b'c', 0, 0, 0, 2]);
+        let fanout: Vec<u32> = (0..256)
+            .map(|i| BigEndian::read_u32(&idx[8 + i * 4..]))
+            .collect();
+        assert!(fanout.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(fanout[255] as usize, OBJECTS.len());
+        // the hashes start with 4e, 67, b2, ba, e1 & e3
+        assert_eq!(fanout[0x4e] - fanout[0x4d], 1);
+        assert_eq!(fanout[0xb2] - fanout[0xb1], 1);
+        assert_eq!(fanout[0xe3], 6);
+        assert_eq!(fanout[0xe0], 4);
+
+        // the offsets & CRC32s `git verify-pack -v` lists
+        let expected = [
+            (OBJECTS[0].0, 12, 0xb6f184de),
+            (OBJECTS[1].0, 139, 0xc06ee73e),
+            (OBJECTS[2].0, 237, 0x53247f50),
+            (OBJECTS[3].0, 284, 0xa84ad865),
+            (OBJECTS[4].0, 429, 0xc990c4ae),
+            (OBJECTS[5].0, 476, 0x8519e7ec),
+        ];
+        for (hash, offset, crc32) in expected {
+            let hash = SHA1::from_str(hash).unwrap();
+            assert_eq!(find_offset(&idx, &hash).unwrap(), Some(offset));
+            assert_eq!(decoder.crc32(offset as usize), Some(crc32));
+        }
+        let missing = SHA1::from_str("4e0ea1ff68682cc24a2792be8d3c2a21d6f1d6b1").unwrap();
+        assert_eq!(find_offset(&idx, &missing).unwrap(), None);
+
+        // byte for byte the index of `git index-pack`, whose checksum is
+        let checksum = SHA1::from_str("95ba4c225239e8308207757506b724b634c7c740").unwrap();
+        assert_eq!(idx[idx.len() - 40..idx.len() - 20], pack_hash.0);
+        assert_eq!(idx[idx.len() - 20..], checksum.0);
+    }
+
+    #[test]
+    fn test_large_offsets() {
+        let entry = |name: &str, offset: u64| IdxEntry {
+            hash: SHA1::new(name.as_bytes()),
+            offset,
+            crc32: 0,
+        };
+        let entries = [
+            entry("small", 12),
+            entry("2 GiB", 1 << 31),
+            entry("5 GiB", 5 << 30),
+        ];
+        let idx = build_index(entries, SHA1::default()).unwrap();
+        assert_eq!(idx.len(), 8 + 1024 + 3 * 28 + 2 * 8 + 40);
+        for entry in entries {
+            assert_eq!(find_offset(&idx, &entry.hash).unwrap(), Some(entry.offset));
+        }
+
+        assert!(build_index([entry("twice", 12), entry("twice", 40)], SHA1::default()).is_err());
+        assert!(find_offset(&idx[..100], &entries[0].hash).is_err());
+        assert!(find_offset(&idx[..idx.len() - 60], &entries[0].hash).is_err());
+    }
+}
@@ -6,8 +6,11 @@ pub mod cache;
 pub mod cache_object;
 pub mod channel_reader;
 pub mod decode;
+pub mod decoder;
+pub mod delta;
 pub mod encode;
 pub mod entry;
+pub mod index;
 pub mod utils;
 pub mod waitlist;
 pub mod wrapper;
@@ -32,6 +35,8 @@ pub struct Pack {
     pub mem_limit: Option<usize>,
     pub cache_objs_mem: Arc<AtomicUsize>, // the memory size of CacheObjects in this Pack
     pub clean_tmp: bool,
+    pub max_object_size: Option<usize>, // the maximum inflated size of a single object, None for unlimited
+    pub max_total_size: Option<usize>,  // the maximum inflated size of all objects, None for unlimited
 }
 
 #[cfg(test)]
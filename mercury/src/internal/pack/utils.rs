@@ -1,5 +1,5 @@
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, BufRead, Read, Write};
 use std::path::Path;
 use sha1::{Digest, Sha1};
 
@@ -16,6 +16,9 @@ use crate::internal::object::types::ObjectType;
 /// Any other return value means that data was successfully read, so
 /// the reader has not reached the end yet.
 ///
+/// **The byte read is consumed**, and an error of the reader is taken as "not EOF".
+/// To check a stream which is still to be parsed, use [is_eof_peek].
+///
 /// # Arguments
 ///
 /// * `reader` - The reader to check for EOF state
@@ -29,6 +32,28 @@ pub fn is_eof(reader: &mut dyn Read) -> bool {
     matches!(reader.read(&mut buf), Ok(0))
 }
 
+/// Checks if the reader has reached EOF without consuming anything.
+///
+/// It fills the buffer of the reader and looks whether it's empty, so the
+/// next read still gets all the remaining data.
+///
+/// # Arguments
+///
+/// * `reader` - The buffered reader to check for EOF state
+///
+/// # Returns
+///
+/// true if the reader reached EOF, false otherwise, or the error of the reader
+pub fn is_eof_peek<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    loop {
+        match reader.fill_buf() {
+            Ok(buf) => return Ok(buf.is_empty()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 /// Reads a byte from the given stream and checks if there are more bytes to continue reading.
 ///
 /// The return value includes two parts: an unsigned integer formed by the first 7 bits of the byte,
@@ -153,6 +178,63 @@ pub fn read_varint_le<R: Read>(reader: &mut R) -> io::Result<(u64, usize)> {
     Ok((value, offset))
 }
 
+/// Writes a variable-length integer (VarInt) in little-endian format, the inverse of [read_varint_le].
+///
+/// The value is split into groups of 7 bits, the least significant first, one per byte.
+/// The most significant bit of each byte is set when more bytes follow.
+///
+/// # Parameters
+/// * `writer`: A destination implementing the Write trait.
+/// * `value`: The integer to encode.
+///
+/// # Returns
+/// Returns a `Result` containing the number of bytes written (1 to 10), or the `io::Error` of the writer.
+///
+pub fn write_varint_le<W: Write>(writer: &mut W, value: u64) -> io::Result<usize> {
+    // A u64 needs at most 10 groups of 7 bits
+    let mut buf = [0; 10];
+    let mut value = value;
+    let mut len = 0;
+    loop {
+        // Take the lower 7 bits of the value
+        buf[len] = (value & 0x7F) as u8;
+        value >>= 7;
+        len += 1;
+        if value == 0 {
+            break;
+        }
+        // Set the continuation bit, more bytes follow
+        buf[len - 1] |= 0x80;
+    }
+    writer.write_all(&buf[..len])?;
+    Ok(len)
+}
+
+/// Writes the type and size of an object, the inverse of [read_type_and_varint_size].
+///
+/// The first byte holds the continuation bit, the 3 bits of the type and the lower 4 bits of the size,
+/// the rest of the size follows as a VarInt in little-endian format, see [write_varint_le].
+///
+/// # Parameters
+/// * `writer`: A destination implementing the Write trait.
+/// * `type_bits`: The type of the object, only its lower 3 bits are written.
+/// * `size`: The size of the object.
+///
+/// # Returns
+/// Returns a `Result` containing the number of bytes written, or the `io::Error` of the writer.
+///
+pub fn write_type_and_varint_size<W: Write>(writer: &mut W, type_bits: u8, size: usize) -> io::Result<usize> {
+    let size = size as u64;
+    let first_byte = ((type_bits & 0b0000_0111) << 4) | (size & 0b0000_1111) as u8;
+    let rest = size >> 4;
+    if rest == 0 {
+        writer.write_all(&[first_byte])?;
+        return Ok(1);
+    }
+    writer.write_all(&[first_byte | 0b1000_0000])?;
+    Ok(1 + write_varint_le(writer, rest)?)
+}
+
 /// The offset for an OffsetDelta object(big-endian order)
 /// # Arguments
 ///
@@ -354,6 +436,26 @@ mod tests {
 
         let mut reader = BrokenReader;
         assert!(!is_eof(&mut reader));
+        // the peek version reports the error
+        let mut reader = io::BufReader::new(BrokenReader);
+        assert!(is_eof_peek(&mut reader).is_err());
+    }
+
+    #[test]
+    fn eof_peek_keeps_the_byte() {
+        // not seekable, a consumed byte would be lost
+        let mut reader = io::BufReader::new(io::Read::chain(&b"a"[..], &b"bc"[..]));
+        assert!(!is_eof_peek(&mut reader).unwrap());
+        let mut buf = [0; 1];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"a");
+
+        let mut rest = Vec::new();
+        assert!(!is_eof_peek(&mut reader).unwrap());
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"bc");
+        assert!(is_eof_peek(&mut reader).unwrap());
+        assert!(is_eof_peek(&mut Cursor::new(&b""[..])).unwrap());
     }
 
     // Test case for a byte without a continuation bit (most significant bit is 0)
@@ -499,6 +601,68 @@ mod tests {
         assert!(result.is_err());
     }
 
+    /// Pseudo-random u64 values (xorshift64), the same on every run, spread over all the bit lengths
+    fn random_values(count: usize) -> Vec<u64> {
+        crate::utils::xorshift64(0x9E37_79B9_7F4A_7C15)
+            .take(count)
+            .enumerate()
+            .map(|(i, value)| value >> (i % 64))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_varint_le() {
+        let encode = |value: u64| {
+            let mut buf = Vec::new();
+            let len = write_varint_le(&mut buf, value).unwrap();
+            assert_eq!(len, buf.len());
+            buf
+        };
+        assert_eq!(encode(0), [0]);
+        assert_eq!(encode(127), [0x7F]);
+        assert_eq!(encode(128), [0x80, 0x01]);
+        assert_eq!(encode(300), [0b1010_1100, 0b0000_0010]);
+        let max = encode(u64::MAX);
+        assert_eq!(max.len(), 10);
+        assert_eq!(max[9], 0x01);
+    }
+
+    #[test]
+    fn test_varint_le_round_trip() {
+        let values = random_values(1000)
+            .into_iter()
+            .chain([0, 1, 127, 128, u64::MAX]);
+        for value in values {
+            let mut buf = Vec::new();
+            let written = write_varint_le(&mut buf, value).unwrap();
+            let mut cursor = Cursor::new(&buf);
+            let read = read_varint_le(&mut cursor).unwrap();
+            assert_eq!(read, (value, written), "{}", value);
+            assert!(is_eof(&mut cursor));
+        }
+    }
+
+    #[test]
+    fn test_type_and_varint_size_round_trip() {
+        let mut buf = Vec::new();
+        assert_eq!(write_type_and_varint_size(&mut buf, 5, 53).unwrap(), 2);
+        assert_eq!(buf, [0b1101_0101, 0b0000_0011]);
+
+        let sizes = random_values(1000).into_iter().chain([0, 15, 16, u64::MAX]);
+        for (i, size) in sizes.enumerate() {
+            let size = size as usize;
+            let type_bits = (i % 8) as u8;
+            let mut buf = Vec::new();
+            let written = write_type_and_varint_size(&mut buf, type_bits, size).unwrap();
+            let mut offset = 0;
+            let mut cursor = Cursor::new(&buf);
+            let read = read_type_and_varint_size(&mut cursor, &mut offset).unwrap();
+            assert_eq!(read, (type_bits, size), "{}", size);
+            assert_eq!(offset, written);
+            assert!(is_eof(&mut cursor));
+        }
+    }
+
     #[test]
     fn test_read_offset_encoding(){
         let data:Vec<u8> = vec![0b_1101_0101,0b_0000_0101];
@@ -11,4 +11,16 @@ pub fn read_bytes(file: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
 
 pub fn read_sha1(file: &mut impl Read) -> io::Result<SHA1> {
     SHA1::from_stream(file)
-}
\ No newline at end of file
+}
+
+/// Pseudo-random u64 values by xorshift64 from a non-zero `seed`, the same on every run.
+/// For tests & generated content, not for anything that must be unpredictable.
+pub fn xorshift64(seed: u64) -> impl Iterator<Item = u64> {
+    let mut state = seed;
+    std::iter::repeat_with(move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    })
+}
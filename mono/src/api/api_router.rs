@@ -2,92 +2,267 @@ use std::path::PathBuf;
 
 use axum::{
     body::Body,
-    extract::{Path, Query, State},
+    extract::{Query, State},
     response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use http::StatusCode;
+use http::{
+    header::{HeaderName, IF_MATCH, IF_NONE_MATCH},
+    HeaderMap, Method, StatusCode, Uri,
+};
+use tower_http::limit::RequestBodyLimitLayer;
 
 use ceres::{
-    api_service::ApiHandler,
+    api_service::{ApiHandler, BlobError, CreateFileError, DiffError},
     model::{
-        create_file::CreateFileInfo,
-        query::{BlobContentQuery, CodePreviewQuery},
-        tree::{LatestCommitInfo, TreeBriefItem, TreeCommitItem},
+        create_file::{CreateFileInfo, DeleteFileInfo, FileWrite, MoveFileInfo, WriteCondition},
+        diff::FileDiff,
+        query::{BlobContentQuery, CodePreviewQuery, DiffQuery, TreeQuery},
+        tree::{BlobDedupStats, LatestCommitInfo, TreeBriefItem, TreeCommitItem},
     },
 };
-use common::{errors::ProtocolError, model::CommonResult};
-use taurus::event::api_request::{ApiRequestEvent, ApiType};
+use common::model::{CommonResult, ErrorCode};
+use taurus::event::api_request::ApiType;
 
 use crate::api::error::ApiError;
+use crate::api::idempotency::{fingerprint, run_idempotent, IDEMPOTENCY_KEY};
 use crate::api::issue::issue_router;
 use crate::api::mr::mr_router;
+use crate::api::object_id::ObjectIdParam;
+use crate::api::openapi;
 use crate::api::user::user_router;
-use crate::api::MonoApiServiceState;
+use crate::api::{check_routable, MonoApiServiceState};
 
-pub fn routers() -> Router<MonoApiServiceState> {
+/// Build the api routers, write routes reject request bodies over `body_limit` bytes
+/// with `413 Payload Too Large`.
+pub fn routers(body_limit: usize) -> Router<MonoApiServiceState> {
+    let write_router = Router::new()
+        .route("/create-file", post(create_file))
+        .route("/create-files", post(create_files))
+        .route("/delete-file", post(delete_file))
+        .route("/move-file", post(move_file));
     let router = Router::new()
         .route("/status", get(life_cycle_check))
-        .route("/create-file", post(create_file))
         .route("/latest-commit", get(get_latest_commit))
         .route("/tree/commit-info", get(get_tree_commit_info))
         .route("/tree/path-can-clone", get(path_can_be_cloned))
         .route("/tree", get(get_tree_info))
+        .route("/diff", get(get_commit_diff))
+        .route("/tree/dedup-stats", get(get_dedup_stats))
         .route("/blob", get(get_blob_string))
         .route("/file/blob/:object_id", get(get_blob_file))
-        .route("/file/tree", get(get_tree_file));
+        .route("/file/tree", get(get_tree_file))
+        .route("/openapi.json", get(get_openapi));
     Router::new()
         .merge(router)
+        .merge(limit_body(write_router, body_limit))
         .merge(mr_router::routers())
         .merge(user_router::routers())
         .merge(issue_router::routers())
 }
 
+fn limit_body<S>(router: Router<S>, body_limit: usize) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(RequestBodyLimitLayer::new(body_limit))
+}
+
 async fn get_blob_string(
     Query(query): Query<BlobContentQuery>,
     state: State<MonoApiServiceState>,
-) -> Result<Json<CommonResult<String>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::Blob, &state.0.context.config);
+) -> Result<(StatusCode, Json<CommonResult<String>>), ApiError> {
+    state.notify(ApiType::Blob);
     let res = state
         .api_handler(query.path.clone().into())
         .await?
         .get_blob_as_string(query.path.into())
         .await;
 
-    let res = match res {
-        Ok(data) => CommonResult::success(data),
-        Err(err) => CommonResult::failed(&err.to_string()),
+    let (status, res) = match res {
+        Ok(data) => (StatusCode::OK, CommonResult::success(Some(data))),
+        Err(err) => {
+            let (status, code) = match err {
+                BlobError::NotFound(_) => (StatusCode::NOT_FOUND, ErrorCode::NotFound),
+                BlobError::IsDirectory(_) => (StatusCode::BAD_REQUEST, ErrorCode::Validation),
+                BlobError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal),
+            };
+            (
+                status,
+                CommonResult::failed_with_code(code, &err.to_string()),
+            )
+        }
     };
-    Ok(Json(res))
+    Ok((status, Json(res)))
 }
 
 async fn life_cycle_check() -> Result<impl IntoResponse, ApiError> {
     Ok(Json("http ready"))
 }
 
+async fn get_openapi() -> Json<serde_json::Value> {
+    Json(openapi::document())
+}
+
+/// Status and body of a `create-file` response, recorded for its `Idempotency-Key`
+pub type CreateFileOutcome = (StatusCode, CommonResult<String>);
+
+/// A request repeating the `Idempotency-Key` of an earlier one gets the earlier outcome
+/// without writing again, reusing the key for another file is refused.
 async fn create_file(
     state: State<MonoApiServiceState>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
     Json(json): Json<CreateFileInfo>,
-) -> Result<Json<CommonResult<String>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::CreateFile, &state.0.context.config);
+) -> Result<(StatusCode, Json<CommonResult<String>>), ApiError> {
+    state.notify(ApiType::CreateFile);
+    let handler = state.api_handler(json.path.clone().into()).await?;
+    let key = headers
+        .get(IDEMPOTENCY_KEY)
+        .map(|key| key.to_str().unwrap_or_default());
+    // the body as parsed, so the formatting of the JSON doesn't matter
+    let body = serde_json::to_vec(&json)?;
+    let fingerprint = fingerprint(&method, uri.path(), &body);
+    let (status, res) = run_idempotent(&state.create_file_outcomes, key, fingerprint, || async {
+        let res = handler
+            .create_monorepo_file(json, write_condition(&headers))
+            .await;
+        write_outcome(res)
+    })
+    .await;
+    Ok((status, Json(res)))
+}
+
+/// Write several files in one commit, none of them is written if one fails.
+/// - a batch touching an imported repo is refused as a whole, like `create-file` there
+async fn create_files(
+    state: State<MonoApiServiceState>,
+    Json(writes): Json<Vec<FileWrite>>,
+) -> Result<(StatusCode, Json<CommonResult<String>>), ApiError> {
+    state.notify(ApiType::CreateFile);
+    if writes.is_empty() {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(CommonResult::failed_with_code(
+                ErrorCode::Validation,
+                "no file to write",
+            )),
+        ));
+    }
+    let import_dir = &state.context.config.monorepo.import_dir;
+    let mut path = PathBuf::from(&writes[0].file.path);
+    for write in &writes {
+        let write_path = PathBuf::from(&write.file.path);
+        check_routable(&write_path)?;
+        if write_path.starts_with(import_dir) {
+            path = write_path;
+        }
+    }
+    let files = writes
+        .into_iter()
+        .map(|write| {
+            let condition = write.condition();
+            (write.file, condition)
+        })
+        .collect();
+    let res = state
+        .api_handler(path)
+        .await?
+        .create_monorepo_files(files)
+        .await;
+    let (status, res) = write_outcome(res);
+    Ok((status, Json(res)))
+}
+
+/// Delete a file or a whole directory, directories left empty are removed as well.
+async fn delete_file(
+    state: State<MonoApiServiceState>,
+    Json(json): Json<DeleteFileInfo>,
+) -> Result<(StatusCode, Json<CommonResult<String>>), ApiError> {
+    state.notify(ApiType::DeleteFile);
+    let path = PathBuf::from(json.path);
+    check_routable(&path)?;
     let res = state
-        .api_handler(json.path.clone().into())
+        .api_handler(path.clone())
         .await?
-        .create_monorepo_file(json.clone())
+        .delete_monorepo_file(path)
         .await;
-    let res = match res {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
+    let (status, res) = write_outcome(res);
+    Ok((status, Json(res)))
+}
+
+/// Rename a file or directory, moving a path into an imported repo or out of it is refused.
+async fn move_file(
+    state: State<MonoApiServiceState>,
+    Json(json): Json<MoveFileInfo>,
+) -> Result<(StatusCode, Json<CommonResult<String>>), ApiError> {
+    state.notify(ApiType::MoveFile);
+    let (from, to) = (PathBuf::from(json.from), PathBuf::from(json.to));
+    check_routable(&from)?;
+    check_routable(&to)?;
+    let import_dir = &state.context.config.monorepo.import_dir;
+    let path = if to.starts_with(import_dir) {
+        to.clone()
+    } else {
+        from.clone()
     };
-    Ok(Json(res))
+    let res = state
+        .api_handler(path)
+        .await?
+        .move_monorepo_file(from, to, json.overwrite)
+        .await;
+    let (status, res) = write_outcome(res);
+    Ok((status, Json(res)))
+}
+
+fn write_outcome(res: Result<(), CreateFileError>) -> CreateFileOutcome {
+    match res {
+        Ok(_) => (StatusCode::OK, CommonResult::success(None)),
+        Err(err @ CreateFileError::Conflict(_)) => (
+            StatusCode::CONFLICT,
+            CommonResult::failed_with_code(ErrorCode::Conflict, &err.to_string()),
+        ),
+        Err(err @ CreateFileError::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            CommonResult::failed_with_code(ErrorCode::NotFound, &err.to_string()),
+        ),
+        Err(err) => (
+            StatusCode::OK,
+            CommonResult::failed_with_code(ErrorCode::Internal, &err.to_string()),
+        ),
+    }
+}
+
+/// `If-Match` carries the oid of the blob the client edited and takes precedence,
+/// `If-None-Match` a list of oids the file must not have, or `*`. Without either the
+/// write may only create a new file, same as `If-None-Match: *`.
+fn write_condition(headers: &HeaderMap) -> WriteCondition {
+    let header = |name: HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(etag) = header(IF_MATCH) {
+        return WriteCondition::IfMatch(parse_etag(etag));
+    }
+    match header(IF_NONE_MATCH).map(str::trim) {
+        Some(etags) if etags != "*" => {
+            WriteCondition::IfNoneMatchAny(etags.split(',').map(parse_etag).collect())
+        }
+        _ => WriteCondition::IfNoneMatch,
+    }
+}
+
+fn parse_etag(etag: &str) -> String {
+    etag.trim()
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .to_string()
 }
 
 async fn get_latest_commit(
     Query(query): Query<CodePreviewQuery>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<LatestCommitInfo>, ApiError> {
-    ApiRequestEvent::notify(ApiType::LastestCommit, &state.0.context.config);
+    state.notify(ApiType::LastestCommit);
     let res = state
         .api_handler(query.path.clone().into())
         .await?
@@ -97,44 +272,91 @@ async fn get_latest_commit(
 }
 
 async fn get_tree_info(
-    Query(query): Query<CodePreviewQuery>,
+    Query(query): Query<TreeQuery>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<Vec<TreeBriefItem>>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::TreeInfo, &state.0.context.config);
+    state.notify(ApiType::TreeInfo);
     let res = state
         .api_handler(query.path.clone().into())
         .await?
         .get_tree_info(query.path.into())
         .await;
-    let res = match res {
-        Ok(data) => CommonResult::success(Some(data)),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
-    Ok(Json(res))
+    let limits = state.page_limits;
+    Ok(Json(CommonResult::from(res).map(|items| {
+        limits.paginate(items, query.page, query.per_page)
+    })))
 }
 
 async fn get_tree_commit_info(
-    Query(query): Query<CodePreviewQuery>,
+    Query(query): Query<TreeQuery>,
     state: State<MonoApiServiceState>,
-) -> Result<Json<CommonResult<Vec<TreeCommitItem>>>, ProtocolError> {
-    ApiRequestEvent::notify(ApiType::CommitInfo, &state.0.context.config);
+) -> Result<Json<CommonResult<Vec<TreeCommitItem>>>, ApiError> {
+    state.notify(ApiType::CommitInfo);
     let res = state
         .api_handler(query.path.clone().into())
         .await?
         .get_tree_commit_info(query.path.into())
         .await;
-    let res = match res {
-        Ok(data) => CommonResult::success(Some(data)),
-        Err(err) => CommonResult::failed(&err.to_string()),
+    let limits = state.page_limits;
+    Ok(Json(CommonResult::from(res).map(|items| {
+        limits.paginate(items, query.page, query.per_page)
+    })))
+}
+
+/// Diff between two commits of the monorepo, optionally limited to a path
+async fn get_commit_diff(
+    Query(query): Query<DiffQuery>,
+    state: State<MonoApiServiceState>,
+) -> Result<(StatusCode, Json<CommonResult<Vec<FileDiff>>>), ApiError> {
+    state.notify(ApiType::Diff);
+    let res = state
+        .monorepo()
+        .commit_diff(&query.base, &query.head, std::path::Path::new(&query.path))
+        .await;
+    let (status, res) = match res {
+        Ok(data) => (StatusCode::OK, CommonResult::success(Some(data))),
+        Err(err @ DiffError::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            CommonResult::failed_with_code(ErrorCode::NotFound, &err.to_string()),
+        ),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            CommonResult::failed_with_code(ErrorCode::Internal, &err.to_string()),
+        ),
     };
-    Ok(Json(res))
+    Ok((status, Json(res)))
+}
+
+/// Blob sharing of the tree of the ref `path`
+async fn get_dedup_stats(
+    Query(query): Query<CodePreviewQuery>,
+    state: State<MonoApiServiceState>,
+) -> Result<(StatusCode, Json<CommonResult<BlobDedupStats>>), ApiError> {
+    state.notify(ApiType::DedupStats);
+    let res = state.monorepo().blob_dedup_stats(&query.path).await;
+    let (status, res) = match res {
+        Ok(Some(stats)) => (StatusCode::OK, CommonResult::success(Some(stats))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            CommonResult::failed_with_code(
+                ErrorCode::NotFound,
+                &format!("No ref at path {}", query.path),
+            ),
+        ),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            CommonResult::failed_with_code(ErrorCode::Internal, &err.to_string()),
+        ),
+    };
+    Ok((status, Json(res)))
 }
 
 pub async fn get_blob_file(
     state: State<MonoApiServiceState>,
-    Path(oid): Path<String>,
+    ObjectIdParam(oid): ObjectIdParam,
 ) -> Result<Response, ApiError> {
     let api_handler = state.monorepo();
+    let oid = oid.to_string();
 
     let result = api_handler.get_raw_blob_by_hash(&oid).await.unwrap();
     let file_name = format!("inline; filename=\"{}\"", oid);
@@ -200,3 +422,98 @@ async fn path_can_be_cloned(
     };
     Ok(Json(CommonResult::success(Some(res))))
 }
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, routing::post, Json, Router};
+    use http::{header::CONTENT_TYPE, Request, StatusCode};
+    use tower::ServiceExt;
+
+    use ceres::model::create_file::WriteCondition;
+    use http::{
+        header::{IF_MATCH, IF_NONE_MATCH},
+        HeaderMap, HeaderValue,
+    };
+
+    use ceres::api_service::CreateFileError;
+    use common::model::ErrorCode;
+
+    use super::{limit_body, write_condition, write_outcome};
+
+    #[test]
+    fn test_write_outcome_status() {
+        let (status, res) = write_outcome(Ok(()));
+        assert_eq!(status, StatusCode::OK);
+        assert!(res.req_result);
+
+        let (status, res) = write_outcome(Err(CreateFileError::NotFound("/a/b.txt".to_owned())));
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(res.code, Some(ErrorCode::NotFound));
+
+        let (status, res) = write_outcome(Err(CreateFileError::Conflict("/a".to_owned())));
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(res.code, Some(ErrorCode::Conflict));
+    }
+
+    #[test]
+    fn test_write_condition_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(write_condition(&headers), WriteCondition::IfNoneMatch);
+
+        headers.insert(IF_MATCH, HeaderValue::from_static("\"2e65efe2\""));
+        assert_eq!(
+            write_condition(&headers),
+            WriteCondition::IfMatch("2e65efe2".to_string())
+        );
+        headers.insert(IF_MATCH, HeaderValue::from_static("W/\"2e65efe2\""));
+        assert_eq!(
+            write_condition(&headers),
+            WriteCondition::IfMatch("2e65efe2".to_string())
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert_eq!(write_condition(&headers), WriteCondition::IfNoneMatch);
+        headers.insert(
+            IF_NONE_MATCH,
+            HeaderValue::from_static("\"2e65efe2\", W/\"8ab686ea\""),
+        );
+        assert_eq!(
+            write_condition(&headers),
+            WriteCondition::IfNoneMatchAny(vec!["2e65efe2".to_string(), "8ab686ea".to_string()])
+        );
+        // If-Match wins
+        headers.insert(IF_MATCH, HeaderValue::from_static("\"2e65efe2\""));
+        assert_eq!(
+            write_condition(&headers),
+            WriteCondition::IfMatch("2e65efe2".to_string())
+        );
+    }
+
+    async fn echo(Json(body): Json<serde_json::Value>) -> Json<serde_json::Value> {
+        Json(body)
+    }
+
+    #[tokio::test]
+    async fn test_write_route_body_limit() {
+        let router = limit_body(Router::new().route("/create-file", post(echo)), 64);
+        let request = |content: String| {
+            Request::post("/create-file")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "content": content }).to_string(),
+                ))
+                .unwrap()
+        };
+
+        let res = router
+            .clone()
+            .oneshot(request("a".repeat(16)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = router.oneshot(request("a".repeat(128))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
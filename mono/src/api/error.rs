@@ -1,14 +1,76 @@
+use std::{io, path::PathBuf};
+
 use axum::response::{IntoResponse, Response};
-use http::StatusCode;
+use common::errors::MegaError;
+use http::{header, StatusCode};
+use sea_orm::DbErr;
+
+/// Seconds a client should wait before retrying when storage is unavailable
+const RETRY_AFTER_SECS: u64 = 5;
 
 #[derive(Debug)]
-pub struct ApiError(anyhow::Error);
+pub enum ApiError {
+    /// The storage backend or database is temporarily unavailable, the request can be retried
+    ServiceUnavailable(anyhow::Error),
+    /// The path can't be routed to the monorepo or an imported repo
+    NotAMonorepoPath(PathBuf),
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    /// [MegaError] is not a std Error, so it can't use the `?` conversion below
+    pub fn from_mega(err: MegaError) -> Self {
+        match err.error {
+            Some(err) => err.into(),
+            None => anyhow::anyhow!("unknown error, code: {}", err.code).into(),
+        }
+    }
+}
+
+/// Whether the error is caused by a lost or refused connection to storage
+fn is_storage_unavailable(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(err) = cause.downcast_ref::<DbErr>() {
+            return matches!(err, DbErr::ConnectionAcquire(_) | DbErr::Conn(_));
+        }
+        if let Some(err) = cause.downcast_ref::<io::Error>() {
+            return matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::TimedOut
+            );
+        }
+        false
+    })
+}
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        tracing::error!("Application error: {:#}", self.0);
+        match self {
+            ApiError::ServiceUnavailable(err) => {
+                tracing::warn!("Storage unavailable: {:#}", err);
 
-        (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+                    "Service temporarily unavailable, please retry later",
+                )
+                    .into_response()
+            }
+            ApiError::NotAMonorepoPath(path) => (
+                StatusCode::NOT_FOUND,
+                format!("{} is not a path in the monorepo", path.display()),
+            )
+                .into_response(),
+            ApiError::Internal(err) => {
+                tracing::error!("Application error: {:#}", err);
+
+                (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+            }
+        }
     }
 }
 
@@ -19,6 +81,50 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        let err = err.into();
+        if is_storage_unavailable(&err) {
+            Self::ServiceUnavailable(err)
+        } else {
+            Self::Internal(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{ConnAcquireErr, RuntimeErr};
+
+    use super::*;
+
+    #[test]
+    fn test_storage_outage_returns_503() {
+        let err = ApiError::from(DbErr::ConnectionAcquire(ConnAcquireErr::Timeout));
+        let res = err.into_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[header::RETRY_AFTER], "5");
+
+        let err = MegaError::from(DbErr::Conn(RuntimeErr::Internal("refused".to_string())));
+        let res = ApiError::from_mega(err).into_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let err = ApiError::from(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[test]
+    fn test_other_errors_return_500() {
+        let err = ApiError::from(DbErr::RecordNotFound("repo".to_string()));
+        let res = err.into_response();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(res.headers().get(header::RETRY_AFTER).is_none());
+
+        let err = ApiError::from(anyhow::anyhow!("bug"));
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
     }
 }
@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_CAPACITY: usize = 1024;
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+struct CacheEntry<T: ?Sized> {
+    value: Arc<T>,
+    inserted_at: Instant,
+    last_used: Instant,
+}
+
+/// A bounded cache of shared handlers keyed by path prefix.
+///
+/// Entries expire after `ttl` so a repo that is re-imported under the same path is picked up
+/// again; when the cache is full the least recently used entry is evicted.
+pub struct HandlerCache<T: ?Sized> {
+    entries: Mutex<HashMap<PathBuf, CacheEntry<T>>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<T: ?Sized> Default for HandlerCache<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_TTL)
+    }
+}
+
+impl<T: ?Sized> HandlerCache<T> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        HandlerCache {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Returns the handler cached for the longest prefix of `path`, if it has not expired.
+    pub fn get(&self, path: &Path) -> Option<Arc<T>> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        for prefix in path.ancestors() {
+            let expired = match entries.get_mut(prefix) {
+                Some(entry) if now.duration_since(entry.inserted_at) < self.ttl => {
+                    entry.last_used = now;
+                    return Some(entry.value.clone());
+                }
+                Some(_) => true,
+                None => false,
+            };
+            if expired {
+                entries.remove(prefix);
+            }
+        }
+        None
+    }
+
+    pub fn insert(&self, prefix: PathBuf, value: Arc<T>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&prefix) && entries.len() >= self.capacity {
+            let lru = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = lru {
+                entries.remove(&key);
+            }
+        }
+        let now = Instant::now();
+        entries.insert(
+            prefix,
+            CacheEntry {
+                value,
+                inserted_at: now,
+                last_used: now,
+            },
+        );
+    }
+
+    /// Drops every handler that may serve `path`: those cached at or below it, e.g. after the
+    /// repo there was replaced, and those above it, which a repo created at `path` now shadows.
+    pub fn invalidate(&self, path: &Path) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|prefix, _| !prefix.starts_with(path) && !path.starts_with(prefix));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::Path, sync::Arc, thread, time::Duration};
+
+    use super::HandlerCache;
+
+    #[test]
+    fn test_same_prefix_reuses_handler() {
+        let cache: HandlerCache<str> = HandlerCache::default();
+        cache.insert("/third-part/mega".into(), Arc::from("mega"));
+
+        let first = cache
+            .get(Path::new("/third-part/mega/src/main.rs"))
+            .unwrap();
+        let second = cache.get(Path::new("/third-part/mega/README.md")).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        assert!(cache.get(Path::new("/third-part/other")).is_none());
+        assert!(cache.get(Path::new("/third-part")).is_none());
+    }
+
+    #[test]
+    fn test_eviction_and_expiry() {
+        let cache: HandlerCache<str> = HandlerCache::new(2, Duration::from_secs(60));
+        cache.insert("/a".into(), Arc::from("a"));
+        cache.insert("/b".into(), Arc::from("b"));
+        thread::sleep(Duration::from_millis(1));
+        cache.get(Path::new("/a/file"));
+        cache.insert("/c".into(), Arc::from("c"));
+        assert!(cache.get(Path::new("/a")).is_some());
+        assert!(cache.get(Path::new("/b")).is_none());
+        assert!(cache.get(Path::new("/c")).is_some());
+
+        cache.invalidate(Path::new("/c"));
+        assert!(cache.get(Path::new("/c")).is_none());
+        // a repo pushed under a cached one
+        cache.invalidate(Path::new("/a/nested"));
+        assert!(cache.get(Path::new("/a/file")).is_none());
+
+        let cache: HandlerCache<str> = HandlerCache::new(2, Duration::from_millis(10));
+        cache.insert("/a".into(), Arc::from("a"));
+        thread::sleep(Duration::from_millis(20));
+        assert!(cache.get(Path::new("/a/file")).is_none());
+    }
+}
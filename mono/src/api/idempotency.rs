@@ -0,0 +1,377 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use http::{Method, StatusCode};
+use tokio::{sync::OnceCell, time::Instant};
+
+use common::model::{CommonResult, ErrorCode};
+use mercury::hash::SHA1;
+
+/// Header a client sets to make a write request safe to retry
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Longest key accepted, enough for a UUID or a hash with a prefix
+pub const MAX_KEY_LEN: usize = 255;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Keys remembered at most, the oldest one is dropped first
+const DEFAULT_CAPACITY: usize = 10_000;
+
+struct Entry<V> {
+    /// hash of the request which first used the key
+    fingerprint: SHA1,
+    outcome: Arc<OnceCell<V>>,
+    inserted_at: Instant,
+}
+
+struct Entries<V> {
+    by_key: HashMap<String, Entry<V>>,
+    /// keys in insertion order, so expired ones are found at the front
+    order: VecDeque<(Instant, String)>,
+}
+
+/// The key was used before by a different request
+#[derive(Debug, PartialEq, Eq)]
+pub struct KeyReused;
+
+/// Outcomes of write requests keyed by their `Idempotency-Key`.
+///
+/// The first request with a key runs, the ones repeating the key within `ttl` get its outcome
+/// instead, waiting for it if the first one is still running. An outcome that isn't kept, like
+/// a server error, is not replayed: the next request with the key runs again.
+/// Expired keys are dropped when a key is added, so are the oldest ones beyond `capacity`.
+pub struct IdempotencyCache<V> {
+    entries: Mutex<Entries<V>>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl<V> Default for IdempotencyCache<V> {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL, DEFAULT_CAPACITY)
+    }
+}
+
+impl<V> IdempotencyCache<V> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        IdempotencyCache {
+            entries: Mutex::new(Entries {
+                by_key: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            ttl,
+            capacity: capacity.max(1),
+        }
+    }
+}
+
+impl<V: Clone> IdempotencyCache<V> {
+    /// Returns the outcome recorded for `key`, running `f` to get it the first time.
+    /// - `fingerprint` identifies the request, reusing `key` for another one is [KeyReused]
+    /// - the outcome of `f` is only recorded if `keep` accepts it
+    /// - if the request running `f` is dropped, the next one with `key` runs it again
+    pub async fn get_or_run<F, Fut>(
+        &self,
+        key: &str,
+        fingerprint: SHA1,
+        f: F,
+        keep: impl FnOnce(&V) -> bool,
+    ) -> Result<V, KeyReused>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let outcome = {
+            let mut entries = self.entries.lock().unwrap();
+            let now = Instant::now();
+            match entries.by_key.get(key) {
+                Some(entry) if now.duration_since(entry.inserted_at) < self.ttl => {
+                    if entry.fingerprint != fingerprint {
+                        return Err(KeyReused);
+                    }
+                    entry.outcome.clone()
+                }
+                _ => {
+                    // an expired entry of `key` is dropped here with the others
+                    self.evict(&mut entries, now);
+                    let outcome = Arc::new(OnceCell::new());
+                    entries.by_key.insert(
+                        key.to_owned(),
+                        Entry {
+                            fingerprint,
+                            outcome: outcome.clone(),
+                            inserted_at: now,
+                        },
+                    );
+                    entries.order.push_back((now, key.to_owned()));
+                    outcome
+                }
+            }
+        };
+        let res = outcome
+            .get_or_try_init(|| async {
+                let v = f().await;
+                if keep(&v) {
+                    Ok(v)
+                } else {
+                    Err(v)
+                }
+            })
+            .await;
+        match res {
+            Ok(v) => Ok(v.clone()),
+            Err(v) => {
+                // forget the key, so it may be retried with another request too
+                let mut entries = self.entries.lock().unwrap();
+                if entries
+                    .by_key
+                    .get(key)
+                    .is_some_and(|entry| Arc::ptr_eq(&entry.outcome, &outcome))
+                {
+                    entries.by_key.remove(key);
+                }
+                Ok(v)
+            }
+        }
+    }
+
+    /// Drop the expired keys, and the oldest ones until there is room for a new key
+    fn evict(&self, entries: &mut Entries<V>, now: Instant) {
+        while let Some((inserted_at, _)) = entries.order.front() {
+            let expired = now.duration_since(*inserted_at) >= self.ttl;
+            if !expired && entries.order.len() < self.capacity {
+                break;
+            }
+            let (inserted_at, key) = entries.order.pop_front().unwrap();
+            // the key may have been dropped & inserted again since
+            if entries
+                .by_key
+                .get(&key)
+                .is_some_and(|entry| entry.inserted_at == inserted_at)
+            {
+                entries.by_key.remove(&key);
+            }
+        }
+    }
+}
+
+/// Hash of what makes two requests the same one: the method, the path and the body
+pub fn fingerprint(method: &Method, path: &str, body: &[u8]) -> SHA1 {
+    let mut data = format!("{} {}\n", method, path).into_bytes();
+    data.extend_from_slice(body);
+    SHA1::new(&data)
+}
+
+/// Outcomes worth replaying: successes and client errors a retry would get again, not server
+/// errors or conflicts with a concurrent write, which a retry may not run into
+pub fn is_replayable<T>((status, res): &(StatusCode, CommonResult<T>)) -> bool {
+    if status.is_success() {
+        return res.req_result;
+    }
+    status.is_client_error()
+        && !matches!(
+            *status,
+            StatusCode::REQUEST_TIMEOUT | StatusCode::CONFLICT | StatusCode::TOO_MANY_REQUESTS
+        )
+}
+
+/// Run the write `f` at most once per `key`, the outcome of a request repeating the key is
+/// replayed from `cache`; without a key `f` just runs.
+pub async fn run_idempotent<T, F, Fut>(
+    cache: &IdempotencyCache<(StatusCode, CommonResult<T>)>,
+    key: Option<&str>,
+    fingerprint: SHA1,
+    f: F,
+) -> (StatusCode, CommonResult<T>)
+where
+    T: Clone,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = (StatusCode, CommonResult<T>)>,
+{
+    let key = match key {
+        None => return f().await,
+        Some(key) if key.is_empty() || key.len() > MAX_KEY_LEN || !key.is_ascii() => {
+            return (
+                StatusCode::BAD_REQUEST,
+                CommonResult::failed_with_code(
+                    ErrorCode::Validation,
+                    &format!("Idempotency-Key must be 1 to {} ASCII chars", MAX_KEY_LEN),
+                ),
+            )
+        }
+        Some(key) => key,
+    };
+    cache
+        .get_or_run(key, fingerprint, f, is_replayable)
+        .await
+        .unwrap_or_else(|KeyReused| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                CommonResult::failed_with_code(
+                    ErrorCode::Validation,
+                    "Idempotency-Key was already used for a different request",
+                ),
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        body::{Body, Bytes},
+        extract::State,
+        http::{HeaderMap, Method, Request, StatusCode, Uri},
+        routing::post,
+        Json, Router,
+    };
+    use tower::ServiceExt;
+
+    use common::model::CommonResult;
+
+    use super::{fingerprint, run_idempotent, IdempotencyCache, IDEMPOTENCY_KEY};
+
+    type Outcome = (StatusCode, CommonResult<String>);
+
+    #[derive(Clone)]
+    struct TestState {
+        cache: Arc<IdempotencyCache<Outcome>>,
+        /// writes done, the ones listed in `failures` answer a server error
+        writes: Arc<AtomicUsize>,
+        failures: Arc<Vec<usize>>,
+    }
+
+    /// A write endpoint set up like `create-file`
+    async fn write(
+        State(state): State<TestState>,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> (StatusCode, Json<CommonResult<String>>) {
+        let key = headers
+            .get(IDEMPOTENCY_KEY)
+            .map(|key| key.to_str().unwrap_or_default());
+        let fingerprint = fingerprint(&method, uri.path(), &body);
+        let (status, res) = run_idempotent(&state.cache, key, fingerprint, || async {
+            let n = state.writes.fetch_add(1, Ordering::SeqCst) + 1;
+            if state.failures.contains(&n) {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    CommonResult::failed("storage unavailable"),
+                )
+            } else {
+                (
+                    StatusCode::OK,
+                    CommonResult::success(Some(format!("write {}", n))),
+                )
+            }
+        })
+        .await;
+        (status, Json(res))
+    }
+
+    fn router(cache: IdempotencyCache<Outcome>, failures: Vec<usize>) -> (Router, TestState) {
+        let state = TestState {
+            cache: Arc::new(cache),
+            writes: Arc::new(AtomicUsize::new(0)),
+            failures: Arc::new(failures),
+        };
+        let router = Router::new()
+            .route("/create-file", post(write))
+            .with_state(state.clone());
+        (router, state)
+    }
+
+    async fn send(router: &Router, key: Option<&str>, body: &str) -> (StatusCode, String) {
+        let mut req = Request::post("/create-file");
+        if let Some(key) = key {
+            req = req.header(IDEMPOTENCY_KEY, key);
+        }
+        let res = router
+            .clone()
+            .oneshot(req.body(Body::from(body.to_owned())).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let res: CommonResult<String> = serde_json::from_slice(&body).unwrap();
+        (status, res.data.unwrap_or(res.err_message))
+    }
+
+    #[tokio::test]
+    async fn test_repeated_key_returns_recorded_outcome() {
+        let (router, state) = router(IdempotencyCache::default(), vec![]);
+        let ok = |body: &str| (StatusCode::OK, body.to_owned());
+
+        assert_eq!(send(&router, Some("key-1"), "a").await, ok("write 1"));
+        assert_eq!(send(&router, Some("key-1"), "a").await, ok("write 1"));
+        assert_eq!(send(&router, Some("key-2"), "a").await, ok("write 2"));
+        assert_eq!(send(&router, None, "a").await, ok("write 3"));
+        assert_eq!(send(&router, None, "a").await, ok("write 4"));
+        assert_eq!(state.writes.load(Ordering::SeqCst), 4);
+
+        // same key, another request
+        let (status, _) = send(&router, Some("key-1"), "b").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = send(&router, Some(""), "a").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(state.writes.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_server_error_is_not_replayed() {
+        let (router, state) = router(IdempotencyCache::default(), vec![1]);
+
+        let (status, _) = send(&router, Some("key"), "a").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        // the retry writes, then is replayed
+        for _ in 0..2 {
+            assert_eq!(
+                send(&router, Some("key"), "a").await,
+                (StatusCode::OK, "write 2".to_owned())
+            );
+        }
+        assert_eq!(state.writes.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_key_expires() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 100);
+        let (router, _) = router(cache, vec![]);
+
+        assert_eq!(send(&router, Some("key"), "a").await.1, "write 1");
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert_eq!(send(&router, Some("key"), "a").await.1, "write 1");
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(send(&router, Some("key"), "a").await.1, "write 2");
+        assert_eq!(send(&router, Some("key"), "a").await.1, "write 2");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_oldest_key_is_evicted() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 2);
+        let (router, _) = router(cache, vec![]);
+
+        for key in ["key-1", "key-2", "key-3"] {
+            send(&router, Some(key), "a").await;
+            tokio::time::advance(Duration::from_secs(1)).await;
+        }
+        assert_eq!(send(&router, Some("key-3"), "a").await.1, "write 3");
+        assert_eq!(send(&router, Some("key-1"), "a").await.1, "write 4");
+    }
+}
@@ -37,14 +37,10 @@ async fn fetch_issue_list(
         .issue_stg()
         .get_issue_by_status(&json.additional.status, json.pagination)
         .await;
-    let res = match res {
-        Ok((items, total)) => CommonResult::success(Some(CommonPage {
-            items: items.into_iter().map(|m| m.into()).collect(),
-            total,
-        })),
-
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from(res).map(|(items, total)| CommonPage {
+        items: items.into_iter().map(|m| m.into()).collect(),
+        total,
+    });
     Ok(Json(res))
 }
 
@@ -52,19 +48,13 @@ async fn issue_detail(
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<IssueDetail>>, ApiError> {
-    let res = match state.issue_stg().get_issue(&link).await {
-        Ok(data) => {
-            if let Some(model) = data {
-                let mut detail: IssueDetail = model.into();
-                let conversations = state.mr_stg().get_mr_conversations(&link).await.unwrap();
-                detail.conversations = conversations.into_iter().map(|x| x.into()).collect();
-                CommonResult::success(Some(detail))
-            } else {
-                CommonResult::success(None)
-            }
-        }
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let mut res: CommonResult<IssueDetail> =
+        CommonResult::from(state.issue_stg().get_issue(&link).await)
+            .and_then(|model| CommonResult::success(model.map(|m| m.into())));
+    if let Some(detail) = res.data.as_mut() {
+        let conversations = state.mr_stg().get_mr_conversations(&link).await.unwrap();
+        detail.conversations = conversations.into_iter().map(|x| x.into()).collect();
+    }
     Ok(Json(res))
 }
 
@@ -78,10 +68,7 @@ async fn new_issue(
     let res = stg
         .add_issue_conversation(&res.link, user.user_id, Some(json.description))
         .await;
-    let res = match res {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(res);
     Ok(Json(res))
 }
 
@@ -90,10 +77,7 @@ async fn close_issue(
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
-    let res = match state.issue_stg().close_issue(&link).await {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(state.issue_stg().close_issue(&link).await);
     Ok(Json(res))
 }
 
@@ -102,10 +86,7 @@ async fn reopen_issue(
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
-    let res = match state.issue_stg().reopen_issue(&link).await {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(state.issue_stg().reopen_issue(&link).await);
     Ok(Json(res))
 }
 
@@ -117,14 +98,12 @@ async fn save_comment(
 ) -> Result<Json<CommonResult<String>>, ApiError> {
     let json_string =
         String::from_utf8(body.to_vec()).unwrap_or_else(|_| "Invalid UTF-8".to_string());
-    let res = match state
-        .issue_stg()
-        .add_issue_conversation(&link, user.user_id, Some(json_string))
-        .await
-    {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(
+        state
+            .issue_stg()
+            .add_issue_conversation(&link, user.user_id, Some(json_string))
+            .await,
+    );
     Ok(Json(res))
 }
 
@@ -133,9 +112,6 @@ async fn delete_comment(
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
     let res = state.issue_stg().remove_issue_conversation(id).await;
-    let res = match res {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(res);
     Ok(Json(res))
 }
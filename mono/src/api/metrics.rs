@@ -0,0 +1,280 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use taurus::event::api_request::ApiType;
+
+/// Upper bounds (seconds) of the request duration histogram buckets
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Routes not recorded, so health checks and scrapes don't skew the numbers
+const UNTRACKED_ROUTES: [&str; 2] = ["/status", "/metrics"];
+
+#[derive(Default)]
+struct Histogram {
+    /// non-cumulative count per bucket, the last one is `+Inf`
+    buckets: [u64; DURATION_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        let idx = DURATION_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[idx] += 1;
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// ApiType => count
+    api_requests: BTreeMap<&'static str, u64>,
+    /// (method, route, status) => count
+    http_requests: BTreeMap<(String, String, u16), u64>,
+    /// route => durations
+    durations: BTreeMap<String, Histogram>,
+    /// route => responses with a 5xx status
+    errors: BTreeMap<String, u64>,
+}
+
+/// In-memory metrics registry of the API, rendered in Prometheus text format by `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    pub fn api_request(&self, api: &ApiType) {
+        // label by variant only, the payload (e.g. an archive path) would create a series per value
+        let mut registry = self.registry.lock().unwrap();
+        *registry.api_requests.entry(api.name()).or_default() += 1;
+    }
+
+    pub fn observe_http(&self, method: &str, route: &str, status: u16, secs: f64) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .http_requests
+            .entry((method.to_owned(), route.to_owned(), status))
+            .or_default() += 1;
+        registry
+            .durations
+            .entry(route.to_owned())
+            .or_default()
+            .observe(secs);
+        if status >= 500 {
+            *registry.errors.entry(route.to_owned()).or_default() += 1;
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format (version 0.0.4)
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP mega_api_requests_total Requests per API type.\n");
+        out.push_str("# TYPE mega_api_requests_total counter\n");
+        for (api, count) in &registry.api_requests {
+            writeln!(
+                out,
+                "mega_api_requests_total{{api=\"{}\"}} {}",
+                escape(api),
+                count
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP mega_http_requests_total HTTP requests per route and status.\n");
+        out.push_str("# TYPE mega_http_requests_total counter\n");
+        for ((method, route, status), count) in &registry.http_requests {
+            writeln!(
+                out,
+                "mega_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(route),
+                status,
+                count
+            )
+            .unwrap();
+        }
+
+        out.push_str(
+            "# HELP mega_http_request_errors_total HTTP requests answered with a 5xx status.\n",
+        );
+        out.push_str("# TYPE mega_http_request_errors_total counter\n");
+        for (route, count) in &registry.errors {
+            writeln!(
+                out,
+                "mega_http_request_errors_total{{route=\"{}\"}} {}",
+                escape(route),
+                count
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP mega_http_request_duration_seconds HTTP request latency.\n");
+        out.push_str("# TYPE mega_http_request_duration_seconds histogram\n");
+        for (route, histogram) in &registry.durations {
+            let route = escape(route);
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "mega_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, cumulative
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "mega_http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "mega_http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "mega_http_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, histogram.count
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Middleware recording count, status & latency of each request, labeled by the matched route
+/// so path parameters don't create a series per object id.
+pub async fn track(State(metrics): State<Arc<Metrics>>, req: Request, next: Next) -> Response {
+    let route = match req.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_owned(),
+        None => return next.run(req).await,
+    };
+    if UNTRACKED_ROUTES.iter().any(|r| route.ends_with(r)) {
+        return next.run(req).await;
+    }
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let res = next.run(req).await;
+    metrics.observe_http(
+        &method,
+        &route,
+        res.status().as_u16(),
+        start.elapsed().as_secs_f64(),
+    );
+    res
+}
+
+pub async fn render(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        extract::State,
+        middleware,
+        routing::get,
+        Router,
+    };
+    use http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use taurus::event::api_request::{ApiType, ArchiveFormat};
+
+    use super::{render, track, Metrics};
+
+    async fn blob(State(metrics): State<Arc<Metrics>>) -> &'static str {
+        metrics.api_request(&ApiType::Blob);
+        "blob"
+    }
+
+    async fn fail() -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    async fn scrape(router: &Router) -> String {
+        let res = router
+            .clone()
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.api_request(&ApiType::Archive {
+            format: ArchiveFormat::Tar,
+            path: "/project".to_owned(),
+        });
+        let router = Router::new()
+            .route("/blob", get(blob))
+            .route("/fail", get(fail))
+            .route("/status", get(|| async { "ok" }))
+            .route("/metrics", get(render))
+            .route_layer(middleware::from_fn_with_state(metrics.clone(), track))
+            .with_state(metrics);
+
+        for uri in ["/blob", "/blob", "/fail", "/status"] {
+            router
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+        }
+
+        let text = scrape(&router).await;
+        assert!(text.contains("mega_api_requests_total{api=\"Blob\"} 2"));
+        assert!(text.contains("mega_api_requests_total{api=\"Archive\"} 1"));
+        assert!(text
+            .contains("mega_http_requests_total{method=\"GET\",route=\"/blob\",status=\"200\"} 2"));
+        assert!(text.contains("mega_http_request_errors_total{route=\"/fail\"} 1"));
+        assert!(text.contains("mega_http_request_duration_seconds_count{route=\"/blob\"} 2"));
+        assert!(text
+            .contains("mega_http_request_duration_seconds_bucket{route=\"/blob\",le=\"+Inf\"} 2"));
+        assert!(!text.contains("route=\"/status\""));
+
+        // scraping is not counted either
+        let text = scrape(&router).await;
+        assert!(!text.contains("route=\"/metrics\""));
+    }
+}
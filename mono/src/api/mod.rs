@@ -1,4 +1,7 @@
-use std::path::PathBuf;
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
 
 use async_session::MemoryStore;
 use axum::extract::FromRef;
@@ -6,22 +9,35 @@ use oauth2::basic::BasicClient;
 
 use ceres::{
     api_service::{
-        import_api_service::ImportApiService, mono_api_service::MonoApiService, ApiHandler,
+        import_api_service::ImportApiService, latest_commit_cache::LatestCommitCache,
+        mono_api_service::MonoApiService, ApiHandler,
     },
     protocol::repo::Repo,
 };
-use common::{errors::ProtocolError, model::CommonOptions};
+use common::{config::MonoConfig, model::CommonOptions};
 use jupiter::{
     context::Context,
     storage::{issue_storage::IssueStorage, mr_storage::MrStorage, user_storage::UserStorage},
 };
 
+use taurus::event::api_request::{ApiRequestEvent, ApiType};
+
+use crate::api::{
+    api_router::CreateFileOutcome, error::ApiError, handler_cache::HandlerCache,
+    idempotency::IdempotencyCache, metrics::Metrics,
+};
+
 pub mod api_router;
 pub mod error;
+pub mod handler_cache;
+pub mod idempotency;
 pub mod issue;
 pub mod lfs;
+pub mod metrics;
 pub mod mr;
 pub mod oauth;
+pub mod object_id;
+pub mod openapi;
 pub mod user;
 
 #[derive(Clone)]
@@ -31,6 +47,45 @@ pub struct MonoApiServiceState {
     pub oauth_client: Option<BasicClient>,
     // TODO: Replace MemoryStore
     pub store: Option<MemoryStore>,
+    pub api_handlers: Arc<HandlerCache<dyn ApiHandler>>,
+    pub metrics: Arc<Metrics>,
+    pub page_limits: PageLimits,
+    /// Outcomes of `create-file` requests by `Idempotency-Key`
+    pub create_file_outcomes: Arc<IdempotencyCache<CreateFileOutcome>>,
+    /// Latest commits of the monorepo paths, invalidated by the commits of the API
+    pub latest_commits: Arc<LatestCommitCache>,
+}
+
+/// Page sizes of the paginated APIs, from `[monorepo]` config
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageLimits {
+    pub default_per_page: u64,
+    pub max_per_page: u64,
+}
+
+impl From<&MonoConfig> for PageLimits {
+    fn from(config: &MonoConfig) -> Self {
+        PageLimits {
+            default_per_page: config.default_per_page,
+            max_per_page: config.max_per_page,
+        }
+    }
+}
+
+impl PageLimits {
+    /// The requested page size clamped to `1..=max_per_page`, the default one if omitted
+    pub fn per_page(&self, per_page: Option<u64>) -> u64 {
+        per_page
+            .unwrap_or(self.default_per_page)
+            .clamp(1, self.max_per_page.max(1))
+    }
+
+    /// Keep the items of the requested page (1-based), the first one if `page` is omitted
+    pub fn paginate<T>(&self, items: Vec<T>, page: Option<u64>, per_page: Option<u64>) -> Vec<T> {
+        let per_page = self.per_page(per_page) as usize;
+        let skip = (page.unwrap_or(1).max(1) as usize - 1).saturating_mul(per_page);
+        items.into_iter().skip(skip).take(per_page).collect()
+    }
 }
 
 impl FromRef<MonoApiServiceState> for MemoryStore {
@@ -55,9 +110,16 @@ impl MonoApiServiceState {
     fn monorepo(&self) -> MonoApiService {
         MonoApiService {
             context: self.context.clone(),
+            latest_commits: self.latest_commits.clone(),
         }
     }
 
+    /// Fire the [ApiRequestEvent] of `api` and count it in the metrics
+    fn notify(&self, api: ApiType) {
+        self.metrics.api_request(&api);
+        ApiRequestEvent::notify(api, &self.context.config);
+    }
+
     fn issue_stg(&self) -> IssueStorage {
         self.context.services.issue_storage()
     }
@@ -70,67 +132,103 @@ impl MonoApiServiceState {
         self.context.services.user_storage()
     }
 
-    async fn api_handler(&self, path: PathBuf) -> Result<Box<dyn ApiHandler>, ProtocolError> {
-        let import_dir = self.context.config.monorepo.import_dir.clone();
-        if path.starts_with(&import_dir) && path != import_dir {
-            if let Some(model) = self
-                .context
-                .services
-                .git_db_storage
-                .find_git_repo_like_path(path.to_str().unwrap())
-                .await
-                .unwrap()
-            {
+    /// Pick the handler of `path`: the imported repo containing it, or else the monorepo.
+    /// - a path that can't be in either, like a relative one, is [ApiError::NotAMonorepoPath]
+    /// - a path outside of `import_dir`, or `import_dir` itself, is always the monorepo
+    /// - a path under `import_dir` but in no imported repo is the monorepo too
+    async fn api_handler(&self, path: PathBuf) -> Result<Arc<dyn ApiHandler>, ApiError> {
+        check_routable(&path)?;
+        if !in_import_dir(&path, &self.context.config.monorepo.import_dir) {
+            return Ok(Arc::new(self.monorepo()));
+        }
+        if let Some(handler) = self.api_handlers.get(&path) {
+            return Ok(handler);
+        }
+        match self
+            .context
+            .services
+            .git_db_storage
+            .find_git_repo_like_path(path.to_str().unwrap())
+            .await
+            .map_err(ApiError::from_mega)?
+        {
+            Some(model) => {
                 let repo: Repo = model.into();
-                return Ok(Box::new(ImportApiService {
+                let repo_path = PathBuf::from(&repo.repo_path);
+                let handler: Arc<dyn ApiHandler> = Arc::new(ImportApiService {
                     context: self.context.clone(),
                     repo,
-                }));
+                });
+                self.api_handlers.insert(repo_path, handler.clone());
+                Ok(handler)
             }
+            None => Ok(Arc::new(self.monorepo())),
         }
-        Ok(Box::new(MonoApiService {
-            context: self.context.clone(),
-        }))
     }
 }
+
+/// Whether `path` may belong to an imported repo, i.e. is strictly under `import_dir`
+fn in_import_dir(path: &Path, import_dir: &Path) -> bool {
+    path.starts_with(import_dir) && path != import_dir
+}
+
+/// Paths are absolute & normalized, an empty path stands for the root (e.g. a file created under root)
+fn check_routable(path: &Path) -> Result<(), ApiError> {
+    if path.as_os_str().is_empty() {
+        return Ok(());
+    }
+    let mut components = path.components();
+    let routable = components.next() == Some(Component::RootDir)
+        && components.all(|c| matches!(c, Component::Normal(_)));
+    if routable {
+        Ok(())
+    } else {
+        Err(ApiError::NotAMonorepoPath(path.to_path_buf()))
+    }
+}
+
 pub mod util {
     use std::path::PathBuf;
 
     use axum::extract::State;
 
     use cedar_policy::Context;
-    use ceres::api_service::ApiHandler;
+    use ceres::api_service::{ApiHandler, BlobError};
     use saturn::{context::CedarContext, entitystore::EntityStore, util::EntityUid, ActionEnum};
 
-    use crate::api::MonoApiServiceState;
+    use crate::api::{error::ApiError, MonoApiServiceState};
 
-    pub async fn get_entitystore(path: PathBuf, state: State<MonoApiServiceState>) -> EntityStore {
+    /// Merge the `.mega_cedar.json` of `path` and of the directories above it, skipping those
+    /// that don't exist; one that can't be read or parsed is an error
+    pub async fn get_entitystore(
+        path: PathBuf,
+        state: State<MonoApiServiceState>,
+    ) -> Result<EntityStore, ApiError> {
         let mut entities: EntityStore = EntityStore::new();
         for component in path.ancestors() {
             if component != std::path::Path::new("/") {
                 let cedar_path = component.join(".mega_cedar.json");
-                let entity_str = state
-                    .monorepo()
-                    .get_blob_as_string(cedar_path)
-                    .await
-                    .unwrap();
-                if let Some(entity_str) = entity_str {
-                    entities.merge(serde_json::from_str(&entity_str).unwrap());
+                match state.monorepo().get_blob_as_string(cedar_path).await {
+                    Ok(entity_str) => entities.merge(serde_json::from_str(&entity_str)?),
+                    Err(BlobError::NotFound(_) | BlobError::IsDirectory(_)) => {}
+                    Err(err) => return Err(err.into()),
                 }
             }
         }
-        entities
+        Ok(entities)
     }
 
+    /// Whether `username` may do `operation` on `path`, the outer error is a failure to read
+    /// the entities of `path`
     pub async fn check_permissions(
         username: &str,
         path: &str,
         operation: ActionEnum,
         state: State<MonoApiServiceState>,
-    ) -> Result<(), saturn::context::Error> {
-        let entities = get_entitystore(path.into(), state).await;
+    ) -> Result<Result<(), saturn::context::Error>, ApiError> {
+        let entities = get_entitystore(path.into(), state).await?;
         let cedar_context = CedarContext::new(entities).unwrap();
-        cedar_context.is_authorized(
+        Ok(cedar_context.is_authorized(
             format!(r#"User::"{}""#, username)
                 .to_owned()
                 .parse::<EntityUid>()
@@ -142,6 +240,70 @@ pub mod util {
                 .parse::<EntityUid>()
                 .unwrap(),
             Context::empty(),
-        )
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use axum::response::IntoResponse;
+    use http::StatusCode;
+
+    use super::{check_routable, in_import_dir, PageLimits};
+
+    #[test]
+    fn test_unroutable_path_is_404() {
+        for path in ["/", "", "/third-part/mega/src", "/project/a/./b"] {
+            assert!(check_routable(Path::new(path)).is_ok(), "{}", path);
+        }
+        for path in ["project/src", "/project/../../etc", "./doc"] {
+            let err = check_routable(Path::new(path)).unwrap_err();
+            assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[test]
+    fn test_in_import_dir() {
+        let import_dir = Path::new("/third-part");
+        assert!(in_import_dir(Path::new("/third-part/mega"), import_dir));
+        assert!(in_import_dir(Path::new("/third-part/mega/src"), import_dir));
+        // the monorepo serves these without looking up an imported repo
+        for path in [
+            "/",
+            "",
+            "/third-part",
+            "/third-party/mega",
+            "/project/third-part",
+        ] {
+            assert!(!in_import_dir(Path::new(path), import_dir), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_page_limits() {
+        let limits = PageLimits {
+            default_per_page: 2,
+            max_per_page: 3,
+        };
+        assert_eq!(limits.per_page(None), 2);
+        assert_eq!(limits.per_page(Some(1)), 1);
+        assert_eq!(limits.per_page(Some(1000)), 3);
+        assert_eq!(limits.per_page(Some(0)), 1);
+
+        let items: Vec<u32> = (1..=10).collect();
+        // omitted per_page is the default, an over-max one is clamped
+        assert_eq!(limits.paginate(items.clone(), None, None), vec![1, 2]);
+        assert_eq!(limits.paginate(items.clone(), Some(2), None), vec![3, 4]);
+        assert_eq!(
+            limits.paginate(items.clone(), Some(2), Some(50)),
+            vec![4, 5, 6]
+        );
+        assert_eq!(
+            limits.paginate(items.clone(), None, Some(50)),
+            vec![1, 2, 3]
+        );
+        assert_eq!(limits.paginate(items, Some(5), Some(3)), Vec::<u32>::new());
     }
 }
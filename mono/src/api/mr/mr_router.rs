@@ -10,9 +10,9 @@ use bytes::Bytes;
 
 use callisto::db_enums::{ConvType, MergeStatus};
 use ceres::protocol::mr::MergeRequest;
-use common::model::{CommonPage, CommonResult, PageParams};
+use common::model::{CommonPage, CommonResult, ErrorCode, PageParams};
 use saturn::ActionEnum;
-use taurus::event::api_request::{ApiRequestEvent, ApiType};
+use taurus::event::api_request::ApiType;
 
 use crate::api::error::ApiError;
 use crate::api::mr::{FilesChangedItem, FilesChangedList, MRDetail, MRStatusParams, MrInfoItem};
@@ -46,22 +46,23 @@ async fn reopen_mr(
                 ActionEnum::EditMergeRequest,
                 state.clone(),
             )
-            .await
+            .await?
             .unwrap();
             let mut mr: MergeRequest = model.into();
             mr.status = MergeStatus::Open;
-            let res = match state
-                .mr_stg()
-                .reopen_mr(mr.into(), user.user_id, &user.name)
-                .await
-            {
-                Ok(_) => CommonResult::success(None),
-                Err(err) => CommonResult::failed(&err.to_string()),
-            };
+            let res = CommonResult::from_effect(
+                state
+                    .mr_stg()
+                    .reopen_mr(mr.into(), user.user_id, &user.name)
+                    .await,
+            );
             return Ok(Json(res));
         }
     }
-    Ok(Json(CommonResult::failed("not found")))
+    Ok(Json(CommonResult::failed_with_code(
+        ErrorCode::NotFound,
+        "not found",
+    )))
 }
 
 async fn close_mr(
@@ -77,22 +78,23 @@ async fn close_mr(
                 ActionEnum::EditMergeRequest,
                 state.clone(),
             )
-            .await
+            .await?
             .unwrap();
             let mut mr: MergeRequest = model.into();
             mr.status = MergeStatus::Closed;
-            let res = match state
-                .mr_stg()
-                .close_mr(mr.into(), user.user_id, &user.name)
-                .await
-            {
-                Ok(_) => CommonResult::success(None),
-                Err(err) => CommonResult::failed(&err.to_string()),
-            };
+            let res = CommonResult::from_effect(
+                state
+                    .mr_stg()
+                    .close_mr(mr.into(), user.user_id, &user.name)
+                    .await,
+            );
             return Ok(Json(res));
         }
     }
-    Ok(Json(CommonResult::failed("not found")))
+    Ok(Json(CommonResult::failed_with_code(
+        ErrorCode::NotFound,
+        "not found",
+    )))
 }
 
 async fn merge(
@@ -109,26 +111,26 @@ async fn merge(
                 ActionEnum::ApproveMergeRequest,
                 state.clone(),
             )
-            .await
+            .await?
             .unwrap();
-            ApiRequestEvent::notify(ApiType::MergeRequest, &state.0.context.config);
+            state.notify(ApiType::MergeRequest);
             let res = state.monorepo().merge_mr(&mut model.into()).await;
-            let res = match res {
-                Ok(_) => CommonResult::success(None),
-                Err(err) => CommonResult::failed(&err.to_string()),
-            };
-            ApiRequestEvent::notify(ApiType::MergeDone, &state.0.context.config);
+            let res = CommonResult::from_effect(res);
+            state.notify(ApiType::MergeDone);
             return Ok(Json(res));
         }
     }
-    Ok(Json(CommonResult::failed("not found")))
+    Ok(Json(CommonResult::failed_with_code(
+        ErrorCode::NotFound,
+        "not found",
+    )))
 }
 
 async fn fetch_mr_list(
     state: State<MonoApiServiceState>,
     Json(json): Json<PageParams<MRStatusParams>>,
 ) -> Result<Json<CommonResult<CommonPage<MrInfoItem>>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::MergeList, &state.0.context.config);
+    state.notify(ApiType::MergeList);
     let status = json.additional.status;
     let status = if status == "open" {
         vec![MergeStatus::Open]
@@ -137,17 +139,14 @@ async fn fetch_mr_list(
     } else {
         vec![MergeStatus::Open, MergeStatus::Closed, MergeStatus::Merged]
     };
-    let res = match state
+    let res = state
         .mr_stg()
         .get_mr_by_status(status, json.pagination.page, json.pagination.per_page)
-        .await
-    {
-        Ok((items, total)) => CommonResult::success(Some(CommonPage {
-            items: items.into_iter().map(|m| m.into()).collect(),
-            total,
-        })),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+        .await;
+    let res = CommonResult::from(res).map(|(items, total)| CommonPage {
+        items: items.into_iter().map(|m| m.into()).collect(),
+        total,
+    });
     Ok(Json(res))
 }
 
@@ -155,20 +154,13 @@ async fn mr_detail(
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<MRDetail>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::MergeDetail, &state.0.context.config);
-    let res = match state.mr_stg().get_mr(&link).await {
-        Ok(data) => {
-            if let Some(model) = data {
-                let mut detail: MRDetail = model.into();
-                let conversations = state.mr_stg().get_mr_conversations(&link).await.unwrap();
-                detail.conversations = conversations.into_iter().map(|x| x.into()).collect();
-                CommonResult::success(Some(detail))
-            } else {
-                CommonResult::success(None)
-            }
-        }
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    state.notify(ApiType::MergeDetail);
+    let mut res: CommonResult<MRDetail> = CommonResult::from(state.mr_stg().get_mr(&link).await)
+        .and_then(|model| CommonResult::success(model.map(|m| m.into())));
+    if let Some(detail) = res.data.as_mut() {
+        let conversations = state.mr_stg().get_mr_conversations(&link).await.unwrap();
+        detail.conversations = conversations.into_iter().map(|x| x.into()).collect();
+    }
     Ok(Json(res))
 }
 
@@ -177,22 +169,17 @@ async fn get_mr_files_changed(
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<FilesChangedList>>, ApiError> {
     let res = state.monorepo().content_diff(&link).await;
-    let res = match res {
-        Ok(data) => {
-            let diff_files = extract_files_with_status(&data);
-            let mut diff_list: Vec<FilesChangedItem> = vec![];
-            for (path, status) in diff_files {
-                diff_list.push(FilesChangedItem { path, status });
-            }
-
-            CommonResult::success(Some(FilesChangedList {
-                files: diff_list,
-                content: data,
-            }))
+    let res = CommonResult::from(res).map(|data| {
+        let diff_files = extract_files_with_status(&data);
+        let mut diff_list: Vec<FilesChangedItem> = vec![];
+        for (path, status) in diff_files {
+            diff_list.push(FilesChangedItem { path, status });
         }
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
-
+        FilesChangedList {
+            files: diff_list,
+            content: data,
+        }
+    });
     Ok(Json(res))
 }
 
@@ -218,7 +205,7 @@ async fn save_comment(
             .unwrap();
         CommonResult::success(None)
     } else {
-        CommonResult::failed("Invalid link")
+        CommonResult::failed_with_code(ErrorCode::NotFound, "Invalid link")
     };
     Ok(Json(res))
 }
@@ -227,10 +214,7 @@ async fn delete_comment(
     Path(conv_id): Path<i64>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
-    let res = match state.mr_stg().remove_mr_conversation(conv_id).await {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(state.mr_stg().remove_mr_conversation(conv_id).await);
     Ok(Json(res))
 }
 
@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    response::{IntoResponse, Response},
+    RequestPartsExt,
+};
+use http::{request::Parts, StatusCode};
+
+use mercury::hash::SHA1;
+
+/// The object id of a route like `/file/blob/:object_id`, a SHA1 of 40 hex digits.
+/// A malformed id is rejected with `400 Bad Request` before the handler runs, so it never
+/// reaches the storage; `Display` gives the lowercase form the objects are saved with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectIdParam(pub SHA1);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ObjectIdParam
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Path(oid) = parts
+            .extract::<Path<String>>()
+            .await
+            .map_err(IntoResponse::into_response)?;
+        SHA1::from_str(&oid).map(ObjectIdParam).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("invalid object id '{}': {}", oid, e),
+            )
+                .into_response()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, routing::get, Router};
+    use http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::ObjectIdParam;
+
+    async fn echo(ObjectIdParam(oid): ObjectIdParam) -> String {
+        oid.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_object_id_param() {
+        let router = Router::new().route("/file/blob/:object_id", get(echo));
+        let get = |oid: &str| {
+            router.clone().oneshot(
+                Request::get(format!("/file/blob/{}", oid))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let oid = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        // the objects are saved lowercase
+        for requested in [oid.to_owned(), oid.to_uppercase()] {
+            let res = get(&requested).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, oid);
+        }
+
+        // the handler isn't reached
+        for invalid in [
+            "8ab686ea",
+            "8ab686eafeb1f44702738c8b0f24f2567c36da6z",
+            "8ab686eafeb1f44702738c8b0f24f2567c36da6d00",
+            "..%2F..%2Fetc%2Fpasswd",
+        ] {
+            let res = get(invalid).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", invalid);
+        }
+    }
+}
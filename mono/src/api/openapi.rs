@@ -0,0 +1,464 @@
+//! OpenAPI 3.0 description of the api router, served at `/api/v1/openapi.json`.
+//!
+//! Response and body schemas are derived by serializing sample values of the model structs,
+//! so a field added to a struct shows up here (and a removed one fails to compile).
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use ceres::model::{
+    create_file::{CreateFileInfo, FileWrite},
+    diff::{DiffHunk, DiffKind, FileDiff},
+    tree::{BlobDedupStats, LatestCommitInfo, TreeBriefItem, TreeCommitItem, UserInfo},
+};
+use common::model::{CommonResult, ErrorCode};
+
+use crate::api::idempotency::MAX_KEY_LEN;
+
+/// Infer the JSON schema of a serialized value
+fn schema_of(value: &Value) -> Value {
+    match value {
+        // `Option` fields are `null` in the samples, their type is unknown
+        Value::Null => json!({ "nullable": true }),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(n) if n.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(items) => json!({
+            "type": "array",
+            "items": items.first().map(schema_of).unwrap_or_else(|| json!({})),
+        }),
+        Value::Object(fields) => {
+            let properties: Map<String, Value> = fields
+                .iter()
+                .map(|(name, value)| (name.clone(), schema_of(value)))
+                .collect();
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": fields.keys().collect::<Vec<_>>(),
+            })
+        }
+    }
+}
+
+fn schema<T: Serialize>(sample: T) -> Value {
+    schema_of(&serde_json::to_value(sample).unwrap())
+}
+
+/// Schema of `CommonResult<T>`, `data` is absent when the request failed and `code` when it succeeded
+fn common_result<T: Serialize>(data: Option<T>) -> Value {
+    let mut schema = schema(CommonResult::success(data));
+    let codes = [
+        ErrorCode::Unknown,
+        ErrorCode::NotFound,
+        ErrorCode::Ambiguous,
+        ErrorCode::Validation,
+        ErrorCode::Conflict,
+        ErrorCode::Internal,
+    ];
+    schema["properties"]["code"] = json!({ "type": "string", "enum": codes });
+    schema["required"] = json!(["req_result", "err_message"]);
+    schema
+}
+
+fn user_info() -> UserInfo {
+    UserInfo::default()
+}
+
+fn latest_commit_info() -> LatestCommitInfo {
+    LatestCommitInfo {
+        oid: String::new(),
+        date: String::new(),
+        short_message: String::new(),
+        author: user_info(),
+        committer: user_info(),
+        status: String::new(),
+    }
+}
+
+fn tree_commit_item() -> TreeCommitItem {
+    TreeCommitItem {
+        oid: String::new(),
+        name: String::new(),
+        content_type: String::new(),
+        message: String::new(),
+        date: String::new(),
+    }
+}
+
+fn tree_brief_item() -> TreeBriefItem {
+    TreeBriefItem {
+        name: String::new(),
+        path: String::new(),
+        content_type: String::new(),
+    }
+}
+
+/// Query parameters of `CodePreviewQuery`
+fn code_preview_query() -> Value {
+    json!([
+        { "name": "path", "in": "query", "required": false,
+          "schema": { "type": "string", "default": "/" } },
+        { "name": "refs", "in": "query", "required": false,
+          "schema": { "type": "string" } },
+    ])
+}
+
+/// Query parameters of `TreeQuery`, the page size is clamped to `monorepo.max_per_page`
+fn tree_query() -> Value {
+    json!([
+        { "name": "path", "in": "query", "required": false,
+          "schema": { "type": "string", "default": "/" } },
+        { "name": "page", "in": "query", "required": false,
+          "schema": { "type": "integer", "minimum": 1 } },
+        { "name": "per_page", "in": "query", "required": false,
+          "schema": { "type": "integer", "minimum": 1 } },
+    ])
+}
+
+/// Query parameters of `DiffQuery`
+fn diff_query() -> Value {
+    json!([
+        { "name": "base", "in": "query", "required": true,
+          "schema": { "type": "string" } },
+        { "name": "head", "in": "query", "required": true,
+          "schema": { "type": "string" } },
+        { "name": "path", "in": "query", "required": false,
+          "schema": { "type": "string", "default": "/" } },
+    ])
+}
+
+/// Schema of `FileDiff`, the oid of the missing side is `null`
+fn file_diff() -> Value {
+    let mut schema = schema(FileDiff {
+        old_oid: Some(String::new()),
+        new_oid: Some(String::new()),
+        hunks: vec![DiffHunk {
+            lines: vec![String::new()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+    let kinds = [DiffKind::Added, DiffKind::Deleted, DiffKind::Modified];
+    schema["properties"]["kind"]["enum"] = json!(kinds);
+    for oid in ["old_oid", "new_oid"] {
+        schema["properties"][oid]["nullable"] = json!(true);
+    }
+    schema
+}
+
+/// Query parameters of `BlobContentQuery`
+fn blob_content_query() -> Value {
+    json!([
+        { "name": "path", "in": "query", "required": false,
+          "schema": { "type": "string", "default": "/" } },
+    ])
+}
+
+/// Schema of `FileWrite`, `if_match` is the blob oid of the file to overwrite, `*` for any
+fn file_write() -> Value {
+    let mut schema = schema(FileWrite {
+        if_match: Some(String::new()),
+        ..Default::default()
+    });
+    let required: Vec<Value> = schema["required"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|name| *name != "if_match")
+        .cloned()
+        .collect();
+    schema["required"] = json!(required);
+    schema
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn binary_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } }
+        },
+    })
+}
+
+fn get(summary: &str, parameters: Value, responses: Value) -> Value {
+    json!({ "get": { "summary": summary, "parameters": parameters, "responses": responses } })
+}
+
+/// Build the OpenAPI document of the routes in [api_router::routers](super::api_router::routers)
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    paths.insert(
+        "/status".to_owned(),
+        get(
+            "Liveness check",
+            json!([]),
+            json!({ "200": json_response("server is ready", json!({ "type": "string" })) }),
+        ),
+    );
+    paths.insert(
+        "/create-file".to_owned(),
+        json!({ "post": {
+            "summary": "Create a file or directory, or overwrite a file with If-Match",
+            "parameters": [{
+                "name": "If-Match", "in": "header", "required": false,
+                "description": "blob oid of the file to overwrite, `*` for any",
+                "schema": { "type": "string" },
+            }, {
+                "name": "Idempotency-Key", "in": "header", "required": false,
+                "description": "a retry with the same key gets the outcome of the first request",
+                "schema": { "type": "string", "maxLength": MAX_KEY_LEN },
+            }],
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": schema(CreateFileInfo::default()) } },
+            },
+            "responses": {
+                "200": json_response("created", common_result::<String>(None)),
+                "400": json_response("invalid Idempotency-Key", common_result::<String>(None)),
+                "409": json_response("precondition failed", common_result::<String>(None)),
+                "413": { "description": "request body too large" },
+            },
+        }}),
+    );
+    paths.insert(
+        "/create-files".to_owned(),
+        json!({ "post": {
+            "summary": "Create or overwrite several files and directories in one commit",
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": {
+                    "type": "array",
+                    "items": file_write(),
+                } } },
+            },
+            "responses": {
+                "200": json_response("all written", common_result::<String>(None)),
+                "400": json_response("no file to write", common_result::<String>(None)),
+                "409": json_response(
+                    "a precondition failed, nothing is written",
+                    common_result::<String>(None)
+                ),
+                "413": { "description": "request body too large" },
+            },
+        }}),
+    );
+    paths.insert(
+        "/delete-file".to_owned(),
+        json!({ "post": {
+            "summary": "Delete a file or directory in a new commit",
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": {
+                    "type": "object",
+                    "required": ["path"],
+                    "properties": { "path": { "type": "string" } },
+                } } },
+            },
+            "responses": {
+                "200": json_response("deleted", common_result::<String>(None)),
+                "404": json_response("path not found", common_result::<String>(None)),
+                "413": { "description": "request body too large" },
+            },
+        }}),
+    );
+    paths.insert(
+        "/move-file".to_owned(),
+        json!({ "post": {
+            "summary": "Move or rename a file or directory in a new commit",
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": {
+                    "type": "object",
+                    "required": ["from", "to"],
+                    "properties": {
+                        "from": { "type": "string" },
+                        "to": { "type": "string" },
+                        "overwrite": { "type": "boolean", "default": false },
+                    },
+                } } },
+            },
+            "responses": {
+                "200": json_response("moved", common_result::<String>(None)),
+                "404": json_response("`from` not found", common_result::<String>(None)),
+                "409": json_response(
+                    "`to` exists and `overwrite` is not set",
+                    common_result::<String>(None)
+                ),
+                "413": { "description": "request body too large" },
+            },
+        }}),
+    );
+    paths.insert(
+        "/latest-commit".to_owned(),
+        get(
+            "Latest commit of a path",
+            code_preview_query(),
+            json!({ "200": json_response("latest commit", schema(latest_commit_info())) }),
+        ),
+    );
+    paths.insert(
+        "/tree/commit-info".to_owned(),
+        get(
+            "Items of a tree with their latest commit",
+            tree_query(),
+            json!({ "200": json_response(
+                "tree items",
+                common_result(Some(vec![tree_commit_item()]))
+            ) }),
+        ),
+    );
+    paths.insert(
+        "/tree/path-can-clone".to_owned(),
+        get(
+            "Whether the path can be cloned",
+            blob_content_query(),
+            json!({ "200": json_response("can be cloned", common_result(Some(true))) }),
+        ),
+    );
+    paths.insert(
+        "/tree".to_owned(),
+        get(
+            "Items of a tree",
+            tree_query(),
+            json!({ "200": json_response(
+                "tree items",
+                common_result(Some(vec![tree_brief_item()]))
+            ) }),
+        ),
+    );
+    paths.insert(
+        "/tree/dedup-stats".to_owned(),
+        get(
+            "Blob deduplication stats of the tree of a ref",
+            code_preview_query(),
+            json!({
+                "200": json_response(
+                    "stats",
+                    common_result(Some(BlobDedupStats::default()))
+                ),
+                "404": json_response("no ref at path", common_result::<String>(None)),
+            }),
+        ),
+    );
+    let mut diff_result = common_result::<Vec<Value>>(None);
+    diff_result["properties"]["data"] = json!({ "type": "array", "items": file_diff() });
+    paths.insert(
+        "/diff".to_owned(),
+        get(
+            "Files changed between two commits",
+            diff_query(),
+            json!({
+                "200": json_response("changed files", diff_result),
+                "404": json_response("commit not found", common_result::<String>(None)),
+            }),
+        ),
+    );
+    let blob_result = common_result(Some(String::new()));
+    paths.insert(
+        "/blob".to_owned(),
+        get(
+            "Content of a file as text",
+            blob_content_query(),
+            json!({
+                "200": json_response("file content", blob_result.clone()),
+                "400": json_response("path is a directory", blob_result.clone()),
+                "404": json_response("file not found", blob_result.clone()),
+                "500": json_response("blob can't be read", blob_result),
+            }),
+        ),
+    );
+    paths.insert(
+        "/file/blob/{object_id}".to_owned(),
+        get(
+            "Download a blob by its oid",
+            json!([{
+                "name": "object_id", "in": "path", "required": true,
+                "schema": { "type": "string" },
+            }]),
+            json!({
+                "200": binary_response("raw blob"),
+                "404": { "description": "blob not found" },
+            }),
+        ),
+    );
+    paths.insert(
+        "/file/tree".to_owned(),
+        get(
+            "Download a tree object",
+            code_preview_query(),
+            json!({
+                "200": binary_response("raw tree"),
+                "404": { "description": "tree not found" },
+            }),
+        ),
+    );
+    paths.insert(
+        "/openapi.json".to_owned(),
+        get(
+            "This document",
+            json!([]),
+            json!({ "200": json_response("OpenAPI document", json!({ "type": "object" })) }),
+        ),
+    );
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Mega mono API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": "/api/v1" }],
+        "paths": paths,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use regex::Regex;
+
+    use super::document;
+
+    #[test]
+    fn test_every_route_documented() {
+        let doc = document();
+        let paths = doc["paths"].as_object().unwrap();
+
+        // routes registered in `api_router::routers`, `:param` in OpenAPI is `{param}`
+        let source = include_str!("api_router.rs");
+        let routers = &source[source.find("pub fn routers").unwrap()..];
+        let routers = &routers[..routers.find("\n}\n").unwrap()];
+        let route = Regex::new(r#"\.route\("([^"]+)""#).unwrap();
+        let param = Regex::new(r":(\w+)").unwrap();
+        let mut count = 0;
+        for caps in route.captures_iter(routers) {
+            let path = param.replace_all(&caps[1], "{${1}}");
+            assert!(
+                paths.contains_key(path.as_ref()),
+                "{} is not documented",
+                path
+            );
+            count += 1;
+        }
+        assert_eq!(count, paths.len(), "documented routes that don't exist");
+
+        let create = &paths["/create-file"]["post"]["requestBody"]["content"]["application/json"];
+        let properties = create["schema"]["properties"].as_object().unwrap();
+        for field in ["is_directory", "name", "path", "content"] {
+            assert!(properties.contains_key(field));
+        }
+        let tree = &paths["/tree"]["get"]["responses"]["200"]["content"]["application/json"];
+        assert_eq!(
+            tree["schema"]["properties"]["data"]["items"]["properties"]["content_type"]["type"],
+            "string"
+        );
+    }
+}
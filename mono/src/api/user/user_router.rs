@@ -56,10 +56,7 @@ async fn add_key(
         .user_stg()
         .save_ssh_key(user.user_id, &title, &json.ssh_key, &key.fingerprint(HashAlg::Sha256).to_string())
         .await;
-    let res = match res {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(res);
     Ok(Json(res))
 }
 
@@ -69,10 +66,7 @@ async fn remove_key(
     Path(key_id): Path<i64>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
     let res = state.user_stg().delete_ssh_key(user.user_id, key_id).await;
-    let res = match res {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(res);
     Ok(Json(res))
 }
 
@@ -80,11 +74,8 @@ async fn list_key(
     user: LoginUser,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<Vec<ListSSHKey>>>, ApiError> {
-    let res = state.user_stg().list_user_ssh(user.user_id).await;
-    let res = match res {
-        Ok(data) => CommonResult::success(Some(data.into_iter().map(|x| x.into()).collect())),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from(state.user_stg().list_user_ssh(user.user_id).await)
+        .map(|data| data.into_iter().map(|x| x.into()).collect());
     Ok(Json(res))
 }
 
@@ -93,11 +84,7 @@ async fn generate_token(
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
     let res = state.user_stg().generate_token(user.user_id).await;
-    let res = match res {
-        Ok(data) => CommonResult::success(Some(data)),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
-    Ok(Json(res))
+    Ok(Json(res.into()))
 }
 
 async fn remove_token(
@@ -106,10 +93,7 @@ async fn remove_token(
     Path(key_id): Path<i64>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
     let res = state.user_stg().delete_token(user.user_id, key_id).await;
-    let res = match res {
-        Ok(_) => CommonResult::success(None),
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from_effect(res);
     Ok(Json(res))
 }
 
@@ -117,14 +101,8 @@ async fn list_token(
     user: LoginUser,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<Vec<ListToken>>>, ApiError> {
-    let res = state.user_stg().list_token(user.user_id).await;
-    let res = match res {
-        Ok(data) => {
-            let res = data.into_iter().map(|x| x.into()).collect();
-            CommonResult::success(Some(res))
-        }
-        Err(err) => CommonResult::failed(&err.to_string()),
-    };
+    let res = CommonResult::from(state.user_stg().list_token(user.user_id).await)
+        .map(|data| data.into_iter().map(|x| x.into()).collect());
     Ok(Json(res))
 }
 
@@ -133,7 +111,7 @@ async fn repo_permissions(
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
     let path = std::path::PathBuf::from(query.get("path").unwrap());
-    let _ = util::get_entitystore(path, state).await;
+    let _ = util::get_entitystore(path, state).await?;
     // TODO
     Ok(Json(CommonResult::success(Some(String::new()))))
 }
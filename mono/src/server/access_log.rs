@@ -0,0 +1,179 @@
+use std::time::Instant;
+
+use axum::{
+    body::HttpBody,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Local;
+
+use common::config::AccessLogFormat;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Middleware writing one access log line per request to the `access_log` tracing target.
+///
+/// The request id is taken from `X-Request-Id` or generated, and echoed in the response.
+pub async fn log_request(
+    State(format): State<AccessLogFormat>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let version = req.version();
+    let (referer, user_agent) = (
+        header_str(req.headers(), header::REFERER),
+        header_str(req.headers(), header::USER_AGENT),
+    );
+
+    let start = Instant::now();
+    let mut res = next.run(req).await;
+    let latency = start.elapsed();
+
+    let status = res.status().as_u16();
+    let bytes = res
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+        .or_else(|| res.body().size_hint().exact());
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID, value);
+    }
+
+    match format {
+        AccessLogFormat::Structured => tracing::info!(
+            target: "access_log",
+            request_id = %request_id,
+            method = %method,
+            path = %uri.path(),
+            status,
+            latency_ms = latency.as_millis() as u64,
+            bytes = bytes.unwrap_or(0),
+            "request"
+        ),
+        AccessLogFormat::Common | AccessLogFormat::Combined => {
+            // the remote address is not known behind the router, log "-" as for a missing field
+            let mut line = format!(
+                "- - - [{}] \"{} {} {:?}\" {} {}",
+                Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+                method,
+                uri,
+                version,
+                status,
+                bytes.map_or("-".to_string(), |b| b.to_string()),
+            );
+            if format == AccessLogFormat::Combined {
+                line += &format!(" \"{}\" \"{}\"", referer, user_agent);
+            }
+            tracing::info!(target: "access_log", request_id = %request_id, "{}", line);
+        }
+    }
+    res
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> String {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{body::Body, middleware, routing::get, Router};
+    use http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use common::config::AccessLogFormat;
+
+    use super::{log_request, REQUEST_ID};
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Capture {
+        fn lines(&self) -> Vec<String> {
+            let buf = self.0.lock().unwrap();
+            String::from_utf8_lossy(&buf)
+                .lines()
+                .map(str::to_owned)
+                .collect()
+        }
+    }
+
+    fn app(format: AccessLogFormat) -> Router {
+        Router::new()
+            .route("/blob", get(|| async { "hello" }))
+            .layer(middleware::from_fn_with_state(format, log_request))
+    }
+
+    async fn send(router: &Router, uri: &str, request_id: Option<&str>) -> StatusCode {
+        let mut req = Request::get(uri);
+        if let Some(id) = request_id {
+            req = req.header(REQUEST_ID, id);
+        }
+        let res = router
+            .clone()
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(res.headers().contains_key(REQUEST_ID));
+        res.status()
+    }
+
+    #[tokio::test]
+    async fn test_access_log_line_per_request() {
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let router = app(AccessLogFormat::Structured);
+        assert_eq!(send(&router, "/blob", Some("req-1")).await, StatusCode::OK);
+        assert_eq!(send(&router, "/missing", None).await, StatusCode::NOT_FOUND);
+
+        let lines = capture.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("request_id=req-1"));
+        assert!(lines[0].contains("path=/blob"));
+        assert!(lines[0].contains("status=200"));
+        assert!(lines[0].contains("bytes=5"));
+        assert!(lines[1].contains("path=/missing"));
+        assert!(lines[1].contains("status=404"));
+
+        let router = app(AccessLogFormat::Combined);
+        send(&router, "/blob?path=/", None).await;
+        let lines = capture.lines();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].contains("\"GET /blob?path=/ HTTP/1.1\" 200 5 \"-\" \"-\""));
+    }
+}
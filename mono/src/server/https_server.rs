@@ -1,12 +1,14 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::Result;
 use async_session::MemoryStore;
 use axum::body::Body;
 use axum::extract::{Query, State};
 use axum::http::{self, Request, Uri};
+use axum::middleware;
 use axum::response::Response;
 use axum::routing::get;
 use axum::Router;
@@ -19,15 +21,20 @@ use tower_http::cors::{Any, CorsLayer};
 use tower_http::decompression::RequestDecompressionLayer;
 use tower_http::trace::TraceLayer;
 
+use ceres::api_service::ApiHandler;
 use ceres::protocol::{ServiceType, SmartProtocol, TransportProtocol};
 use common::errors::ProtocolError;
 use common::model::{CommonOptions, InfoRefsParams};
 use jupiter::context::Context;
 
 use crate::api::api_router::{self};
+use crate::api::handler_cache::HandlerCache;
+use crate::api::idempotency::IdempotencyCache;
 use crate::api::lfs::lfs_router;
+use crate::api::metrics::{self, Metrics};
 use crate::api::oauth::{self, oauth_client};
-use crate::api::MonoApiServiceState;
+use crate::api::{MonoApiServiceState, PageLimits};
+use crate::server::access_log;
 
 #[derive(Args, Clone, Debug)]
 pub struct HttpOptions {
@@ -59,6 +66,8 @@ pub struct AppState {
     pub host: String,
     pub port: u16,
     pub common: CommonOptions,
+    /// Shared with the API, a push may create the imported repo of a path it cached
+    pub api_handlers: Arc<HandlerCache<dyn ApiHandler>>,
 }
 
 pub fn remove_git_suffix(uri: Uri, git_suffix: &str) -> PathBuf {
@@ -106,7 +115,7 @@ pub async fn start_http(context: Context, options: HttpOptions) {
 /// This is the main entry for the mono server.
 /// It is responsible for creating the main router and setting up the necessary middleware.
 ///
-/// The main router is composed of three nested routers:
+/// The main router is composed of these routers:
 /// 1. The LFS router nested in the `/`:
 ///   - GET or PUT `/objects/:object_id`
 ///   - GET or PUT `/locks`
@@ -124,20 +133,25 @@ pub async fn start_http(context: Context, options: HttpOptions) {
 ///   - GET        `/api/v1/file/blob/:object_id`
 ///   - GET        `/api/v1/file/tree`
 ///   - GET        `/api/v1/path-can-clone`
+///   - GET        `/api/v1/openapi.json`
 /// 3. The OAuth router nested in the `/auth`:
 ///   - GET        `/auth/github`
 ///   - GET        `/auth/authorized`
 ///   - GET        `/auth/logout`
-/// 4. The other routers for the git protocol:
+/// 4. The Prometheus metrics of the API router, except `/status`:
+///   - GET        `/metrics`
+/// 5. The other routers for the git protocol:
 ///   - GET        end of `Regex::new(r"/info/refs$")`
 ///   - POST       end of `Regex::new(r"/git-upload-pack$")`
 ///   - POST       end of `Regex::new(r"/git-receive-pack$")`
 pub async fn app(context: Context, host: String, port: u16, common: CommonOptions) -> Router {
+    let api_handlers = Arc::new(HandlerCache::default());
     let state = AppState {
         host,
         port,
         context: context.clone(),
         common: common.clone(),
+        api_handlers: api_handlers.clone(),
     };
 
     let api_state = MonoApiServiceState {
@@ -145,17 +159,32 @@ pub async fn app(context: Context, host: String, port: u16, common: CommonOption
         common: common.clone(),
         oauth_client: Some(oauth_client(context.config.oauth.unwrap()).unwrap()),
         store: Some(MemoryStore::new()),
+        api_handlers,
+        metrics: Arc::new(Metrics::default()),
+        page_limits: PageLimits::from(&context.config.monorepo),
+        create_file_outcomes: Arc::new(IdempotencyCache::default()),
+        latest_commits: Arc::default(),
     };
 
     // add RequestDecompressionLayer for handle gzip encode
     // add TraceLayer for log record
     // add CorsLayer to add cors header
+    // add access log layer to log every request in the configured format
     Router::new()
         .nest("/", lfs_router::routers())
         .with_state(api_state.clone())
         .nest(
             "/api/v1",
-            api_router::routers().with_state(api_state.clone()),
+            api_router::routers(context.config.monorepo.max_request_body_size * 1024 * 1024)
+                .route_layer(middleware::from_fn_with_state(
+                    api_state.metrics.clone(),
+                    metrics::track,
+                ))
+                .with_state(api_state.clone()),
+        )
+        .route(
+            "/metrics",
+            get(metrics::render).with_state(api_state.metrics.clone()),
         )
         .nest("/auth", oauth::routers().with_state(api_state.clone()))
         // Using Regular Expressions for Path Matching in Protocol
@@ -168,6 +197,10 @@ pub async fn app(context: Context, host: String, port: u16, common: CommonOption
         )
         .layer(TraceLayer::new_for_http())
         .layer(RequestDecompressionLayer::new())
+        .layer(middleware::from_fn_with_state(
+            context.config.log.access_log_format,
+            access_log::log_request,
+        ))
         .with_state(state)
 }
 
@@ -211,13 +244,15 @@ pub async fn post_method_router(
         pack_protocol.service_type = Some(ServiceType::UploadPack);
         crate::git_protocol::http::git_upload_pack(req, pack_protocol).await
     } else if REGEX_GIT_RECEIVE_PACK.is_match(uri.path()) {
-        let mut pack_protocol = SmartProtocol::new(
-            remove_git_suffix(uri.clone(), "/git-receive-pack"),
-            state.context.clone(),
-            TransportProtocol::Http,
-        );
+        let path = remove_git_suffix(uri.clone(), "/git-receive-pack");
+        let mut pack_protocol =
+            SmartProtocol::new(path.clone(), state.context.clone(), TransportProtocol::Http);
         pack_protocol.service_type = Some(ServiceType::ReceivePack);
-        crate::git_protocol::http::git_receive_pack(req, pack_protocol).await
+        let res = crate::git_protocol::http::git_receive_pack(req, pack_protocol).await;
+        if res.is_ok() && path.starts_with(&state.context.config.monorepo.import_dir) {
+            state.api_handlers.invalidate(&path);
+        }
+        res
     } else {
         return Err(ProtocolError::NotFound(
             "Operation not supported".to_owned(),
@@ -1,2 +1,3 @@
+pub mod access_log;
 pub mod https_server;
 pub mod ssh_server;
\ No newline at end of file
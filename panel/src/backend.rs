@@ -12,6 +12,9 @@ pub(crate) async fn init(config: &MegaConfig) {
     };
     let ztm = ZtmOptions {
         ztm_agent_port: 7777,
+        ztm_tunnel_host: String::from("127.0.0.1"),
+        ztm_max_response_size: 64 * 1024 * 1024,
+        ztm_tunnel_ttl: None,
         #[cfg(feature = "p2p")]
         bootstrap_node: None,
         #[cfg(not(feature = "p2p"))]
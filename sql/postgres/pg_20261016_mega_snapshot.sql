@@ -0,0 +1,12 @@
+-- Snapshots of the monorepo tree at a commit, one row per path
+CREATE TABLE IF NOT EXISTS "mega_snapshot" (
+  "id" BIGINT PRIMARY KEY,
+  "commit_id" VARCHAR(40) NOT NULL,
+  "path" TEXT NOT NULL,
+  "import_dir" BOOLEAN,
+  "tree_id" VARCHAR(40),
+  "sub_trees" JSON,  -- for compatibility with sqlite, DO NOT use Array Type
+  "size" BIGINT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_ms_commit_path UNIQUE (commit_id, path)
+);
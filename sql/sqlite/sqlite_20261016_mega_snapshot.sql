@@ -0,0 +1,12 @@
+-- Snapshots of the monorepo tree at a commit, one row per path
+CREATE TABLE IF NOT EXISTS "mega_snapshot" (
+  "id" INTEGER PRIMARY KEY,
+  "commit_id" TEXT NOT NULL,
+  "path" TEXT NOT NULL,
+  "import_dir" BOOLEAN,
+  "tree_id" TEXT,
+  "sub_trees" TEXT,  -- Use JSON to store array
+  "size" INTEGER NOT NULL,
+  "created_at" TEXT NOT NULL,
+  CONSTRAINT uniq_ms_commit_path UNIQUE (commit_id, path)
+);
@@ -22,11 +22,20 @@ pub struct ApiRequestEvent {
 pub enum ApiType {
     // Common Api enum for api_routers
     CreateFile,
+    DeleteFile,
+    MoveFile,
+    Diff,
+    DedupStats,
     LastestCommit,
     CommitInfo,
     TreeInfo,
     Blob,
     Publish,
+    /// Download of a directory as an archive, `path` is the requested directory
+    Archive {
+        format: ArchiveFormat,
+        path: String,
+    },
 
     // Merge Api enum for mr_routers
     MergeRequest,
@@ -36,6 +45,37 @@ pub enum ApiType {
     MergeFiles,
 }
 
+impl ApiType {
+    /// Name of the variant without its payload, e.g. `Archive`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ApiType::CreateFile => "CreateFile",
+            ApiType::DeleteFile => "DeleteFile",
+            ApiType::MoveFile => "MoveFile",
+            ApiType::Diff => "Diff",
+            ApiType::DedupStats => "DedupStats",
+            ApiType::LastestCommit => "LastestCommit",
+            ApiType::CommitInfo => "CommitInfo",
+            ApiType::TreeInfo => "TreeInfo",
+            ApiType::Blob => "Blob",
+            ApiType::Publish => "Publish",
+            ApiType::Archive { .. } => "Archive",
+            ApiType::MergeRequest => "MergeRequest",
+            ApiType::MergeDone => "MergeDone",
+            ApiType::MergeList => "MergeList",
+            ApiType::MergeDetail => "MergeDetail",
+            ApiType::MergeFiles => "MergeFiles",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
 impl std::fmt::Display for ApiRequestEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Api Request Event: {:?}", self.api)
@@ -52,7 +92,11 @@ impl EventBase for ApiRequestEvent {
 impl ApiRequestEvent {
     // Create and enqueue this event.
     pub fn notify(api: ApiType, config: &Config) {
-        get_mq().send(EventType::ApiRequest(ApiRequestEvent {
+        Self::notify_to(api, config, |evt| get_mq().send(evt));
+    }
+
+    fn notify_to(api: ApiType, config: &Config, send: impl FnOnce(EventType)) {
+        send(EventType::ApiRequest(ApiRequestEvent {
             api,
             config: config.clone(),
         }));
@@ -78,7 +122,8 @@ impl TryFrom<serde_json::Value> for ApiRequestEvent {
 
 #[cfg(test)]
 mod tests {
-    use super::{ApiRequestEvent, ApiType};
+    use super::{ApiRequestEvent, ApiType, ArchiveFormat};
+    use crate::event::EventType;
     use common::config::Config;
     use serde_json::Value;
 
@@ -96,4 +141,29 @@ mod tests {
         // Convert from value
         let _ = ApiRequestEvent::try_from(serialized).unwrap();
     }
+
+    #[test]
+    fn test_archive_event() {
+        let mut sink = Vec::new();
+        let api = ApiType::Archive {
+            format: ArchiveFormat::Zip,
+            path: "/project/mega".to_owned(),
+        };
+        ApiRequestEvent::notify_to(api, &Config::default(), |evt| sink.push(evt));
+
+        assert_eq!(sink.len(), 1);
+        let EventType::ApiRequest(evt) = &sink[0] else {
+            panic!("expected an api request event");
+        };
+        let payload = Value::from(evt.clone());
+        assert_eq!(payload["api"]["Archive"]["format"], "zip");
+        assert_eq!(payload["api"]["Archive"]["path"], "/project/mega");
+        match ApiRequestEvent::try_from(payload).unwrap().api {
+            ApiType::Archive { format, path } => {
+                assert_eq!(format, ArchiveFormat::Zip);
+                assert_eq!(path, "/project/mega");
+            }
+            api => panic!("unexpected api {:?}", api),
+        }
+    }
 }